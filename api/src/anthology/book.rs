@@ -0,0 +1,219 @@
+use crate::haiku::entity::Haiku;
+use crate::haiku::kigo::{Saijiki, Season, season_of};
+use crate::tags::entity::Tag;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    #[default]
+    Season,
+    Tag,
+}
+
+pub struct Section {
+    pub title: String,
+    /// Oldest first.
+    pub haikus: Vec<Haiku>,
+}
+
+/// An anthology, ready to be laid out as an EPUB or a PDF.
+pub struct Book {
+    pub title: String,
+    /// Short description of what was selected, e.g. "tagged moon".
+    pub subtitle: Option<String>,
+    pub attribution: String,
+    /// BCP 47 tags of the haikus' languages, the most used first.
+    pub languages: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub sections: Vec<Section>,
+}
+
+impl Book {
+    pub fn haiku_count(&self) -> usize {
+        self.sections
+            .iter()
+            .map(|section| section.haikus.len())
+            .sum()
+    }
+
+    /// The main language, `und` when unknown.
+    pub fn language(&self) -> &str {
+        self.languages.first().map_or("und", String::as_str)
+    }
+}
+
+/// BCP 47 tags of the `haikus`' languages, the most used first.
+pub async fn languages(pool: &PgPool, haikus: &[Haiku]) -> Result<Vec<String>, sqlx::Error> {
+    let ids: Vec<_> = haikus.iter().map(|haiku| haiku.id).collect();
+    let mut tags: Vec<String> = Vec::new();
+    for config in Haiku::languages(pool, &ids).await? {
+        let tag = language_tag(&config).to_string();
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    Ok(tags)
+}
+
+/// Language of a built-in text search configuration; `und` for `simple`
+/// and custom ones.
+fn language_tag(config: &str) -> &'static str {
+    match config {
+        "arabic" => "ar",
+        "armenian" => "hy",
+        "basque" => "eu",
+        "catalan" => "ca",
+        "danish" => "da",
+        "dutch" => "nl",
+        "english" => "en",
+        "finnish" => "fi",
+        "french" => "fr",
+        "german" => "de",
+        "greek" => "el",
+        "hindi" => "hi",
+        "hungarian" => "hu",
+        "indonesian" => "id",
+        "irish" => "ga",
+        "italian" => "it",
+        "lithuanian" => "lt",
+        "nepali" => "ne",
+        "norwegian" => "no",
+        "portuguese" => "pt",
+        "romanian" => "ro",
+        "russian" => "ru",
+        "serbian" => "sr",
+        "spanish" => "es",
+        "swedish" => "sv",
+        "tamil" => "ta",
+        "turkish" => "tr",
+        "yiddish" => "yi",
+        _ => "und",
+    }
+}
+
+/// Groups `haikus` into sections, in calendar order for seasons and by
+/// name for tags. Haikus without a season word or tag come last.
+///
+/// A haiku appears once, under its first tag by name that is not one of
+/// the `filter_tags` every haiku carries anyway.
+pub async fn sections(
+    pool: &PgPool,
+    mut haikus: Vec<Haiku>,
+    group_by: GroupBy,
+    filter_tags: &[String],
+) -> Result<Vec<Section>, sqlx::Error> {
+    haikus.sort_by_key(|haiku| (haiku.created_at, haiku.id));
+
+    let mut keys: Vec<(Option<String>, u8)> = Vec::with_capacity(haikus.len());
+    match group_by {
+        GroupBy::Season => {
+            let saijiki = Saijiki::current(pool).await?;
+            for haiku in &haikus {
+                let season = season_of(&saijiki.find(&haiku.content));
+                keys.push((
+                    season.map(|s| season_title(s).to_string()),
+                    season_rank(season),
+                ));
+            }
+        }
+        GroupBy::Tag => {
+            let ids: Vec<_> = haikus.iter().map(|haiku| haiku.id).collect();
+            let mut names: HashMap<_, Vec<String>> = HashMap::new();
+            for (haiku_id, name) in Tag::names_for_haikus(pool, &ids).await? {
+                names.entry(haiku_id).or_default().push(name);
+            }
+            for haiku in &haikus {
+                let tags = names.remove(&haiku.id).unwrap_or_default();
+                let tag = tags
+                    .iter()
+                    .find(|tag| !filter_tags.contains(tag))
+                    .or(tags.first())
+                    .cloned();
+                let rank = if tag.is_some() { 0 } else { 1 };
+                keys.push((tag, rank));
+            }
+        }
+    }
+
+    let mut sections: Vec<(u8, Option<String>, Vec<Haiku>)> = Vec::new();
+    for (haiku, (key, rank)) in haikus.into_iter().zip(keys) {
+        match sections.iter_mut().find(|(_, title, _)| *title == key) {
+            Some((_, _, haikus)) => haikus.push(haiku),
+            None => sections.push((rank, key, vec![haiku])),
+        }
+    }
+    sections.sort_by(|(a_rank, a, _), (b_rank, b, _)| (a_rank, a).cmp(&(b_rank, b)));
+
+    let untitled = match group_by {
+        GroupBy::Season => "Seasonless",
+        GroupBy::Tag => "Untagged",
+    };
+    Ok(sections
+        .into_iter()
+        .map(|(_, title, haikus)| Section {
+            title: title.unwrap_or_else(|| untitled.to_string()),
+            haikus,
+        })
+        .collect())
+}
+
+fn season_title(season: Season) -> &'static str {
+    match season {
+        Season::NewYear => "New Year",
+        Season::Spring => "Spring",
+        Season::Summer => "Summer",
+        Season::Autumn => "Autumn",
+        Season::Winter => "Winter",
+    }
+}
+
+/// The saijiki's traditional order, starting with the New Year.
+fn season_rank(season: Option<Season>) -> u8 {
+    match season {
+        Some(Season::NewYear) => 0,
+        Some(Season::Spring) => 1,
+        Some(Season::Summer) => 2,
+        Some(Season::Autumn) => 3,
+        Some(Season::Winter) => 4,
+        None => 5,
+    }
+}
+
+/// Non-empty, trimmed lines of a haiku.
+pub fn lines(haiku: &Haiku) -> Vec<&str> {
+    haiku
+        .content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// The first line, used to list a haiku in the table of contents.
+pub fn first_line(haiku: &Haiku) -> &str {
+    lines(haiku).first().copied().unwrap_or("Untitled haiku")
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_text_search_configurations_to_language_tags() {
+        assert_eq!(language_tag("english"), "en");
+        assert_eq!(language_tag("norwegian"), "no");
+        assert_eq!(language_tag("simple"), "und");
+        assert_eq!(language_tag("public.custom"), "und");
+    }
+}
@@ -0,0 +1,180 @@
+//! EPUB 3 layout: a title page, a navigation document doubling as the
+//! table of contents, then one XHTML document per section divider and per
+//! haiku, so reading systems start each on a new page.
+
+use super::book::{Book, escape, first_line, lines};
+use std::io::{Cursor, Write};
+use uuid::Uuid;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+const STYLE: &str = r#"body { font-family: serif; margin: 0; padding: 0; }
+.title { text-align: center; margin-top: 30%; }
+.title h1 { font-size: 2em; font-weight: normal; }
+.title p { font-style: italic; }
+.section { text-align: center; margin-top: 40%; font-weight: normal; }
+.haiku { text-align: center; margin-top: 35%; font-size: 1.2em; line-height: 1.8; }
+nav ol { list-style: none; }
+"#;
+
+pub fn write(book: &Book) -> zip::result::ZipResult<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    // The mimetype must come first and be stored uncompressed.
+    zip.start_file(
+        "mimetype",
+        SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
+    )?;
+    zip.write_all(b"application/epub+zip")?;
+
+    let options = SimpleFileOptions::default();
+    zip.start_file("META-INF/container.xml", options)?;
+    zip.write_all(
+        br#"<?xml version="1.0" encoding="utf-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#,
+    )?;
+
+    zip.start_file("OEBPS/style.css", options)?;
+    zip.write_all(STYLE.as_bytes())?;
+
+    let mut documents = vec!["title".to_string(), "nav".to_string()];
+    let subtitle = book
+        .subtitle
+        .as_deref()
+        .map(|subtitle| format!("<p>{}</p>", escape(subtitle)))
+        .unwrap_or_default();
+    zip.start_file("OEBPS/title.xhtml", options)?;
+    zip.write_all(
+        page(
+            book,
+            &book.title,
+            &format!(
+                r#"<div class="title"><h1>{}</h1>{}<p>{}</p></div>"#,
+                escape(&book.title),
+                subtitle,
+                escape(&book.attribution)
+            ),
+        )
+        .as_bytes(),
+    )?;
+
+    let mut toc = String::new();
+    for (index, section) in book.sections.iter().enumerate() {
+        let section_id = format!("section-{}", index + 1);
+        zip.start_file(format!("OEBPS/{}.xhtml", section_id), options)?;
+        zip.write_all(
+            page(
+                book,
+                &section.title,
+                &format!(r#"<h2 class="section">{}</h2>"#, escape(&section.title)),
+            )
+            .as_bytes(),
+        )?;
+        toc.push_str(&format!(
+            r#"<li><a href="{}.xhtml">{}</a><ol>"#,
+            section_id,
+            escape(&section.title)
+        ));
+        documents.push(section_id);
+
+        for haiku in &section.haikus {
+            let haiku_id = format!("haiku-{}", haiku.id);
+            let body: Vec<String> = lines(haiku).into_iter().map(escape).collect();
+            zip.start_file(format!("OEBPS/{}.xhtml", haiku_id), options)?;
+            zip.write_all(
+                page(
+                    book,
+                    first_line(haiku),
+                    &format!(r#"<p class="haiku">{}</p>"#, body.join("<br/>")),
+                )
+                .as_bytes(),
+            )?;
+            toc.push_str(&format!(
+                r#"<li><a href="{}.xhtml">{}</a></li>"#,
+                haiku_id,
+                escape(first_line(haiku))
+            ));
+            documents.push(haiku_id);
+        }
+        toc.push_str("</ol></li>");
+    }
+
+    zip.start_file("OEBPS/nav.xhtml", options)?;
+    zip.write_all(
+        page(
+            book,
+            "Contents",
+            &format!(
+                r#"<nav epub:type="toc" id="toc"><h2>Contents</h2><ol>{}</ol></nav>"#,
+                toc
+            ),
+        )
+        .as_bytes(),
+    )?;
+
+    zip.start_file("OEBPS/content.opf", options)?;
+    zip.write_all(package(book, &documents).as_bytes())?;
+
+    Ok(zip.finish()?.into_inner())
+}
+
+fn page(book: &Book, title: &str, body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops" xml:lang="{language}" lang="{language}">
+<head><meta charset="utf-8"/><title>{}</title><link rel="stylesheet" type="text/css" href="style.css"/></head>
+<body>{}</body>
+</html>
+"#,
+        escape(title),
+        body,
+        language = escape(book.language()),
+    )
+}
+
+fn package(book: &Book, documents: &[String]) -> String {
+    let mut manifest = String::from(r#"<item id="style" href="style.css" media-type="text/css"/>"#);
+    let mut spine = String::new();
+    let mut languages = String::new();
+    for language in &book.languages {
+        languages.push_str(&format!("<dc:language>{}</dc:language>", escape(language)));
+    }
+    if languages.is_empty() {
+        languages.push_str("<dc:language>und</dc:language>");
+    }
+    for id in documents {
+        let properties = match id.as_str() {
+            "nav" => r#" properties="nav""#,
+            _ => "",
+        };
+        manifest.push_str(&format!(
+            r#"<item id="{id}" href="{id}.xhtml" media-type="application/xhtml+xml"{properties}/>"#
+        ));
+        spine.push_str(&format!(r#"<itemref idref="{id}"/>"#));
+    }
+
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="book-id">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="book-id">urn:uuid:{id}</dc:identifier>
+    <dc:title>{title}</dc:title>
+    <dc:creator>{creator}</dc:creator>
+    {languages}
+    <meta property="dcterms:modified">{modified}</meta>
+  </metadata>
+  <manifest>{manifest}</manifest>
+  <spine>{spine}</spine>
+</package>
+"#,
+        id = Uuid::new_v4(),
+        title = escape(&book.title),
+        creator = escape(&book.attribution),
+        modified = book.created_at.format("%Y-%m-%dT%H:%M:%SZ"),
+    )
+}
@@ -0,0 +1,155 @@
+use super::book::{Book, GroupBy, languages, sections};
+use super::{epub, pdf};
+use crate::auth::token::TokenKeys;
+use crate::cards::handler::DefaultAttribution;
+use crate::cards::render::CardRenderer;
+use crate::feeds::handler::FilterParams;
+use axum::{
+    extract::{Extension, Query},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use serde::Deserialize;
+use sqlx::PgPool;
+use thiserror::Error;
+
+/// Larger selections make unwieldy books, and are built in memory.
+const MAX_HAIKUS: i64 = 1000;
+const MAX_TITLE_LEN: usize = 120;
+
+#[derive(Debug, Error)]
+pub enum AnthologyError {
+    #[error("Anthology not found")]
+    NotFound,
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("Failed to write EPUB: {0}")]
+    Epub(#[from] zip::result::ZipError),
+    #[error("Failed to write PDF: {0}")]
+    Pdf(#[from] pdf::PdfError),
+    #[error("Failed to lay out the anthology: {0}")]
+    Task(#[from] tokio::task::JoinError),
+}
+
+impl IntoResponse for AnthologyError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            AnthologyError::NotFound => StatusCode::NOT_FOUND,
+            AnthologyError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AnthologyError::Database(_)
+            | AnthologyError::Epub(_)
+            | AnthologyError::Pdf(_)
+            | AnthologyError::Task(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, self.to_string()).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnthologyParams {
+    #[serde(default)]
+    group: GroupBy,
+    title: Option<String>,
+    author: Option<String>,
+}
+
+pub async fn anthology_epub(
+    Extension(pool): Extension<PgPool>,
+    Extension(keys): Extension<TokenKeys>,
+    Extension(attribution): Extension<DefaultAttribution>,
+    Query(filters): Query<FilterParams>,
+    Query(params): Query<AnthologyParams>,
+    headers: HeaderMap,
+) -> Result<Response, AnthologyError> {
+    let book = book(&pool, &keys, &attribution, filters, params, &headers).await?;
+    let epub = tokio::task::spawn_blocking(move || epub::write(&book)).await??;
+    Ok(attachment(epub, "application/epub+zip", "anthology.epub"))
+}
+
+pub async fn anthology_pdf(
+    Extension(pool): Extension<PgPool>,
+    Extension(keys): Extension<TokenKeys>,
+    Extension(attribution): Extension<DefaultAttribution>,
+    Extension(renderer): Extension<CardRenderer>,
+    Query(filters): Query<FilterParams>,
+    Query(params): Query<AnthologyParams>,
+    headers: HeaderMap,
+) -> Result<Response, AnthologyError> {
+    let book = book(&pool, &keys, &attribution, filters, params, &headers).await?;
+    let pdf = tokio::task::spawn_blocking(move || pdf::write(&book, &renderer)).await??;
+    Ok(attachment(pdf, "application/pdf", "anthology.pdf"))
+}
+
+async fn book(
+    pool: &PgPool,
+    keys: &TokenKeys,
+    attribution: &DefaultAttribution,
+    filters: FilterParams,
+    params: AnthologyParams,
+    headers: &HeaderMap,
+) -> Result<Book, AnthologyError> {
+    let title = params
+        .title
+        .map(|title| title.trim().to_string())
+        .filter(|title| !title.is_empty())
+        .unwrap_or_else(|| "Haiku Anthology".to_string());
+    if title.chars().count() > MAX_TITLE_LEN {
+        return Err(AnthologyError::BadRequest(format!(
+            "title must be at most {} characters",
+            MAX_TITLE_LEN
+        )));
+    }
+
+    let (query, descriptions) = filters
+        .resolve(pool, keys, headers)
+        .await?
+        .ok_or(AnthologyError::NotFound)?;
+    let stats = query.stats(pool).await?;
+    if stats.total == 0 {
+        return Err(AnthologyError::NotFound);
+    }
+    if stats.total > MAX_HAIKUS {
+        return Err(AnthologyError::BadRequest(format!(
+            "{} haikus match, narrow the selection to at most {}",
+            stats.total, MAX_HAIKUS
+        )));
+    }
+    let haikus = query.haikus(pool, MAX_HAIKUS, 0).await?;
+    let filter_tags = query.tags.unwrap_or_default();
+
+    Ok(Book {
+        title,
+        subtitle: (!descriptions.is_empty()).then(|| format!("Haikus {}", descriptions.join(", "))),
+        attribution: params
+            .author
+            .map(|author| {
+                author
+                    .trim()
+                    .chars()
+                    .take(MAX_TITLE_LEN)
+                    .collect::<String>()
+            })
+            .filter(|author| !author.is_empty())
+            .unwrap_or_else(|| attribution.0.clone()),
+        languages: languages(pool, &haikus).await?,
+        created_at: Utc::now(),
+        sections: sections(pool, haikus, params.group, &filter_tags).await?,
+    })
+}
+
+fn attachment(body: Vec<u8>, content_type: &'static str, filename: &str) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
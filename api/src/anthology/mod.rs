@@ -0,0 +1,4 @@
+pub mod book;
+pub mod epub;
+pub mod handler;
+pub mod pdf;
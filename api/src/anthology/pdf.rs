@@ -0,0 +1,293 @@
+//! PDF layout: the pages are drawn one below the other in a single SVG,
+//! converted once by `svg2pdf` with the card fonts so that each font is
+//! embedded once, and every A5 page of the PDF shows its own slice of it.
+
+use super::book::{Book, escape, first_line, lines};
+use crate::cards::render::{CardRenderer, RenderError, em_width};
+use pdf_writer::{Content, Finish, Name, Pdf, Rect, Ref, TextStr};
+use std::collections::HashMap;
+use std::fmt::Write;
+use thiserror::Error;
+
+/// A5, in points.
+const WIDTH: f32 = 420.0;
+const HEIGHT: f32 = 595.0;
+const MARGIN: f32 = 48.0;
+const HAIKU_FONT_SIZE: f32 = 16.0;
+const TOC_FONT_SIZE: f32 = 10.0;
+const TOC_LINE_HEIGHT: f32 = 16.0;
+const TOC_TOP: f32 = 110.0;
+const TOC_LINES_PER_PAGE: usize = 27;
+
+const TEXT: &str = "#2b2620";
+const ACCENT: &str = "#b0412e";
+
+#[derive(Debug, Error)]
+pub enum PdfError {
+    #[error(transparent)]
+    Render(#[from] RenderError),
+    #[error("PDF conversion failed: {0}")]
+    Convert(String),
+}
+
+enum TocLine<'a> {
+    Section(&'a str, usize),
+    Haiku(&'a str, usize),
+}
+
+pub fn write(book: &Book, renderer: &CardRenderer) -> Result<Vec<u8>, PdfError> {
+    // Title page, then the contents, then a divider and the haikus of
+    // each section.
+    let mut toc = Vec::new();
+    let toc_len = book.sections.len() + book.haiku_count();
+    let toc_pages = toc_len.div_ceil(TOC_LINES_PER_PAGE).max(1);
+    let mut number = 1 + toc_pages;
+    for section in &book.sections {
+        number += 1;
+        toc.push(TocLine::Section(&section.title, number));
+        for haiku in &section.haikus {
+            number += 1;
+            toc.push(TocLine::Haiku(first_line(haiku), number));
+        }
+    }
+
+    let mut pages = vec![title_page(book)];
+    let mut chunks = toc.chunks(TOC_LINES_PER_PAGE);
+    for index in 0..toc_pages {
+        pages.push(toc_page(chunks.next().unwrap_or_default(), index == 0));
+    }
+    for section in &book.sections {
+        pages.push(section_page(&section.title));
+        for haiku in &section.haikus {
+            let number = pages.len() + 1;
+            pages.push(haiku_page(&lines(haiku), &section.title, number));
+        }
+    }
+
+    assemble(&pages, &book.title, renderer)
+}
+
+fn assemble(pages: &[String], title: &str, renderer: &CardRenderer) -> Result<Vec<u8>, PdfError> {
+    let height = HEIGHT * pages.len() as f32;
+    let mut document = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{height}" viewBox="0 0 {WIDTH} {height}" font-family="serif" fill="{TEXT}">"#
+    );
+    for (index, page) in pages.iter().enumerate() {
+        let _ = write!(
+            document,
+            r#"<g transform="translate(0 {})">{page}</g>"#,
+            HEIGHT * index as f32
+        );
+    }
+    document.push_str("</svg>");
+
+    let mut alloc = Ref::new(1);
+    let catalog_id = alloc.bump();
+    let tree_id = alloc.bump();
+    let info_id = alloc.bump();
+    let content_id = alloc.bump();
+    let name = Name(b"P");
+    let mut pdf = Pdf::new();
+
+    let tree = renderer.tree(&document)?;
+    let (chunk, svg_id) = svg2pdf::to_chunk(&tree, svg2pdf::ConversionOptions::default())
+        .map_err(|e| PdfError::Convert(e.to_string()))?;
+    let mut ids = HashMap::new();
+    let chunk = chunk.renumber(|old| *ids.entry(old).or_insert_with(|| alloc.bump()));
+    let svg_id = ids[&svg_id];
+
+    // Every page draws the whole document, and its media box crops it to
+    // the page's slice; PDF coordinates start at the bottom.
+    let mut content = Content::new();
+    content
+        .transform([WIDTH, 0.0, 0.0, height, 0.0, 0.0])
+        .x_object(name);
+    pdf.stream(content_id, &content.finish());
+    let mut page_ids = Vec::with_capacity(pages.len());
+    for index in 0..pages.len() {
+        let page_id = alloc.bump();
+        let top = height - HEIGHT * index as f32;
+        let mut page = pdf.page(page_id);
+        page.media_box(Rect::new(0.0, top - HEIGHT, WIDTH, top));
+        page.parent(tree_id);
+        page.contents(content_id);
+        page.resources().x_objects().pair(name, svg_id);
+        page.finish();
+        page_ids.push(page_id);
+    }
+    pdf.extend(&chunk);
+
+    pdf.catalog(catalog_id).pages(tree_id);
+    pdf.pages(tree_id)
+        .kids(page_ids.iter().copied())
+        .count(page_ids.len() as i32);
+    pdf.document_info(info_id).title(TextStr(title));
+    Ok(pdf.finish())
+}
+
+/// Font size at which the widest of `lines` fits between the margins.
+fn fit(lines: &[&str], max: f32) -> f32 {
+    let widest = lines.iter().map(|line| em_width(line)).fold(1.0, f32::max);
+    max.min((WIDTH - 2.0 * MARGIN) / widest)
+}
+
+fn title_page(book: &Book) -> String {
+    let title_size = fit(&[&book.title], 28.0);
+    let mut body = format!(
+        r#"<text x="{x}" y="230" font-size="{title_size:.1}" text-anchor="middle">{}</text>"#,
+        escape(&book.title),
+        x = WIDTH / 2.0,
+    );
+    if let Some(subtitle) = &book.subtitle {
+        body.push_str(&format!(
+            r#"<text x="{x}" y="262" font-size="{:.1}" font-style="italic" text-anchor="middle">{}</text>"#,
+            fit(&[subtitle], 13.0),
+            escape(subtitle),
+            x = WIDTH / 2.0,
+        ));
+    }
+    body.push_str(&format!(
+        r#"<rect x="{}" y="290" width="32" height="2" fill="{ACCENT}"/><text x="{x}" y="330" font-size="12" text-anchor="middle">{}</text><text x="{x}" y="{}" font-size="10" text-anchor="middle">{}</text>"#,
+        WIDTH / 2.0 - 16.0,
+        escape(&book.attribution),
+        HEIGHT - MARGIN,
+        book.created_at.format("%B %Y"),
+        x = WIDTH / 2.0,
+    ));
+    body
+}
+
+fn toc_page(lines: &[TocLine], first: bool) -> String {
+    let mut body = String::new();
+    if first {
+        body.push_str(&format!(
+            r#"<text x="{MARGIN}" y="80" font-size="18">Contents</text>"#
+        ));
+    }
+    let number_x = WIDTH - MARGIN;
+    for (index, line) in lines.iter().enumerate() {
+        let y = TOC_TOP + TOC_LINE_HEIGHT * index as f32;
+        let (text, number, x, weight) = match *line {
+            TocLine::Section(title, number) => (title, number, MARGIN, "bold"),
+            TocLine::Haiku(first_line, number) => (first_line, number, MARGIN + 14.0, "normal"),
+        };
+        // Leave room for the page number.
+        let max_ems = (number_x - x - 28.0) / TOC_FONT_SIZE;
+        body.push_str(&format!(
+            r#"<text x="{x}" y="{y:.1}" font-size="{TOC_FONT_SIZE}" font-weight="{weight}">{}</text><text x="{number_x}" y="{y:.1}" font-size="{TOC_FONT_SIZE}" text-anchor="end">{number}</text>"#,
+            escape(&truncate(text, max_ems)),
+        ));
+    }
+    body
+}
+
+fn section_page(title: &str) -> String {
+    format!(
+        r#"<text x="{x}" y="{y}" font-size="{:.1}" text-anchor="middle">{}</text><rect x="{}" y="{}" width="32" height="2" fill="{ACCENT}"/>"#,
+        fit(&[title], 24.0),
+        escape(title),
+        WIDTH / 2.0 - 16.0,
+        HEIGHT / 2.0 + 20.0,
+        x = WIDTH / 2.0,
+        y = HEIGHT / 2.0,
+    )
+}
+
+fn haiku_page(lines: &[&str], section: &str, number: usize) -> String {
+    let font_size = fit(lines, HAIKU_FONT_SIZE);
+    let line_height = font_size * 1.8;
+    let first_baseline = (HEIGHT - line_height * lines.len() as f32) / 2.0 + font_size;
+    let mut body = format!(
+        r#"<text x="{x}" y="{MARGIN}" font-size="9" fill="{ACCENT}" text-anchor="middle">{}</text>"#,
+        escape(section),
+        x = WIDTH / 2.0,
+    );
+    for (index, line) in lines.iter().enumerate() {
+        body.push_str(&format!(
+            r#"<text x="{x}" y="{:.1}" font-size="{font_size:.1}" text-anchor="middle">{}</text>"#,
+            first_baseline + line_height * index as f32,
+            escape(line),
+            x = WIDTH / 2.0,
+        ));
+    }
+    body.push_str(&format!(
+        r#"<text x="{x}" y="{}" font-size="9" text-anchor="middle">{number}</text>"#,
+        HEIGHT - MARGIN + 16.0,
+        x = WIDTH / 2.0,
+    ));
+    body
+}
+
+/// Cuts `text` to about `max_ems` wide, ending it with an ellipsis.
+fn truncate(text: &str, max_ems: f32) -> String {
+    if em_width(text) <= max_ems {
+        return text.to_string();
+    }
+    let mut truncated = String::new();
+    for c in text.chars() {
+        if em_width(&truncated) + em_width(&c.to_string()) > max_ems - 1.0 {
+            break;
+        }
+        truncated.push(c);
+    }
+    format!("{}…", truncated.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::anthology::book::Section;
+    use chrono::Utc;
+
+    fn book(haikus: usize) -> Book {
+        let haikus = (0..haikus)
+            .map(|index| {
+                serde_json::from_value(serde_json::json!({
+                    "id": uuid::Uuid::new_v4(),
+                    "content": format!("first line {index}\nsecond line\nthird line"),
+                    "is_funny": false,
+                    "prompt_id": uuid::Uuid::new_v4(),
+                    "duplicate_of": null,
+                    "author": null,
+                    "version": 1,
+                    "created_at": Utc::now(),
+                    "updated_at": Utc::now(),
+                    "deleted_at": null,
+                }))
+                .unwrap()
+            })
+            .collect();
+        Book {
+            title: "Moons".to_string(),
+            subtitle: None,
+            attribution: "anonymous".to_string(),
+            languages: vec!["en".to_string()],
+            created_at: Utc::now(),
+            sections: vec![Section {
+                title: "Autumn".to_string(),
+                haikus,
+            }],
+        }
+    }
+
+    fn count(pdf: &[u8], needle: &str) -> usize {
+        pdf.windows(needle.len())
+            .filter(|window| *window == needle.as_bytes())
+            .count()
+    }
+
+    #[test]
+    fn lays_out_every_page_from_one_conversion() {
+        let renderer = CardRenderer::new(None);
+        // Title, contents, section divider and one page per haiku.
+        let pdf = write(&book(3), &renderer).unwrap();
+        assert_eq!(count(&pdf, "/Type /Page\n"), 6);
+        assert_eq!(count(&pdf, "/MediaBox [0 0 420 595]"), 1);
+        assert_eq!(count(&pdf, "/MediaBox [0 2975 420 3570]"), 1);
+
+        let fonts = count(&pdf, "/Type /Font");
+        let more = write(&book(30), &renderer).unwrap();
+        assert_eq!(count(&more, "/Type /Page\n"), 34);
+        assert_eq!(count(&more, "/Type /Font"), fonts);
+    }
+}
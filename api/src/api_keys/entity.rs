@@ -0,0 +1,142 @@
+use async_graphql::{Enum, SimpleObject};
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+/// Marks a bearer token as an API key rather than a JWT.
+pub const KEY_PREFIX: &str = "hk_";
+/// How much of a key is kept in clear, to tell keys apart in listings.
+const SHOWN_LEN: usize = 11;
+
+/// What requests made with a key may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum, sqlx::Type)]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ApiKeyScope {
+    /// Queries only.
+    ReadOnly,
+    /// Queries and mutations, except those reserved to admins.
+    Generate,
+    /// Everything the owner may do, as long as they are an admin.
+    Admin,
+}
+
+#[derive(Debug, Serialize, Deserialize, SimpleObject, FromRow)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub name: String,
+    /// The first characters of the key.
+    pub prefix: String,
+    pub scope: ApiKeyScope,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A newly created key. `key` is only ever shown here.
+#[derive(Debug, SimpleObject)]
+pub struct CreatedApiKey {
+    pub key: String,
+    pub api_key: ApiKey,
+}
+
+fn hash(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+impl ApiKey {
+    /// Keys of `user_id`, revoked ones included.
+    pub async fn list(pool: &PgPool, user_id: Uuid) -> Result<Vec<ApiKey>, sqlx::Error> {
+        let keys = sqlx::query_as::<_, ApiKey>(
+            r#"
+            SELECT id, name, prefix, scope, last_used_at, revoked_at, created_at
+            FROM api_keys
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(keys)
+    }
+
+    /// Creates a key for `user_id`. Only its hash is stored.
+    pub async fn create(
+        pool: &PgPool,
+        user_id: Uuid,
+        name: &str,
+        scope: ApiKeyScope,
+    ) -> Result<CreatedApiKey, sqlx::Error> {
+        let mut bytes = [0u8; 20];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let key = format!("{}{}", KEY_PREFIX, hex::encode(bytes));
+
+        let api_key = sqlx::query_as::<_, ApiKey>(
+            r#"
+            INSERT INTO api_keys (user_id, name, prefix, key_hash, scope)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, name, prefix, scope, last_used_at, revoked_at, created_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(name.trim())
+        .bind(&key[..SHOWN_LEN])
+        .bind(hash(&key))
+        .bind(scope)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(CreatedApiKey { key, api_key })
+    }
+
+    /// Revokes a key of `user_id`. Revoking it again keeps the first date.
+    pub async fn revoke(pool: &PgPool, id: Uuid, user_id: Uuid) -> Result<ApiKey, sqlx::Error> {
+        let api_key = sqlx::query_as::<_, ApiKey>(
+            r#"
+            UPDATE api_keys
+            SET revoked_at = COALESCE(revoked_at, now())
+            WHERE id = $1 AND user_id = $2
+            RETURNING id, name, prefix, scope, last_used_at, revoked_at, created_at
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(api_key)
+    }
+
+    /// The owner and scope of a live `key` belonging to an active user,
+    /// recording that it was used. Also tells whether it stands for a second
+    /// factor: admin keys created while the owner had two-factor
+    /// authentication on took one, and lose it if that is turned off.
+    pub async fn authenticate(
+        pool: &PgPool,
+        key: &str,
+    ) -> Result<Option<(Uuid, ApiKeyScope, bool)>, sqlx::Error> {
+        let owner = sqlx::query_as::<_, (Uuid, ApiKeyScope, bool)>(
+            r#"
+            UPDATE api_keys k
+            SET last_used_at = now()
+            FROM users u
+            WHERE u.id = k.user_id
+              AND k.key_hash = $1
+              AND k.revoked_at IS NULL
+              AND u.deleted_at IS NULL
+            RETURNING k.user_id, k.scope,
+                      k.scope = 'admin' AND COALESCE(k.created_at >= u.totp_enabled_at, false)
+            "#,
+        )
+        .bind(hash(key))
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(owner)
+    }
+}
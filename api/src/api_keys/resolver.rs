@@ -0,0 +1,54 @@
+use super::entity::{ApiKey, ApiKeyScope, CreatedApiKey};
+use crate::app::validation::{Rule, validate_argument};
+use crate::auth::viewer::Viewer;
+use async_graphql::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const NAME: &[Rule] = &[Rule::NotBlank, Rule::MaxLength(255)];
+
+pub struct QueryRoot;
+
+#[async_graphql::Object]
+impl QueryRoot {
+    /// The viewer's keys, revoked ones included.
+    async fn api_keys(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<ApiKey>> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require_session(ctx)?;
+        let keys = ApiKey::list(pool, viewer.user_id).await?;
+        Ok(keys)
+    }
+}
+
+pub struct MutationRoot;
+
+#[async_graphql::Object]
+impl MutationRoot {
+    /// Creates a key for the viewer. The key itself is only returned here;
+    /// send it as `Authorization: Bearer hk_...`. Keys with the `ADMIN`
+    /// scope can only be created by admins.
+    async fn create_api_key(
+        &self,
+        ctx: &Context<'_>,
+        name: String,
+        scope: ApiKeyScope,
+    ) -> async_graphql::Result<CreatedApiKey> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require_session(ctx)?;
+        if scope == ApiKeyScope::Admin {
+            Viewer::require_admin(ctx).await?;
+        }
+        let name = name.trim().to_string();
+        validate_argument("name", &name, NAME)?;
+        let created = ApiKey::create(pool, viewer.user_id, &name, scope).await?;
+        Ok(created)
+    }
+
+    /// Revokes one of the viewer's keys for good.
+    async fn revoke_api_key(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<ApiKey> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require_session(ctx)?;
+        let api_key = ApiKey::revoke(pool, id, viewer.user_id).await?;
+        Ok(api_key)
+    }
+}
@@ -0,0 +1,233 @@
+use crate::app::config::Config;
+use crate::haiku::corpus::{self, CorpusOptions};
+use crate::transfer::export::export;
+use crate::transfer::import::{ImportOptions, import, parse};
+use crate::transfer::record::{TransferError, TransferFormat};
+use sqlx::PgPool;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, BufWriter};
+use uuid::Uuid;
+
+const USAGE: &str = "\
+Usage:
+    api [serve]
+    api export [--format ndjson|json|csv] [--output FILE]
+    api import [--format ndjson|json|csv] [--upsert] [--dry-run] [--remap-ids] [FILE]
+    api import-haikus [--prompt ID] [--tolerance N] [--dry-run] [FILE...]
+    api --print-config
+
+Without a file, `export` writes to stdout and the imports read from stdin.
+The format defaults to the file extension, or ndjson.
+
+`--print-config` shows the settings from haiku.toml, haiku.$HAIKU_ENV.toml
+and the environment, with secrets redacted, and checks them.";
+
+#[derive(Debug, Error)]
+pub enum CliError {
+    #[error("{0}\n\n{USAGE}")]
+    Usage(String),
+    #[error(transparent)]
+    Transfer(#[from] TransferError),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("Failed to read `{0}`: {1}")]
+    Read(String, std::io::Error),
+}
+
+pub enum Command {
+    Serve,
+    PrintConfig,
+    Export {
+        format: TransferFormat,
+        output: Option<PathBuf>,
+    },
+    Import {
+        format: TransferFormat,
+        input: Option<PathBuf>,
+        options: ImportOptions,
+    },
+    ImportHaikus {
+        inputs: Vec<PathBuf>,
+        prompt_id: Option<Uuid>,
+        tolerance: i32,
+        dry_run: bool,
+    },
+}
+
+impl Command {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, CliError> {
+        let mut args = args.into_iter();
+        let command = args.next();
+        if command.as_deref() == Some("import-haikus") {
+            return Command::parse_import_haikus(args);
+        }
+        let mut format = None;
+        let mut path = None;
+        let mut options = ImportOptions::default();
+        let importing = command.as_deref() == Some("import");
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--format" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| CliError::Usage("`--format` needs a value".to_string()))?;
+                    format = Some(value.parse().map_err(CliError::Usage)?);
+                }
+                "--output" if !importing => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| CliError::Usage("`--output` needs a value".to_string()))?;
+                    path = Some(PathBuf::from(value));
+                }
+                "--upsert" if importing => options.upsert = true,
+                "--dry-run" if importing => options.dry_run = true,
+                "--remap-ids" if importing => options.remap_ids = true,
+                "-" if importing => path = None,
+                _ if importing && !arg.starts_with('-') => {
+                    path = Some(PathBuf::from(arg));
+                }
+                _ => return Err(CliError::Usage(format!("Unexpected argument `{}`", arg))),
+            }
+        }
+
+        let format = format
+            .or_else(|| path.as_deref().and_then(format_of))
+            .unwrap_or(TransferFormat::Ndjson);
+        match command.as_deref() {
+            None | Some("serve") => Ok(Command::Serve),
+            Some("--print-config") => Ok(Command::PrintConfig),
+            Some("export") => Ok(Command::Export {
+                format,
+                output: path,
+            }),
+            Some("import") => Ok(Command::Import {
+                format,
+                input: path,
+                options,
+            }),
+            Some("help" | "--help" | "-h") => Err(CliError::Usage(String::new())),
+            Some(command) => Err(CliError::Usage(format!("Unknown command `{}`", command))),
+        }
+    }
+
+    fn parse_import_haikus(mut args: impl Iterator<Item = String>) -> Result<Command, CliError> {
+        let mut inputs = Vec::new();
+        let mut prompt_id = None;
+        let mut tolerance = 0;
+        let mut dry_run = false;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--prompt" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| CliError::Usage("`--prompt` needs a value".to_string()))?;
+                    prompt_id =
+                        Some(value.parse().map_err(|_| {
+                            CliError::Usage(format!("`{}` is not a prompt id", value))
+                        })?);
+                }
+                "--tolerance" => {
+                    let value = args.next().ok_or_else(|| {
+                        CliError::Usage("`--tolerance` needs a value".to_string())
+                    })?;
+                    tolerance = value.parse().map_err(|_| {
+                        CliError::Usage(format!("`{}` is not a syllable count", value))
+                    })?;
+                }
+                "--dry-run" => dry_run = true,
+                _ if !arg.starts_with('-') => inputs.push(PathBuf::from(arg)),
+                _ => return Err(CliError::Usage(format!("Unexpected argument `{}`", arg))),
+            }
+        }
+
+        Ok(Command::ImportHaikus {
+            inputs,
+            prompt_id,
+            tolerance,
+            dry_run,
+        })
+    }
+
+    /// Runs `export` or an import. Serving and printing the configuration
+    /// are left to the caller.
+    pub async fn run(self, pool: &PgPool, config: &Config) -> Result<(), CliError> {
+        match self {
+            Command::Serve | Command::PrintConfig => Ok(()),
+            Command::Export { format, output } => {
+                let summary = match output {
+                    Some(path) => {
+                        let file = tokio::fs::File::create(path)
+                            .await
+                            .map_err(TransferError::from)?;
+                        export(pool, format, &mut BufWriter::new(file)).await?
+                    }
+                    None => export(pool, format, &mut BufWriter::new(tokio::io::stdout())).await?,
+                };
+                eprintln!(
+                    "Exported {} users, {} prompts, {} haikus and {} user_haikus links",
+                    summary.users, summary.prompts, summary.haikus, summary.user_haikus
+                );
+                Ok(())
+            }
+            Command::Import {
+                format,
+                input,
+                options,
+            } => {
+                let data = match input {
+                    Some(path) => tokio::fs::read(path).await.map_err(TransferError::from)?,
+                    None => {
+                        let mut data = Vec::new();
+                        tokio::io::stdin()
+                            .read_to_end(&mut data)
+                            .await
+                            .map_err(TransferError::from)?;
+                        data
+                    }
+                };
+                let records = parse(format, &data)?;
+                let report = import(pool, records, options).await?;
+                println!("{}", report);
+                Ok(())
+            }
+            Command::ImportHaikus {
+                inputs,
+                prompt_id,
+                tolerance,
+                dry_run,
+            } => {
+                let mut text = String::new();
+                if inputs.is_empty() {
+                    tokio::io::stdin()
+                        .read_to_string(&mut text)
+                        .await
+                        .map_err(|err| CliError::Read("stdin".to_string(), err))?;
+                }
+                for path in inputs {
+                    let file = tokio::fs::read_to_string(&path)
+                        .await
+                        .map_err(|err| CliError::Read(path.display().to_string(), err))?;
+                    // Keep files apart so a stanza never runs across two of them.
+                    text.push_str(&file);
+                    text.push_str("\n\n");
+                }
+                let options = CorpusOptions {
+                    prompt_id,
+                    dry_run,
+                    tolerance,
+                    duplicate_threshold: config.haikus.duplicates.threshold,
+                };
+                let report = corpus::import(pool, &text, options).await?;
+                println!("{}", report);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn format_of(path: &Path) -> Option<TransferFormat> {
+    path.extension()?.to_str()?.parse().ok()
+}
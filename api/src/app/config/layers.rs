@@ -0,0 +1,711 @@
+//! Where settings come from. Each one has a key in `haiku.toml`, such as
+//! `db.host`, and an environment variable, such as `DB_HOST`. Later layers
+//! override earlier ones:
+//!
+//! 1. built-in defaults,
+//! 2. `haiku.toml`, or the file `HAIKU_CONFIG` names,
+//! 3. `haiku.<HAIKU_ENV>.toml` next to it, when `HAIKU_ENV` is set,
+//! 4. environment variables, including those of a `.env` file.
+
+use super::ConfigIssue;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item, Table, Value};
+
+const DEFAULT_FILE: &str = "haiku.toml";
+
+/// How a value is written in a dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Text,
+    Number,
+    Flag,
+    /// Comma-separated in the environment, an array in files.
+    List,
+}
+
+pub struct Setting {
+    pub key: &'static str,
+    pub env: &'static str,
+    pub default: Option<&'static str>,
+    /// Without a default, whether it must be set.
+    pub required: bool,
+    pub kind: Kind,
+    /// Redacted from dumps.
+    pub secret: bool,
+}
+
+impl Setting {
+    const fn required(key: &'static str, env: &'static str, kind: Kind) -> Self {
+        Self {
+            key,
+            env,
+            default: None,
+            required: true,
+            kind,
+            secret: false,
+        }
+    }
+
+    const fn optional(key: &'static str, env: &'static str, kind: Kind) -> Self {
+        Self {
+            required: false,
+            ..Self::required(key, env, kind)
+        }
+    }
+
+    const fn default(
+        key: &'static str,
+        env: &'static str,
+        value: &'static str,
+        kind: Kind,
+    ) -> Self {
+        Self {
+            default: Some(value),
+            ..Self::optional(key, env, kind)
+        }
+    }
+
+    const fn secret(self) -> Self {
+        Self {
+            secret: true,
+            ..self
+        }
+    }
+
+    /// The setting with `key`, which must exist.
+    pub fn get(key: &str) -> &'static Setting {
+        SETTINGS
+            .iter()
+            .find(|setting| setting.key == key)
+            .unwrap_or_else(|| panic!("unknown setting `{}`", key))
+    }
+}
+
+/// Every setting, in dump order. Those without a default here but derived
+/// from others are filled in by [`Layers::derive`].
+pub const SETTINGS: &[Setting] = &[
+    Setting::required("db.host", "DB_HOST", Kind::Text),
+    Setting::required("db.port", "DB_PORT", Kind::Number),
+    Setting::required("db.name", "DB_NAME", Kind::Text),
+    Setting::required("db.user", "DB_USER", Kind::Text),
+    Setting::required("db.password", "DB_PASSWORD", Kind::Text).secret(),
+    Setting::default(
+        "db.max_connections",
+        "DB_MAX_CONNECTIONS",
+        "10",
+        Kind::Number,
+    ),
+    Setting::default(
+        "db.acquire_timeout_secs",
+        "DB_ACQUIRE_TIMEOUT_SECS",
+        "10",
+        Kind::Number,
+    ),
+    Setting::required("server.host", "SRV_HOST", Kind::Text),
+    Setting::required("server.port", "SRV_PORT", Kind::Number),
+    Setting::optional("server.public_url", "PUBLIC_URL", Kind::Text),
+    Setting::optional("server.app_url", "APP_URL", Kind::Text),
+    Setting::default("server.trusted_proxies", "TRUSTED_PROXIES", "", Kind::List),
+    Setting::default(
+        "cors.allowed_origins",
+        "CORS_ALLOWED_ORIGINS",
+        "",
+        Kind::List,
+    ),
+    Setting::required("auth.jwt_secret", "JWT_SECRET", Kind::Text).secret(),
+    Setting::default("auth.jwt_ttl_secs", "JWT_TTL_SECS", "86400", Kind::Number),
+    Setting::required(
+        "auth.totp_encryption_key",
+        "TOTP_ENCRYPTION_KEY",
+        Kind::Text,
+    )
+    .secret(),
+    Setting::default("auth.totp_issuer", "TOTP_ISSUER", "Haiku", Kind::Text),
+    Setting::default(
+        "auth.two_factor_roles",
+        "TWO_FACTOR_ROLES",
+        "admin",
+        Kind::List,
+    ),
+    Setting::default(
+        "auth.email_verification_ttl_mins",
+        "EMAIL_VERIFICATION_TTL_MINS",
+        "1440",
+        Kind::Number,
+    ),
+    Setting::default(
+        "auth.password_reset_ttl_mins",
+        "PASSWORD_RESET_TTL_MINS",
+        "60",
+        Kind::Number,
+    ),
+    Setting::default(
+        "auth.login.max_failures",
+        "LOGIN_MAX_FAILURES",
+        "5",
+        Kind::Number,
+    ),
+    Setting::default(
+        "auth.login.max_failures_per_ip",
+        "LOGIN_MAX_FAILURES_PER_IP",
+        "20",
+        Kind::Number,
+    ),
+    Setting::default(
+        "auth.login.failure_window_mins",
+        "LOGIN_FAILURE_WINDOW_MINS",
+        "15",
+        Kind::Number,
+    ),
+    Setting::default(
+        "auth.login.lockout_mins",
+        "LOGIN_LOCKOUT_MINS",
+        "15",
+        Kind::Number,
+    ),
+    Setting::default(
+        "auth.login.delay_base_ms",
+        "LOGIN_DELAY_BASE_MS",
+        "1000",
+        Kind::Number,
+    ),
+    Setting::default(
+        "auth.login.delay_max_ms",
+        "LOGIN_DELAY_MAX_MS",
+        "30000",
+        Kind::Number,
+    ),
+    Setting::optional(
+        "providers.generation.api_url",
+        "DEEPSEEK_API_URL",
+        Kind::Text,
+    ),
+    Setting::optional(
+        "providers.generation.api_key",
+        "DEEPSEEK_API_KEY",
+        Kind::Text,
+    )
+    .secret(),
+    // No default, so that a deployment never logs emails by accident.
+    Setting::required("providers.mail.transport", "MAIL_TRANSPORT", Kind::Text),
+    Setting::default(
+        "providers.mail.from",
+        "MAIL_FROM",
+        "Haiku <no-reply@localhost>",
+        Kind::Text,
+    ),
+    Setting::default("providers.mail.dir", "MAIL_DIR", "mail", Kind::Text),
+    Setting::optional("providers.mail.smtp_host", "SMTP_HOST", Kind::Text),
+    Setting::default("providers.mail.smtp_port", "SMTP_PORT", "587", Kind::Number),
+    Setting::default(
+        "providers.mail.smtp_tls",
+        "SMTP_TLS",
+        "starttls",
+        Kind::Text,
+    ),
+    Setting::optional("providers.mail.smtp_username", "SMTP_USERNAME", Kind::Text),
+    Setting::optional("providers.mail.smtp_password", "SMTP_PASSWORD", Kind::Text).secret(),
+    Setting::optional("providers.oidc.issuer", "OIDC_ISSUER", Kind::Text),
+    Setting::optional("providers.oidc.client_id", "OIDC_CLIENT_ID", Kind::Text),
+    Setting::optional(
+        "providers.oidc.client_secret",
+        "OIDC_CLIENT_SECRET",
+        Kind::Text,
+    )
+    .secret(),
+    Setting::optional(
+        "providers.oidc.redirect_url",
+        "OIDC_REDIRECT_URL",
+        Kind::Text,
+    ),
+    Setting::default(
+        "providers.oidc.scopes",
+        "OIDC_SCOPES",
+        "openid email profile",
+        Kind::Text,
+    ),
+    Setting::optional("providers.oidc.role_claim", "OIDC_ROLE_CLAIM", Kind::Text),
+    Setting::default("providers.oidc.role_map", "OIDC_ROLE_MAP", "", Kind::List),
+    Setting::default("jobs.workers", "JOBS_WORKERS", "4", Kind::Number),
+    Setting::default(
+        "jobs.poll_interval_ms",
+        "JOBS_POLL_INTERVAL_MS",
+        "1000",
+        Kind::Number,
+    ),
+    Setting::default(
+        "jobs.batch_concurrency",
+        "BATCH_CONCURRENCY",
+        "2",
+        Kind::Number,
+    ),
+    Setting::default(
+        "haikus.duplicate_threshold",
+        "DUPLICATE_THRESHOLD",
+        "0.8",
+        Kind::Number,
+    ),
+    Setting::default(
+        "haikus.duplicate_action",
+        "DUPLICATE_ACTION",
+        "flag",
+        Kind::Text,
+    ),
+    Setting::default("haikus.auto_tag", "AUTO_TAG", "false", Kind::Flag),
+    Setting::default("search.language", "SEARCH_LANGUAGE", "english", Kind::Text),
+    Setting::default(
+        "cards.attribution",
+        "CARD_ATTRIBUTION",
+        "anonymous",
+        Kind::Text,
+    ),
+    Setting::optional("cards.fonts_dir", "CARD_FONTS_DIR", Kind::Text),
+    Setting::default(
+        "trash.retention_days",
+        "TRASH_RETENTION_DAYS",
+        "30",
+        Kind::Number,
+    ),
+    Setting::optional(
+        "trash.retention_users_days",
+        "TRASH_RETENTION_USERS_DAYS",
+        Kind::Number,
+    ),
+    Setting::optional(
+        "trash.retention_prompts_days",
+        "TRASH_RETENTION_PROMPTS_DAYS",
+        Kind::Number,
+    ),
+    Setting::optional(
+        "trash.retention_haikus_days",
+        "TRASH_RETENTION_HAIKUS_DAYS",
+        Kind::Number,
+    ),
+    Setting::default(
+        "trash.purge_interval_secs",
+        "TRASH_PURGE_INTERVAL_SECS",
+        "3600",
+        Kind::Number,
+    ),
+    Setting::default(
+        "trash.purge_batch_size",
+        "TRASH_PURGE_BATCH_SIZE",
+        "500",
+        Kind::Number,
+    ),
+];
+
+/// Which layer a value comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    File { path: PathBuf, key: &'static str },
+    Env(&'static str),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File { path, .. } => write!(f, "{}", path.display()),
+            Origin::Env(name) => write!(f, "${}", name),
+        }
+    }
+}
+
+/// The raw value of every setting that has one, and what went wrong
+/// reading the files.
+pub struct Layers {
+    values: HashMap<&'static str, (String, Origin)>,
+    pub issues: Vec<ConfigIssue>,
+}
+
+impl Layers {
+    pub fn load() -> Self {
+        // Variables already set win over those of the file.
+        let dotenv = dotenvy::dotenv();
+        let mut layers = Self::read(|name| std::env::var(name).ok());
+        match dotenv {
+            Err(err) if !err.not_found() => layers.issues.insert(
+                0,
+                ConfigIssue::Unreadable {
+                    path: ".env".to_string(),
+                    message: err.to_string(),
+                },
+            ),
+            _ => {}
+        }
+        layers
+    }
+
+    /// The layers, with `var` looking up environment variables.
+    pub(super) fn read(var: impl Fn(&str) -> Option<String>) -> Self {
+        let mut layers = Self {
+            values: HashMap::new(),
+            issues: Vec::new(),
+        };
+        for setting in SETTINGS {
+            if let Some(value) = setting.default {
+                layers.set(setting.key, value.to_string(), Origin::Default);
+            }
+        }
+
+        let file = var("HAIKU_CONFIG").filter(|path| !path.is_empty());
+        let path = PathBuf::from(file.as_deref().unwrap_or(DEFAULT_FILE));
+        layers.read_file(&path, file.is_some());
+        if let Some(name) = var("HAIKU_ENV").filter(|name| !name.is_empty()) {
+            layers.read_file(&path.with_file_name(format!("haiku.{}.toml", name)), true);
+        }
+
+        for setting in SETTINGS {
+            if let Some(value) = var(setting.env) {
+                layers.set(setting.key, value, Origin::Env(setting.env));
+            }
+        }
+        layers.derive();
+        layers
+    }
+
+    fn set(&mut self, key: &'static str, value: String, origin: Origin) {
+        self.values.insert(key, (value, origin));
+    }
+
+    /// The value of `key`, unless unset or blank.
+    pub fn get(&self, key: &str) -> Option<(&str, &Origin)> {
+        self.values
+            .get(key)
+            .filter(|(value, _)| !value.trim().is_empty())
+            .map(|(value, origin)| (value.as_str(), origin))
+    }
+
+    /// Defaults depending on other settings, such as URLs built from the
+    /// server address.
+    fn derive(&mut self) {
+        if let (Some((host, _)), Some((port, _))) =
+            (self.get("server.host"), self.get("server.port"))
+        {
+            let url = format!("http://{}:{}", host, port);
+            self.derive_from("server.public_url", Some((url, Origin::Default)));
+        }
+        self.derive_from("server.app_url", self.cloned("server.public_url"));
+        let redirect_url = self.cloned("server.app_url").map(|(url, _)| {
+            let url = format!("{}/oidc/callback", url.trim_end_matches('/'));
+            (url, Origin::Default)
+        });
+        self.derive_from("providers.oidc.redirect_url", redirect_url);
+        for key in [
+            "trash.retention_users_days",
+            "trash.retention_prompts_days",
+            "trash.retention_haikus_days",
+        ] {
+            self.derive_from(key, self.cloned("trash.retention_days"));
+        }
+    }
+
+    /// Sets `key` to `value`, keeping its origin, unless already set.
+    fn derive_from(&mut self, key: &'static str, value: Option<(String, Origin)>) {
+        if self.get(key).is_none()
+            && let Some((value, origin)) = value
+        {
+            self.set(key, value, origin);
+        }
+    }
+
+    fn cloned(&self, key: &str) -> Option<(String, Origin)> {
+        self.get(key)
+            .map(|(value, origin)| (value.to_string(), origin.clone()))
+    }
+
+    /// Merges the settings of a TOML file. A missing file is only an issue
+    /// when asked for by name.
+    fn read_file(&mut self, path: &Path, required: bool) {
+        let unreadable = |message: String| ConfigIssue::Unreadable {
+            path: path.display().to_string(),
+            message,
+        };
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound && !required => return,
+            Err(err) => return self.issues.push(unreadable(err.to_string())),
+        };
+        let document = match text.parse::<DocumentMut>() {
+            Ok(document) => document,
+            Err(err) => return self.issues.push(unreadable(err.to_string())),
+        };
+        let mut values = Vec::new();
+        flatten("", document.as_table(), &mut values);
+
+        for (key, value) in values {
+            match SETTINGS.iter().find(|setting| setting.key == key) {
+                Some(setting) => {
+                    let origin = Origin::File {
+                        path: path.to_path_buf(),
+                        key: setting.key,
+                    };
+                    match value {
+                        Some(value) => self.set(setting.key, value, origin),
+                        None => self.issues.push(ConfigIssue::InvalidValue {
+                            key: setting.key,
+                            origin,
+                            message: "expected a value, not a table".to_string(),
+                        }),
+                    }
+                }
+                None => self.issues.push(ConfigIssue::UnknownKey {
+                    key,
+                    path: path.display().to_string(),
+                }),
+            }
+        }
+    }
+
+    /// The settings as TOML, each commented with where it comes from, and
+    /// secrets redacted.
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        let mut section = "";
+        for setting in SETTINGS {
+            let (table, name) = setting.key.rsplit_once('.').unwrap_or(("", setting.key));
+            if table != section {
+                if !out.is_empty() {
+                    out.push('\n');
+                }
+                let _ = writeln!(out, "[{}]", table);
+                section = table;
+            }
+            // An empty list is a value of its own.
+            let value = match setting.kind {
+                Kind::List => self
+                    .values
+                    .get(setting.key)
+                    .map(|(value, origin)| (value.as_str(), origin)),
+                _ => self.get(setting.key),
+            };
+            let _ = match value {
+                Some((_, origin)) if setting.secret => {
+                    writeln!(out, "{} = \"[redacted]\"  # {}", name, origin)
+                }
+                Some((value, origin)) => {
+                    writeln!(
+                        out,
+                        "{} = {}  # {}",
+                        name,
+                        literal(setting.kind, value),
+                        origin
+                    )
+                }
+                None => writeln!(out, "# {} is not set (${})", name, setting.env),
+            };
+        }
+        out
+    }
+}
+
+/// The leaves of `table` as dotted keys, arrays joined with commas. Tables
+/// in arrays have no value.
+fn flatten(prefix: &str, table: &Table, values: &mut Vec<(String, Option<String>)>) {
+    for (name, item) in table.iter() {
+        let key = if prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+        match item {
+            Item::Table(table) => flatten(&key, table, values),
+            Item::Value(Value::InlineTable(table)) => {
+                flatten(&key, &table.clone().into_table(), values)
+            }
+            Item::Value(value) => values.push((key, text(value))),
+            Item::ArrayOfTables(_) => values.push((key, None)),
+            Item::None => {}
+        }
+    }
+}
+
+fn text(value: &Value) -> Option<String> {
+    Some(match value {
+        Value::String(value) => value.value().clone(),
+        Value::Integer(value) => value.value().to_string(),
+        Value::Float(value) => value.value().to_string(),
+        Value::Boolean(value) => value.value().to_string(),
+        Value::Datetime(value) => value.value().to_string(),
+        Value::Array(values) => values
+            .iter()
+            .map(text)
+            .collect::<Option<Vec<_>>>()?
+            .join(","),
+        Value::InlineTable(_) => return None,
+    })
+}
+
+/// `value` as a TOML literal, numbers and flags quoted unless valid.
+fn literal(kind: Kind, value: &str) -> String {
+    let quote = |value: &str| format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""));
+    let value = value.trim();
+    match kind {
+        Kind::Number if value.parse::<f64>().is_ok() => value.to_string(),
+        Kind::Flag if value.parse::<bool>().is_ok() => value.to_string(),
+        Kind::List => {
+            let items: Vec<String> = value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(quote)
+                .collect();
+            format!("[{}]", items.join(", "))
+        }
+        _ => quote(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory of its own with `files` in it.
+    fn dir(files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("haiku-config-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        for (name, text) in files {
+            std::fs::write(dir.join(name), text).unwrap();
+        }
+        dir
+    }
+
+    fn layers(dir: &Path, env: &[(&str, &str)]) -> Layers {
+        let config = dir.join("haiku.toml").display().to_string();
+        Layers::read(|name| match name {
+            "HAIKU_CONFIG" => Some(config.clone()),
+            _ => env
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string()),
+        })
+    }
+
+    fn value(layers: &Layers, key: &str) -> Option<(String, Origin)> {
+        layers.cloned(key)
+    }
+
+    #[test]
+    fn later_layers_win() {
+        let dir = dir(&[
+            (
+                "haiku.toml",
+                "[db]\nhost = \"file\"\nport = 5432\nname = \"haiku\"\n[jobs]\nworkers = 2\n",
+            ),
+            (
+                "haiku.prod.toml",
+                "[db]\nhost = \"prod\"\nname = \"prod\"\n",
+            ),
+        ]);
+        let layers = layers(&dir, &[("HAIKU_ENV", "prod"), ("DB_HOST", "env")]);
+        let base = dir.join("haiku.toml");
+        let prod = dir.join("haiku.prod.toml");
+
+        assert!(layers.issues.is_empty(), "{:?}", layers.issues);
+        assert_eq!(
+            value(&layers, "db.host"),
+            Some(("env".to_string(), Origin::Env("DB_HOST")))
+        );
+        assert_eq!(
+            value(&layers, "db.name"),
+            Some((
+                "prod".to_string(),
+                Origin::File {
+                    path: prod,
+                    key: "db.name"
+                }
+            ))
+        );
+        assert_eq!(
+            value(&layers, "db.port"),
+            Some((
+                "5432".to_string(),
+                Origin::File {
+                    path: base.clone(),
+                    key: "db.port"
+                }
+            ))
+        );
+        assert_eq!(
+            value(&layers, "jobs.workers"),
+            Some((
+                "2".to_string(),
+                Origin::File {
+                    path: base,
+                    key: "jobs.workers"
+                }
+            ))
+        );
+        assert_eq!(
+            value(&layers, "jobs.poll_interval_ms"),
+            Some(("1000".to_string(), Origin::Default))
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn derived_settings_follow_their_source() {
+        let dir = dir(&[(
+            "haiku.toml",
+            "[server]\nhost = \"example.org\"\nport = 80\n[trash]\nretention_days = 7\n",
+        )]);
+        let layers = layers(&dir, &[("TRASH_RETENTION_USERS_DAYS", "90")]);
+
+        assert_eq!(
+            value(&layers, "server.app_url").map(|(url, _)| url),
+            Some("http://example.org:80".to_string())
+        );
+        assert_eq!(
+            value(&layers, "providers.oidc.redirect_url").map(|(url, _)| url),
+            Some("http://example.org:80/oidc/callback".to_string())
+        );
+        assert_eq!(
+            value(&layers, "trash.retention_users_days").unwrap().0,
+            "90"
+        );
+        assert_eq!(
+            value(&layers, "trash.retention_haikus_days").unwrap().0,
+            "7"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reports_unknown_keys_and_missing_files() {
+        let dir = dir(&[("haiku.toml", "[db]\nhots = \"typo\"\n")]);
+        let layers = layers(&dir, &[("HAIKU_ENV", "staging")]);
+        let issues: Vec<String> = layers.issues.iter().map(ToString::to_string).collect();
+
+        assert_eq!(issues.len(), 2, "{issues:?}");
+        assert!(issues[0].starts_with("Unknown setting `db.hots`"));
+        assert!(issues[1].contains("haiku.staging.toml"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dump_redacts_secrets() {
+        let dir = dir(&[("haiku.toml", "[auth]\njwt_secret = \"hunter2\"\n")]);
+        let layers = layers(
+            &dir,
+            &[
+                ("DB_PASSWORD", "s3cret"),
+                ("CORS_ALLOWED_ORIGINS", "https://a.io, https://b.io"),
+            ],
+        );
+        let dump = layers.dump();
+
+        assert!(
+            !dump.contains("hunter2") && !dump.contains("s3cret"),
+            "{dump}"
+        );
+        assert!(dump.contains("jwt_secret = \"[redacted]\"  # "));
+        assert!(dump.contains("password = \"[redacted]\"  # $DB_PASSWORD"));
+        assert!(dump.contains("allowed_origins = [\"https://a.io\", \"https://b.io\"]"));
+        assert!(dump.contains("# smtp_password is not set ($SMTP_PASSWORD)"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,732 @@
+pub mod layers;
+
+use self::layers::{Layers, Origin, Setting};
+use crate::auth::cipher::SecretCipher;
+use crate::auth::oidc::OidcSettings;
+use crate::auth::throttle::LoginPolicy;
+use crate::auth::two_factor::{TwoFactorPolicy, TwoFactorSettings};
+use crate::haiku::entity::ModelProvider;
+use crate::haiku::similarity::DuplicatePolicy;
+use crate::mail::mailer::{
+    FileMailer, LogMailer, MailError, MailTransport, Mailer, SmtpMailer, SmtpTls,
+};
+use crate::trash::entity::RetentionPolicy;
+use crate::users::account::AccountSettings;
+use crate::users::entity::UserRole;
+use axum::http::HeaderValue;
+use lettre::message::Mailbox;
+use std::fmt::Display;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Error)]
+pub enum ConfigIssue {
+    #[error("Cannot read `{path}`: {message}")]
+    Unreadable { path: String, message: String },
+    #[error("Unknown setting `{key}` in `{path}`")]
+    UnknownKey { key: String, path: String },
+    #[error("Setting `{key}` is missing, set it in haiku.toml or with ${env}")]
+    Missing {
+        key: &'static str,
+        env: &'static str,
+    },
+    #[error("Setting `{key}` ({origin}) is invalid: {message}")]
+    InvalidValue {
+        key: &'static str,
+        origin: Origin,
+        message: String,
+    },
+}
+
+/// Everything wrong with the configuration, so that it can be fixed in one
+/// go.
+#[derive(Debug, Error)]
+#[error("Invalid configuration:{}", list(.issues))]
+pub struct ConfigError {
+    pub issues: Vec<ConfigIssue>,
+}
+
+fn list(issues: &[ConfigIssue]) -> String {
+    issues
+        .iter()
+        .map(|issue| format!("\n  - {}", issue))
+        .collect()
+}
+
+/// Reads settings out of the layers, collecting issues instead of stopping
+/// at the first one.
+struct Reader<'a> {
+    layers: &'a Layers,
+    issues: Vec<ConfigIssue>,
+}
+
+impl Reader<'_> {
+    /// The parsed value of `key`, `None` if unset or invalid.
+    fn value<T>(&mut self, key: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let Some((value, _)) = self.layers.get(key) else {
+            if Setting::get(key).required {
+                self.missing(key);
+            }
+            return None;
+        };
+        match value.trim().parse() {
+            Ok(value) => Some(value),
+            Err(err) => {
+                self.invalid(key, err);
+                None
+            }
+        }
+    }
+
+    /// The comma-separated values of `key`, empty if unset.
+    fn list<T>(&mut self, key: &str) -> Option<Vec<T>>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let Some((value, _)) = self.layers.get(key) else {
+            return Some(Vec::new());
+        };
+        let values = value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<T>, _>>();
+        match values {
+            Ok(values) => Some(values),
+            Err(err) => {
+                self.invalid(key, err);
+                None
+            }
+        }
+    }
+
+    fn missing(&mut self, key: &str) {
+        let setting = Setting::get(key);
+        self.issues.push(ConfigIssue::Missing {
+            key: setting.key,
+            env: setting.env,
+        });
+    }
+
+    /// Records that the value of `key` is wrong. Values derived from the
+    /// same source are only reported once.
+    fn invalid(&mut self, key: &str, message: impl Display) {
+        let setting = Setting::get(key);
+        let origin = self
+            .layers
+            .get(key)
+            .map(|(_, origin)| origin.clone())
+            .unwrap_or(Origin::Default);
+        let reported = self.issues.iter().any(|issue| {
+            matches!(issue, ConfigIssue::InvalidValue { origin: other, .. }
+                if *other == origin && origin != Origin::Default)
+        });
+        if !reported {
+            self.issues.push(ConfigIssue::InvalidValue {
+                key: setting.key,
+                origin,
+                message: message.to_string(),
+            });
+        }
+    }
+
+    /// Like [`Reader::value`], checking the value with `valid`.
+    fn checked<T>(&mut self, key: &str, message: &str, valid: impl Fn(&T) -> bool) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let value = self.value(key)?;
+        if !valid(&value) {
+            self.invalid(key, message);
+            return None;
+        }
+        Some(value)
+    }
+}
+
+pub struct DbConfig {
+    pub host: String,
+    pub port: u16,
+    pub name: String,
+    pub user: String,
+    pub password: String,
+    pub max_connections: u32,
+    pub acquire_timeout: Duration,
+}
+
+impl DbConfig {
+    fn read(reader: &mut Reader) -> Option<Self> {
+        let host = reader.value("db.host");
+        let port = reader.value("db.port");
+        let name = reader.value("db.name");
+        let user = reader.value("db.user");
+        let password = reader.value("db.password");
+        let max_connections =
+            reader.checked("db.max_connections", "must be at least 1", |n| *n > 0);
+        let acquire_timeout_secs = reader.value("db.acquire_timeout_secs");
+
+        Some(Self {
+            host: host?,
+            port: port?,
+            name: name?,
+            user: user?,
+            password: password?,
+            max_connections: max_connections?,
+            acquire_timeout: Duration::from_secs(acquire_timeout_secs?),
+        })
+    }
+
+    pub fn url(&self) -> String {
+        format!(
+            "postgres://{}:{}@{}:{}/{}",
+            self.user, self.password, self.host, self.port, self.name
+        )
+    }
+}
+
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    /// Base URL used in share links and OpenGraph tags.
+    pub public_url: String,
+    /// Where verification, reset and sign-in links point to.
+    pub app_url: String,
+    /// Reverse proxies whose `x-forwarded-for` tells the client's address.
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl ServerConfig {
+    fn read(reader: &mut Reader) -> Option<Self> {
+        let host = reader.value("server.host");
+        let port = reader.value("server.port");
+        let public_url = reader.value("server.public_url");
+        let app_url = reader.value("server.app_url");
+        let trusted_proxies = reader.list("server.trusted_proxies");
+
+        Some(Self {
+            host: host?,
+            port: port?,
+            public_url: public_url?,
+            app_url: app_url?,
+            trusted_proxies: trusted_proxies?,
+        })
+    }
+}
+
+pub struct CorsConfig {
+    /// Origins browsers may call the API from, none but the API's own if
+    /// empty.
+    pub allowed_origins: Vec<HeaderValue>,
+}
+
+impl CorsConfig {
+    fn read(reader: &mut Reader) -> Option<Self> {
+        let allowed_origins: Vec<HeaderValue> = reader.list("cors.allowed_origins")?;
+        if allowed_origins.iter().any(|origin| origin == "*") {
+            reader.invalid(
+                "cors.allowed_origins",
+                "`*` cannot be used, as requests carry credentials",
+            );
+            return None;
+        }
+        Some(Self { allowed_origins })
+    }
+}
+
+pub struct AuthConfig {
+    pub jwt_secret: String,
+    pub jwt_ttl_secs: i64,
+    pub totp_cipher: SecretCipher,
+    pub totp_issuer: String,
+    pub two_factor_roles: Vec<UserRole>,
+    pub email_verification_ttl: chrono::Duration,
+    pub password_reset_ttl: chrono::Duration,
+    pub login: LoginPolicy,
+}
+
+impl AuthConfig {
+    fn read(reader: &mut Reader) -> Option<Self> {
+        let jwt_secret = reader.value("auth.jwt_secret");
+        let jwt_ttl_secs = reader.value("auth.jwt_ttl_secs");
+        let totp_key: Option<String> = reader.value("auth.totp_encryption_key");
+        let totp_cipher = totp_key.and_then(|key| {
+            let cipher = SecretCipher::from_hex(&key);
+            if cipher.is_none() {
+                reader.invalid("auth.totp_encryption_key", "expected 64 hex digits");
+            }
+            cipher
+        });
+        let totp_issuer = reader.value("auth.totp_issuer");
+        let two_factor_roles: Option<Vec<UserRole>> = reader.list("auth.two_factor_roles");
+        if two_factor_roles
+            .as_ref()
+            .is_some_and(|roles| roles.iter().any(|role| *role != UserRole::Admin))
+        {
+            reader.invalid(
+                "auth.two_factor_roles",
+                "only admin rights can require a second factor",
+            );
+        }
+        let email_verification_ttl_mins = reader.value("auth.email_verification_ttl_mins");
+        let password_reset_ttl_mins = reader.value("auth.password_reset_ttl_mins");
+        let max_account_failures = reader.value("auth.login.max_failures");
+        let max_ip_failures = reader.value("auth.login.max_failures_per_ip");
+        let failure_window_mins = reader.value("auth.login.failure_window_mins");
+        let lockout_mins = reader.value("auth.login.lockout_mins");
+        let delay_base_ms = reader.value("auth.login.delay_base_ms");
+        let delay_max_ms = reader.value("auth.login.delay_max_ms");
+
+        Some(Self {
+            jwt_secret: jwt_secret?,
+            jwt_ttl_secs: jwt_ttl_secs?,
+            totp_cipher: totp_cipher?,
+            totp_issuer: totp_issuer?,
+            two_factor_roles: two_factor_roles?,
+            email_verification_ttl: chrono::Duration::minutes(email_verification_ttl_mins?),
+            password_reset_ttl: chrono::Duration::minutes(password_reset_ttl_mins?),
+            login: LoginPolicy {
+                max_account_failures: max_account_failures?,
+                max_ip_failures: max_ip_failures?,
+                window: chrono::Duration::minutes(failure_window_mins?),
+                lockout: chrono::Duration::minutes(lockout_mins?),
+                base_delay: chrono::Duration::milliseconds(delay_base_ms?),
+                max_delay: chrono::Duration::milliseconds(delay_max_ms?),
+            },
+        })
+    }
+}
+
+pub struct MailConfig {
+    pub transport: MailTransport,
+    pub from: Mailbox,
+    /// Where the file transport writes emails.
+    pub dir: String,
+    pub smtp_host: Option<String>,
+    pub smtp_port: u16,
+    pub smtp_tls: SmtpTls,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+}
+
+impl MailConfig {
+    fn read(reader: &mut Reader) -> Option<Self> {
+        let transport = reader.value("providers.mail.transport");
+        let from = reader.value("providers.mail.from");
+        let dir = reader.value("providers.mail.dir");
+        let smtp_host = reader.value("providers.mail.smtp_host");
+        if transport == Some(MailTransport::Smtp) && smtp_host.is_none() {
+            reader.missing("providers.mail.smtp_host");
+        }
+        let smtp_port = reader.value("providers.mail.smtp_port");
+        let smtp_tls = reader.value("providers.mail.smtp_tls");
+        let smtp_username = reader.value("providers.mail.smtp_username");
+        let smtp_password = reader.value("providers.mail.smtp_password");
+
+        Some(Self {
+            transport: transport?,
+            from: from?,
+            dir: dir?,
+            smtp_host,
+            smtp_port: smtp_port?,
+            smtp_tls: smtp_tls?,
+            smtp_username,
+            smtp_password,
+        })
+    }
+}
+
+/// An `OIDC_ROLE_MAP` entry, `value=role`.
+struct RoleGrant(String, UserRole);
+
+impl FromStr for RoleGrant {
+    type Err = String;
+
+    fn from_str(entry: &str) -> Result<Self, Self::Err> {
+        let (value, role) = entry
+            .rsplit_once('=')
+            .ok_or_else(|| format!("`{}` is not of the form value=role", entry))?;
+        Ok(Self(value.trim().to_string(), role.trim().parse()?))
+    }
+}
+
+pub struct ProvidersConfig {
+    /// `None` unless both the URL and key of the model are set.
+    pub generation: Option<ModelProvider>,
+    pub mail: MailConfig,
+    /// `None` unless an OpenID Connect provider is configured.
+    pub oidc: Option<OidcSettings>,
+}
+
+impl ProvidersConfig {
+    fn read(reader: &mut Reader) -> Option<Self> {
+        let api_url: Option<String> = reader.value("providers.generation.api_url");
+        let api_key: Option<String> = reader.value("providers.generation.api_key");
+        let generation = match (api_url, api_key) {
+            (Some(api_url), Some(api_key)) => Some(ModelProvider { api_url, api_key }),
+            (Some(_), None) => {
+                reader.missing("providers.generation.api_key");
+                None
+            }
+            _ => None,
+        };
+        let mail = MailConfig::read(reader);
+
+        let issuer: Option<String> = reader.value("providers.oidc.issuer");
+        let client_id: Option<String> = reader.value("providers.oidc.client_id");
+        if issuer.is_some() && client_id.is_none() {
+            reader.missing("providers.oidc.client_id");
+        }
+        let client_secret = reader.value("providers.oidc.client_secret");
+        let redirect_url = reader.value("providers.oidc.redirect_url");
+        let scopes = reader.value("providers.oidc.scopes");
+        let role_claim = reader.value("providers.oidc.role_claim");
+        let role_map: Option<Vec<RoleGrant>> = reader.list("providers.oidc.role_map");
+        let oidc = match issuer {
+            Some(issuer) => Some(OidcSettings {
+                issuer,
+                client_id: client_id?,
+                client_secret,
+                redirect_url: redirect_url?,
+                scopes: scopes?,
+                role_claim,
+                role_map: role_map?
+                    .into_iter()
+                    .map(|RoleGrant(value, role)| (value, role))
+                    .collect(),
+            }),
+            None => None,
+        };
+
+        Some(Self {
+            generation,
+            mail: mail?,
+            oidc,
+        })
+    }
+}
+
+pub struct JobsConfig {
+    pub workers: usize,
+    pub poll_interval: Duration,
+    /// Batch items generated at once, across workers.
+    pub batch_concurrency: usize,
+}
+
+impl JobsConfig {
+    fn read(reader: &mut Reader) -> Option<Self> {
+        let workers = reader.checked("jobs.workers", "must be at least 1", |n| *n > 0);
+        let poll_interval_ms = reader.value("jobs.poll_interval_ms");
+        let batch_concurrency = reader.value("jobs.batch_concurrency");
+
+        Some(Self {
+            workers: workers?,
+            poll_interval: Duration::from_millis(poll_interval_ms?),
+            batch_concurrency: batch_concurrency?,
+        })
+    }
+}
+
+pub struct HaikuConfig {
+    pub duplicates: DuplicatePolicy,
+    /// Tag new haikus with the existing tags their text mentions.
+    pub auto_tag: bool,
+}
+
+impl HaikuConfig {
+    fn read(reader: &mut Reader) -> Option<Self> {
+        let threshold = reader.value("haikus.duplicate_threshold");
+        let action = reader.value("haikus.duplicate_action");
+        let auto_tag = reader.value("haikus.auto_tag");
+
+        Some(Self {
+            duplicates: DuplicatePolicy {
+                threshold: threshold?,
+                action: action?,
+            },
+            auto_tag: auto_tag?,
+        })
+    }
+}
+
+pub struct CardsConfig {
+    pub attribution: String,
+    /// Fonts added to the system ones.
+    pub fonts_dir: Option<String>,
+}
+
+impl CardsConfig {
+    fn read(reader: &mut Reader) -> Option<Self> {
+        let attribution = reader.value("cards.attribution");
+        let fonts_dir = reader.value("cards.fonts_dir");
+
+        Some(Self {
+            attribution: attribution?,
+            fonts_dir,
+        })
+    }
+}
+
+/// Retentions of 0 days or less keep trashed rows forever.
+fn read_trash(reader: &mut Reader) -> Option<RetentionPolicy> {
+    // Only the default of the per-entity retentions, checked all the same.
+    reader.value::<i64>("trash.retention_days");
+    let mut retention = |key: &str| {
+        let days: i64 = reader.value(key)?;
+        Some((days > 0).then(|| chrono::Duration::days(days)))
+    };
+    let users = retention("trash.retention_users_days");
+    let prompts = retention("trash.retention_prompts_days");
+    let haikus = retention("trash.retention_haikus_days");
+    let batch_size = reader.checked("trash.purge_batch_size", "must be at least 1", |n| *n > 0);
+    let interval_secs = reader.value("trash.purge_interval_secs");
+
+    Some(RetentionPolicy {
+        users: users?,
+        prompts: prompts?,
+        haikus: haikus?,
+        batch_size: batch_size?,
+        interval: Duration::from_secs(interval_secs?),
+    })
+}
+
+pub struct Config {
+    pub db: DbConfig,
+    pub server: ServerConfig,
+    pub cors: CorsConfig,
+    pub auth: AuthConfig,
+    pub providers: ProvidersConfig,
+    pub jobs: JobsConfig,
+    pub haikus: HaikuConfig,
+    pub search_language: String,
+    pub cards: CardsConfig,
+    pub trash: RetentionPolicy,
+}
+
+impl Config {
+    /// Reads the configuration from its layers, see [`layers`].
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::read(&Layers::load())
+    }
+
+    /// Prints the configuration as loaded, with secrets redacted, and
+    /// whether it is valid.
+    pub fn print() -> Result<(), ConfigError> {
+        let layers = Layers::load();
+        print!("{}", layers.dump());
+        Self::read(&layers).map(|_| ())
+    }
+
+    fn read(layers: &Layers) -> Result<Self, ConfigError> {
+        let mut reader = Reader {
+            layers,
+            issues: layers.issues.clone(),
+        };
+        let db = DbConfig::read(&mut reader);
+        let server = ServerConfig::read(&mut reader);
+        let cors = CorsConfig::read(&mut reader);
+        let auth = AuthConfig::read(&mut reader);
+        let providers = ProvidersConfig::read(&mut reader);
+        let jobs = JobsConfig::read(&mut reader);
+        let haikus = HaikuConfig::read(&mut reader);
+        let search_language = reader.value("search.language");
+        let cards = CardsConfig::read(&mut reader);
+        let trash = read_trash(&mut reader);
+
+        let (
+            Some(db),
+            Some(server),
+            Some(cors),
+            Some(auth),
+            Some(providers),
+            Some(jobs),
+            Some(haikus),
+            Some(search_language),
+            Some(cards),
+            Some(trash),
+        ) = (
+            db,
+            server,
+            cors,
+            auth,
+            providers,
+            jobs,
+            haikus,
+            search_language,
+            cards,
+            trash,
+        )
+        else {
+            return Err(ConfigError {
+                issues: reader.issues,
+            });
+        };
+        if !reader.issues.is_empty() {
+            return Err(ConfigError {
+                issues: reader.issues,
+            });
+        }
+
+        Ok(Self {
+            db,
+            server,
+            cors,
+            auth,
+            providers,
+            jobs,
+            haikus,
+            search_language,
+            cards,
+            trash,
+        })
+    }
+
+    pub fn mailer(&self) -> Result<Arc<dyn Mailer>, MailError> {
+        let mail = &self.providers.mail;
+        let from = mail.from.clone();
+        Ok(match mail.transport {
+            MailTransport::Log => Arc::new(LogMailer::new(from)),
+            MailTransport::File => Arc::new(FileMailer::new(from, &mail.dir)),
+            MailTransport::Smtp => {
+                let credentials = mail
+                    .smtp_username
+                    .clone()
+                    .map(|username| (username, mail.smtp_password.clone().unwrap_or_default()));
+                Arc::new(SmtpMailer::new(
+                    from,
+                    mail.smtp_host.as_deref().unwrap_or_default(),
+                    mail.smtp_port,
+                    mail.smtp_tls,
+                    credentials,
+                )?)
+            }
+        })
+    }
+
+    pub fn account_settings(&self) -> AccountSettings {
+        AccountSettings {
+            app_url: self.server.app_url.clone(),
+            verification_ttl: self.auth.email_verification_ttl,
+            reset_ttl: self.auth.password_reset_ttl,
+        }
+    }
+
+    pub fn two_factor_policy(&self) -> TwoFactorPolicy {
+        TwoFactorPolicy {
+            roles: self.auth.two_factor_roles.clone(),
+        }
+    }
+
+    pub fn two_factor_settings(&self) -> TwoFactorSettings {
+        TwoFactorSettings {
+            cipher: self.auth.totp_cipher.clone(),
+            issuer: self.auth.totp_issuer.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUIRED: &[(&str, &str)] = &[
+        ("DB_HOST", "localhost"),
+        ("DB_PORT", "5432"),
+        ("DB_NAME", "haiku"),
+        ("DB_USER", "root"),
+        ("DB_PASSWORD", "root"),
+        ("SRV_HOST", "127.0.0.1"),
+        ("SRV_PORT", "8080"),
+        ("JWT_SECRET", "secret"),
+        (
+            "TOTP_ENCRYPTION_KEY",
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+        ),
+        ("MAIL_TRANSPORT", "log"),
+    ];
+
+    /// The configuration from `env` only, with `unset` left out.
+    fn config(env: &[(&str, &str)], unset: &str) -> Result<Config, ConfigError> {
+        let file = std::env::temp_dir().join(format!("haiku-{}.toml", uuid::Uuid::new_v4()));
+        std::fs::write(&file, "").unwrap();
+        let layers = Layers::read(|name| match name {
+            "HAIKU_CONFIG" => Some(file.display().to_string()),
+            name if name == unset => None,
+            name => env
+                .iter()
+                .chain(REQUIRED)
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string()),
+        });
+        std::fs::remove_file(file).unwrap();
+        Config::read(&layers)
+    }
+
+    fn issues(err: ConfigError) -> Vec<String> {
+        err.issues.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn reads_the_required_settings() {
+        let config = config(&[], "").unwrap();
+        assert_eq!(config.providers.mail.transport, MailTransport::Log);
+        assert_eq!(config.server.public_url, "http://127.0.0.1:8080");
+    }
+
+    #[test]
+    fn mail_transport_has_no_default() {
+        let issues = issues(config(&[], "MAIL_TRANSPORT").err().unwrap());
+        assert_eq!(
+            issues,
+            [
+                "Setting `providers.mail.transport` is missing, set it in haiku.toml or with $MAIL_TRANSPORT"
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_every_issue_at_once() {
+        let issues = issues(
+            config(
+                &[
+                    ("MAIL_TRANSPORT", "smtp"),
+                    ("DB_PORT", "five"),
+                    ("TWO_FACTOR_ROLES", "admin,user"),
+                ],
+                "JWT_SECRET",
+            )
+            .err()
+            .unwrap(),
+        );
+        assert_eq!(issues.len(), 4, "{issues:?}");
+        assert!(
+            issues
+                .iter()
+                .any(|issue| issue.contains("`db.port` ($DB_PORT) is invalid"))
+        );
+        assert!(
+            issues
+                .iter()
+                .any(|issue| issue.contains("`auth.jwt_secret` is missing"))
+        );
+        assert!(
+            issues
+                .iter()
+                .any(|issue| issue.contains("`providers.mail.smtp_host` is missing"))
+        );
+        assert!(
+            issues
+                .iter()
+                .any(|issue| issue.contains("only admin rights"))
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use super::validation::FieldError;
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory, NextRequest};
+use async_graphql::{Response, ServerError};
+use serde::Serialize;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Errors surfaced to GraphQL clients, each with a stable `code` in the
+/// error's `extensions`.
+///
+/// Resolvers return them through `?` like any other error; the
+/// [`ErrorCodes`] extension then fills in the code, and also classifies
+/// plain `sqlx::Error`s so that those need no conversion.
+#[derive(Debug, Error)]
+pub enum AppError {
+    #[error("Not found")]
+    NotFound,
+    #[error("{0}")]
+    Conflict(String),
+    /// The row changed since the caller read it.
+    #[error("Expected version {expected}, but the row was modified in the meantime")]
+    VersionConflict {
+        expected: i32,
+        current: async_graphql::Value,
+    },
+    #[error("{0}")]
+    Validation(String),
+    /// Fields of an input breaking their rules, all of them.
+    #[error("Invalid input")]
+    InvalidInput(Vec<FieldError>),
+    #[error("Unauthenticated")]
+    Unauthenticated,
+    #[error("Invalid email or password")]
+    InvalidCredentials,
+    #[error("Invalid two-factor code")]
+    InvalidCode,
+    /// The identity provider did not vouch for the user; details are
+    /// logged.
+    #[error("Sign-in with the identity provider failed")]
+    ExternalLoginFailed,
+    #[error("Forbidden")]
+    Forbidden,
+    /// The role requires a token obtained with a second factor.
+    #[error("Sign in with two-factor authentication to do this")]
+    TwoFactorRequired,
+    /// The API key the request was made with does not allow it.
+    #[error("The API key's scope does not allow this")]
+    InsufficientScope,
+    /// Too many failed logins or reset requests for the account or from the
+    /// address.
+    #[error("Too many attempts, try again in {retry_after}s")]
+    TooManyAttempts { retry_after: i64 },
+    /// Logged server-side; clients only get a generic message.
+    #[error("Internal server error")]
+    Internal(String),
+}
+
+impl AppError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound => "NOT_FOUND",
+            AppError::Conflict(_) | AppError::VersionConflict { .. } => "CONFLICT",
+            AppError::Validation(_) | AppError::InvalidInput(_) => "VALIDATION",
+            AppError::Unauthenticated
+            | AppError::InvalidCredentials
+            | AppError::InvalidCode
+            | AppError::ExternalLoginFailed => "UNAUTHENTICATED",
+            AppError::Forbidden | AppError::TwoFactorRequired | AppError::InsufficientScope => {
+                "FORBIDDEN"
+            }
+            AppError::TooManyAttempts { .. } => "RATE_LIMITED",
+            AppError::Internal(_) => "INTERNAL",
+        }
+    }
+
+    pub fn validation(message: impl Into<String>) -> Self {
+        AppError::Validation(message.into())
+    }
+
+    /// Rewrites `err` as this error, logging internal details.
+    fn apply(&self, err: &mut ServerError) {
+        if let AppError::Internal(detail) = self {
+            tracing::error!(path = ?err.path, "Internal error: {}", detail);
+        }
+        err.message = self.to_string();
+        let extensions = err.extensions.get_or_insert_with(Default::default);
+        extensions.set("code", self.code());
+        if let AppError::VersionConflict { expected, current } = self {
+            extensions.set("expectedVersion", *expected);
+            extensions.set("current", current.clone());
+        }
+        if let AppError::TooManyAttempts { retry_after } = self {
+            extensions.set("retryAfter", *retry_after);
+        }
+        if let AppError::InvalidInput(fields) = self {
+            extensions.set(
+                "fields",
+                async_graphql::to_value(fields).unwrap_or_default(),
+            );
+        }
+    }
+}
+
+impl From<&sqlx::Error> for AppError {
+    fn from(err: &sqlx::Error) -> Self {
+        let Some(db) = err.as_database_error() else {
+            return match err {
+                sqlx::Error::RowNotFound => AppError::NotFound,
+                err => AppError::Internal(err.to_string()),
+            };
+        };
+        match db.code().as_deref() {
+            Some("23505") => AppError::Conflict(
+                match db.constraint() {
+                    Some("users_email_key" | "idx_users_email_lower") => "Email is already in use",
+                    Some("tags_name_key") => "A tag with this name already exists",
+                    Some("kigo_word_language_key") => "This season word already exists",
+                    _ => "Already exists",
+                }
+                .to_string(),
+            ),
+            Some("23503") => AppError::validation("Refers to something that does not exist"),
+            Some("22001") => AppError::validation("A value is too long"),
+            // Invalid text representations, out of range values and unknown
+            // text search languages.
+            Some("22P02" | "22003" | "22007" | "22008" | "23514" | "42704") => {
+                AppError::validation("Invalid value")
+            }
+            _ => AppError::Internal(err.to_string()),
+        }
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(err: sqlx::Error) -> Self {
+        AppError::from(&err)
+    }
+}
+
+/// Failure of an update guarded by an `expectedVersion`.
+#[derive(Debug, Error)]
+pub enum UpdateError<T> {
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    /// Someone else updated the row since the caller read it.
+    #[error("Expected version {expected}, but the row was modified in the meantime")]
+    Conflict { expected: i32, current: Box<T> },
+}
+
+impl<T> UpdateError<T> {
+    /// The error for an update that matched no row, given the live row as it
+    /// is now: a conflict if the caller expected a version, as that is the
+    /// only condition left that can fail, and not found otherwise.
+    pub fn unmatched(expected: Option<i32>, current: Option<T>) -> Self {
+        match (expected, current) {
+            (Some(expected), Some(current)) => UpdateError::Conflict {
+                expected,
+                current: Box::new(current),
+            },
+            _ => UpdateError::Database(sqlx::Error::RowNotFound),
+        }
+    }
+}
+
+impl<T: Serialize> From<UpdateError<T>> for AppError {
+    /// Conflicts carry the current row, with camelCase keys like the rest of
+    /// the API.
+    fn from(err: UpdateError<T>) -> Self {
+        match err {
+            UpdateError::Database(err) => AppError::from(err),
+            UpdateError::Conflict { expected, current } => {
+                let current = match serde_json::to_value(current) {
+                    Ok(serde_json::Value::Object(fields)) => fields
+                        .into_iter()
+                        .map(|(key, value)| (camel_case(&key), value))
+                        .collect::<serde_json::Map<_, _>>()
+                        .into(),
+                    _ => serde_json::Value::Null,
+                };
+                AppError::VersionConflict {
+                    expected,
+                    current: async_graphql::Value::from_json(current)
+                        .unwrap_or(async_graphql::Value::Null),
+                }
+            }
+        }
+    }
+}
+
+fn camel_case(name: &str) -> String {
+    let mut words = name.split('_');
+    let mut camel = words.next().unwrap_or_default().to_string();
+    for word in words {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            camel.extend(first.to_uppercase());
+            camel.push_str(chars.as_str());
+        }
+    }
+    camel
+}
+
+/// Gives every error of a response a `code` extension.
+///
+/// Errors from [`AppError`] and `sqlx::Error` get theirs; errors raised by
+/// async-graphql itself, such as unparsable queries or arguments failing
+/// their validators, are `VALIDATION`; anything else is `INTERNAL` and its
+/// message is replaced so that it never reaches clients.
+pub struct ErrorCodes;
+
+impl ExtensionFactory for ErrorCodes {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(ErrorCodesExtension)
+    }
+}
+
+struct ErrorCodesExtension;
+
+#[async_graphql::async_trait::async_trait]
+impl Extension for ErrorCodesExtension {
+    async fn request(&self, ctx: &ExtensionContext<'_>, next: NextRequest<'_>) -> Response {
+        let mut response = next.run(ctx).await;
+        for err in &mut response.errors {
+            classify(err);
+        }
+        response
+    }
+}
+
+fn classify(err: &mut ServerError) {
+    let has_code = err
+        .extensions
+        .as_ref()
+        .is_some_and(|extensions| extensions.get("code").is_some());
+    if has_code {
+        return;
+    }
+
+    let source = err.source.clone();
+    match source.as_deref() {
+        Some(source) => {
+            if let Some(app) = source.downcast_ref::<AppError>() {
+                app.apply(err);
+            } else if let Some(db) = source.downcast_ref::<sqlx::Error>() {
+                AppError::from(db).apply(err);
+            } else {
+                AppError::Internal(err.message.clone()).apply(err);
+            }
+        }
+        None => {
+            err.extensions
+                .get_or_insert_with(Default::default)
+                .set("code", "VALIDATION");
+        }
+    }
+}
@@ -1,8 +1,18 @@
-use axum::http::{Method, header::HeaderName};
-use tower_http::cors::CorsLayer;
+use crate::app::config::CorsConfig;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{HeaderValue, Method, header::HeaderName};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use uuid::Uuid;
 
-pub fn cors_middleware() -> CorsLayer {
+const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+pub fn cors_middleware(config: &CorsConfig) -> CorsLayer {
     CorsLayer::new()
+        .allow_origin(AllowOrigin::list(config.allowed_origins.clone()))
         .allow_methods(vec![
             Method::GET,
             Method::POST,
@@ -13,6 +23,128 @@ pub fn cors_middleware() -> CorsLayer {
         .allow_headers(vec![
             HeaderName::from_static("content-type"),
             HeaderName::from_static("authorization"),
+            X_REQUEST_ID,
         ])
+        .expose_headers(vec![X_REQUEST_ID])
         .allow_credentials(true)
 }
+
+/// Where a request comes from, attached to its GraphQL execution for the
+/// audit log.
+#[derive(Debug, Clone)]
+pub struct RequestMeta {
+    pub request_id: String,
+    pub ip: Option<String>,
+}
+
+/// Reverse proxies allowed to tell the client's address.
+#[derive(Debug, Clone)]
+pub struct TrustedProxies(pub Arc<[IpAddr]>);
+
+impl TrustedProxies {
+    /// The client behind `peer`: the last `x-forwarded-for` hop that is not
+    /// a trusted proxy, when the peer is one. Anyone else could claim any
+    /// address in the header.
+    fn client(&self, peer: Option<IpAddr>, forwarded: Option<&str>) -> Option<IpAddr> {
+        let trusted = |ip: &IpAddr| self.0.contains(ip);
+        let Some(forwarded) = forwarded.filter(|_| peer.as_ref().is_some_and(trusted)) else {
+            return peer;
+        };
+        // Each proxy appends the address it got the request from.
+        let mut client = peer;
+        for hop in forwarded.rsplit(',') {
+            let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = Some(ip);
+            if !trusted(&ip) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// Tags each request with a [`RequestMeta`], keeping the caller's
+/// `x-request-id` when it has a sane one and echoing it in the response.
+///
+/// The address is the peer's, or the client's it forwards for when it is
+/// one of the [`TrustedProxies`].
+pub async fn request_meta_middleware(
+    State(proxies): State<TrustedProxies>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let headers = req.headers();
+    let request_id = headers
+        .get(X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let forwarded = headers
+        .get("x-forwarded-for")
+        .and_then(|value| value.to_str().ok());
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let ip = proxies.client(peer, forwarded).map(|ip| ip.to_string());
+
+    req.extensions_mut().insert(RequestMeta {
+        request_id: request_id.clone(),
+        ip,
+    });
+    let mut response = next.run(req).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(X_REQUEST_ID, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn proxies(ips: &[&str]) -> TrustedProxies {
+        TrustedProxies(ips.iter().map(|value| ip(value)).collect())
+    }
+
+    #[test]
+    fn ignores_forwarded_for_from_untrusted_peers() {
+        let proxies = proxies(&["10.0.0.1"]);
+        let client = proxies.client(Some(ip("203.0.113.9")), Some("198.51.100.1"));
+        assert_eq!(client, Some(ip("203.0.113.9")));
+    }
+
+    #[test]
+    fn takes_the_last_untrusted_hop() {
+        let proxies = proxies(&["10.0.0.1", "10.0.0.2"]);
+        let forwarded = "198.51.100.1, 203.0.113.7, 10.0.0.2";
+        let client = proxies.client(Some(ip("10.0.0.1")), Some(forwarded));
+        assert_eq!(client, Some(ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn stops_at_malformed_hops() {
+        let proxies = proxies(&["10.0.0.1"]);
+        let client = proxies.client(Some(ip("10.0.0.1")), Some("nonsense"));
+        assert_eq!(client, Some(ip("10.0.0.1")));
+        let client = proxies.client(Some(ip("10.0.0.1")), Some("198.51.100.1, nonsense"));
+        assert_eq!(client, Some(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn uses_the_peer_without_a_header() {
+        let proxies = proxies(&["10.0.0.1"]);
+        assert_eq!(
+            proxies.client(Some(ip("10.0.0.1")), None),
+            Some(ip("10.0.0.1"))
+        );
+        assert_eq!(proxies.client(None, Some("198.51.100.1")), None);
+    }
+}
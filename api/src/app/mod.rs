@@ -1,6 +1,9 @@
+pub mod cli;
 pub mod config;
+pub mod error;
 pub mod routes;
 pub mod server;
+pub mod validation;
 
 pub mod middlewares;
 
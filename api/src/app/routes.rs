@@ -1,48 +1,198 @@
+use crate::anthology::handler::{anthology_epub, anthology_pdf};
+use crate::api_keys::schema::ApiKeySchema;
+use crate::app::config::Config;
+use crate::app::middlewares::{
+    RequestMeta, TrustedProxies, cors_middleware, request_meta_middleware,
+};
 use crate::app::schemas::AppSchema;
+use crate::audit::schema::AuditSchema;
+use crate::auth::token::TokenKeys;
+use crate::auth::two_factor::TwoFactorPolicy;
+use crate::auth::viewer::Viewer;
+use crate::batches::schema::BatchSchema;
+use crate::cards::handler::{DefaultAttribution, card_png, card_svg};
+use crate::cards::render::CardRenderer;
+use crate::collections::schema::CollectionSchema;
+use crate::exports::handler::{markdown_export, text_export};
+use crate::feeds::handler::{atom_feed, rss_feed};
+use crate::haiku::page::share_page;
+use crate::haiku::schema::HaikuSchema;
+use crate::haiku::share::PublicUrl;
+use crate::jobs::schema::JobSchema;
+use crate::mail::mailer::Mailer;
 use crate::prompts::schema::PromptSchema;
+use crate::search::schema::SearchSchema;
+use crate::tags::schema::TagSchema;
+use crate::transfer::schema::TransferSchema;
+use crate::trash::schema::TrashSchema;
 use crate::users::schema::UserSchema;
-use async_graphql::http::GraphiQLSource;
-use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
-use axum::routing::post;
+use async_graphql::http::{ALL_WEBSOCKET_PROTOCOLS, GraphiQLSource};
+use async_graphql::{Data, Executor, Request};
+use async_graphql_axum::{GraphQLProtocol, GraphQLRequest, GraphQLResponse, GraphQLWebSocket};
 use axum::{
     Router,
-    extract::Extension,
+    extract::{Extension, WebSocketUpgrade},
+    http::{HeaderMap, HeaderValue, header::AUTHORIZATION},
+    middleware,
     response::{Html, IntoResponse},
-    routing::get,
+    routing::{MethodRouter, get},
 };
 use sqlx::PgPool;
+use std::sync::Arc;
 
-pub fn config_routes(pool: &PgPool) -> Router {
-    let app_schema = AppSchema::new(pool);
+pub fn config_routes(pool: &PgPool, config: &Config, mailer: Arc<dyn Mailer>) -> Router {
+    let keys = TokenKeys::new(&config.auth.jwt_secret, config.auth.jwt_ttl_secs);
+    let app_schema = AppSchema::new(pool, config, &keys, mailer);
 
     Router::new()
         .route("/", get(|| async { "Hello, world!" }))
-        .route(
-            "/users",
-            get(graphql_handler_users).post(graphql_handler_users),
-        )
-        .route(
-            "/prompts",
-            get(graphql_handler_prompts).post(graphql_handler_prompts),
-        )
-        .route("/gql", get(graphql))
+        .route("/users", graphql::<UserSchema>())
+        .route("/prompts", graphql::<PromptSchema>())
+        .route("/haikus", graphql::<HaikuSchema>())
+        .route("/jobs", graphql::<JobSchema>())
+        .route("/batches", graphql::<BatchSchema>())
+        .route("/batches/ws", get(graphql_ws::<BatchSchema>))
+        .route("/collections", graphql::<CollectionSchema>())
+        .route("/search", graphql::<SearchSchema>())
+        .route("/tags", graphql::<TagSchema>())
+        .route("/transfer", graphql::<TransferSchema>())
+        .route("/trash", graphql::<TrashSchema>())
+        .route("/api-keys", graphql::<ApiKeySchema>())
+        .route("/audit", graphql::<AuditSchema>())
+        .route("/haikus/{id}/card.svg", get(card_svg))
+        .route("/haikus/{id}/card.png", get(card_png))
+        .route("/s/{slug}", get(share_page))
+        .route("/feeds/haikus.atom", get(atom_feed))
+        .route("/feeds/haikus.rss", get(rss_feed))
+        .route("/anthology.epub", get(anthology_epub))
+        .route("/anthology.pdf", get(anthology_pdf))
+        .route("/exports/haikus.md", get(markdown_export))
+        .route("/exports/haikus.txt", get(text_export))
+        .route("/gql", get(graphiql))
         .layer(Extension(app_schema.user_schema))
+        .layer(Extension(app_schema.prompt_schema))
+        .layer(Extension(app_schema.haiku_schema))
+        .layer(Extension(app_schema.job_schema))
+        .layer(Extension(app_schema.batch_schema))
+        .layer(Extension(app_schema.collection_schema))
+        .layer(Extension(app_schema.search_schema))
+        .layer(Extension(app_schema.tag_schema))
+        .layer(Extension(app_schema.transfer_schema))
+        .layer(Extension(app_schema.trash_schema))
+        .layer(Extension(app_schema.audit_schema))
+        .layer(Extension(app_schema.api_key_schema))
+        .layer(Extension(keys))
+        .layer(Extension(config.two_factor_policy()))
+        .layer(Extension(pool.clone()))
+        .layer(Extension(PublicUrl(config.server.public_url.clone())))
+        .layer(Extension(DefaultAttribution(
+            config.cards.attribution.clone(),
+        )))
+        .layer(Extension(CardRenderer::new(
+            config.cards.fonts_dir.as_deref(),
+        )))
+        .layer(middleware::from_fn_with_state(
+            TrustedProxies(config.server.trusted_proxies.clone().into()),
+            request_meta_middleware,
+        ))
+        .layer(cors_middleware(&config.cors))
+}
+
+/// Serves GET and POST requests to `schema`.
+fn graphql<E>() -> MethodRouter
+where
+    E: Executor + Clone,
+{
+    get(graphql_handler::<E>).post(graphql_handler::<E>)
 }
 
-async fn graphql_handler_users(
-    Extension(schema): Extension<UserSchema>,
+async fn graphql_handler<E>(
+    Extension(schema): Extension<E>,
+    Extension(pool): Extension<PgPool>,
+    Extension(keys): Extension<TokenKeys>,
+    Extension(two_factor): Extension<TwoFactorPolicy>,
+    Extension(meta): Extension<RequestMeta>,
+    headers: HeaderMap,
     req: GraphQLRequest,
-) -> GraphQLResponse {
-    schema.execute(req.into_inner()).await.into()
+) -> GraphQLResponse
+where
+    E: Executor + Clone,
+{
+    schema
+        .execute(
+            with_viewer(req, &pool, &keys, &two_factor, &headers)
+                .await
+                .data(meta),
+        )
+        .await
+        .into()
+}
+
+/// Serves subscriptions to `schema` over a WebSocket. The viewer
+/// authenticates as for queries, or with an `Authorization` entry in the
+/// `connection_init` payload, as browsers cannot set headers on WebSockets.
+async fn graphql_ws<E>(
+    Extension(schema): Extension<E>,
+    Extension(pool): Extension<PgPool>,
+    Extension(keys): Extension<TokenKeys>,
+    Extension(two_factor): Extension<TwoFactorPolicy>,
+    protocol: GraphQLProtocol,
+    mut headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> impl IntoResponse
+where
+    E: Executor + Clone,
+{
+    upgrade
+        .protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |stream| {
+            GraphQLWebSocket::new(stream, schema, protocol)
+                .on_connection_init(move |payload| async move {
+                    if let Some(value) = payload
+                        .get("Authorization")
+                        .and_then(|value| value.as_str())
+                        .and_then(|value| HeaderValue::from_str(value).ok())
+                    {
+                        headers.insert(AUTHORIZATION, value);
+                    }
+                    let mut data = Data::default();
+                    data.insert(two_factor);
+                    if let Some(viewer) = viewer(&pool, &keys, &headers).await {
+                        data.insert(viewer);
+                    }
+                    Ok(data)
+                })
+                .serve()
+        })
 }
 
-async fn graphql_handler_prompts(
-    Extension(schema): Extension<PromptSchema>,
+/// Attaches the authenticated [`Viewer`], if any, to the GraphQL request,
+/// along with the policy deciding what they may do without a second factor.
+async fn with_viewer(
     req: GraphQLRequest,
-) -> GraphQLResponse {
-    schema.execute(req.into_inner()).await.into()
+    pool: &PgPool,
+    keys: &TokenKeys,
+    two_factor: &TwoFactorPolicy,
+    headers: &HeaderMap,
+) -> Request {
+    let req = req.into_inner().data(two_factor.clone());
+    match viewer(pool, keys, headers).await {
+        Some(viewer) => req.data(viewer),
+        None => req,
+    }
+}
+
+/// The user behind the `Authorization` header, if any.
+async fn viewer(pool: &PgPool, keys: &TokenKeys, headers: &HeaderMap) -> Option<Viewer> {
+    Viewer::authenticate(pool, keys, headers)
+        .await
+        // Resolvers needing a viewer then fail as unauthenticated.
+        .unwrap_or_else(|err| {
+            tracing::error!(error = %err, "Failed to look up API key");
+            None
+        })
 }
 
-async fn graphql() -> impl IntoResponse {
+async fn graphiql() -> impl IntoResponse {
     Html(GraphiQLSource::build().endpoint("/graphql").finish())
 }
@@ -1,25 +1,68 @@
-use crate::users::schema::{MutationRoot, QueryRoot, UserSchema};
-use async_graphql::{EmptySubscription, Schema};
+use crate::api_keys::schema::ApiKeySchema;
+use crate::app::config::Config;
+use crate::audit::schema::AuditSchema;
+use crate::auth::oidc::OidcClient;
+use crate::auth::token::TokenKeys;
+use crate::batches::schema::BatchSchema;
+use crate::collections::schema::CollectionSchema;
+use crate::haiku::schema::HaikuSchema;
+use crate::haiku::share::PublicUrl;
+use crate::jobs::schema::JobSchema;
+use crate::mail::mailer::Mailer;
+use crate::prompts::schema::PromptSchema;
+use crate::search::schema::SearchSchema;
+use crate::tags::schema::TagSchema;
+use crate::transfer::schema::TransferSchema;
+use crate::trash::schema::TrashSchema;
+use crate::users::schema::UserSchema;
 use sqlx::PgPool;
+use std::sync::Arc;
 
 pub struct AppSchema {
     pub user_schema: UserSchema,
+    pub prompt_schema: PromptSchema,
+    pub haiku_schema: HaikuSchema,
+    pub job_schema: JobSchema,
+    pub batch_schema: BatchSchema,
+    pub collection_schema: CollectionSchema,
+    pub search_schema: SearchSchema,
+    pub tag_schema: TagSchema,
+    pub transfer_schema: TransferSchema,
+    pub trash_schema: TrashSchema,
+    pub audit_schema: AuditSchema,
+    pub api_key_schema: ApiKeySchema,
 }
 
 impl AppSchema {
-    pub fn new(pool: &PgPool) -> Self {
+    pub fn new(pool: &PgPool, config: &Config, keys: &TokenKeys, mailer: Arc<dyn Mailer>) -> Self {
         Self {
-            user_schema: crate::users::schema::create_schema(pool.clone()),
+            user_schema: crate::users::schema::create_schema(
+                pool.clone(),
+                keys.clone(),
+                mailer,
+                config.account_settings(),
+                config.two_factor_settings(),
+                config.providers.oidc.clone().map(OidcClient::new),
+                config.auth.login.clone(),
+            ),
+            prompt_schema: crate::prompts::schema::create_schema(pool.clone()),
+            haiku_schema: crate::haiku::schema::create_schema(
+                pool.clone(),
+                PublicUrl(config.server.public_url.clone()),
+                config.haikus.duplicates,
+            ),
+            job_schema: crate::jobs::schema::create_schema(pool.clone()),
+            batch_schema: crate::batches::schema::create_schema(pool.clone()),
+            collection_schema: crate::collections::schema::create_schema(pool.clone()),
+            search_schema: crate::search::schema::create_schema(
+                pool.clone(),
+                config.search_language.clone(),
+            ),
+            tag_schema: crate::tags::schema::create_schema(pool.clone()),
+            transfer_schema: crate::transfer::schema::create_schema(pool.clone()),
+            trash_schema: crate::trash::schema::create_schema(pool.clone(), config.trash),
+            audit_schema: crate::audit::schema::create_schema(pool.clone()),
+            api_key_schema: crate::api_keys::schema::create_schema(pool.clone()),
         }
     }
-
-    pub fn build(pool: &PgPool) -> Schema<QueryRoot, MutationRoot, EmptySubscription> {
-        Schema::build(
-            crate::users::schema::QueryRoot,
-            crate::users::schema::MutationRoot,
-            EmptySubscription,
-        )
-        .data(pool.clone())
-        .finish()
-    }
 }
@@ -1,5 +1,12 @@
 use super::{config::Config, routes::config_routes};
 use crate::app::config::ConfigError;
+use crate::batches::jobs::{GENERATE_BATCH_ITEM, batch_item_dead, generate_batch_item};
+use crate::haiku::jobs::{
+    FINGERPRINT_HAIKUS, GENERATE_HAIKU, GenerationSettings, REINDEX_HAIKUS, fingerprint_haikus,
+    generate_haiku, reindex_haikus,
+};
+use crate::jobs::worker::{JobRegistry, WorkerPool};
+use crate::trash::jobs::{PURGE_TRASH, purge_dead, purge_trash, schedule_purge};
 use sqlx::{PgPool, postgres::PgPoolOptions};
 use std::net::SocketAddr;
 use tokio::net::TcpListener;
@@ -11,26 +18,72 @@ pub struct Server {
 
 impl Server {
     pub async fn new() -> Result<Self, ConfigError> {
-        let config = Config::load().await?;
+        let config = Config::load()?;
         let pool = PgPoolOptions::new()
-            .acquire_timeout(std::time::Duration::from_secs(10))
-            .connect(&config.db_url())
+            .max_connections(config.db.max_connections)
+            .acquire_timeout(config.db.acquire_timeout)
+            .connect(&config.db.url())
             .await
             .expect("Failed to connect to database");
 
         Ok(Self { pool, config })
     }
 
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
     pub async fn run(&self) -> Result<(), Box<dyn std::error::Error>> {
         let Self { pool, config } = self;
-        let app = config_routes(&pool);
-        let addr: SocketAddr = format!("{}:{}", config.srv_host, config.srv_port)
+        let settings = GenerationSettings {
+            provider: config.providers.generation.clone(),
+            duplicates: config.haikus.duplicates,
+            auto_tag: config.haikus.auto_tag,
+        };
+        let batch_settings = settings.clone();
+        let retention = config.trash;
+        let registry = JobRegistry::new()
+            .register(GENERATE_HAIKU, move |pool, job| {
+                generate_haiku(pool, job, settings.clone())
+            })
+            .register(GENERATE_BATCH_ITEM, move |pool, job| {
+                generate_batch_item(pool, job, batch_settings.clone())
+            })
+            .on_dead_letter(GENERATE_BATCH_ITEM, batch_item_dead)
+            .register(FINGERPRINT_HAIKUS, fingerprint_haikus)
+            .register(REINDEX_HAIKUS, reindex_haikus)
+            .register(PURGE_TRASH, move |pool, job| {
+                purge_trash(pool, job, retention)
+            })
+            .on_dead_letter(PURGE_TRASH, move |pool, job| {
+                purge_dead(pool, job, retention)
+            })
+            .limit(GENERATE_BATCH_ITEM, config.jobs.batch_concurrency);
+        WorkerPool::new(
+            pool.clone(),
+            registry,
+            config.jobs.workers,
+            config.jobs.poll_interval,
+        )
+        .start();
+        schedule_purge(pool, chrono::Utc::now(), None).await?;
+
+        let app = config_routes(pool, config, config.mailer()?);
+        let addr: SocketAddr = format!("{}:{}", config.server.host, config.server.port)
             .parse()
             .expect("Invalid address format");
         let listener = TcpListener::bind(addr).await?;
 
-        println!("Server running at http://{}", addr);
-        axum::serve(listener, app.into_make_service()).await?;
+        tracing::info!("Server running at http://{}", addr);
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await?;
 
         Ok(())
     }
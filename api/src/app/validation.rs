@@ -0,0 +1,262 @@
+use super::error::AppError;
+use serde::Serialize;
+
+/// A rule a string field of an input must follow.
+#[derive(Debug, Clone, Copy)]
+pub enum Rule {
+    /// Something other than whitespace.
+    NotBlank,
+    /// At most this many characters.
+    MaxLength(usize),
+    /// Looks like `local@domain.tld`.
+    Email,
+    /// At least 8 characters, mixing letters with digits or symbols.
+    Password,
+}
+
+impl Rule {
+    /// Why `value` breaks the rule, if it does.
+    fn check(self, value: &str) -> Option<String> {
+        let length = value.chars().count();
+        match self {
+            Rule::NotBlank if value.trim().is_empty() => Some("must not be blank".to_string()),
+            Rule::MaxLength(max) if length > max => {
+                Some(format!("must be at most {} characters long", max))
+            }
+            Rule::Email if !is_email(value) => Some("must be a valid email address".to_string()),
+            Rule::Password if length < 8 => Some("must be at least 8 characters long".to_string()),
+            Rule::Password
+                if !value.chars().any(char::is_alphabetic)
+                    || value.chars().all(char::is_alphabetic) =>
+            {
+                Some("must mix letters with digits or symbols".to_string())
+            }
+            _ => None,
+        }
+    }
+}
+
+fn is_email(value: &str) -> bool {
+    let Some((local, domain)) = value.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && !value.chars().any(char::is_whitespace)
+        && !domain.contains('@')
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !domain.contains("..")
+}
+
+/// A field of an input that broke one of its rules.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    /// The argument, then the field, e.g. `["data", "email"]`.
+    pub path: Vec<String>,
+    pub message: String,
+}
+
+/// A value a [`Rule`] applies to. Absent optional fields are left alone, as
+/// updates keep their current value.
+pub trait FieldValue {
+    fn as_field(&self) -> Option<&str>;
+
+    /// Strips the whitespace around the value, as text is stored trimmed.
+    fn trim_in_place(&mut self);
+}
+
+impl FieldValue for String {
+    fn as_field(&self) -> Option<&str> {
+        Some(self)
+    }
+
+    fn trim_in_place(&mut self) {
+        let trimmed = self.trim();
+        if trimmed.len() != self.len() {
+            *self = trimmed.to_string();
+        }
+    }
+}
+
+impl FieldValue for Option<String> {
+    fn as_field(&self) -> Option<&str> {
+        self.as_deref()
+    }
+
+    fn trim_in_place(&mut self) {
+        if let Some(value) = self {
+            value.trim_in_place();
+        }
+    }
+}
+
+/// Collects the rules broken by the fields of one input.
+pub struct Validator {
+    /// Path of the input, prepended to its fields'.
+    path: Vec<String>,
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    /// Checks `value` against `rules`, recording the first one it breaks.
+    pub fn field(&mut self, name: &str, value: &impl FieldValue, rules: &[Rule]) {
+        let Some(value) = value.as_field() else {
+            return;
+        };
+        if let Some(message) = rules.iter().find_map(|rule| rule.check(value)) {
+            let mut path = self.path.clone();
+            path.push(name.to_string());
+            self.errors.push(FieldError { path, message });
+        }
+    }
+
+    fn finish(self) -> Result<(), AppError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::InvalidInput(self.errors))
+        }
+    }
+}
+
+/// Checks a scalar argument against `rules`, failing like
+/// [`Validate::validate`].
+pub fn validate_argument(
+    argument: &str,
+    value: &impl FieldValue,
+    rules: &[Rule],
+) -> Result<(), AppError> {
+    let mut validator = Validator {
+        path: Vec::new(),
+        errors: Vec::new(),
+    };
+    validator.field(argument, value, rules);
+    validator.finish()
+}
+
+/// Inputs with rules on their fields.
+pub trait Validate {
+    /// Puts the fields in the form they are stored in, e.g. trimmed, before
+    /// their rules are checked.
+    fn normalize(&mut self) {}
+
+    fn rules(&self, validator: &mut Validator);
+
+    /// Normalizes the input given as `argument`, then checks every field,
+    /// failing with all the broken rules at once rather than only the first.
+    fn validate(&mut self, argument: &str) -> Result<(), AppError> {
+        self.normalize();
+        let mut validator = Validator {
+            path: vec![argument.to_string()],
+            errors: Vec::new(),
+        };
+        self.rules(&mut validator);
+        validator.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn not_blank_rejects_whitespace_only() {
+        assert!(Rule::NotBlank.check(" \t\n").is_some());
+        assert!(Rule::NotBlank.check(" a ").is_none());
+    }
+
+    #[test]
+    fn max_length_counts_characters_not_bytes() {
+        assert!(Rule::MaxLength(4).check("古池や蛙").is_none());
+        assert!(Rule::MaxLength(4).check("古池や蛙飛").is_some());
+    }
+
+    #[test]
+    fn email_needs_a_local_part_and_a_dotted_domain() {
+        for valid in ["a@b.co", "first.last+tag@mail.example.org"] {
+            assert!(Rule::Email.check(valid).is_none(), "{valid}");
+        }
+        for invalid in [
+            "", "a", "@b.co", "a@b", "a@.b.co", "a@b.co.", "a@b..co", "a@b@c.co", "a b@c.co",
+        ] {
+            assert!(Rule::Email.check(invalid).is_some(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn password_mixes_letters_with_something_else() {
+        assert!(Rule::Password.check("abc12").is_some());
+        assert!(Rule::Password.check("abcdefgh").is_some());
+        assert!(Rule::Password.check("12345678").is_some());
+        assert!(Rule::Password.check("abcdefg1").is_none());
+        assert!(Rule::Password.check("abcdefg!").is_none());
+    }
+
+    struct Input {
+        name: String,
+        nickname: Option<String>,
+    }
+
+    impl Validate for Input {
+        fn normalize(&mut self) {
+            self.name.trim_in_place();
+            self.nickname.trim_in_place();
+        }
+
+        fn rules(&self, validator: &mut Validator) {
+            validator.field("name", &self.name, &[Rule::NotBlank, Rule::MaxLength(3)]);
+            validator.field("nickname", &self.nickname, &[Rule::MaxLength(2)]);
+        }
+    }
+
+    fn field_errors(result: Result<(), AppError>) -> Vec<FieldError> {
+        match result {
+            Err(AppError::InvalidInput(errors)) => errors,
+            other => panic!("expected invalid input, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn validate_trims_before_checking() {
+        let mut input = Input {
+            name: "  bob \n".to_string(),
+            nickname: Some(" b ".to_string()),
+        };
+        input.validate("data").unwrap();
+        assert_eq!(input.name, "bob");
+        assert_eq!(input.nickname.as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn validate_reports_every_broken_field_with_its_path() {
+        let mut input = Input {
+            name: "   ".to_string(),
+            nickname: Some("abc".to_string()),
+        };
+        let errors = field_errors(input.validate("data"));
+        let paths: Vec<_> = errors.iter().map(|error| error.path.join(".")).collect();
+        assert_eq!(paths, ["data.name", "data.nickname"]);
+        assert_eq!(errors[0].message, "must not be blank");
+    }
+
+    #[test]
+    fn absent_optional_fields_are_left_alone() {
+        let mut input = Input {
+            name: "bob".to_string(),
+            nickname: None,
+        };
+        input.validate("data").unwrap();
+        assert_eq!(input.nickname, None);
+    }
+
+    #[test]
+    fn arguments_have_no_input_in_their_path() {
+        let errors = field_errors(validate_argument(
+            "newPassword",
+            &"short".to_string(),
+            &[Rule::Password],
+        ));
+        assert_eq!(errors[0].path, ["newPassword"]);
+    }
+}
@@ -0,0 +1,182 @@
+use async_graphql::{InputObject, SimpleObject};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+/// A mutation, who made it and what it changed, or a security event such
+/// as a failed login.
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject, FromRow)]
+pub struct AuditEvent {
+    pub id: Uuid,
+    /// User the request was authenticated as, `null` when anonymous.
+    pub actor_id: Option<Uuid>,
+    /// Name of the mutation, e.g. `deletePrompt`, or of the event, e.g.
+    /// `loginFailed`.
+    pub action: String,
+    /// `user`, `prompt`, `haiku`, ... when the mutation targets one.
+    pub entity_type: Option<String>,
+    pub entity_id: Option<Uuid>,
+    /// Fields of the entity the mutation changed, as they were before.
+    pub before: Option<Value>,
+    /// The same fields afterwards.
+    pub after: Option<Value>,
+    pub request_id: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, InputObject)]
+pub struct AuditFilter {
+    pub actor_id: Option<Uuid>,
+    pub action: Option<String>,
+    pub entity_type: Option<String>,
+    pub entity_id: Option<Uuid>,
+    pub request_id: Option<String>,
+    /// Only events from this instant on.
+    pub since: Option<DateTime<Utc>>,
+    /// Only events before this instant.
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl AuditEvent {
+    /// Events matching `filter`, newest first.
+    pub async fn list(
+        pool: &PgPool,
+        filter: AuditFilter,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<AuditEvent>, sqlx::Error> {
+        let events = sqlx::query_as::<_, AuditEvent>(
+            r#"
+            SELECT id, actor_id, action, entity_type, entity_id, before, after, request_id, ip,
+                   created_at
+            FROM audit_events
+            WHERE ($1::uuid IS NULL OR actor_id = $1)
+              AND ($2::varchar IS NULL OR action = $2)
+              AND ($3::varchar IS NULL OR entity_type = $3)
+              AND ($4::uuid IS NULL OR entity_id = $4)
+              AND ($5::varchar IS NULL OR request_id = $5)
+              AND ($6::timestamptz IS NULL OR created_at >= $6)
+              AND ($7::timestamptz IS NULL OR created_at < $7)
+            ORDER BY created_at DESC, id
+            LIMIT $8 OFFSET $9
+            "#,
+        )
+        .bind(filter.actor_id)
+        .bind(filter.action)
+        .bind(filter.entity_type)
+        .bind(filter.entity_id)
+        .bind(filter.request_id)
+        .bind(filter.since)
+        .bind(filter.until)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(events)
+    }
+}
+
+pub struct NewAuditEvent {
+    pub actor_id: Option<Uuid>,
+    pub action: String,
+    pub entity_type: Option<&'static str>,
+    pub entity_id: Option<Uuid>,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub request_id: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl NewAuditEvent {
+    pub async fn record(self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO audit_events
+                (actor_id, action, entity_type, entity_id, before, after, request_id, ip)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+        )
+        .bind(self.actor_id)
+        .bind(self.action)
+        .bind(self.entity_type)
+        .bind(self.entity_id)
+        .bind(self.before)
+        .bind(self.after)
+        .bind(self.request_id)
+        .bind(self.ip)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Columns left out of snapshots: derived from other columns and too noisy
+/// to be worth keeping.
+const IGNORED_COLUMNS: &[&str] = &[
+    "search_vector",
+    "fingerprint",
+    "fingerprint_bands",
+    "totp_last_step",
+];
+
+/// Columns whose changes are recorded without their values.
+const SECRET_COLUMNS: &[&str] = &["password", "totp_secret", "key_hash"];
+
+/// The row `id` of `table` as a JSON object, without the ignored columns.
+pub async fn snapshot(
+    pool: &PgPool,
+    table: &str,
+    id: Uuid,
+) -> Result<Option<serde_json::Map<String, Value>>, sqlx::Error> {
+    let query = format!("SELECT to_jsonb(t) FROM {} t WHERE id = $1", table);
+    let row = sqlx::query_scalar::<_, Value>(&query)
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.and_then(|row| match row {
+        Value::Object(mut fields) => {
+            fields.retain(|key, _| !IGNORED_COLUMNS.contains(&key.as_str()));
+            Some(fields)
+        }
+        _ => None,
+    }))
+}
+
+/// Restricts two snapshots of a row to the fields that differ, masking
+/// secrets. A missing snapshot, as for a creation or a hard delete, keeps
+/// the other whole.
+pub fn diff(
+    before: Option<serde_json::Map<String, Value>>,
+    after: Option<serde_json::Map<String, Value>>,
+) -> (Option<Value>, Option<Value>) {
+    let (mut before, mut after) = match (before, after) {
+        (Some(mut before), Some(mut after)) => {
+            let unchanged: Vec<String> = before
+                .iter()
+                .filter(|(key, value)| after.get(*key) == Some(*value))
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in &unchanged {
+                before.remove(key);
+                after.remove(key);
+            }
+            (Some(before), Some(after))
+        }
+        (before, after) => (before, after),
+    };
+    for fields in [&mut before, &mut after].into_iter().flatten() {
+        for key in SECRET_COLUMNS {
+            if let Some(value) = fields.get_mut(*key) {
+                *value = Value::String("[redacted]".to_string());
+            }
+        }
+    }
+
+    (before.map(Value::Object), after.map(Value::Object))
+}
@@ -0,0 +1,204 @@
+use super::entity::{NewAuditEvent, diff, snapshot};
+use crate::app::middlewares::RequestMeta;
+use crate::auth::viewer::Viewer;
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextParseQuery, NextResolve, ResolveInfo,
+};
+use async_graphql::parser::types::ExecutableDocument;
+use async_graphql::{ServerResult, Value, Variables};
+use sqlx::PgPool;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use uuid::Uuid;
+
+/// Entities a mutation can target, with their table.
+const ENTITIES: &[(&str, &str)] = &[
+    ("user", "users"),
+    ("prompt", "prompts"),
+    ("haiku", "haikus"),
+    ("tag", "tags"),
+    ("kigo", "kigo"),
+    ("collection", "collections"),
+    ("share", "haiku_shares"),
+    ("batch", "batches"),
+    ("job", "jobs"),
+    ("key", "api_keys"),
+];
+
+/// Records every top-level mutation field that succeeds in `audit_events`,
+/// along with the fields it changed on the entity it targets.
+///
+/// The entity is the last one named in the mutation, e.g. the collection in
+/// `addHaikuToCollection`, or else the one its `kind` argument names. Its id
+/// is the `id` argument, the `<entity>Id` one, or the `id` of the result
+/// when the mutation returns the entity and the request selects it.
+pub struct Audit;
+
+impl ExtensionFactory for Audit {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(AuditExtension::default())
+    }
+}
+
+#[derive(Default)]
+struct AuditExtension {
+    variables: Mutex<Variables>,
+}
+
+#[async_graphql::async_trait::async_trait]
+impl Extension for AuditExtension {
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> ServerResult<ExecutableDocument> {
+        *self.variables() = variables.clone();
+        next.run(ctx, query, variables).await
+    }
+
+    async fn resolve(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        info: ResolveInfo<'_>,
+        next: NextResolve<'_>,
+    ) -> ServerResult<Option<Value>> {
+        let is_mutation = ctx.schema_env.registry.mutation_type.as_deref()
+            == Some(info.parent_type)
+            && info.path_node.parent.is_none();
+        let Some(pool) = ctx.data_opt::<PgPool>().filter(|_| is_mutation) else {
+            return next.run(ctx, info).await;
+        };
+
+        let action = info.name.to_string();
+        let arguments = self.arguments(&info);
+        let entity = entity_of(&action, &arguments);
+        let mut entity_id = entity.and_then(|(name, _)| id_of(name, &arguments));
+        let returns_entity = entity.is_some_and(|(name, _)| {
+            info.return_type
+                .trim_end_matches('!')
+                .eq_ignore_ascii_case(name)
+        });
+        let before = match (entity, entity_id) {
+            (Some((_, table)), Some(id)) => snapshot(pool, table, id).await.ok().flatten(),
+            _ => None,
+        };
+
+        let result = next.run(ctx, info).await;
+        let Ok(value) = &result else {
+            return result;
+        };
+
+        if entity_id.is_none() && returns_entity {
+            entity_id = value.as_ref().and_then(result_id);
+        }
+        let after = match (entity, entity_id) {
+            (Some((_, table)), Some(id)) => snapshot(pool, table, id).await.ok().flatten(),
+            _ => None,
+        };
+        let (before, after) = diff(before, after);
+        let meta = ctx.data_opt::<RequestMeta>();
+        let event = NewAuditEvent {
+            actor_id: ctx.data_opt::<Viewer>().map(|viewer| viewer.user_id),
+            action: action.clone(),
+            entity_type: entity.map(|(name, _)| name),
+            entity_id,
+            before,
+            after,
+            request_id: meta.map(|meta| meta.request_id.clone()),
+            ip: meta.and_then(|meta| meta.ip.clone()),
+        };
+        if let Err(err) = event.record(pool).await {
+            tracing::error!(error = %err, action = %action, "Failed to record audit event");
+        }
+
+        result
+    }
+}
+
+impl AuditExtension {
+    /// The variables of the request. Nothing panics while they are held,
+    /// and even then they are replaced whole, so a poisoned lock is used as
+    /// is.
+    fn variables(&self) -> MutexGuard<'_, Variables> {
+        self.variables
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Arguments of the field with their variables substituted.
+    fn arguments(&self, info: &ResolveInfo<'_>) -> Vec<(String, Value)> {
+        let variables = self.variables();
+        info.field
+            .arguments
+            .iter()
+            .filter_map(|(name, value)| {
+                let value = value
+                    .node
+                    .clone()
+                    .into_const_with(|name| variables.get(&name).cloned().ok_or(()))
+                    .ok()?;
+                Some((name.node.to_string(), value))
+            })
+            .collect()
+    }
+}
+
+fn entity_of(action: &str, arguments: &[(String, Value)]) -> Option<(&'static str, &'static str)> {
+    let find = |word: &str| {
+        let word = word.to_lowercase();
+        ENTITIES
+            .iter()
+            .find(|(name, _)| word == *name || word.strip_suffix('s') == Some(*name))
+            .copied()
+    };
+
+    // The first word is the verb, as in `tagHaiku`.
+    let named = words(action)
+        .into_iter()
+        .skip(1)
+        .rev()
+        .find_map(|word| find(&word));
+    named.or_else(|| match argument(arguments, "kind") {
+        Some(Value::Enum(kind)) => find(kind.as_str()),
+        Some(Value::String(kind)) => find(kind),
+        _ => None,
+    })
+}
+
+fn id_of(entity: &str, arguments: &[(String, Value)]) -> Option<Uuid> {
+    let id = argument(arguments, "id").or_else(|| argument(arguments, &format!("{}Id", entity)));
+    match id {
+        Some(Value::String(id)) => id.parse().ok(),
+        _ => None,
+    }
+}
+
+fn result_id(value: &Value) -> Option<Uuid> {
+    match value {
+        Value::Object(fields) => match fields.get("id") {
+            Some(Value::String(id)) => id.parse().ok(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn argument<'a>(arguments: &'a [(String, Value)], name: &str) -> Option<&'a Value> {
+    arguments
+        .iter()
+        .find(|(argument, _)| argument == name)
+        .map(|(_, value)| value)
+}
+
+/// Splits a camelCase name into its words.
+fn words(name: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    for c in name.chars() {
+        match words.last_mut() {
+            Some(word) if !c.is_uppercase() => word.push(c),
+            _ => words.push(c.to_string()),
+        }
+    }
+    words
+}
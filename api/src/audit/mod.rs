@@ -0,0 +1,4 @@
+pub mod entity;
+pub mod extension;
+pub mod resolver;
+pub mod schema;
@@ -0,0 +1,23 @@
+use super::entity::{AuditEvent, AuditFilter};
+use crate::auth::viewer::Viewer;
+use async_graphql::Context;
+use sqlx::PgPool;
+
+pub struct QueryRoot;
+
+#[async_graphql::Object]
+impl QueryRoot {
+    /// Recorded mutations matching `filter`, newest first. Admin only.
+    async fn audit_log(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] filter: AuditFilter,
+        #[graphql(default = 50, validator(minimum = 1, maximum = 500))] limit: i64,
+        #[graphql(default = 0, validator(minimum = 0))] offset: i64,
+    ) -> async_graphql::Result<Vec<AuditEvent>> {
+        Viewer::require_admin(ctx).await?;
+        let pool = ctx.data::<PgPool>()?;
+        let events = AuditEvent::list(pool, filter, limit, offset).await?;
+        Ok(events)
+    }
+}
@@ -0,0 +1,13 @@
+pub(crate) use super::resolver::QueryRoot;
+use crate::app::error::ErrorCodes;
+use async_graphql::{EmptyMutation, EmptySubscription, Schema};
+use sqlx::PgPool;
+
+pub type AuditSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+pub fn create_schema(pool: PgPool) -> AuditSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(pool)
+        .extension(ErrorCodes)
+        .finish()
+}
@@ -0,0 +1,91 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use std::sync::Arc;
+
+/// Length of the nonce prepended to every sealed secret.
+const NONCE_LEN: usize = 12;
+
+/// Encrypts secrets kept in the database, such as TOTP seeds, with
+/// AES-256-GCM under a key from the config.
+#[derive(Clone)]
+pub struct SecretCipher {
+    cipher: Arc<Aes256Gcm>,
+}
+
+impl SecretCipher {
+    /// Takes the key as 64 hex digits, as printed by `openssl rand -hex 32`.
+    pub fn from_hex(key: &str) -> Option<Self> {
+        let key = hex::decode(key.trim()).ok()?;
+        if key.len() != 32 {
+            return None;
+        }
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+        Some(Self {
+            cipher: Arc::new(cipher),
+        })
+    }
+
+    /// The nonce followed by the ciphertext.
+    pub fn seal(&self, secret: &[u8]) -> Vec<u8> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, secret)
+            .expect("AES-GCM encryption does not fail on in-memory buffers");
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        sealed
+    }
+
+    /// `None` if `sealed` was not sealed with this key or was tampered with.
+    pub fn open(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    #[test]
+    fn opens_what_it_sealed() {
+        let cipher = SecretCipher::from_hex(KEY).unwrap();
+        let sealed = cipher.seal(b"totp seed");
+        assert_ne!(&sealed[NONCE_LEN..], b"totp seed");
+        assert_eq!(cipher.open(&sealed).as_deref(), Some(&b"totp seed"[..]));
+    }
+
+    #[test]
+    fn seals_with_a_fresh_nonce_every_time() {
+        let cipher = SecretCipher::from_hex(KEY).unwrap();
+        assert_ne!(cipher.seal(b"totp seed"), cipher.seal(b"totp seed"));
+    }
+
+    #[test]
+    fn refuses_other_keys_and_tampered_secrets() {
+        let cipher = SecretCipher::from_hex(KEY).unwrap();
+        let other = SecretCipher::from_hex(&KEY.replace('0', "f")).unwrap();
+        let mut sealed = cipher.seal(b"totp seed");
+        assert_eq!(other.open(&sealed), None);
+
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert_eq!(cipher.open(&sealed), None);
+        assert_eq!(cipher.open(&sealed[..NONCE_LEN - 1]), None);
+    }
+
+    #[test]
+    fn takes_only_32_byte_hex_keys() {
+        assert!(SecretCipher::from_hex(&format!(" {KEY}\n")).is_some());
+        assert!(SecretCipher::from_hex(&KEY[2..]).is_none());
+        assert!(SecretCipher::from_hex(&KEY.replace('0', "g")).is_none());
+    }
+}
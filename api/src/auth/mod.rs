@@ -0,0 +1,8 @@
+pub mod cipher;
+pub mod oidc;
+pub mod password;
+pub mod throttle;
+pub mod token;
+pub mod totp;
+pub mod two_factor;
+pub mod viewer;
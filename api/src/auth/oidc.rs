@@ -0,0 +1,362 @@
+//! Sign-in through an OpenID Connect provider, with the authorization code
+//! flow and PKCE. The provider's endpoints and signing keys come from its
+//! discovery document, fetched on first use.
+
+use crate::app::error::AppError;
+use crate::users::entity::UserRole;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use rand::RngCore;
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::RwLock;
+
+/// How long a user has to come back from the provider.
+const LOGIN_TTL_MINS: i64 = 10;
+
+#[derive(Debug, Error)]
+pub enum OidcError {
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Invalid provider metadata: {0}")]
+    Discovery(String),
+    /// The provider refused the code, e.g. because it expired.
+    #[error("Token request failed: {0}")]
+    Provider(String),
+    #[error("Invalid ID token: {0}")]
+    IdToken(String),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl From<OidcError> for AppError {
+    /// Refused codes and tokens fail the sign-in; an unreachable or
+    /// misconfigured provider is on the server.
+    fn from(err: OidcError) -> Self {
+        match err {
+            OidcError::Database(err) => AppError::from(err),
+            OidcError::Provider(_) | OidcError::IdToken(_) => {
+                tracing::warn!(error = %err, "OIDC sign-in rejected");
+                AppError::ExternalLoginFailed
+            }
+            err => AppError::Internal(err.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OidcSettings {
+    /// Discovery happens at `<issuer>/.well-known/openid-configuration`.
+    pub issuer: String,
+    pub client_id: String,
+    /// `None` for public clients, which PKCE is enough for.
+    pub client_secret: Option<String>,
+    /// Page of the app the provider sends users back to, which calls
+    /// `oidcLogin` with the `code` and `state` it receives.
+    pub redirect_url: String,
+    pub scopes: String,
+    /// Claim holding the user's groups or roles, if roles come from the
+    /// provider.
+    pub role_claim: Option<String>,
+    /// Claim values and the role they grant, the first match winning.
+    pub role_map: Vec<(String, UserRole)>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Metadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+/// The claims of a validated ID token that sign-in relies on.
+#[derive(Debug, Deserialize)]
+pub struct IdClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub name: Option<String>,
+    nonce: Option<String>,
+    #[serde(flatten)]
+    other: HashMap<String, serde_json::Value>,
+}
+
+/// Where to send the user to sign in, with what to check when they are
+/// back.
+struct Authorization {
+    url: String,
+    state: String,
+    nonce: String,
+    code_verifier: String,
+}
+
+#[derive(Clone)]
+pub struct OidcClient {
+    settings: Arc<OidcSettings>,
+    http: Client,
+    metadata: Arc<RwLock<Option<Metadata>>>,
+    jwks: Arc<RwLock<Option<JwkSet>>>,
+}
+
+impl OidcClient {
+    pub fn new(settings: OidcSettings) -> Self {
+        Self {
+            settings: Arc::new(settings),
+            http: Client::new(),
+            metadata: Arc::new(RwLock::new(None)),
+            jwks: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    pub fn settings(&self) -> &OidcSettings {
+        &self.settings
+    }
+
+    /// The provider's metadata, fetched once. Failures are not cached, so
+    /// that a provider down at startup does not need a restart.
+    async fn metadata(&self) -> Result<Metadata, OidcError> {
+        if let Some(metadata) = self.metadata.read().await.as_ref() {
+            return Ok(metadata.clone());
+        }
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.settings.issuer.trim_end_matches('/')
+        );
+        let metadata: Metadata = self
+            .http
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if metadata.issuer.trim_end_matches('/') != self.settings.issuer.trim_end_matches('/') {
+            return Err(OidcError::Discovery(format!(
+                "issuer `{}` does not match the configured one",
+                metadata.issuer
+            )));
+        }
+        *self.metadata.write().await = Some(metadata.clone());
+        Ok(metadata)
+    }
+
+    /// The provider's signing keys, fetched again on `refresh` for keys
+    /// rotated in since.
+    async fn jwks(&self, metadata: &Metadata, refresh: bool) -> Result<JwkSet, OidcError> {
+        if !refresh && let Some(jwks) = self.jwks.read().await.as_ref() {
+            return Ok(jwks.clone());
+        }
+        let jwks: JwkSet = self
+            .http
+            .get(&metadata.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        *self.jwks.write().await = Some(jwks.clone());
+        Ok(jwks)
+    }
+
+    /// Starts a sign-in, remembering its state, nonce and PKCE verifier
+    /// until [`OidcClient::finish`], and returns the provider URL to send
+    /// the user to.
+    pub async fn start(&self, pool: &PgPool) -> Result<String, OidcError> {
+        let metadata = self.metadata().await?;
+        let code_verifier = random_token();
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+        let state = random_token();
+        let nonce = random_token();
+
+        let mut url = Url::parse(&metadata.authorization_endpoint)
+            .map_err(|err| OidcError::Discovery(err.to_string()))?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.settings.client_id)
+            .append_pair("redirect_uri", &self.settings.redirect_url)
+            .append_pair("scope", &self.settings.scopes)
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        let authorization = Authorization {
+            url: url.to_string(),
+            state,
+            nonce,
+            code_verifier,
+        };
+        save_login(pool, &authorization).await?;
+        Ok(authorization.url)
+    }
+
+    /// Exchanges the `code` the provider sent the user back with for their
+    /// validated ID token claims. `None` if `state` is not one of an
+    /// unfinished sign-in.
+    pub async fn finish(
+        &self,
+        pool: &PgPool,
+        code: &str,
+        state: &str,
+    ) -> Result<Option<IdClaims>, OidcError> {
+        let Some((code_verifier, nonce)) = take_login(pool, state).await? else {
+            return Ok(None);
+        };
+        let metadata = self.metadata().await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.settings.redirect_url),
+            ("client_id", &self.settings.client_id),
+            ("code_verifier", &code_verifier),
+        ];
+        if let Some(secret) = &self.settings.client_secret {
+            form.push(("client_secret", secret));
+        }
+        let response: TokenResponse = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await?
+            .json()
+            .await?;
+        if let Some(error) = response.error {
+            let description = response.error_description.unwrap_or_default();
+            return Err(OidcError::Provider(format!("{} {}", error, description)));
+        }
+        let id_token = response
+            .id_token
+            .ok_or_else(|| OidcError::Provider("no ID token in the response".to_string()))?;
+
+        let claims = self.validate(&metadata, &id_token).await?;
+        if claims.nonce.as_deref() != Some(nonce.as_str()) {
+            return Err(OidcError::IdToken("nonce mismatch".to_string()));
+        }
+        Ok(Some(claims))
+    }
+
+    /// Checks the ID token's signature against the provider's keys, and its
+    /// issuer, audience and expiry.
+    async fn validate(&self, metadata: &Metadata, id_token: &str) -> Result<IdClaims, OidcError> {
+        let invalid = |err: jsonwebtoken::errors::Error| OidcError::IdToken(err.to_string());
+        let header = jsonwebtoken::decode_header(id_token).map_err(invalid)?;
+        // Shared secret algorithms would take the client secret as the key.
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(OidcError::IdToken(format!(
+                "unsupported algorithm {:?}",
+                header.alg
+            )));
+        }
+
+        let mut jwk = None;
+        for refresh in [false, true] {
+            let jwks = self.jwks(metadata, refresh).await?;
+            jwk = match &header.kid {
+                Some(kid) => jwks.find(kid).cloned(),
+                None if jwks.keys.len() == 1 => jwks.keys.first().cloned(),
+                None => None,
+            };
+            if jwk.is_some() {
+                break;
+            }
+        }
+        let jwk = jwk.ok_or_else(|| OidcError::IdToken("unknown signing key".to_string()))?;
+        let key = DecodingKey::from_jwk(&jwk).map_err(invalid)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&self.settings.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let data =
+            jsonwebtoken::decode::<IdClaims>(id_token, &key, &validation).map_err(invalid)?;
+        Ok(data.claims)
+    }
+
+    /// The role `claims` grant, or `None` if roles are not managed by the
+    /// provider. Users matching no entry of the map are plain users.
+    pub fn role(&self, claims: &IdClaims) -> Option<UserRole> {
+        let claim = self.settings.role_claim.as_ref()?;
+        let values: Vec<&str> = match claims.other.get(claim) {
+            Some(serde_json::Value::String(value)) => vec![value.as_str()],
+            Some(serde_json::Value::Array(values)) => {
+                values.iter().filter_map(|value| value.as_str()).collect()
+            }
+            _ => Vec::new(),
+        };
+        let role = self
+            .settings
+            .role_map
+            .iter()
+            .find(|(value, _)| values.contains(&value.as_str()))
+            .map(|(_, role)| *role);
+        Some(role.unwrap_or(UserRole::User))
+    }
+}
+
+/// 32 random bytes, base64url encoded, which also makes a valid PKCE
+/// verifier.
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+async fn save_login(pool: &PgPool, authorization: &Authorization) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM oidc_logins WHERE expires_at <= now()")
+        .execute(pool)
+        .await?;
+    sqlx::query(
+        r#"
+        INSERT INTO oidc_logins (state, code_verifier, nonce, expires_at)
+        VALUES ($1, $2, $3, now() + make_interval(mins => $4))
+        "#,
+    )
+    .bind(&authorization.state)
+    .bind(&authorization.code_verifier)
+    .bind(&authorization.nonce)
+    .bind(LOGIN_TTL_MINS as i32)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// The PKCE verifier and nonce of an unexpired sign-in, which can only be
+/// finished once.
+async fn take_login(pool: &PgPool, state: &str) -> Result<Option<(String, String)>, sqlx::Error> {
+    let login = sqlx::query_as::<_, (String, String)>(
+        r#"
+        DELETE FROM oidc_logins
+        WHERE state = $1 AND expires_at > now()
+        RETURNING code_verifier, nonce
+        "#,
+    )
+    .bind(state)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(login)
+}
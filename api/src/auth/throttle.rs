@@ -0,0 +1,413 @@
+use crate::app::error::AppError;
+use crate::app::middlewares::RequestMeta;
+use crate::audit::entity::NewAuditEvent;
+use crate::users::entity::normalize_email;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::json;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+/// What failed logins, or password reset requests, are counted against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+enum ThrottleKind {
+    /// The email tried, whether an account has it or not, so that lockouts
+    /// say nothing about which ones exist.
+    Account,
+    Ip,
+    ResetAccount,
+    ResetIp,
+}
+
+/// Password reset emails sent to an address, and requested from a client
+/// address, within [`RESET_WINDOW_MINS`].
+const MAX_RESETS_PER_ACCOUNT: i32 = 3;
+const MAX_RESETS_PER_IP: i32 = 20;
+const RESET_WINDOW_MINS: i64 = 60;
+
+/// Limits on failed logins. Each failure makes the next attempt wait
+/// longer, and enough of them within the window lock the account or
+/// address out for a while.
+#[derive(Debug, Clone)]
+pub struct LoginPolicy {
+    /// Failures that lock an account, 0 for never.
+    pub max_account_failures: i32,
+    /// Failures that lock an address, 0 for never.
+    pub max_ip_failures: i32,
+    /// How long failures are counted for.
+    pub window: Duration,
+    pub lockout: Duration,
+    /// Wait after a first failure, doubled with each further one.
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl LoginPolicy {
+    fn max_failures(&self, kind: ThrottleKind) -> i32 {
+        match kind {
+            ThrottleKind::Account => self.max_account_failures,
+            ThrottleKind::Ip => self.max_ip_failures,
+            // Reset requests are refused for the rest of their window
+            // rather than locked out.
+            ThrottleKind::ResetAccount | ThrottleKind::ResetIp => 0,
+        }
+    }
+
+    fn delay(&self, failures: i32) -> Duration {
+        if failures < 1 {
+            return Duration::zero();
+        }
+        let factor = 1i64 << (failures - 1).min(30);
+        let delay =
+            Duration::milliseconds(self.base_delay.num_milliseconds().saturating_mul(factor));
+        delay.min(self.max_delay)
+    }
+}
+
+#[derive(FromRow)]
+struct Throttle {
+    failures: i32,
+    window_started_at: DateTime<Utc>,
+    last_failure_at: DateTime<Utc>,
+    locked_until: Option<DateTime<Utc>>,
+}
+
+/// A login with a password, from an address for an email.
+pub struct LoginAttempt {
+    email: String,
+    ip: Option<String>,
+    request_id: Option<String>,
+}
+
+impl LoginAttempt {
+    pub fn new(email: &str, meta: Option<&RequestMeta>) -> Self {
+        Self {
+            email: normalize_email(email),
+            ip: meta.and_then(|meta| meta.ip.clone()),
+            request_id: meta.map(|meta| meta.request_id.clone()),
+        }
+    }
+
+    fn subjects(&self) -> Vec<(ThrottleKind, &str)> {
+        let mut subjects = vec![(ThrottleKind::Account, self.email.as_str())];
+        if let Some(ip) = &self.ip {
+            subjects.push((ThrottleKind::Ip, ip.as_str()));
+        }
+        subjects
+    }
+
+    /// Counts the attempt as a failure before the password is even checked,
+    /// so that concurrent guesses cannot all pass the limits, or refuses it
+    /// while the account or address is locked out, has to wait after a
+    /// failure or has used up its attempts. [`Self::succeeded`] takes the
+    /// attempt back.
+    pub async fn reserve(&self, pool: &PgPool, policy: &LoginPolicy) -> Result<(), AppError> {
+        let now = Utc::now();
+        let mut retry_at = now;
+        let mut tx = pool.begin().await?;
+        for (kind, subject) in self.subjects() {
+            // Locking the row, created if need be, makes attempts on one
+            // subject take turns.
+            sqlx::query(
+                r#"
+                INSERT INTO login_throttles (kind, subject)
+                VALUES ($1, $2)
+                ON CONFLICT (kind, subject) DO NOTHING
+                "#,
+            )
+            .bind(kind)
+            .bind(subject)
+            .execute(&mut *tx)
+            .await?;
+            let throttle = sqlx::query_as::<_, Throttle>(
+                r#"
+                SELECT failures, window_started_at, last_failure_at, locked_until
+                FROM login_throttles
+                WHERE kind = $1 AND subject = $2
+                FOR UPDATE
+                "#,
+            )
+            .bind(kind)
+            .bind(subject)
+            .fetch_one(&mut *tx)
+            .await?;
+            if let Some(locked_until) = throttle.locked_until {
+                retry_at = retry_at.max(locked_until);
+            }
+            if throttle.failures > 0 && throttle.window_started_at > now - policy.window {
+                retry_at = retry_at.max(throttle.last_failure_at + policy.delay(throttle.failures));
+                let max = policy.max_failures(kind);
+                if max > 0 && throttle.failures >= max {
+                    // Attempts still being checked; the last of them locks
+                    // the subject out if it fails.
+                    retry_at = retry_at.max(now + Duration::seconds(1));
+                }
+            }
+        }
+        if retry_at <= now {
+            for (kind, subject) in self.subjects() {
+                sqlx::query(
+                    r#"
+                    UPDATE login_throttles
+                    SET failures = CASE
+                            WHEN window_started_at <= now() - make_interval(secs => $3)
+                            THEN 1 ELSE failures + 1
+                        END,
+                        window_started_at = CASE
+                            WHEN window_started_at <= now() - make_interval(secs => $3)
+                            THEN now() ELSE window_started_at
+                        END,
+                        last_failure_at = now()
+                    WHERE kind = $1 AND subject = $2
+                    "#,
+                )
+                .bind(kind)
+                .bind(subject)
+                .bind(policy.window.num_seconds() as f64)
+                .execute(&mut *tx)
+                .await?;
+            }
+            tx.commit().await?;
+            return Ok(());
+        }
+        tx.commit().await?;
+
+        let retry_after = (((retry_at - now).num_milliseconds() + 999) / 1000).max(1);
+        self.audit(pool, "loginThrottled", json!({ "retryAfter": retry_after }))
+            .await;
+        Err(AppError::TooManyAttempts { retry_after })
+    }
+
+    /// Confirms the reserved attempt as a wrong password, locking the
+    /// account and the address out once they reach their maximum.
+    pub async fn failed(&self, pool: &PgPool, policy: &LoginPolicy) -> Result<(), sqlx::Error> {
+        let mut locked = Vec::new();
+        let mut account_failures = 0;
+        for (kind, subject) in self.subjects() {
+            let failures = sqlx::query_scalar::<_, i32>(
+                "SELECT failures FROM login_throttles WHERE kind = $1 AND subject = $2",
+            )
+            .bind(kind)
+            .bind(subject)
+            .fetch_optional(pool)
+            .await?
+            .unwrap_or_default();
+            if kind == ThrottleKind::Account {
+                account_failures = failures;
+            }
+
+            let max = policy.max_failures(kind);
+            if max > 0 && failures >= max {
+                // The lockout replaces the count, so delays start over
+                // once it ends.
+                let result = sqlx::query(
+                    r#"
+                    UPDATE login_throttles
+                    SET failures = 0, window_started_at = now(),
+                        locked_until = now() + make_interval(secs => $3)
+                    WHERE kind = $1 AND subject = $2 AND failures >= $4
+                    "#,
+                )
+                .bind(kind)
+                .bind(subject)
+                .bind(policy.lockout.num_seconds() as f64)
+                .bind(max)
+                .execute(pool)
+                .await?;
+                if result.rows_affected() > 0 {
+                    locked.push(kind);
+                }
+            }
+        }
+
+        self.audit(pool, "loginFailed", json!({ "failures": account_failures }))
+            .await;
+        let lockout = json!({ "minutes": policy.lockout.num_minutes() });
+        if locked.contains(&ThrottleKind::Account) {
+            self.audit(pool, "accountLocked", lockout.clone()).await;
+        }
+        if locked.contains(&ThrottleKind::Ip) {
+            self.audit(pool, "addressLocked", lockout).await;
+        }
+        Ok(())
+    }
+
+    /// Forgets the account's failures, and takes the reserved attempt back
+    /// from the address. The address keeps its other failures, so that one
+    /// valid account does not cover for guesses at others.
+    pub async fn succeeded(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        unlock(pool, &self.email).await?;
+        if let Some(ip) = &self.ip {
+            sqlx::query(
+                r#"
+                UPDATE login_throttles
+                SET failures = GREATEST(failures - 1, 0)
+                WHERE kind = $1 AND subject = $2
+                "#,
+            )
+            .bind(ThrottleKind::Ip)
+            .bind(ip)
+            .execute(pool)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Records `action` against the user with the email, if any. Failing to
+    /// is only logged, like for mutations.
+    async fn audit(&self, pool: &PgPool, action: &str, details: serde_json::Value) {
+        let user_id = sqlx::query_scalar::<_, Uuid>(
+            "SELECT id FROM users WHERE lower(email) = $1 AND deleted_at IS NULL",
+        )
+        .bind(&self.email)
+        .fetch_optional(pool)
+        .await
+        .ok()
+        .flatten();
+        let mut after = json!({ "email": self.email });
+        if let (Some(after), serde_json::Value::Object(details)) = (after.as_object_mut(), details)
+        {
+            after.extend(details);
+        }
+        let event = NewAuditEvent {
+            actor_id: None,
+            action: action.to_string(),
+            entity_type: user_id.map(|_| "user"),
+            entity_id: user_id,
+            before: None,
+            after: Some(after),
+            request_id: self.request_id.clone(),
+            ip: self.ip.clone(),
+        };
+        if let Err(err) = event.record(pool).await {
+            tracing::error!(%action, error = %err, "Failed to record audit event");
+        }
+    }
+}
+
+/// A request for a password reset email, from an address for an email.
+pub struct ResetRequest {
+    email: String,
+    ip: Option<String>,
+}
+
+impl ResetRequest {
+    pub fn new(email: &str, meta: Option<&RequestMeta>) -> Self {
+        Self {
+            email: normalize_email(email),
+            ip: meta.and_then(|meta| meta.ip.clone()),
+        }
+    }
+
+    /// Counts the request against the email and the address, refusing it
+    /// once either has had its share for the window. Known and unknown
+    /// emails are counted alike.
+    pub async fn reserve(&self, pool: &PgPool) -> Result<(), AppError> {
+        let mut subjects = vec![(
+            ThrottleKind::ResetAccount,
+            self.email.as_str(),
+            MAX_RESETS_PER_ACCOUNT,
+        )];
+        if let Some(ip) = &self.ip {
+            subjects.push((ThrottleKind::ResetIp, ip.as_str(), MAX_RESETS_PER_IP));
+        }
+        let window = Duration::minutes(RESET_WINDOW_MINS);
+        let now = Utc::now();
+        let mut retry_at = now;
+        for (kind, subject, max) in subjects {
+            let (requests, window_started_at) = sqlx::query_as::<_, (i32, DateTime<Utc>)>(
+                r#"
+                INSERT INTO login_throttles (kind, subject, failures)
+                VALUES ($1, $2, 1)
+                ON CONFLICT (kind, subject) DO UPDATE
+                SET failures = CASE
+                        WHEN login_throttles.window_started_at <= now() - make_interval(secs => $3)
+                        THEN 1 ELSE login_throttles.failures + 1
+                    END,
+                    window_started_at = CASE
+                        WHEN login_throttles.window_started_at <= now() - make_interval(secs => $3)
+                        THEN now() ELSE login_throttles.window_started_at
+                    END,
+                    last_failure_at = now()
+                RETURNING failures, window_started_at
+                "#,
+            )
+            .bind(kind)
+            .bind(subject)
+            .bind(window.num_seconds() as f64)
+            .fetch_one(pool)
+            .await?;
+            if requests > max {
+                retry_at = retry_at.max(window_started_at + window);
+            }
+        }
+        if retry_at <= now {
+            return Ok(());
+        }
+
+        let retry_after = ((retry_at - now).num_milliseconds() + 999) / 1000;
+        Err(AppError::TooManyAttempts { retry_after })
+    }
+}
+
+/// Lifts a lockout of the account with `email` and forgets its failures.
+pub async fn unlock(pool: &PgPool, email: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM login_throttles WHERE kind = $1 AND subject = $2")
+        .bind(ThrottleKind::Account)
+        .bind(normalize_email(email))
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> LoginPolicy {
+        LoginPolicy {
+            max_account_failures: 5,
+            max_ip_failures: 0,
+            window: Duration::minutes(15),
+            lockout: Duration::minutes(15),
+            base_delay: Duration::seconds(1),
+            max_delay: Duration::seconds(30),
+        }
+    }
+
+    #[test]
+    fn delay_doubles_up_to_the_maximum() {
+        let policy = policy();
+        let delays: Vec<i64> = (0..8)
+            .map(|failures| policy.delay(failures).num_seconds())
+            .collect();
+        assert_eq!(delays, [0, 1, 2, 4, 8, 16, 30, 30]);
+        assert_eq!(policy.delay(-1), Duration::zero());
+        assert_eq!(policy.delay(i32::MAX), Duration::seconds(30));
+    }
+
+    #[test]
+    fn resets_are_never_locked_out() {
+        let policy = policy();
+        assert_eq!(policy.max_failures(ThrottleKind::Account), 5);
+        assert_eq!(policy.max_failures(ThrottleKind::Ip), 0);
+        assert_eq!(policy.max_failures(ThrottleKind::ResetAccount), 0);
+        assert_eq!(policy.max_failures(ThrottleKind::ResetIp), 0);
+    }
+
+    #[test]
+    fn attempts_count_against_the_normalized_email() {
+        let attempt = LoginAttempt::new("  Basho@Example.COM ", None);
+        assert_eq!(
+            attempt.subjects(),
+            [(ThrottleKind::Account, "basho@example.com")]
+        );
+        let meta = RequestMeta {
+            request_id: "req".to_string(),
+            ip: Some("203.0.113.7".to_string()),
+        };
+        let attempt = LoginAttempt::new("basho@example.com", Some(&meta));
+        assert_eq!(attempt.subjects()[1], (ThrottleKind::Ip, "203.0.113.7"));
+    }
+}
@@ -0,0 +1,59 @@
+use chrono::Utc;
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: Uuid,
+    pub iat: i64,
+    pub exp: i64,
+    /// The user's token generation when the token was issued.
+    #[serde(default, rename = "gen")]
+    pub generation: i32,
+    /// Whether the user passed a second factor to get the token.
+    #[serde(default)]
+    pub mfa: bool,
+}
+
+#[derive(Clone)]
+pub struct TokenKeys {
+    encoding: Arc<EncodingKey>,
+    decoding: Arc<DecodingKey>,
+    ttl_secs: i64,
+}
+
+impl TokenKeys {
+    pub fn new(secret: &str, ttl_secs: i64) -> Self {
+        Self {
+            encoding: Arc::new(EncodingKey::from_secret(secret.as_bytes())),
+            decoding: Arc::new(DecodingKey::from_secret(secret.as_bytes())),
+            ttl_secs,
+        }
+    }
+
+    pub fn issue(
+        &self,
+        user_id: Uuid,
+        generation: i32,
+        mfa: bool,
+    ) -> Result<String, jsonwebtoken::errors::Error> {
+        let now = Utc::now().timestamp();
+        let claims = Claims {
+            sub: user_id,
+            iat: now,
+            exp: now + self.ttl_secs,
+            generation,
+            mfa,
+        };
+
+        jsonwebtoken::encode(&Header::default(), &claims, &self.encoding)
+    }
+
+    pub fn verify(&self, token: &str) -> Option<Claims> {
+        jsonwebtoken::decode::<Claims>(token, &self.decoding, &Validation::default())
+            .map(|data| data.claims)
+            .ok()
+    }
+}
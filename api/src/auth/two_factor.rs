@@ -0,0 +1,298 @@
+use super::cipher::SecretCipher;
+use super::totp;
+use crate::app::error::AppError;
+use crate::users::entity::{User, UserRole};
+use async_graphql::SimpleObject;
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+/// How long the challenge returned by `login` can be answered for.
+pub const CHALLENGE_TTL_MINS: i64 = 5;
+/// Wrong codes after which the challenge is used up, and the user has to
+/// log in again.
+pub const CHALLENGE_MAX_FAILURES: i32 = 3;
+
+const RECOVERY_CODES: usize = 10;
+/// No look-alike characters, as the codes are meant to be written down.
+const RECOVERY_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// Roles that only get admin rights with a token obtained through a second
+/// factor. Only admin rights are guarded, so the configuration refuses any
+/// other role.
+#[derive(Debug, Clone)]
+pub struct TwoFactorPolicy {
+    pub roles: Vec<UserRole>,
+}
+
+impl TwoFactorPolicy {
+    pub fn requires(&self, role: UserRole) -> bool {
+        self.roles.contains(&role)
+    }
+}
+
+#[derive(Clone)]
+pub struct TwoFactorSettings {
+    pub cipher: SecretCipher,
+    /// Name authenticator apps list the account under.
+    pub issuer: String,
+}
+
+/// A secret to add to an authenticator app, pending confirmation.
+#[derive(Debug, SimpleObject)]
+pub struct TwoFactorEnrollment {
+    /// Base32, for typing into the app by hand.
+    pub secret: String,
+    /// For a QR code.
+    pub otpauth_uri: String,
+}
+
+#[derive(FromRow)]
+struct TotpState {
+    totp_secret: Option<Vec<u8>>,
+    totp_enabled_at: Option<DateTime<Utc>>,
+}
+
+impl TotpState {
+    async fn get(pool: &PgPool, user_id: Uuid) -> Result<TotpState, sqlx::Error> {
+        sqlx::query_as::<_, TotpState>(
+            r#"
+            SELECT totp_secret, totp_enabled_at
+            FROM users
+            WHERE id = $1 AND deleted_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+    }
+
+    fn secret(&self, settings: &TwoFactorSettings) -> Result<Vec<u8>, AppError> {
+        let sealed = self
+            .totp_secret
+            .as_deref()
+            .ok_or_else(|| AppError::validation("Call enrollTwoFactor first"))?;
+        settings.cipher.open(sealed).ok_or_else(|| {
+            AppError::Internal("TOTP secret does not open with the configured key".to_string())
+        })
+    }
+}
+
+/// Generates a new secret for `user`, replacing any pending one. It only
+/// takes effect once [`confirm`]ed with a code.
+pub async fn enroll(
+    pool: &PgPool,
+    settings: &TwoFactorSettings,
+    user: &User,
+) -> Result<TwoFactorEnrollment, AppError> {
+    if user.two_factor_enabled {
+        return Err(AppError::Conflict(
+            "Two-factor authentication is already enabled".to_string(),
+        ));
+    }
+    let secret = totp::generate_secret();
+    sqlx::query(
+        r#"
+        UPDATE users
+        SET totp_secret = $2, totp_last_step = NULL
+        WHERE id = $1 AND totp_enabled_at IS NULL
+        "#,
+    )
+    .bind(user.id)
+    .bind(settings.cipher.seal(&secret))
+    .execute(pool)
+    .await?;
+
+    Ok(TwoFactorEnrollment {
+        secret: totp::encode_secret(&secret),
+        otpauth_uri: totp::otpauth_uri(&settings.issuer, &user.email, &secret),
+    })
+}
+
+/// Turns two-factor authentication on once the app produces a valid code,
+/// returning fresh recovery codes. They are only ever shown here.
+pub async fn confirm(
+    pool: &PgPool,
+    settings: &TwoFactorSettings,
+    user_id: Uuid,
+    code: &str,
+) -> Result<Vec<String>, AppError> {
+    let state = TotpState::get(pool, user_id).await?;
+    if state.totp_enabled_at.is_some() {
+        return Err(AppError::Conflict(
+            "Two-factor authentication is already enabled".to_string(),
+        ));
+    }
+    let secret = state.secret(settings)?;
+    if !accept_totp(pool, user_id, &secret, code).await? {
+        return Err(AppError::validation("Invalid code"));
+    }
+
+    let codes: Vec<String> = (0..RECOVERY_CODES).map(|_| recovery_code()).collect();
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        r#"
+        UPDATE users
+        SET totp_enabled_at = now(), version = version + 1, updated_at = now()
+        WHERE id = $1
+        "#,
+    )
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        r#"
+        INSERT INTO recovery_codes (user_id, code_hash)
+        SELECT $1, unnest($2::varchar[])
+        "#,
+    )
+    .bind(user_id)
+    .bind(codes.iter().map(|code| hash(code)).collect::<Vec<_>>())
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(codes)
+}
+
+/// Whether `code` is a current code from the user's app or one of their
+/// unused recovery codes, using it up either way.
+pub async fn check(
+    pool: &PgPool,
+    settings: &TwoFactorSettings,
+    user_id: Uuid,
+    code: &str,
+) -> Result<bool, AppError> {
+    let state = TotpState::get(pool, user_id).await?;
+    if state.totp_enabled_at.is_none() {
+        return Ok(false);
+    }
+    if code.trim().chars().all(|c| c.is_ascii_digit()) {
+        let secret = state.secret(settings)?;
+        return Ok(accept_totp(pool, user_id, &secret, code).await?);
+    }
+
+    let used = sqlx::query(
+        r#"
+        UPDATE recovery_codes
+        SET used_at = now()
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+    )
+    .bind(user_id)
+    .bind(hash(code))
+    .execute(pool)
+    .await?;
+    Ok(used.rows_affected() > 0)
+}
+
+/// Turns two-factor authentication off and forgets the secret and the
+/// recovery codes.
+pub async fn disable(pool: &PgPool, user_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        r#"
+        UPDATE users
+        SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL,
+            version = version + 1, updated_at = now()
+        WHERE id = $1
+        "#,
+    )
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Checks a TOTP code and records its time step, so that neither it nor an
+/// older one is accepted again.
+async fn accept_totp(
+    pool: &PgPool,
+    user_id: Uuid,
+    secret: &[u8],
+    code: &str,
+) -> Result<bool, sqlx::Error> {
+    let Some(step) = totp::verify(secret, code, Utc::now().timestamp()) else {
+        return Ok(false);
+    };
+    let accepted = sqlx::query(
+        r#"
+        UPDATE users
+        SET totp_last_step = $2
+        WHERE id = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)
+        "#,
+    )
+    .bind(user_id)
+    .bind(step)
+    .execute(pool)
+    .await?;
+
+    Ok(accepted.rows_affected() > 0)
+}
+
+/// Like `k7m2p-9xq4z`.
+fn recovery_code() -> String {
+    let mut rng = rand::thread_rng();
+    let mut code: String = (0..10)
+        .map(|_| RECOVERY_ALPHABET[rng.gen_range(0..RECOVERY_ALPHABET.len())] as char)
+        .collect();
+    code.insert(5, '-');
+    code
+}
+
+/// Recovery codes are compared without case, spaces or dashes.
+fn hash(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery_codes_look_alike_free() {
+        for _ in 0..100 {
+            let code = recovery_code();
+            assert_eq!(code.len(), 11);
+            assert_eq!(code.as_bytes()[5], b'-');
+            assert!(
+                code.bytes()
+                    .filter(|byte| *byte != b'-')
+                    .all(|byte| RECOVERY_ALPHABET.contains(&byte))
+            );
+        }
+    }
+
+    #[test]
+    fn recovery_codes_ignore_case_spaces_and_dashes() {
+        assert_eq!(hash("k7m2p-9xq4z"), hash(" K7M2P 9XQ4Z "));
+        assert_eq!(hash("k7m2p-9xq4z"), hash("k7m2p9xq4z"));
+        assert_ne!(hash("k7m2p-9xq4z"), hash("k7m2p-9xq4y"));
+    }
+
+    #[test]
+    fn policy_requires_listed_roles() {
+        let policy = TwoFactorPolicy {
+            roles: vec![UserRole::Admin],
+        };
+        assert!(policy.requires(UserRole::Admin));
+        assert!(!policy.requires(UserRole::User));
+    }
+}
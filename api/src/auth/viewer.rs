@@ -0,0 +1,132 @@
+use super::token::TokenKeys;
+use super::two_factor::TwoFactorPolicy;
+use crate::api_keys::entity::{ApiKey, ApiKeyScope, KEY_PREFIX};
+use crate::app::error::AppError;
+use crate::users::entity::{User, UserRole};
+use async_graphql::Context;
+use async_graphql::parser::types::OperationType;
+use axum::http::{HeaderMap, header::AUTHORIZATION};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// The authenticated user a GraphQL request is made on behalf of.
+#[derive(Debug, Clone, Copy)]
+pub struct Viewer {
+    pub user_id: Uuid,
+    /// Whether the token was obtained through a second factor.
+    pub second_factor: bool,
+    /// Set when authenticated with an API key rather than a session token.
+    pub scope: Option<ApiKeyScope>,
+}
+
+impl Viewer {
+    /// The user behind the `Authorization: Bearer` header, which holds
+    /// either a token from `login` or an API key.
+    pub async fn authenticate(
+        pool: &PgPool,
+        keys: &TokenKeys,
+        headers: &HeaderMap,
+    ) -> Result<Option<Viewer>, sqlx::Error> {
+        let Some(token) = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+        else {
+            return Ok(None);
+        };
+
+        if token.starts_with(KEY_PREFIX) {
+            let viewer =
+                ApiKey::authenticate(pool, token)
+                    .await?
+                    .map(|(user_id, scope, second_factor)| Viewer {
+                        user_id,
+                        second_factor,
+                        scope: Some(scope),
+                    });
+            return Ok(viewer);
+        }
+        let Some(claims) = keys.verify(token) else {
+            return Ok(None);
+        };
+        // Tokens die with their user, and when they are signed out
+        // everywhere.
+        let current = sqlx::query_scalar::<_, bool>(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM users
+                WHERE id = $1 AND token_generation = $2 AND deleted_at IS NULL
+            )
+            "#,
+        )
+        .bind(claims.sub)
+        .bind(claims.generation)
+        .fetch_one(pool)
+        .await?;
+        Ok(current.then_some(Viewer {
+            user_id: claims.sub,
+            second_factor: claims.mfa,
+            scope: None,
+        }))
+    }
+
+    pub fn current(ctx: &Context<'_>) -> Option<Viewer> {
+        ctx.data_opt::<Viewer>().copied()
+    }
+
+    /// The viewer, as long as their key's scope covers the operation.
+    pub fn require(ctx: &Context<'_>) -> async_graphql::Result<Viewer> {
+        let viewer = Self::current(ctx).ok_or(AppError::Unauthenticated)?;
+        let is_mutation = ctx.query_env.operation.node.ty == OperationType::Mutation;
+        if viewer.scope == Some(ApiKeyScope::ReadOnly) && is_mutation {
+            return Err(AppError::InsufficientScope.into());
+        }
+        Ok(viewer)
+    }
+
+    /// Like [`Viewer::require`], but refuses API keys, for managing the
+    /// account's credentials.
+    pub fn require_session(ctx: &Context<'_>) -> async_graphql::Result<Viewer> {
+        let viewer = Self::require(ctx)?;
+        if viewer.scope.is_some() {
+            return Err(AppError::InsufficientScope.into());
+        }
+        Ok(viewer)
+    }
+
+    /// Like [`Viewer::require`], but only for the user `user_id` themself or
+    /// an admin.
+    pub async fn require_self_or_admin(
+        ctx: &Context<'_>,
+        user_id: Uuid,
+    ) -> async_graphql::Result<Viewer> {
+        let viewer = Self::require(ctx)?;
+        if viewer.user_id == user_id {
+            return Ok(viewer);
+        }
+        Self::require_admin(ctx).await
+    }
+
+    /// Like [`Viewer::require`], but also checks the user is currently an
+    /// admin, so revoking the role takes effect without a new token, and
+    /// that the token went through a second factor if the role requires it.
+    pub async fn require_admin(ctx: &Context<'_>) -> async_graphql::Result<Viewer> {
+        let viewer = Self::require(ctx)?;
+        if viewer
+            .scope
+            .is_some_and(|scope| scope != ApiKeyScope::Admin)
+        {
+            return Err(AppError::InsufficientScope.into());
+        }
+        let pool = ctx.data::<PgPool>()?;
+        let user = User::get(pool, viewer.user_id).await?;
+        if user.role != UserRole::Admin {
+            return Err(AppError::Forbidden.into());
+        }
+        let policy = ctx.data::<TwoFactorPolicy>()?;
+        if policy.requires(user.role) && !viewer.second_factor {
+            return Err(AppError::TwoFactorRequired.into());
+        }
+        Ok(viewer)
+    }
+}
@@ -0,0 +1,265 @@
+use async_graphql::{ComplexObject, Context, Enum, InputObject, SimpleObject};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum, sqlx::Type)]
+#[sqlx(type_name = "batch_status", rename_all = "lowercase")]
+pub enum BatchStatus {
+    Running,
+    Completed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum, sqlx::Type)]
+#[sqlx(type_name = "batch_item_status", rename_all = "lowercase")]
+pub enum BatchItemStatus {
+    Pending,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject, FromRow)]
+#[graphql(complex)]
+pub struct Batch {
+    pub id: Uuid,
+    pub status: BatchStatus,
+    prompt_ids: Vec<Uuid>,
+    per_prompt: i32,
+    pub max_tokens: i32,
+    pub temperature: f32,
+    total: i32,
+    completed: i32,
+    failed: i32,
+    finished_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// The user who started the batch, `None` once they are purged.
+    pub created_by: Option<Uuid>,
+}
+
+#[ComplexObject]
+impl Batch {
+    async fn items(
+        &self,
+        ctx: &Context<'_>,
+        status: Option<BatchItemStatus>,
+    ) -> async_graphql::Result<Vec<BatchItem>> {
+        let pool = ctx.data::<PgPool>()?;
+        let items = BatchItem::list(pool, self.id, status).await?;
+        Ok(items)
+    }
+}
+
+impl Batch {
+    pub async fn get(pool: &PgPool, id: Uuid) -> Result<Batch, sqlx::Error> {
+        let batch = sqlx::query_as::<_, Batch>(
+            r#"
+            SELECT id, status, prompt_ids, per_prompt, max_tokens, temperature, total, completed,
+                   failed, finished_at, created_at, updated_at, created_by
+            FROM batches
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(batch)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject, FromRow)]
+pub struct BatchItem {
+    pub id: Uuid,
+    pub batch_id: Uuid,
+    /// `None` once the prompt is purged from the trash.
+    pub prompt_id: Option<Uuid>,
+    pub status: BatchItemStatus,
+    haiku_id: Option<Uuid>,
+    error: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl BatchItem {
+    pub async fn list(
+        pool: &PgPool,
+        batch_id: Uuid,
+        status: Option<BatchItemStatus>,
+    ) -> Result<Vec<BatchItem>, sqlx::Error> {
+        let items = sqlx::query_as::<_, BatchItem>(
+            r#"
+            SELECT id, batch_id, prompt_id, status, haiku_id, error, created_at, updated_at
+            FROM batch_items
+            WHERE batch_id = $1 AND ($2::batch_item_status IS NULL OR status = $2)
+            ORDER BY created_at, id
+            "#,
+        )
+        .bind(batch_id)
+        .bind(status)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(items)
+    }
+
+    pub async fn get(pool: &PgPool, id: Uuid) -> Result<BatchItem, sqlx::Error> {
+        let item = sqlx::query_as::<_, BatchItem>(
+            r#"
+            SELECT id, batch_id, prompt_id, status, haiku_id, error, created_at, updated_at
+            FROM batch_items
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(item)
+    }
+
+    /// Marks the item as done and bumps the batch counters, closing the batch
+    /// once every item has either completed or failed.
+    pub async fn complete(pool: &PgPool, id: Uuid, haiku_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            WITH item AS (
+                UPDATE batch_items
+                SET status = 'completed', haiku_id = $2, error = NULL, updated_at = now()
+                WHERE id = $1 AND status = 'pending'
+                RETURNING batch_id
+            )
+            UPDATE batches
+            SET completed = completed + 1,
+                status = CASE WHEN completed + 1 + failed >= total THEN 'completed' ELSE status END,
+                finished_at = CASE WHEN completed + 1 + failed >= total THEN now() END,
+                updated_at = now()
+            FROM item
+            WHERE batches.id = item.batch_id
+            "#,
+        )
+        .bind(id)
+        .bind(haiku_id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records the error of a failed attempt. The item only counts as failed
+    /// once `last_attempt` is set, earlier errors are kept for inspection.
+    pub async fn fail(
+        pool: &PgPool,
+        id: Uuid,
+        error: &str,
+        last_attempt: bool,
+    ) -> Result<(), sqlx::Error> {
+        if !last_attempt {
+            sqlx::query(
+                r#"
+                UPDATE batch_items
+                SET error = $2, updated_at = now()
+                WHERE id = $1 AND status = 'pending'
+                "#,
+            )
+            .bind(id)
+            .bind(error)
+            .execute(pool)
+            .await?;
+
+            return Ok(());
+        }
+
+        sqlx::query(
+            r#"
+            WITH item AS (
+                UPDATE batch_items
+                SET status = 'failed', error = $2, updated_at = now()
+                WHERE id = $1 AND status = 'pending'
+                RETURNING batch_id
+            )
+            UPDATE batches
+            SET failed = failed + 1,
+                status = CASE WHEN completed + failed + 1 >= total THEN 'completed' ELSE status END,
+                finished_at = CASE WHEN completed + failed + 1 >= total THEN now() END,
+                updated_at = now()
+            FROM item
+            WHERE batches.id = item.batch_id
+            "#,
+        )
+        .bind(id)
+        .bind(error)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, InputObject)]
+pub struct GenerationParams {
+    #[graphql(default = 64)]
+    pub max_tokens: i32,
+    #[graphql(default_with = "0.7")]
+    pub temperature: f32,
+}
+
+impl Default for GenerationParams {
+    fn default() -> Self {
+        Self {
+            max_tokens: 64,
+            temperature: 0.7,
+        }
+    }
+}
+
+pub struct NewBatch {
+    pub prompt_ids: Vec<Uuid>,
+    pub per_prompt: i32,
+    pub params: GenerationParams,
+    pub created_by: Uuid,
+}
+
+impl NewBatch {
+    /// Creates the batch and one pending item per haiku to generate, inside
+    /// the caller's transaction.
+    pub async fn create(
+        tx: &mut sqlx::PgConnection,
+        input: NewBatch,
+    ) -> Result<(Batch, Vec<BatchItem>), sqlx::Error> {
+        let total = input.prompt_ids.len() as i32 * input.per_prompt;
+        let batch = sqlx::query_as::<_, Batch>(
+            r#"
+            INSERT INTO batches (prompt_ids, per_prompt, max_tokens, temperature, total, created_by)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, status, prompt_ids, per_prompt, max_tokens, temperature, total, completed,
+                      failed, finished_at, created_at, updated_at, created_by
+            "#,
+        )
+        .bind(&input.prompt_ids)
+        .bind(input.per_prompt)
+        .bind(input.params.max_tokens)
+        .bind(input.params.temperature)
+        .bind(total)
+        .bind(input.created_by)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let items = sqlx::query_as::<_, BatchItem>(
+            r#"
+            INSERT INTO batch_items (batch_id, prompt_id)
+            SELECT $1, prompt_id
+            FROM UNNEST($2::uuid[]) AS prompt_id, generate_series(1, $3)
+            RETURNING id, batch_id, prompt_id, status, haiku_id, error, created_at, updated_at
+            "#,
+        )
+        .bind(batch.id)
+        .bind(&input.prompt_ids)
+        .bind(input.per_prompt)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        Ok((batch, items))
+    }
+}
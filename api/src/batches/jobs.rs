@@ -0,0 +1,65 @@
+use super::entity::{Batch, BatchItem, BatchItemStatus};
+use crate::haiku::jobs::{GenerationSettings, generate};
+use crate::jobs::entity::Job;
+use crate::jobs::worker::JobError;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub const GENERATE_BATCH_ITEM: &str = "generate_batch_item";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchItemPayload {
+    pub item_id: Uuid,
+}
+
+/// Fails the item of a job that ended up in the dead letter state without
+/// recording it, as when it panicked or its worker died.
+pub async fn batch_item_dead(pool: PgPool, job: Job) -> Result<(), JobError> {
+    let payload: BatchItemPayload = serde_json::from_value(job.payload)?;
+    let error = job
+        .last_error
+        .as_deref()
+        .unwrap_or("The job was dead-lettered");
+    BatchItem::fail(&pool, payload.item_id, error, true).await?;
+    Ok(())
+}
+
+pub async fn generate_batch_item(
+    pool: PgPool,
+    job: Job,
+    settings: GenerationSettings,
+) -> Result<(), JobError> {
+    let payload: BatchItemPayload = serde_json::from_value(job.payload)?;
+    let item = BatchItem::get(&pool, payload.item_id).await?;
+    if item.status != BatchItemStatus::Pending {
+        return Ok(());
+    }
+
+    let batch = Batch::get(&pool, item.batch_id).await?;
+    let generated = match item.prompt_id {
+        Some(prompt_id) => {
+            generate(
+                &pool,
+                prompt_id,
+                batch.max_tokens,
+                batch.temperature,
+                &settings,
+            )
+            .await
+        }
+        None => Err(JobError::Permanent("The prompt was purged".to_string())),
+    };
+    match generated {
+        Ok(haiku) => {
+            BatchItem::complete(&pool, item.id, haiku.id).await?;
+            Ok(())
+        }
+        Err(err) => {
+            let last_attempt =
+                matches!(err, JobError::Permanent(_)) || job.attempts >= job.max_attempts;
+            BatchItem::fail(&pool, item.id, &err.to_string(), last_attempt).await?;
+            Err(err)
+        }
+    }
+}
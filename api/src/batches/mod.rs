@@ -0,0 +1,4 @@
+pub mod entity;
+pub mod jobs;
+pub mod resolver;
+pub mod schema;
@@ -0,0 +1,128 @@
+use super::entity::{Batch, BatchStatus, GenerationParams, NewBatch};
+use super::jobs::{BatchItemPayload, GENERATE_BATCH_ITEM};
+use crate::app::error::AppError;
+use crate::auth::viewer::Viewer;
+use crate::jobs::entity::NewJob;
+use async_graphql::Context;
+use async_graphql::futures_util::{Stream, stream};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Largest number of haikus a single batch may ask for.
+const MAX_BATCH_SIZE: usize = 1000;
+
+/// Batch items run behind interactive generation requests.
+const BATCH_JOB_PRIORITY: i32 = -10;
+
+const PROGRESS_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The batch, as long as the viewer started it or is an admin.
+async fn viewable_batch(ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<Batch> {
+    let pool = ctx.data::<PgPool>()?;
+    let viewer = Viewer::require(ctx)?;
+    let batch = Batch::get(pool, id).await?;
+    if batch.created_by != Some(viewer.user_id) {
+        Viewer::require_admin(ctx).await?;
+    }
+    Ok(batch)
+}
+
+pub struct QueryRoot;
+
+#[async_graphql::Object]
+impl QueryRoot {
+    /// Only for the user who started the batch, or an admin.
+    async fn batch(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<Batch> {
+        viewable_batch(ctx, id).await
+    }
+}
+
+pub struct MutationRoot;
+
+#[async_graphql::Object]
+impl MutationRoot {
+    /// Creates a batch generating `per_prompt` haikus for each prompt and
+    /// returns it right away, the haikus are generated in the background.
+    async fn start_batch_generation(
+        &self,
+        ctx: &Context<'_>,
+        prompt_ids: Vec<Uuid>,
+        per_prompt: i32,
+        params: Option<GenerationParams>,
+    ) -> async_graphql::Result<Batch> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require(ctx)?;
+
+        if prompt_ids.is_empty() {
+            return Err(AppError::validation("promptIds must not be empty").into());
+        }
+        if per_prompt < 1 || prompt_ids.len() * per_prompt as usize > MAX_BATCH_SIZE {
+            return Err(AppError::Validation(format!(
+                "A batch may generate between 1 and {} haikus",
+                MAX_BATCH_SIZE
+            ))
+            .into());
+        }
+
+        let mut tx = pool.begin().await?;
+        let (batch, items) = NewBatch::create(
+            &mut tx,
+            NewBatch {
+                prompt_ids,
+                per_prompt,
+                params: params.unwrap_or_default(),
+                created_by: viewer.user_id,
+            },
+        )
+        .await?;
+
+        let payloads = items
+            .iter()
+            .map(|item| serde_json::to_value(BatchItemPayload { item_id: item.id }))
+            .collect::<Result<Vec<_>, _>>()?;
+        NewJob::enqueue_many(&mut *tx, GENERATE_BATCH_ITEM, payloads, BATCH_JOB_PRIORITY).await?;
+        tx.commit().await?;
+
+        Ok(batch)
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[async_graphql::Subscription]
+impl SubscriptionRoot {
+    /// Emits the batch every time its progress changes, ending once it has
+    /// completed. Only for the user who started the batch, or an admin.
+    async fn batch_progress(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+    ) -> async_graphql::Result<impl Stream<Item = async_graphql::Result<Batch>>> {
+        viewable_batch(ctx, id).await?;
+        let pool = ctx.data::<PgPool>()?.clone();
+
+        let progress = stream::unfold(Some(None::<DateTime<Utc>>), move |last_update| {
+            let pool = pool.clone();
+            async move {
+                let last_update = last_update?;
+                loop {
+                    match Batch::get(&pool, id).await {
+                        Ok(batch) if Some(batch.updated_at) != last_update => {
+                            let next = match batch.status {
+                                BatchStatus::Completed => None,
+                                BatchStatus::Running => Some(Some(batch.updated_at)),
+                            };
+                            return Some((Ok(batch), next));
+                        }
+                        Ok(_) => tokio::time::sleep(PROGRESS_POLL_INTERVAL).await,
+                        Err(err) => return Some((Err(err.into()), None)),
+                    }
+                }
+            }
+        });
+
+        Ok(progress)
+    }
+}
@@ -0,0 +1,15 @@
+pub(crate) use super::resolver::{MutationRoot, QueryRoot, SubscriptionRoot};
+use crate::app::error::ErrorCodes;
+use crate::audit::extension::Audit;
+use async_graphql::Schema;
+use sqlx::PgPool;
+
+pub type BatchSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
+
+pub fn create_schema(pool: PgPool) -> BatchSchema {
+    Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .data(pool)
+        .extension(ErrorCodes)
+        .extension(Audit)
+        .finish()
+}
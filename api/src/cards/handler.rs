@@ -0,0 +1,181 @@
+use super::render::{
+    Card, CardRenderer, CardStyle, Font, LAYOUT_VERSION, RenderError, Theme, is_hex_color,
+};
+use crate::haiku::entity::Haiku;
+use axum::{
+    extract::{Extension, Path, Query},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use thiserror::Error;
+use tokio::task::JoinError;
+use uuid::Uuid;
+
+const MAX_ATTRIBUTION_LEN: usize = 64;
+
+/// Attribution used when the request does not name an author.
+#[derive(Clone)]
+pub struct DefaultAttribution(pub String);
+
+#[derive(Debug, Error)]
+pub enum CardError {
+    #[error("Haiku not found")]
+    NotFound,
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("Rendering failed: {0}")]
+    Render(#[from] RenderError),
+    #[error("Rendering failed: {0}")]
+    Task(#[from] JoinError),
+}
+
+impl IntoResponse for CardError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            CardError::NotFound => StatusCode::NOT_FOUND,
+            CardError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            CardError::Database(_) | CardError::Render(_) | CardError::Task(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        (status, self.to_string()).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CardQuery {
+    #[serde(default)]
+    theme: Theme,
+    #[serde(default)]
+    font: Font,
+    /// Hex color, with or without the leading `#`.
+    background: Option<String>,
+    /// Overrides the haiku's author.
+    author: Option<String>,
+}
+
+pub async fn card_svg(
+    Extension(pool): Extension<PgPool>,
+    Extension(attribution): Extension<DefaultAttribution>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<CardQuery>,
+    headers: HeaderMap,
+) -> Result<Response, CardError> {
+    card(&pool, &attribution, None, id, query, &headers).await
+}
+
+pub async fn card_png(
+    Extension(pool): Extension<PgPool>,
+    Extension(attribution): Extension<DefaultAttribution>,
+    Extension(renderer): Extension<CardRenderer>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<CardQuery>,
+    headers: HeaderMap,
+) -> Result<Response, CardError> {
+    card(&pool, &attribution, Some(renderer), id, query, &headers).await
+}
+
+/// Renders the card as PNG with `renderer`, or as SVG without one.
+async fn card(
+    pool: &PgPool,
+    default_attribution: &DefaultAttribution,
+    renderer: Option<CardRenderer>,
+    id: Uuid,
+    query: CardQuery,
+    headers: &HeaderMap,
+) -> Result<Response, CardError> {
+    let background = query.background.map(|color| match color.starts_with('#') {
+        true => color,
+        false => format!("#{}", color),
+    });
+    if let Some(color) = background.as_ref().filter(|color| !is_hex_color(color)) {
+        return Err(CardError::BadRequest(format!(
+            "Invalid background color `{}`",
+            color
+        )));
+    }
+    let style = CardStyle {
+        theme: query.theme,
+        font: query.font,
+        background,
+    };
+
+    let haiku = match Haiku::get(pool, id).await {
+        Err(sqlx::Error::RowNotFound) => return Err(CardError::NotFound),
+        result => result?,
+    };
+    let attribution: String = query
+        .author
+        .or_else(|| haiku.author.clone())
+        .unwrap_or_else(|| default_attribution.0.clone())
+        .chars()
+        .take(MAX_ATTRIBUTION_LEN)
+        .collect();
+
+    // Cards only change with the haiku, the requested style or the layout.
+    // The tag is hashed with SHA-256 so that it stays the same across builds.
+    let key = format!(
+        "{}\n{}\n{}\n{:?}\n{:?}\n{}\n{}\n{}",
+        LAYOUT_VERSION,
+        haiku.id,
+        haiku.updated_at.timestamp_micros(),
+        style.theme,
+        style.font,
+        style.background.as_deref().unwrap_or(""),
+        attribution,
+        if renderer.is_some() { "png" } else { "svg" },
+    );
+    let etag = format!("\"{}\"", &hex::encode(Sha256::digest(key.as_bytes()))[..32]);
+    let cache_headers = [
+        (header::ETAG, etag.clone()),
+        (header::CACHE_CONTROL, "public, max-age=3600".to_string()),
+    ];
+
+    let matches = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value.split(',').any(|tag| {
+                let tag = tag.trim().trim_start_matches("W/");
+                tag == "*" || tag == etag
+            })
+        });
+    if matches {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+
+    let svg = Card {
+        content: &haiku.content,
+        attribution: &attribution,
+        style: &style,
+    }
+    .svg();
+
+    let response = match renderer {
+        Some(renderer) => {
+            let png = tokio::task::spawn_blocking(move || renderer.png(&svg)).await??;
+            (
+                cache_headers,
+                [(header::CONTENT_TYPE, HeaderValue::from_static("image/png"))],
+                png,
+            )
+                .into_response()
+        }
+        None => (
+            cache_headers,
+            [(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("image/svg+xml"),
+            )],
+            svg,
+        )
+            .into_response(),
+    };
+
+    Ok(response)
+}
@@ -0,0 +1,253 @@
+use resvg::{tiny_skia, usvg};
+use serde::Deserialize;
+use std::sync::Arc;
+use thiserror::Error;
+
+pub const WIDTH: u32 = 1200;
+pub const HEIGHT: u32 = 630;
+const PADDING: f32 = 96.0;
+const MAX_FONT_SIZE: f32 = 64.0;
+
+/// Bumped whenever the layout changes, so cached cards are not reused.
+pub const LAYOUT_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum RenderError {
+    #[error("Invalid SVG: {0}")]
+    Svg(#[from] usvg::Error),
+    #[error("Failed to encode PNG: {0}")]
+    Png(String),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    #[default]
+    Paper,
+    Ink,
+    Dusk,
+    Matcha,
+}
+
+struct Palette {
+    background: &'static str,
+    text: &'static str,
+    accent: &'static str,
+}
+
+impl Theme {
+    fn palette(self) -> Palette {
+        match self {
+            Theme::Paper => Palette {
+                background: "#f5efe0",
+                text: "#2b2620",
+                accent: "#b0412e",
+            },
+            Theme::Ink => Palette {
+                background: "#16161a",
+                text: "#f2f0ea",
+                accent: "#c9a227",
+            },
+            Theme::Dusk => Palette {
+                background: "#2d2a4a",
+                text: "#f6e7d8",
+                accent: "#f08a5d",
+            },
+            Theme::Matcha => Palette {
+                background: "#dfe8d0",
+                text: "#243322",
+                accent: "#5b7a3a",
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Font {
+    #[default]
+    Serif,
+    Sans,
+    Mono,
+}
+
+impl Font {
+    fn family(self) -> &'static str {
+        match self {
+            Font::Serif => "serif",
+            Font::Sans => "sans-serif",
+            Font::Mono => "monospace",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct CardStyle {
+    pub theme: Theme,
+    pub font: Font,
+    /// Overrides the theme background, a `#rgb` or `#rrggbb` color.
+    pub background: Option<String>,
+}
+
+pub struct Card<'a> {
+    pub content: &'a str,
+    pub attribution: &'a str,
+    pub style: &'a CardStyle,
+}
+
+impl Card<'_> {
+    pub fn svg(&self) -> String {
+        let palette = self.style.theme.palette();
+        let background = self
+            .style
+            .background
+            .as_deref()
+            .unwrap_or(palette.background);
+        let lines: Vec<&str> = self
+            .content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+
+        // Shrink the text until the longest line fits between the paddings.
+        let widest = lines.iter().map(|line| em_width(line)).fold(1.0, f32::max);
+        let font_size = MAX_FONT_SIZE.min((WIDTH as f32 - 2.0 * PADDING) / widest);
+        let line_height = font_size * 1.5;
+        let first_baseline =
+            (HEIGHT as f32 - line_height * lines.len() as f32) / 2.0 + font_size - 24.0;
+
+        let mut svg = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{HEIGHT}" viewBox="0 0 {WIDTH} {HEIGHT}">"#
+        );
+        svg.push_str(&format!(
+            r#"<rect width="100%" height="100%" fill="{}"/>"#,
+            escape(background)
+        ));
+        svg.push_str(&format!(
+            r#"<g font-family="{}" font-size="{:.1}" fill="{}" text-anchor="middle">"#,
+            self.style.font.family(),
+            font_size,
+            palette.text
+        ));
+        for (index, line) in lines.iter().enumerate() {
+            svg.push_str(&format!(
+                r#"<text x="{}" y="{:.1}">{}</text>"#,
+                WIDTH / 2,
+                first_baseline + line_height * index as f32,
+                escape(line)
+            ));
+        }
+        svg.push_str("</g>");
+
+        let rule_y = HEIGHT as f32 - PADDING + 8.0;
+        svg.push_str(&format!(
+            r#"<rect x="{}" y="{:.1}" width="48" height="3" fill="{}"/>"#,
+            WIDTH as f32 - PADDING - 48.0,
+            rule_y - 40.0,
+            palette.accent
+        ));
+        svg.push_str(&format!(
+            r#"<text x="{}" y="{:.1}" font-family="{}" font-size="26" fill="{}" text-anchor="end">— {}</text>"#,
+            WIDTH as f32 - PADDING,
+            rule_y,
+            self.style.font.family(),
+            palette.accent,
+            escape(self.attribution)
+        ));
+        svg.push_str("</svg>");
+        svg
+    }
+}
+
+/// Approximate width of `line` in ems; wide (CJK) characters take a full em.
+pub fn em_width(line: &str) -> f32 {
+    line.chars()
+        .map(|c| if c >= '\u{1100}' { 1.0 } else { 0.6 })
+        .sum()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Whether `color` is a hex color that can be inlined into the SVG.
+pub fn is_hex_color(color: &str) -> bool {
+    color
+        .strip_prefix('#')
+        .is_some_and(|hex| matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Rasterizes card SVGs, holding the fonts loaded at startup.
+#[derive(Clone)]
+pub struct CardRenderer {
+    options: Arc<usvg::Options<'static>>,
+}
+
+impl CardRenderer {
+    /// Loads the system fonts plus those in `fonts_dir`, and points the
+    /// generic families at the first installed match of a few common fonts.
+    pub fn new(fonts_dir: Option<&str>) -> Self {
+        let mut options = usvg::Options::default();
+        let fontdb = options.fontdb_mut();
+        fontdb.load_system_fonts();
+        if let Some(dir) = fonts_dir {
+            fontdb.load_fonts_dir(dir);
+        }
+
+        let installed = |candidates: &[&str]| {
+            candidates
+                .iter()
+                .find(|candidate| {
+                    fontdb
+                        .faces()
+                        .any(|face| face.families.iter().any(|(family, _)| family == *candidate))
+                })
+                .map(|family| family.to_string())
+        };
+        let serif = installed(&[
+            "Noto Serif CJK JP",
+            "Noto Serif",
+            "DejaVu Serif",
+            "Liberation Serif",
+        ]);
+        let sans = installed(&[
+            "Noto Sans CJK JP",
+            "Noto Sans",
+            "DejaVu Sans",
+            "Liberation Sans",
+        ]);
+        let mono = installed(&["Noto Sans Mono", "DejaVu Sans Mono", "Liberation Mono"]);
+        if let Some(family) = serif {
+            fontdb.set_serif_family(family);
+        }
+        if let Some(family) = sans {
+            fontdb.set_sans_serif_family(family);
+        }
+        if let Some(family) = mono {
+            fontdb.set_monospace_family(family);
+        }
+
+        Self {
+            options: Arc::new(options),
+        }
+    }
+
+    /// Parses `svg` against the loaded fonts.
+    pub fn tree(&self, svg: &str) -> Result<usvg::Tree, RenderError> {
+        Ok(usvg::Tree::from_str(svg, &self.options)?)
+    }
+
+    pub fn png(&self, svg: &str) -> Result<Vec<u8>, RenderError> {
+        let tree = self.tree(svg)?;
+        let mut pixmap =
+            tiny_skia::Pixmap::new(WIDTH, HEIGHT).expect("card dimensions are non-zero");
+        resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+        pixmap
+            .encode_png()
+            .map_err(|e| RenderError::Png(e.to_string()))
+    }
+}
@@ -0,0 +1,345 @@
+use crate::haiku::entity::Haiku;
+use async_graphql::{ComplexObject, Context, InputObject, SimpleObject};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize, SimpleObject, FromRow)]
+#[graphql(complex)]
+pub struct Collection {
+    pub id: Uuid,
+    pub owner_id: Uuid,
+    pub name: String,
+    description: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[ComplexObject]
+impl Collection {
+    async fn haikus(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Haiku>> {
+        let pool = ctx.data::<PgPool>()?;
+        let haikus = Collection::list_haikus(pool, self.id).await?;
+        Ok(haikus)
+    }
+
+    async fn shared_with(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Uuid>> {
+        let pool = ctx.data::<PgPool>()?;
+        let user_ids = Collection::list_shares(pool, self.id).await?;
+        Ok(user_ids)
+    }
+}
+
+impl Collection {
+    /// Collections owned by or shared with `user_id`.
+    pub async fn list(pool: &PgPool, user_id: Uuid) -> Result<Vec<Collection>, sqlx::Error> {
+        let collections = sqlx::query_as::<_, Collection>(
+            r#"
+            SELECT id, owner_id, name, description, created_at, updated_at
+            FROM collections
+            WHERE owner_id = $1
+               OR id IN (SELECT collection_id FROM collection_shares WHERE user_id = $1)
+            ORDER BY created_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(collections)
+    }
+
+    /// Fetches a collection `user_id` may read, either as owner or because it
+    /// was shared with them.
+    pub async fn get(pool: &PgPool, id: Uuid, user_id: Uuid) -> Result<Collection, sqlx::Error> {
+        let collection = sqlx::query_as::<_, Collection>(
+            r#"
+            SELECT id, owner_id, name, description, created_at, updated_at
+            FROM collections
+            WHERE id = $1
+              AND (owner_id = $2
+                   OR id IN (SELECT collection_id FROM collection_shares WHERE user_id = $2))
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(collection)
+    }
+
+    /// Fetches a collection only if `owner_id` owns it.
+    pub async fn get_owned(
+        pool: &PgPool,
+        id: Uuid,
+        owner_id: Uuid,
+    ) -> Result<Collection, sqlx::Error> {
+        let collection = sqlx::query_as::<_, Collection>(
+            r#"
+            SELECT id, owner_id, name, description, created_at, updated_at
+            FROM collections
+            WHERE id = $1 AND owner_id = $2
+            "#,
+        )
+        .bind(id)
+        .bind(owner_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(collection)
+    }
+
+    pub async fn destroy(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM collections
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn list_haikus(pool: &PgPool, id: Uuid) -> Result<Vec<Haiku>, sqlx::Error> {
+        let haikus = sqlx::query_as::<_, Haiku>(
+            r#"
+            SELECT h.id, h.content, h.is_funny, h.prompt_id, h.duplicate_of, h.author, h.version, h.created_at, h.updated_at, h.deleted_at
+            FROM collection_haikus ch
+            JOIN haikus h ON h.id = ch.haiku_id
+            WHERE ch.collection_id = $1 AND h.deleted_at IS NULL
+            ORDER BY ch.position
+            "#,
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(haikus)
+    }
+
+    pub async fn haiku_ids(pool: &PgPool, id: Uuid) -> Result<Vec<Uuid>, sqlx::Error> {
+        let haiku_ids = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            SELECT haiku_id
+            FROM collection_haikus
+            WHERE collection_id = $1
+            ORDER BY position
+            "#,
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(haiku_ids)
+    }
+
+    /// Inserts the haiku at `position` (1-based), shifting the following
+    /// haikus down, or appends it when no position is given.
+    pub async fn add_haiku(
+        pool: &PgPool,
+        id: Uuid,
+        haiku_id: Uuid,
+        position: Option<i32>,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM collection_haikus
+            WHERE collection_id = $1 AND haiku_id = $2
+            "#,
+        )
+        .bind(id)
+        .bind(haiku_id)
+        .execute(&mut *tx)
+        .await?;
+
+        if let Some(position) = position {
+            sqlx::query(
+                r#"
+                UPDATE collection_haikus
+                SET position = position + 1
+                WHERE collection_id = $1 AND position >= $2
+                "#,
+            )
+            .bind(id)
+            .bind(position)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO collection_haikus (collection_id, haiku_id, position)
+            VALUES (
+                $1, $2,
+                COALESCE($3, (SELECT COALESCE(MAX(position), 0) + 1 FROM collection_haikus WHERE collection_id = $1))
+            )
+            "#,
+        )
+        .bind(id)
+        .bind(haiku_id)
+        .bind(position)
+        .execute(&mut *tx)
+        .await?;
+
+        // Feeds of the collection tell when its haikus last changed.
+        sqlx::query("UPDATE collections SET updated_at = now() WHERE id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_haiku(pool: &PgPool, id: Uuid, haiku_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            WITH removed AS (
+                DELETE FROM collection_haikus
+                WHERE collection_id = $1 AND haiku_id = $2
+                RETURNING collection_id
+            )
+            UPDATE collections SET updated_at = now()
+            WHERE id IN (SELECT collection_id FROM removed)
+            "#,
+        )
+        .bind(id)
+        .bind(haiku_id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Rewrites the positions so haikus appear in the order of `haiku_ids`.
+    pub async fn reorder(pool: &PgPool, id: Uuid, haiku_ids: &[Uuid]) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            UPDATE collection_haikus ch
+            SET position = ordered.position
+            FROM UNNEST($2::uuid[]) WITH ORDINALITY AS ordered(haiku_id, position)
+            WHERE ch.collection_id = $1 AND ch.haiku_id = ordered.haiku_id
+            "#,
+        )
+        .bind(id)
+        .bind(haiku_ids)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn list_shares(pool: &PgPool, id: Uuid) -> Result<Vec<Uuid>, sqlx::Error> {
+        let user_ids = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            SELECT user_id
+            FROM collection_shares
+            WHERE collection_id = $1
+            ORDER BY created_at
+            "#,
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(user_ids)
+    }
+
+    pub async fn share(pool: &PgPool, id: Uuid, user_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO collection_shares (collection_id, user_id)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn unshare(pool: &PgPool, id: Uuid, user_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM collection_shares
+            WHERE collection_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, InputObject)]
+pub struct CollectionInput {
+    name: String,
+    description: Option<String>,
+}
+
+impl CollectionInput {
+    pub async fn create(
+        pool: &PgPool,
+        owner_id: Uuid,
+        data: CollectionInput,
+    ) -> Result<Collection, sqlx::Error> {
+        let collection = sqlx::query_as::<_, Collection>(
+            r#"
+            INSERT INTO collections (owner_id, name, description)
+            VALUES ($1, $2, $3)
+            RETURNING id, owner_id, name, description, created_at, updated_at
+            "#,
+        )
+        .bind(owner_id)
+        .bind(data.name)
+        .bind(data.description)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(collection)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, InputObject)]
+pub struct UpdateCollection {
+    name: Option<String>,
+    description: Option<String>,
+}
+
+impl UpdateCollection {
+    pub async fn update(
+        pool: &PgPool,
+        id: Uuid,
+        data: UpdateCollection,
+    ) -> Result<Collection, sqlx::Error> {
+        let collection = sqlx::query_as::<_, Collection>(
+            r#"
+            UPDATE collections
+            SET name = COALESCE($2, name), description = COALESCE($3, description), updated_at = now()
+            WHERE id = $1
+            RETURNING id, owner_id, name, description, created_at, updated_at
+            "#,
+        )
+        .bind(id)
+        .bind(data.name)
+        .bind(data.description)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(collection)
+    }
+}
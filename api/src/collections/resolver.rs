@@ -0,0 +1,155 @@
+use super::entity::{Collection, CollectionInput, UpdateCollection};
+use crate::app::error::AppError;
+use crate::auth::viewer::Viewer;
+use crate::haiku::entity::Haiku;
+use async_graphql::Context;
+use sqlx::PgPool;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+pub struct QueryRoot;
+
+#[async_graphql::Object]
+impl QueryRoot {
+    /// Collections the viewer owns or that were shared with them.
+    async fn list_collections(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Collection>> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require(ctx)?;
+        let collections = Collection::list(pool, viewer.user_id).await?;
+        Ok(collections)
+    }
+
+    async fn get_collection(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+    ) -> async_graphql::Result<Collection> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require(ctx)?;
+        let collection = Collection::get(pool, id, viewer.user_id).await?;
+        Ok(collection)
+    }
+}
+
+pub struct MutationRoot;
+
+#[async_graphql::Object]
+impl MutationRoot {
+    async fn create_collection(
+        &self,
+        ctx: &Context<'_>,
+        data: CollectionInput,
+    ) -> async_graphql::Result<Collection> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require(ctx)?;
+        let collection = CollectionInput::create(pool, viewer.user_id, data).await?;
+        Ok(collection)
+    }
+
+    async fn update_collection(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+        data: UpdateCollection,
+    ) -> async_graphql::Result<Collection> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require(ctx)?;
+        let collection = Collection::get_owned(pool, id, viewer.user_id).await?;
+        let collection = UpdateCollection::update(pool, collection.id, data).await?;
+        Ok(collection)
+    }
+
+    async fn destroy_collection(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<bool> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require(ctx)?;
+        let collection = Collection::get_owned(pool, id, viewer.user_id).await?;
+        Collection::destroy(pool, collection.id).await?;
+        Ok(true)
+    }
+
+    /// Adds a haiku at `position` (1-based), or at the end when omitted.
+    async fn add_haiku_to_collection(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+        haiku_id: Uuid,
+        #[graphql(validator(minimum = 1))] position: Option<i32>,
+    ) -> async_graphql::Result<Collection> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require(ctx)?;
+        let collection = Collection::get_owned(pool, id, viewer.user_id).await?;
+        let haiku = Haiku::get(pool, haiku_id).await?;
+        Collection::add_haiku(pool, collection.id, haiku.id, position).await?;
+        Ok(collection)
+    }
+
+    async fn remove_haiku_from_collection(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+        haiku_id: Uuid,
+    ) -> async_graphql::Result<Collection> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require(ctx)?;
+        let collection = Collection::get_owned(pool, id, viewer.user_id).await?;
+        Collection::remove_haiku(pool, collection.id, haiku_id).await?;
+        Ok(collection)
+    }
+
+    /// Reorders the collection. `haiku_ids` must list every haiku of the
+    /// collection exactly once.
+    async fn reorder_collection(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+        haiku_ids: Vec<Uuid>,
+    ) -> async_graphql::Result<Collection> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require(ctx)?;
+        let collection = Collection::get_owned(pool, id, viewer.user_id).await?;
+
+        let current = Collection::haiku_ids(pool, collection.id).await?;
+        let requested: HashSet<&Uuid> = haiku_ids.iter().collect();
+        if requested.len() != haiku_ids.len() || requested != current.iter().collect::<HashSet<_>>()
+        {
+            return Err(AppError::validation(
+                "haikuIds must list every haiku of the collection exactly once",
+            )
+            .into());
+        }
+
+        Collection::reorder(pool, collection.id, &haiku_ids).await?;
+        Ok(collection)
+    }
+
+    async fn share_collection(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+        user_id: Uuid,
+    ) -> async_graphql::Result<Collection> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require(ctx)?;
+        let collection = Collection::get_owned(pool, id, viewer.user_id).await?;
+        if user_id == viewer.user_id {
+            return Err(
+                AppError::validation("A collection cannot be shared with its owner").into(),
+            );
+        }
+        Collection::share(pool, collection.id, user_id).await?;
+        Ok(collection)
+    }
+
+    async fn unshare_collection(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+        user_id: Uuid,
+    ) -> async_graphql::Result<Collection> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require(ctx)?;
+        let collection = Collection::get_owned(pool, id, viewer.user_id).await?;
+        Collection::unshare(pool, collection.id, user_id).await?;
+        Ok(collection)
+    }
+}
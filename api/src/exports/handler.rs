@@ -0,0 +1,156 @@
+use super::render;
+use crate::auth::token::TokenKeys;
+use crate::feeds::entity::{FeedQuery, HaikuEntry};
+use crate::feeds::handler::FilterParams;
+use async_graphql::futures_util::{StreamExt, stream};
+use axum::{
+    body::Body,
+    extract::{Extension, Query},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use thiserror::Error;
+use uuid::Uuid;
+
+/// Haikus fetched, and written to the response, at a time.
+const BATCH_SIZE: i64 = 200;
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("Export not found")]
+    NotFound,
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl IntoResponse for ExportError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            ExportError::NotFound => StatusCode::NOT_FOUND,
+            ExportError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, self.to_string()).into_response()
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Markdown,
+    Text,
+}
+
+impl Format {
+    /// Written once, before the first haiku.
+    fn header(self) -> Option<String> {
+        match self {
+            Format::Markdown => Some(render::markdown_header(Utc::now())),
+            Format::Text => None,
+        }
+    }
+
+    fn render(self, entry: &HaikuEntry) -> String {
+        match self {
+            Format::Markdown => render::markdown(entry),
+            Format::Text => render::text(entry),
+        }
+    }
+}
+
+pub async fn markdown_export(
+    Extension(pool): Extension<PgPool>,
+    Extension(keys): Extension<TokenKeys>,
+    Query(filters): Query<FilterParams>,
+    headers: HeaderMap,
+) -> Result<Response, ExportError> {
+    let query = resolve(&pool, &keys, filters, &headers).await?;
+    Ok(export(
+        pool,
+        query,
+        Format::Markdown,
+        "text/markdown; charset=utf-8",
+        "haikus.md",
+    ))
+}
+
+pub async fn text_export(
+    Extension(pool): Extension<PgPool>,
+    Extension(keys): Extension<TokenKeys>,
+    Query(filters): Query<FilterParams>,
+    headers: HeaderMap,
+) -> Result<Response, ExportError> {
+    let query = resolve(&pool, &keys, filters, &headers).await?;
+    Ok(export(
+        pool,
+        query,
+        Format::Text,
+        "text/plain; charset=utf-8",
+        "haikus.txt",
+    ))
+}
+
+async fn resolve(
+    pool: &PgPool,
+    keys: &TokenKeys,
+    filters: FilterParams,
+    headers: &HeaderMap,
+) -> Result<FeedQuery, ExportError> {
+    let (query, _) = filters
+        .resolve(pool, keys, headers)
+        .await?
+        .ok_or(ExportError::NotFound)?;
+    Ok(query)
+}
+
+struct Cursor {
+    pool: PgPool,
+    query: FeedQuery,
+    after: Option<(DateTime<Utc>, Uuid)>,
+    done: bool,
+}
+
+/// Streams the matching haikus oldest first, one batch per chunk, so only
+/// a batch is ever held in memory.
+fn export(
+    pool: PgPool,
+    query: FeedQuery,
+    format: Format,
+    content_type: &'static str,
+    filename: &'static str,
+) -> Response {
+    let cursor = Cursor {
+        pool,
+        query,
+        after: None,
+        done: false,
+    };
+    let chunks = stream::try_unfold(cursor, move |mut cursor| async move {
+        if cursor.done {
+            return Ok::<_, sqlx::Error>(None);
+        }
+        let entries = cursor
+            .query
+            .entries_after(&cursor.pool, cursor.after, BATCH_SIZE)
+            .await?;
+        cursor.done = (entries.len() as i64) < BATCH_SIZE;
+        let Some(last) = entries.last() else {
+            return Ok(None);
+        };
+        cursor.after = Some((last.haiku.created_at, last.haiku.id));
+        let chunk: String = entries.iter().map(|entry| format.render(entry)).collect();
+        Ok(Some((chunk, cursor)))
+    });
+
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        Body::from_stream(stream::iter(format.header().map(Ok)).chain(chunks)),
+    )
+        .into_response()
+}
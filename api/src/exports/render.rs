@@ -0,0 +1,132 @@
+use crate::feeds::entity::HaikuEntry;
+use chrono::{DateTime, Utc};
+
+/// YAML front matter opening a Markdown export.
+pub fn markdown_header(exported_at: DateTime<Utc>) -> String {
+    format!(
+        "---\ntitle: Haikus\nexported: {}\n---\n\n",
+        exported_at.to_rfc3339()
+    )
+}
+
+/// A heading with the prompt title, the ID, prompt and date in HTML
+/// comments so they do not render, then the lines as hard line breaks.
+pub fn markdown(entry: &HaikuEntry) -> String {
+    let haiku = &entry.haiku;
+    let title = entry.prompt_title.replace('\n', " ");
+    let lines: Vec<String> = lines(&haiku.content).map(escape_markdown).collect();
+    format!(
+        "## {}\n\n<!-- id: {} -->\n<!-- prompt: {} -->\n<!-- date: {} -->\n{}\n\n",
+        escape_markdown(title.trim()),
+        haiku.id,
+        // JSON strings are valid YAML scalars; `--` cannot end the comment.
+        serde_json::Value::from(title.as_str())
+            .to_string()
+            .replace("--", "-\\u002d"),
+        haiku.created_at.to_rfc3339(),
+        lines.join("\\\n")
+    )
+}
+
+/// `key: value` header lines followed by the haiku, with a blank line
+/// before the next one.
+pub fn text(entry: &HaikuEntry) -> String {
+    let haiku = &entry.haiku;
+    let lines: Vec<&str> = lines(&haiku.content).collect();
+    format!(
+        "prompt: {}\ndate: {}\n{}\n\n",
+        entry.prompt_title.replace('\n', " "),
+        haiku.created_at.to_rfc3339(),
+        lines.join("\n")
+    )
+}
+
+fn lines(content: &str) -> impl Iterator<Item = &str> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+}
+
+/// Escapes inline markup, and line starts that would open a block.
+fn escape_markdown(line: &str) -> String {
+    let mut escaped = String::with_capacity(line.len());
+    for c in line.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '>') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    let digits = escaped.chars().take_while(char::is_ascii_digit).count();
+    if digits > 0 && escaped[digits..].starts_with(['.', ')']) {
+        // An ordered list item.
+        escaped.insert(digits, '\\');
+    } else if escaped.starts_with(['#', '-', '+', '=']) {
+        escaped.insert(0, '\\');
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn entry(title: &str, content: &str) -> HaikuEntry {
+        let haiku = serde_json::from_value(serde_json::json!({
+            "id": "6f1c1a8e-0000-4000-8000-000000000001",
+            "content": content,
+            "is_funny": false,
+            "prompt_id": "6f1c1a8e-0000-4000-8000-000000000002",
+            "duplicate_of": null,
+            "author": null,
+            "version": 1,
+            "created_at": "2025-03-01T12:00:00Z",
+            "updated_at": "2025-03-01T12:00:00Z",
+            "deleted_at": null,
+        }))
+        .unwrap();
+        HaikuEntry {
+            haiku,
+            prompt_title: title.to_string(),
+        }
+    }
+
+    #[test]
+    fn markdown_has_one_front_matter() {
+        let at = Utc.with_ymd_and_hms(2025, 3, 2, 0, 0, 0).unwrap();
+        let document = markdown_header(at)
+            + &markdown(&entry("Autumn", "one\ntwo\nthree"))
+            + &markdown(&entry("Winter", "four\nfive\nsix"));
+        assert!(
+            document
+                .starts_with("---\ntitle: Haikus\nexported: 2025-03-02T00:00:00+00:00\n---\n\n")
+        );
+        assert_eq!(document.matches("---").count(), 2);
+        assert!(
+            document.contains("## Autumn\n\n<!-- id: 6f1c1a8e-0000-4000-8000-000000000001 -->\n")
+        );
+        assert!(document.contains("<!-- prompt: \"Winter\" -->\n<!-- date: 2025-03-01T12:00:00+00:00 -->\nfour\\\nfive\\\nsix\n\n"));
+    }
+
+    #[test]
+    fn markdown_escapes_titles_and_lines() {
+        let rendered = markdown(&entry(
+            "# <b>moon</b> --> sun\nrise",
+            "1. *one*\n- two\n  \nthree",
+        ));
+        assert!(rendered.starts_with("## \\# \\<b\\>moon\\</b\\> --\\> sun rise\n"));
+        assert!(rendered.contains("<!-- prompt: \"# <b>moon</b> -\\u002d> sun rise\" -->"));
+        assert_eq!(rendered.matches("-->").count(), 3);
+        assert!(rendered.ends_with("1\\. \\*one\\*\\\n\\- two\\\nthree\n\n"));
+    }
+
+    #[test]
+    fn text_puts_headers_above_each_haiku() {
+        let rendered = text(&entry("Autumn\nmoon", " one \n\ntwo\nthree"));
+        assert_eq!(
+            rendered,
+            "prompt: Autumn moon\ndate: 2025-03-01T12:00:00+00:00\none\ntwo\nthree\n\n"
+        );
+    }
+}
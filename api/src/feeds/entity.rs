@@ -0,0 +1,137 @@
+use crate::haiku::entity::Haiku;
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+/// Filters shared by the feed and export endpoints, mirroring those of
+/// `listHaikus`.
+#[derive(Debug, Default)]
+pub struct FeedQuery {
+    pub prompt_id: Option<Uuid>,
+    /// Only haikus carrying every one of these tags.
+    pub tags: Option<Vec<String>>,
+    pub collection_id: Option<Uuid>,
+}
+
+/// A haiku with the title of its prompt, as exported.
+#[derive(Debug, FromRow)]
+pub struct HaikuEntry {
+    #[sqlx(flatten)]
+    pub haiku: Haiku,
+    pub prompt_title: String,
+}
+
+/// The `FeedQuery` filters on haikus `h`, bound as `$1` to `$3`.
+const FILTER: &str = r#"($1::uuid IS NULL OR h.prompt_id = $1)
+              AND ($2::text[] IS NULL OR h.id IN (
+                  SELECT ht.haiku_id
+                  FROM haiku_tags ht
+                  JOIN tags t ON t.id = ht.tag_id
+                  WHERE t.name = ANY($2)
+                  GROUP BY ht.haiku_id
+                  HAVING count(*) = cardinality($2)
+              ))
+              AND ($3::uuid IS NULL OR h.id IN (
+                  SELECT haiku_id FROM collection_haikus WHERE collection_id = $3
+              ))"#;
+
+#[derive(Debug, FromRow)]
+pub struct FeedStats {
+    pub total: i64,
+    /// Latest creation, edit or deletion among the matching haikus, or
+    /// change to the haikus of the tags or collection filtered by.
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl FeedQuery {
+    pub async fn stats(&self, pool: &PgPool) -> Result<FeedStats, sqlx::Error> {
+        let stats = sqlx::query_as::<_, FeedStats>(&format!(
+            r#"
+            SELECT count(*) FILTER (WHERE h.deleted_at IS NULL) AS total,
+                   GREATEST(
+                       max(GREATEST(h.updated_at, h.deleted_at)),
+                       (SELECT max(ht.created_at)
+                        FROM haiku_tags ht
+                        JOIN tags t ON t.id = ht.tag_id
+                        WHERE t.name = ANY($2)),
+                       (SELECT max(t.updated_at) FROM tags t WHERE t.name = ANY($2)),
+                       (SELECT max(ch.created_at)
+                        FROM collection_haikus ch
+                        WHERE ch.collection_id = $3),
+                       (SELECT c.updated_at FROM collections c WHERE c.id = $3)
+                   ) AS updated_at
+            FROM haikus h
+            WHERE {FILTER}
+            "#
+        ))
+        .bind(self.prompt_id)
+        .bind(&self.tags)
+        .bind(self.collection_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(stats)
+    }
+
+    /// Matching haikus, newest first.
+    pub async fn haikus(
+        &self,
+        pool: &PgPool,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Haiku>, sqlx::Error> {
+        let haikus = sqlx::query_as::<_, Haiku>(&format!(
+            r#"
+            SELECT h.id, h.content, h.is_funny, h.prompt_id, h.duplicate_of, h.author, h.version, h.created_at, h.updated_at, h.deleted_at
+            FROM haikus h
+            WHERE h.deleted_at IS NULL
+              AND {FILTER}
+            ORDER BY h.created_at DESC, h.id
+            LIMIT $4 OFFSET $5
+            "#
+        ))
+        .bind(self.prompt_id)
+        .bind(&self.tags)
+        .bind(self.collection_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(haikus)
+    }
+
+    /// Matching haikus created after `after`, oldest first, for paging
+    /// through large selections by `(created_at, id)`.
+    pub async fn entries_after(
+        &self,
+        pool: &PgPool,
+        after: Option<(DateTime<Utc>, Uuid)>,
+        limit: i64,
+    ) -> Result<Vec<HaikuEntry>, sqlx::Error> {
+        let (after_created_at, after_id) = after.unzip();
+        let entries = sqlx::query_as::<_, HaikuEntry>(&format!(
+            r#"
+            SELECT h.id, h.content, h.is_funny, h.prompt_id, h.duplicate_of, h.author, h.version, h.created_at, h.updated_at, h.deleted_at,
+                   p.title AS prompt_title
+            FROM haikus h
+            JOIN prompts p ON p.id = h.prompt_id
+            WHERE h.deleted_at IS NULL
+              AND {FILTER}
+              AND ($4::timestamptz IS NULL OR (h.created_at, h.id) > ($4, $5))
+            ORDER BY h.created_at, h.id
+            LIMIT $6
+            "#
+        ))
+        .bind(self.prompt_id)
+        .bind(&self.tags)
+        .bind(self.collection_id)
+        .bind(after_created_at)
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(entries)
+    }
+}
@@ -0,0 +1,290 @@
+use super::entity::FeedQuery;
+use super::render::{Feed, PageLinks};
+use crate::auth::token::TokenKeys;
+use crate::auth::viewer::Viewer;
+use crate::collections::entity::Collection;
+use crate::haiku::share::PublicUrl;
+use crate::prompts::entity::Prompt;
+use crate::tags::entity::normalize_names;
+use axum::{
+    extract::{Extension, Query},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::DateTime;
+use serde::Deserialize;
+use sqlx::PgPool;
+use thiserror::Error;
+use uuid::Uuid;
+
+const PAGE_SIZE: i64 = 50;
+
+#[derive(Debug, Error)]
+pub enum FeedError {
+    #[error("Feed not found")]
+    NotFound,
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl IntoResponse for FeedError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            FeedError::NotFound => StatusCode::NOT_FOUND,
+            FeedError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            FeedError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, self.to_string()).into_response()
+    }
+}
+
+/// Haiku filters shared by the feed and export endpoints.
+#[derive(Debug, Deserialize)]
+pub struct FilterParams {
+    prompt: Option<Uuid>,
+    /// Comma-separated tag names, all of which must be present.
+    tags: Option<String>,
+    /// Requires an `Authorization` header, with a token or an API key, for
+    /// a user who can read it.
+    collection: Option<Uuid>,
+}
+
+impl FilterParams {
+    /// The query for these filters and a description of each, e.g.
+    /// `tagged moon`. `None` if the prompt or collection cannot be found
+    /// by the viewer.
+    pub async fn resolve(
+        self,
+        pool: &PgPool,
+        keys: &TokenKeys,
+        headers: &HeaderMap,
+    ) -> Result<Option<(FeedQuery, Vec<String>)>, sqlx::Error> {
+        let tags = self
+            .tags
+            .as_deref()
+            .map(|tags| normalize_names(tags.split(',').map(str::to_string).collect()))
+            .filter(|tags| !tags.is_empty());
+
+        let mut descriptions = Vec::new();
+        if let Some(prompt_id) = self.prompt {
+            let prompt = match Prompt::get(pool, prompt_id).await {
+                Err(sqlx::Error::RowNotFound) => return Ok(None),
+                result => result?,
+            };
+            descriptions.push(format!("on “{}”", prompt.title));
+        }
+        if let Some(tags) = &tags {
+            descriptions.push(format!("tagged {}", tags.join(", ")));
+        }
+        if let Some(collection_id) = self.collection {
+            let Some(viewer) = Viewer::authenticate(pool, keys, headers).await? else {
+                return Ok(None);
+            };
+            let collection = match Collection::get(pool, collection_id, viewer.user_id).await {
+                Err(sqlx::Error::RowNotFound) => return Ok(None),
+                result => result?,
+            };
+            descriptions.push(format!("in {}", collection.name));
+        }
+
+        let query = FeedQuery {
+            prompt_id: self.prompt,
+            tags,
+            collection_id: self.collection,
+        };
+        Ok(Some((query, descriptions)))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PageParams {
+    /// 1-based page number.
+    page: Option<i64>,
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Atom,
+    Rss,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Atom => "atom",
+            Format::Rss => "rss",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Atom => "application/atom+xml; charset=utf-8",
+            Format::Rss => "application/rss+xml; charset=utf-8",
+        }
+    }
+}
+
+pub async fn atom_feed(
+    Extension(pool): Extension<PgPool>,
+    Extension(public_url): Extension<PublicUrl>,
+    Extension(keys): Extension<TokenKeys>,
+    Query(filters): Query<FilterParams>,
+    Query(paging): Query<PageParams>,
+    headers: HeaderMap,
+) -> Result<Response, FeedError> {
+    feed(
+        &pool,
+        &public_url,
+        &keys,
+        filters,
+        paging,
+        &headers,
+        Format::Atom,
+    )
+    .await
+}
+
+pub async fn rss_feed(
+    Extension(pool): Extension<PgPool>,
+    Extension(public_url): Extension<PublicUrl>,
+    Extension(keys): Extension<TokenKeys>,
+    Query(filters): Query<FilterParams>,
+    Query(paging): Query<PageParams>,
+    headers: HeaderMap,
+) -> Result<Response, FeedError> {
+    feed(
+        &pool,
+        &public_url,
+        &keys,
+        filters,
+        paging,
+        &headers,
+        Format::Rss,
+    )
+    .await
+}
+
+async fn feed(
+    pool: &PgPool,
+    public_url: &PublicUrl,
+    keys: &TokenKeys,
+    filters: FilterParams,
+    paging: PageParams,
+    headers: &HeaderMap,
+    format: Format,
+) -> Result<Response, FeedError> {
+    let page = paging.page.unwrap_or(1);
+    if page < 1 {
+        return Err(FeedError::BadRequest("page must be at least 1".to_string()));
+    }
+    let (query, descriptions) = filters
+        .resolve(pool, keys, headers)
+        .await?
+        .ok_or(FeedError::NotFound)?;
+    let title = match descriptions.is_empty() {
+        true => "Haikus".to_string(),
+        false => format!("Haikus {}", descriptions.join(", ")),
+    };
+
+    let stats = query.stats(pool).await?;
+    let updated = stats.updated_at.unwrap_or(DateTime::UNIX_EPOCH);
+    let last_modified = updated.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+
+    // HTTP dates have a one second resolution.
+    let not_modified = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+        .is_some_and(|since| updated.timestamp() <= since.timestamp());
+    if not_modified {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            [(header::LAST_MODIFIED, last_modified)],
+        )
+            .into_response());
+    }
+
+    let last_page = ((stats.total + PAGE_SIZE - 1) / PAGE_SIZE).max(1);
+    if page > last_page {
+        return Err(FeedError::NotFound);
+    }
+    let haikus = query
+        .haikus(pool, PAGE_SIZE, (page - 1) * PAGE_SIZE)
+        .await?;
+
+    let base = public_url.0.trim_end_matches('/');
+    let filters = filter_params(&query);
+    let page_url = |page: i64| {
+        let mut params = filters.clone();
+        if page > 1 {
+            params.push(format!("page={}", page));
+        }
+        url(base, format, &params)
+    };
+    let feed = Feed {
+        title,
+        id: url(base, format, &filters),
+        updated,
+        base,
+        links: PageLinks {
+            current: page_url(page),
+            first: page_url(1),
+            last: page_url(last_page),
+            previous: (page > 1).then(|| page_url(page - 1)),
+            next: (page < last_page).then(|| page_url(page + 1)),
+        },
+        haikus: &haikus,
+    };
+    let body = match format {
+        Format::Atom => feed.atom(),
+        Format::Rss => feed.rss(),
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::LAST_MODIFIED, last_modified),
+            (header::CACHE_CONTROL, "no-cache".to_string()),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+fn filter_params(query: &FeedQuery) -> Vec<String> {
+    let mut params = Vec::new();
+    if let Some(prompt_id) = query.prompt_id {
+        params.push(format!("prompt={}", prompt_id));
+    }
+    if let Some(tags) = &query.tags {
+        let tags: Vec<String> = tags.iter().map(|tag| percent_encode(tag)).collect();
+        params.push(format!("tags={}", tags.join(",")));
+    }
+    if let Some(collection_id) = query.collection_id {
+        params.push(format!("collection={}", collection_id));
+    }
+    params
+}
+
+fn url(base: &str, format: Format, params: &[String]) -> String {
+    let mut url = format!("{}/feeds/haikus.{}", base, format.extension());
+    if !params.is_empty() {
+        url.push('?');
+        url.push_str(&params.join("&"));
+    }
+    url
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
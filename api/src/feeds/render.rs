@@ -0,0 +1,128 @@
+use crate::haiku::entity::Haiku;
+use chrono::{DateTime, Utc};
+
+/// RFC 5005 paging links of one feed page.
+pub struct PageLinks {
+    pub current: String,
+    pub first: String,
+    pub last: String,
+    pub previous: Option<String>,
+    pub next: Option<String>,
+}
+
+impl PageLinks {
+    fn iter(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        [
+            Some(("self", self.current.as_str())),
+            Some(("first", self.first.as_str())),
+            Some(("last", self.last.as_str())),
+            self.previous.as_deref().map(|url| ("previous", url)),
+            self.next.as_deref().map(|url| ("next", url)),
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
+pub struct Feed<'a> {
+    pub title: String,
+    /// Stable identifier of the feed, independent of the page.
+    pub id: String,
+    pub updated: DateTime<Utc>,
+    /// Public base URL, used to link entries to their cards.
+    pub base: &'a str,
+    pub links: PageLinks,
+    pub haikus: &'a [Haiku],
+}
+
+impl Feed<'_> {
+    pub fn atom(&self) -> String {
+        let mut xml = String::from(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+        xml.push_str(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#);
+        xml.push_str(&format!(
+            "<id>{}</id><title>{}</title><updated>{}</updated>",
+            escape(&self.id),
+            escape(&self.title),
+            self.updated.to_rfc3339()
+        ));
+        for (rel, href) in self.links.iter() {
+            xml.push_str(&format!(
+                r#"<link rel="{}" type="application/atom+xml" href="{}"/>"#,
+                rel,
+                escape(href)
+            ));
+        }
+        for haiku in self.haikus {
+            xml.push_str(&format!(
+                r#"<entry><id>urn:uuid:{id}</id><title>{title}</title><published>{published}</published><updated>{updated}</updated><link rel="enclosure" type="image/png" href="{card}"/><content type="html">{content}</content></entry>"#,
+                id = haiku.id,
+                title = escape(title(haiku)),
+                published = haiku.created_at.to_rfc3339(),
+                updated = haiku.updated_at.to_rfc3339(),
+                card = escape(&self.card_url(haiku)),
+                content = escape(&html(haiku)),
+            ));
+        }
+        xml.push_str("</feed>");
+        xml
+    }
+
+    pub fn rss(&self) -> String {
+        let mut xml = String::from(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+        xml.push_str(r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom"><channel>"#);
+        xml.push_str(&format!(
+            "<title>{}</title><link>{}</link><description>{}</description><lastBuildDate>{}</lastBuildDate>",
+            escape(&self.title),
+            escape(&self.links.first),
+            escape(&self.title),
+            self.updated.to_rfc2822()
+        ));
+        for (rel, href) in self.links.iter() {
+            xml.push_str(&format!(
+                r#"<atom:link rel="{}" type="application/rss+xml" href="{}"/>"#,
+                rel,
+                escape(href)
+            ));
+        }
+        for haiku in self.haikus {
+            xml.push_str(&format!(
+                r#"<item><guid isPermaLink="false">urn:uuid:{id}</guid><title>{title}</title><pubDate>{published}</pubDate><link>{card}</link><description>{content}</description></item>"#,
+                id = haiku.id,
+                title = escape(title(haiku)),
+                published = haiku.created_at.to_rfc2822(),
+                card = escape(&self.card_url(haiku)),
+                content = escape(&html(haiku)),
+            ));
+        }
+        xml.push_str("</channel></rss>");
+        xml
+    }
+
+    fn card_url(&self, haiku: &Haiku) -> String {
+        format!("{}/haikus/{}/card.png", self.base, haiku.id)
+    }
+}
+
+fn lines(haiku: &Haiku) -> impl Iterator<Item = &str> {
+    haiku
+        .content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+}
+
+fn title(haiku: &Haiku) -> &str {
+    lines(haiku).next().unwrap_or("Untitled haiku")
+}
+
+fn html(haiku: &Haiku) -> String {
+    let lines: Vec<String> = lines(haiku).map(escape).collect();
+    format!("<p>{}</p>", lines.join("<br>"))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
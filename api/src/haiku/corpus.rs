@@ -0,0 +1,442 @@
+//! Import of hand-written haikus from plain text and Markdown.
+//!
+//! Haikus are stanzas of three lines separated by blank lines. Front matter
+//! between `---` lines sets the `prompt` (by title), `tags` and `author` of
+//! the stanzas after it, and a stanza may start with its own `prompt:`,
+//! `tags:` or `author:` lines, bare or in HTML comments, so the Markdown and
+//! text exports can be imported back. Markdown headings, other comments,
+//! hard line breaks and escapes are ignored.
+
+use super::entity::{InputHaiku, SimilarHaiku};
+use super::meter::{self, Meter};
+use super::similarity::Fingerprint;
+use crate::prompts::entity::{Prompt, PromptInput};
+use crate::tags::entity::{Tag, normalize_names};
+use async_graphql::{Enum, SimpleObject};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::fmt;
+use uuid::Uuid;
+
+/// Front matter and stanza header keys; any other key is ignored.
+const KEYS: [&str; 5] = ["prompt", "tags", "author", "date", "id"];
+
+#[derive(Debug, Clone, Default)]
+struct Metadata {
+    prompt: Option<String>,
+    tags: Vec<String>,
+    author: Option<String>,
+}
+
+impl Metadata {
+    fn set(&mut self, key: &str, value: &str) {
+        let value = unquote(value);
+        match key {
+            "prompt" => self.prompt = Some(value).filter(|value| !value.is_empty()),
+            "author" => self.author = Some(value).filter(|value| !value.is_empty()),
+            "tags" => {
+                let list = value.trim_start_matches('[').trim_end_matches(']');
+                self.tags = normalize_names(list.split(',').map(unquote).collect());
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Stanza {
+    /// 1-based line the stanza starts on.
+    line: usize,
+    lines: Vec<String>,
+    metadata: Metadata,
+}
+
+fn parse(text: &str) -> Vec<Stanza> {
+    let mut stanzas = Vec::new();
+    let mut defaults = Metadata::default();
+    let mut current: Option<Stanza> = None;
+    let mut front_matter = false;
+
+    for (index, raw) in text.lines().enumerate() {
+        let line = raw.trim();
+        if front_matter {
+            if line == "---" {
+                front_matter = false;
+            } else if let Some((key, value)) = line.split_once(':') {
+                defaults.set(key.trim(), value);
+            }
+            continue;
+        }
+        if line == "---" && current.is_none() {
+            front_matter = true;
+            defaults = Metadata::default();
+            continue;
+        }
+        if line.is_empty() || is_heading(line) {
+            stanzas.extend(current.take());
+            continue;
+        }
+        let comment = line
+            .strip_prefix("<!--")
+            .and_then(|line| line.strip_suffix("-->"))
+            .map(str::trim);
+        let header = header(comment.unwrap_or(line));
+        if comment.is_some() && header.is_none() {
+            continue;
+        }
+
+        let stanza = current.get_or_insert_with(|| Stanza {
+            line: index + 1,
+            lines: Vec::new(),
+            metadata: defaults.clone(),
+        });
+        if let Some((key, value)) = header
+            && stanza.lines.is_empty()
+        {
+            stanza.metadata.set(key, value);
+            continue;
+        }
+        if comment.is_some() {
+            continue;
+        }
+        stanza.lines.push(unescape_markdown(line));
+    }
+    stanzas.extend(current);
+    stanzas
+}
+
+/// A `key: value` line with one of the `KEYS`.
+fn header(line: &str) -> Option<(&str, &str)> {
+    line.split_once(": ").filter(|(key, _)| KEYS.contains(key))
+}
+
+fn is_heading(line: &str) -> bool {
+    let hashes = line.chars().take_while(|&c| c == '#').count();
+    (1..=6).contains(&hashes) && line[hashes..].starts_with(' ')
+}
+
+/// Drops hard line breaks (a trailing backslash or spaces) and backslash
+/// escapes.
+fn unescape_markdown(line: &str) -> String {
+    let line = line.strip_suffix('\\').unwrap_or(line).trim_end();
+    let mut unescaped = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Strips the quotes of a YAML or JSON scalar.
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        return serde_json::from_str(value)
+            .unwrap_or_else(|_| value[1..value.len() - 1].to_string());
+    }
+    if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+        return value[1..value.len() - 1].replace("''", "'");
+    }
+    value.to_string()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CorpusOptions {
+    /// Prompt of stanzas whose front matter names none.
+    pub prompt_id: Option<Uuid>,
+    /// Check everything without writing.
+    pub dry_run: bool,
+    /// Syllables each line may be off by.
+    pub tolerance: i32,
+    /// Similarity from which an entry counts as a duplicate.
+    pub duplicate_threshold: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+pub enum EntryStatus {
+    Accepted,
+    Rejected,
+    Duplicate,
+}
+
+#[derive(Debug, SimpleObject)]
+pub struct CorpusEntry {
+    /// 1-based line of the input the stanza starts on.
+    pub line: i32,
+    pub content: String,
+    pub status: EntryStatus,
+    pub reason: Option<String>,
+    pub meter: Meter,
+    /// The stored haiku, unless this was a dry run.
+    pub haiku_id: Option<Uuid>,
+    /// Existing haiku this entry is a near-duplicate of, if it was not one
+    /// earlier in the same input.
+    pub duplicate_of: Option<Uuid>,
+}
+
+#[derive(Debug, SimpleObject)]
+pub struct CorpusReport {
+    pub dry_run: bool,
+    pub accepted: i32,
+    pub rejected: i32,
+    pub duplicates: i32,
+    pub entries: Vec<CorpusEntry>,
+}
+
+impl fmt::Display for CorpusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in &self.entries {
+            let first_line = entry.content.lines().next().unwrap_or_default();
+            let status = match entry.status {
+                EntryStatus::Accepted => "accepted",
+                EntryStatus::Rejected => "rejected",
+                EntryStatus::Duplicate => "duplicate",
+            };
+            write!(f, "line {:>5}  {:<9}  {}", entry.line, status, first_line)?;
+            if let Some(reason) = &entry.reason {
+                write!(f, "  ({})", reason)?;
+            }
+            writeln!(f)?;
+        }
+        write!(
+            f,
+            "{} accepted, {} rejected, {} duplicates",
+            self.accepted, self.rejected, self.duplicates
+        )?;
+        if self.dry_run {
+            write!(f, "\nDry run, nothing was written.")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses `text` and stores every stanza that follows the meter, has a
+/// prompt and is not a near-duplicate of an existing haiku or of an
+/// earlier stanza. Prompts named in front matter are created if missing.
+///
+/// Everything happens in one transaction, so an error stores nothing rather
+/// than part of the input.
+pub async fn import(
+    pool: &PgPool,
+    text: &str,
+    options: CorpusOptions,
+) -> Result<CorpusReport, sqlx::Error> {
+    let mut report = CorpusReport {
+        dry_run: options.dry_run,
+        accepted: 0,
+        rejected: 0,
+        duplicates: 0,
+        entries: Vec::new(),
+    };
+    // Prompt ids by lowercased title, `None` for prompts a dry run would create.
+    let mut prompts: HashMap<String, Option<Uuid>> = HashMap::new();
+    let mut accepted: Vec<(Fingerprint, Option<Uuid>)> = Vec::new();
+    let mut tx = pool.begin().await?;
+
+    for stanza in parse(text) {
+        let content = stanza.lines.join("\n");
+        let meter = meter::check(&content, options.tolerance);
+        let mut entry = CorpusEntry {
+            line: stanza.line as i32,
+            content,
+            status: EntryStatus::Rejected,
+            reason: meter.problem.clone(),
+            meter,
+            haiku_id: None,
+            duplicate_of: None,
+        };
+        if !entry.meter.valid {
+            report.rejected += 1;
+            report.entries.push(entry);
+            continue;
+        }
+
+        let prompt_id = match &stanza.metadata.prompt {
+            Some(title) => match prompts.get(&title.to_lowercase()) {
+                Some(&id) => id,
+                None => {
+                    let id = match Prompt::find_by_title(&mut *tx, title).await? {
+                        Some(prompt) => Some(prompt.id),
+                        None if options.dry_run => None,
+                        None => {
+                            let input = PromptInput {
+                                title: title.clone(),
+                                content: title.clone(),
+                                language: None,
+                            };
+                            Some(PromptInput::create(&mut *tx, input).await?.id)
+                        }
+                    };
+                    prompts.insert(title.to_lowercase(), id);
+                    id
+                }
+            },
+            None if options.prompt_id.is_some() => options.prompt_id,
+            None => {
+                entry.reason = Some("No prompt in the front matter and no default prompt".into());
+                report.rejected += 1;
+                report.entries.push(entry);
+                continue;
+            }
+        };
+
+        let fingerprint = Fingerprint::of(&entry.content);
+        let earlier = accepted
+            .iter()
+            .find(|(other, _)| fingerprint.similarity(other) >= options.duplicate_threshold);
+        let existing = match earlier {
+            Some(_) => None,
+            None => {
+                SimilarHaiku::list(&mut *tx, &fingerprint, None, options.duplicate_threshold, 1)
+                    .await?
+                    .into_iter()
+                    .next()
+            }
+        };
+        if let Some((_, id)) = earlier {
+            entry.status = EntryStatus::Duplicate;
+            entry.reason = Some("Near-duplicate of an earlier entry".into());
+            entry.duplicate_of = *id;
+        } else if let Some(similar) = existing {
+            entry.status = EntryStatus::Duplicate;
+            entry.reason = Some(format!(
+                "Near-duplicate of {} ({:.0}% similar)",
+                similar.haiku.id,
+                similar.similarity * 100.0
+            ));
+            entry.duplicate_of = Some(similar.haiku.id);
+        }
+        if entry.status == EntryStatus::Duplicate {
+            report.duplicates += 1;
+            report.entries.push(entry);
+            continue;
+        }
+
+        if let (false, Some(prompt_id)) = (options.dry_run, prompt_id) {
+            let haiku = InputHaiku::create(
+                &mut *tx,
+                InputHaiku {
+                    content: entry.content.clone(),
+                    is_funny: false,
+                    prompt_id,
+                    duplicate_of: None,
+                    author: stanza.metadata.author.clone(),
+                },
+            )
+            .await?;
+            if !stanza.metadata.tags.is_empty() {
+                Tag::tag_haiku(&mut *tx, haiku.id, &stanza.metadata.tags).await?;
+            }
+            entry.haiku_id = Some(haiku.id);
+        }
+        entry.status = EntryStatus::Accepted;
+        accepted.push((fingerprint, entry.haiku_id));
+        report.accepted += 1;
+        report.entries.push(entry);
+    }
+    tx.commit().await?;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(stanza: &Stanza) -> Vec<&str> {
+        stanza.lines.iter().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn splits_stanzas_on_blank_lines_and_headings() {
+        let stanzas = parse(
+            "An old silent pond\nA frog jumps into the pond\nSplash! Silence again\n\n\
+             # Autumn\nLightning flash\nWhat I thought were faces\nAre plumes of pampas grass\n",
+        );
+        assert_eq!(stanzas.len(), 2);
+        assert_eq!(stanzas[0].line, 1);
+        assert_eq!(lines(&stanzas[0])[0], "An old silent pond");
+        assert_eq!(stanzas[1].line, 6);
+        assert_eq!(lines(&stanzas[1]).len(), 3);
+    }
+
+    #[test]
+    fn front_matter_sets_defaults_until_the_next() {
+        let stanzas = parse(
+            "---\nprompt: \"Autumn moon\"\ntags: [autumn, moon]\nauthor: Bashō\n---\n\
+             one\ntwo\nthree\n\nfour\nfive\nsix\n\n---\nprompt: Winter\n---\nseven\neight\nnine\n",
+        );
+        assert_eq!(stanzas.len(), 3);
+        for stanza in &stanzas[..2] {
+            assert_eq!(stanza.metadata.prompt.as_deref(), Some("Autumn moon"));
+            assert_eq!(stanza.metadata.tags, ["autumn", "moon"]);
+            assert_eq!(stanza.metadata.author.as_deref(), Some("Bashō"));
+        }
+        assert_eq!(stanzas[2].metadata.prompt.as_deref(), Some("Winter"));
+        assert!(stanzas[2].metadata.tags.is_empty());
+        assert_eq!(stanzas[2].metadata.author, None);
+    }
+
+    #[test]
+    fn stanza_headers_override_front_matter() {
+        let stanzas = parse(
+            "---\nprompt: Autumn\n---\nprompt: Spring\nauthor: 'Issa''s'\nid: 1234\none\ntwo\nthree\n",
+        );
+        assert_eq!(stanzas.len(), 1);
+        assert_eq!(stanzas[0].metadata.prompt.as_deref(), Some("Spring"));
+        assert_eq!(stanzas[0].metadata.author.as_deref(), Some("Issa's"));
+        assert_eq!(lines(&stanzas[0]), ["one", "two", "three"]);
+    }
+
+    #[test]
+    fn drops_markdown_line_breaks_and_escapes() {
+        assert_eq!(
+            unescape_markdown("the first cold shower\\"),
+            "the first cold shower"
+        );
+        assert_eq!(unescape_markdown("even the monkey  "), "even the monkey");
+        assert_eq!(unescape_markdown("\\*seems\\* to want"), "*seems* to want");
+        assert!(is_heading("## Winter"));
+        assert!(!is_heading("#hashtag"));
+        assert!(!is_heading("####### seven"));
+    }
+
+    #[test]
+    fn unquotes_yaml_and_json_scalars() {
+        assert_eq!(unquote(" plain "), "plain");
+        assert_eq!(unquote("\"a \\\"b\\\"\""), "a \"b\"");
+        assert_eq!(unquote("'it''s'"), "it's");
+        assert_eq!(unquote("\""), "\"");
+    }
+
+    #[test]
+    fn reads_back_the_markdown_export() {
+        let entry = crate::feeds::entity::HaikuEntry {
+            haiku: serde_json::from_value(serde_json::json!({
+                "id": "6f1c1a8e-0000-4000-8000-000000000001",
+                "content": "An old silent pond\nA frog jumps into the pond\nSplash! Silence again",
+                "is_funny": false,
+                "prompt_id": "6f1c1a8e-0000-4000-8000-000000000002",
+                "duplicate_of": null,
+                "author": null,
+                "version": 1,
+                "created_at": "2025-03-01T12:00:00Z",
+                "updated_at": "2025-03-01T12:00:00Z",
+                "deleted_at": null,
+            }))
+            .unwrap(),
+            prompt_title: "Old -- pond".to_string(),
+        };
+        let document = crate::exports::render::markdown_header(chrono::Utc::now())
+            + "<!-- a note -->\n\n"
+            + &crate::exports::render::markdown(&entry);
+        let stanzas = parse(&document);
+        assert_eq!(stanzas.len(), 1);
+        assert_eq!(stanzas[0].metadata.prompt.as_deref(), Some("Old -- pond"));
+        assert_eq!(lines(&stanzas[0])[1], "A frog jumps into the pond");
+        assert_eq!(lines(&stanzas[0]).len(), 3);
+    }
+}
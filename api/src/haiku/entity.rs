@@ -1,145 +1,434 @@
-use async_graphql::SimpleObject;
+use super::favorite::Favorite;
+use super::kigo::{Kigo, Saijiki, Season, season_of};
+use super::meter::{self, Meter};
+use super::rating::Rating;
+use super::similarity::Fingerprint;
+use crate::app::error::UpdateError;
+use crate::app::validation::{FieldValue, Rule, Validate, Validator};
+use crate::auth::viewer::Viewer;
+use crate::prompts::entity::Prompt;
+use crate::tags::entity::Tag;
+use async_graphql::{ComplexObject, Context, InputObject, SimpleObject};
 use chrono::{DateTime, Utc};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool};
+use sqlx::{FromRow, PgExecutor, PgPool};
 use uuid::Uuid;
-use crate::prompts::entity::{Prompt, PromptInput, UpdatePrompt};
 
 #[derive(SimpleObject, Serialize, Deserialize, Debug, FromRow)]
+#[graphql(complex)]
 pub struct Haiku {
-    id: i32,
-    content: String,
-    is_funny: bool,
-    created_at: DateTime<Utc>,
-    updated_at: DateTime<Utc>,
+    pub id: Uuid,
+    pub content: String,
+    pub is_funny: bool,
+    pub prompt_id: Uuid,
+    /// Earlier haiku this one was flagged as a near-duplicate of.
+    pub duplicate_of: Option<Uuid>,
+    /// Who wrote it, for haikus that were not generated.
+    pub author: Option<String>,
+    /// Incremented on every update; pass it back as `expectedVersion` to
+    /// detect concurrent edits.
+    pub version: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
     deleted_at: Option<DateTime<Utc>>,
 }
 
+#[ComplexObject]
 impl Haiku {
-    pub async fn list(pool: &PgPool) -> Result<Vec<Haiku>, sqlx::Error> {
+    async fn average_rating(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<f64>> {
+        let pool = ctx.data::<PgPool>()?;
+        let average = Rating::average(pool, self.id).await?;
+        Ok(average)
+    }
+
+    /// The viewer's own rating, `null` when anonymous or not rated yet.
+    async fn my_rating(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<i32>> {
+        let pool = ctx.data::<PgPool>()?;
+        let Some(viewer) = Viewer::current(ctx) else {
+            return Ok(None);
+        };
+        let rating = Rating::get(pool, viewer.user_id, self.id).await?;
+        Ok(rating)
+    }
+
+    async fn is_favorite(&self, ctx: &Context<'_>) -> async_graphql::Result<bool> {
+        let pool = ctx.data::<PgPool>()?;
+        let Some(viewer) = Viewer::current(ctx) else {
+            return Ok(false);
+        };
+        let favorite = Favorite::exists(pool, viewer.user_id, self.id).await?;
+        Ok(favorite)
+    }
+
+    /// Season words found in the text.
+    async fn kigo(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Kigo>> {
+        let pool = ctx.data::<PgPool>()?;
+        let saijiki = Saijiki::current(pool).await?;
+        Ok(saijiki.find(&self.content))
+    }
+
+    /// Syllable count per line, checked against 5-7-5.
+    async fn meter(&self) -> Meter {
+        meter::check(&self.content, 0)
+    }
+
+    /// Season the haiku's kigo point to, `null` without any.
+    async fn season(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<Season>> {
+        let pool = ctx.data::<PgPool>()?;
+        let saijiki = Saijiki::current(pool).await?;
+        Ok(season_of(&saijiki.find(&self.content)))
+    }
+
+    async fn tags(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Tag>> {
+        let pool = ctx.data::<PgPool>()?;
+        let tags = Tag::list_for_haiku(pool, self.id).await?;
+        Ok(tags)
+    }
+}
+
+impl Haiku {
+    /// Live haikus, restricted to those carrying every tag in `tags` if given.
+    pub async fn list(pool: &PgPool, tags: Option<&[String]>) -> Result<Vec<Haiku>, sqlx::Error> {
         let haikus = sqlx::query_as::<_, Haiku>(
             r#"
-            SELECT id, content, is_funny, created_at, updated_at, deleted_at
+            SELECT id, content, is_funny, prompt_id, duplicate_of, author, version, created_at, updated_at, deleted_at
             FROM haikus
             WHERE deleted_at IS NULL
+              AND ($1::text[] IS NULL OR id IN (
+                  SELECT ht.haiku_id
+                  FROM haiku_tags ht
+                  JOIN tags t ON t.id = ht.tag_id
+                  WHERE t.name = ANY($1)
+                  GROUP BY ht.haiku_id
+                  HAVING count(*) = cardinality($1)
+              ))
+            "#,
+        )
+        .bind(tags)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(haikus)
+    }
+
+    pub async fn list_by_ids(pool: &PgPool, ids: &[Uuid]) -> Result<Vec<Haiku>, sqlx::Error> {
+        let haikus = sqlx::query_as::<_, Haiku>(
+            r#"
+            SELECT id, content, is_funny, prompt_id, duplicate_of, author, version, created_at, updated_at, deleted_at
+            FROM haikus
+            WHERE id = ANY($1) AND deleted_at IS NULL
             "#,
-        ).fetch_all(pool).await?;
+        )
+        .bind(ids)
+        .fetch_all(pool)
+        .await?;
 
         Ok(haikus)
     }
 
+    /// Text search configurations of the haikus, the most used first.
+    pub async fn languages(pool: &PgPool, ids: &[Uuid]) -> Result<Vec<String>, sqlx::Error> {
+        let languages = sqlx::query_scalar::<_, String>(
+            r#"
+            SELECT language::text
+            FROM haikus
+            WHERE id = ANY($1)
+            GROUP BY language
+            ORDER BY count(*) DESC, language::text
+            "#,
+        )
+        .bind(ids)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(languages)
+    }
+
     pub async fn get(pool: &PgPool, id: Uuid) -> Result<Haiku, sqlx::Error> {
-        let prompt = sqlx::query_as::<_, Haiku>(
+        let haiku = sqlx::query_as::<_, Haiku>(
             r#"
-            SELECT id, title, created_at, updated_at, deleted_at
+            SELECT id, content, is_funny, prompt_id, duplicate_of, author, version, created_at, updated_at, deleted_at
             FROM haikus
             WHERE id = $1 AND deleted_at IS NULL
             "#,
         )
-            .bind(id)
-            .fetch_one(pool)
-            .await?;
+        .bind(id)
+        .fetch_one(pool)
+        .await?;
 
-        Ok(prompt)
+        Ok(haiku)
     }
 
     pub async fn delete(pool: &PgPool, id: Uuid) -> Result<Haiku, sqlx::Error> {
-        let prompt = sqlx::query_as::<_, Haiku>(
+        let haiku = sqlx::query_as::<_, Haiku>(
             r#"
             UPDATE haikus
             SET deleted_at = now()
             WHERE id = $1 AND deleted_at IS NULL
-            RETURNING id, content, created_at, updated_at, deleted_at
+            RETURNING id, content, is_funny, prompt_id, duplicate_of, author, version, created_at, updated_at, deleted_at
             "#,
         )
-            .bind(id)
-            .fetch_one(pool)
-            .await?;
+        .bind(id)
+        .fetch_one(pool)
+        .await?;
 
-        Ok(prompt)
+        Ok(haiku)
     }
 
-    pub async fn destroy(pool: &PgPool, id: Uuid) -> Result<Haiku, sqlx::Error> {
-        let prompt = sqlx::query_as::<_, Haiku>(
+    pub async fn destroy(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query(
             r#"
             DELETE FROM haikus
             WHERE id = $1
             "#,
         )
-            .bind(id)
-            .execute(pool)
-            .await?;
+        .bind(id)
+        .execute(pool)
+        .await?;
 
-        Ok(prompt)
+        Ok(())
     }
 
+    /// Haikus created before fingerprints existed, oldest first.
+    pub async fn list_unfingerprinted(
+        pool: &PgPool,
+        limit: i64,
+    ) -> Result<Vec<Haiku>, sqlx::Error> {
+        let haikus = sqlx::query_as::<_, Haiku>(
+            r#"
+            SELECT id, content, is_funny, prompt_id, duplicate_of, author, version, created_at, updated_at, deleted_at
+            FROM haikus
+            WHERE fingerprint IS NULL
+            ORDER BY created_at
+            LIMIT $1
+            "#,
+        )
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(haikus)
+    }
+
+    pub async fn set_fingerprint(
+        pool: &PgPool,
+        id: Uuid,
+        fingerprint: &Fingerprint,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            UPDATE haikus
+            SET fingerprint = $2, fingerprint_bands = $3
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .bind(&fingerprint.signature)
+        .bind(&fingerprint.bands)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Gives up to `limit` haikus of `prompt_id` the prompt's current
+    /// language, which regenerates their search vectors. Returns how many
+    /// changed.
+    pub async fn reindex(pool: &PgPool, prompt_id: Uuid, limit: i64) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE haikus h
+            SET language = p.language
+            FROM prompts p
+            WHERE p.id = h.prompt_id
+              AND h.id IN (
+                  SELECT h.id
+                  FROM haikus h
+                  JOIN prompts p ON p.id = h.prompt_id
+                  WHERE h.prompt_id = $1 AND h.language <> p.language
+                  LIMIT $2
+              )
+            "#,
+        )
+        .bind(prompt_id)
+        .bind(limit)
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Fails as if the haiku were missing while its prompt is in the trash;
+    /// restoring the prompt brings it back instead.
     pub async fn restore(pool: &PgPool, id: Uuid) -> Result<Haiku, sqlx::Error> {
-        let prompt = sqlx::query_as::<_, Haiku>(
+        let haiku = sqlx::query_as::<_, Haiku>(
             r#"
             UPDATE haikus
             SET deleted_at = NULL
             WHERE id = $1 AND deleted_at IS NOT NULL
-            RETURNING id, content, created_at, updated_at, deleted_at
+              AND EXISTS (SELECT 1 FROM prompts p WHERE p.id = prompt_id AND p.deleted_at IS NULL)
+            RETURNING id, content, is_funny, prompt_id, duplicate_of, author, version, created_at, updated_at, deleted_at
             "#,
         )
-            .bind(id)
-            .fetch_one(pool)
-            .await?;
+        .bind(id)
+        .fetch_one(pool)
+        .await?;
 
-        Ok(prompt)
+        Ok(haiku)
+    }
+}
+
+#[derive(Debug, SimpleObject, FromRow)]
+pub struct SimilarHaiku {
+    #[sqlx(flatten)]
+    pub haiku: Haiku,
+    /// Estimated Jaccard similarity of the two texts, between 0 and 1.
+    pub similarity: f32,
+}
+
+impl SimilarHaiku {
+    /// Haikus whose fingerprint is at least `min_similarity` close to
+    /// `fingerprint`, most similar first. Only haikus sharing a band with it
+    /// are considered, which reliably covers similarities above ~0.6.
+    pub async fn list<'e>(
+        executor: impl PgExecutor<'e>,
+        fingerprint: &Fingerprint,
+        exclude_id: Option<Uuid>,
+        min_similarity: f32,
+        limit: i64,
+    ) -> Result<Vec<SimilarHaiku>, sqlx::Error> {
+        let similar = sqlx::query_as::<_, SimilarHaiku>(
+            r#"
+            SELECT *
+            FROM (
+                SELECT id, content, is_funny, prompt_id, duplicate_of, author, version, created_at, updated_at, deleted_at,
+                       (SELECT count(*) FROM UNNEST(fingerprint, $1::int[]) AS slots(a, b) WHERE a = b)::real
+                           / cardinality($1::int[])::real AS similarity
+                FROM haikus
+                WHERE fingerprint_bands && $2
+                  AND deleted_at IS NULL
+                  AND ($3::uuid IS NULL OR id <> $3)
+            ) candidates
+            WHERE similarity >= $4
+            ORDER BY similarity DESC, created_at
+            LIMIT $5
+            "#,
+        )
+        .bind(&fingerprint.signature)
+        .bind(&fingerprint.bands)
+        .bind(exclude_id)
+        .bind(min_similarity)
+        .bind(limit)
+        .fetch_all(executor)
+        .await?;
+
+        Ok(similar)
     }
 }
 
 pub struct InputHaiku {
     pub content: String,
     pub is_funny: bool,
+    pub prompt_id: Uuid,
+    pub duplicate_of: Option<Uuid>,
+    pub author: Option<String>,
 }
 
 impl InputHaiku {
-    pub async fn create(pool: &PgPool, input: InputHaiku) -> Result<Haiku, sqlx::Error> {
-        let prompt = sqlx::query_as::<_, Haiku>(
+    pub async fn create<'e>(
+        executor: impl PgExecutor<'e>,
+        input: InputHaiku,
+    ) -> Result<Haiku, sqlx::Error> {
+        let fingerprint = Fingerprint::of(&input.content);
+        let haiku = sqlx::query_as::<_, Haiku>(
             r#"
-            INSERT INTO haikus (content)
-            VALUES ($1)
-            RETURNING id, content, created_at, updated_at, deleted_at
+            INSERT INTO haikus (content, is_funny, prompt_id, duplicate_of, fingerprint, fingerprint_bands, author, language)
+            SELECT $1, $2, $3, $4, $5, $6, $7, language
+            FROM prompts
+            WHERE id = $3
+            RETURNING id, content, is_funny, prompt_id, duplicate_of, author, version, created_at, updated_at, deleted_at
             "#,
         )
-            .bind(input.content)
-            .fetch_one(pool)
-            .await?;
+        .bind(input.content)
+        .bind(input.is_funny)
+        .bind(input.prompt_id)
+        .bind(input.duplicate_of)
+        .bind(fingerprint.signature)
+        .bind(fingerprint.bands)
+        .bind(input.author)
+        .fetch_one(executor)
+        .await?;
 
-        Ok(prompt)
+        Ok(haiku)
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, InputObject)]
 pub struct UpdateHaiku {
-    pub id: i32,
     pub content: Option<String>,
     pub is_funny: Option<bool>,
+    pub author: Option<String>,
+}
+
+impl Validate for UpdateHaiku {
+    fn normalize(&mut self) {
+        self.content.trim_in_place();
+        self.author.trim_in_place();
+    }
+
+    fn rules(&self, validator: &mut Validator) {
+        validator.field(
+            "content",
+            &self.content,
+            &[Rule::NotBlank, Rule::MaxLength(1000)],
+        );
+        validator.field(
+            "author",
+            &self.author,
+            &[Rule::NotBlank, Rule::MaxLength(255)],
+        );
+    }
 }
 
 impl UpdateHaiku {
+    /// Fails with a conflict when `expected_version` is given and the haiku
+    /// is no longer at that version.
     pub async fn update(
         pool: &PgPool,
         id: Uuid,
         input: UpdateHaiku,
-    ) -> Result<Haiku, sqlx::Error> {
-        let prompt = sqlx::query_as::<_, Haiku>(
+        expected_version: Option<i32>,
+    ) -> Result<Haiku, UpdateError<Haiku>> {
+        let fingerprint = input.content.as_deref().map(Fingerprint::of);
+        let haiku = sqlx::query_as::<_, Haiku>(
             r#"
             UPDATE haikus
-            SET content = COALESCE($1, content), is_funny_, updated_at = now()
-            WHERE id = $3 AND deleted_at IS NULL
-            RETURNING id, title, content, created_at, updated_at, deleted_at
+            SET content = COALESCE($1, content), is_funny = COALESCE($2, is_funny),
+                fingerprint = COALESCE($4, fingerprint),
+                fingerprint_bands = COALESCE($5, fingerprint_bands),
+                author = COALESCE($6, author), version = version + 1, updated_at = now()
+            WHERE id = $3 AND deleted_at IS NULL AND ($7::integer IS NULL OR version = $7)
+            RETURNING id, content, is_funny, prompt_id, duplicate_of, author, version, created_at, updated_at, deleted_at
             "#,
         )
-            .bind(input.title)
-            .bind(input.content)
-            .bind(id)
-            .fetch_one(pool)
-            .await?;
+        .bind(input.content)
+        .bind(input.is_funny)
+        .bind(id)
+        .bind(fingerprint.as_ref().map(|f| &f.signature))
+        .bind(fingerprint.as_ref().map(|f| &f.bands))
+        .bind(input.author)
+        .bind(expected_version)
+        .fetch_optional(pool)
+        .await?;
 
-        Ok(prompt)
+        match haiku {
+            Some(haiku) => Ok(haiku),
+            None => {
+                let current = Haiku::get(pool, id).await.ok();
+                Err(UpdateError::unmatched(expected_version, current))
+            }
+        }
     }
 }
 
@@ -156,6 +445,13 @@ pub struct HaikuResponse {
     pub is_funny: bool,
 }
 
+/// Where the model generating haikus is served.
+#[derive(Debug, Clone)]
+pub struct ModelProvider {
+    pub api_url: String,
+    pub api_key: String,
+}
+
 pub struct DeepseekClient {
     url: String,
     api_key: String,
@@ -163,15 +459,12 @@ pub struct DeepseekClient {
 }
 
 impl DeepseekClient {
-    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let url = std::env::var("DEEPSEEK_API_URL")?;
-        let api_key = std::env::var("DEEPSEEK_API_KEY")?;
-
-        Ok(Self {
-            url,
-            api_key,
+    pub fn new(provider: &ModelProvider) -> Self {
+        Self {
+            url: provider.api_url.clone(),
+            api_key: provider.api_key.clone(),
             client: Client::new(),
-        })
+        }
     }
 
     pub async fn generate_haiku(
@@ -187,7 +480,8 @@ impl DeepseekClient {
             temperature,
         };
 
-        let response = self.client
+        let response = self
+            .client
             .post(url)
             .header("Authorization", format!("Bearer {}", self.api_key))
             .json(&request_body)
@@ -201,4 +495,4 @@ impl DeepseekClient {
             Err(format!("API request failed with status: {}", response.status()).into())
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,73 @@
+use super::entity::Haiku;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub struct Favorite;
+
+impl Favorite {
+    pub async fn list(pool: &PgPool, user_id: Uuid) -> Result<Vec<Haiku>, sqlx::Error> {
+        let haikus = sqlx::query_as::<_, Haiku>(
+            r#"
+            SELECT h.id, h.content, h.is_funny, h.prompt_id, h.duplicate_of, h.author, h.version, h.created_at, h.updated_at, h.deleted_at
+            FROM haiku_favorites f
+            JOIN haikus h ON h.id = f.haiku_id
+            WHERE f.user_id = $1 AND h.deleted_at IS NULL
+            ORDER BY f.created_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(haikus)
+    }
+
+    pub async fn exists(pool: &PgPool, user_id: Uuid, haiku_id: Uuid) -> Result<bool, sqlx::Error> {
+        let exists = sqlx::query_scalar::<_, bool>(
+            r#"
+            SELECT EXISTS (
+                SELECT 1
+                FROM haiku_favorites
+                WHERE user_id = $1 AND haiku_id = $2
+            )
+            "#,
+        )
+        .bind(user_id)
+        .bind(haiku_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(exists)
+    }
+
+    pub async fn add(pool: &PgPool, user_id: Uuid, haiku_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO haiku_favorites (user_id, haiku_id)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(user_id)
+        .bind(haiku_id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove(pool: &PgPool, user_id: Uuid, haiku_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM haiku_favorites
+            WHERE user_id = $1 AND haiku_id = $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(haiku_id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+use super::entity::{DeepseekClient, Haiku, InputHaiku, ModelProvider, SimilarHaiku};
+use super::similarity::{DuplicateAction, DuplicatePolicy, Fingerprint};
+use crate::jobs::entity::Job;
+use crate::jobs::worker::JobError;
+use crate::prompts::entity::Prompt;
+use crate::tags::entity::Tag;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub const GENERATE_HAIKU: &str = "generate_haiku";
+pub const FINGERPRINT_HAIKUS: &str = "fingerprint_haikus";
+pub const REINDEX_HAIKUS: &str = "reindex_haikus";
+
+/// Server-wide options applied to every generated haiku.
+#[derive(Debug, Clone)]
+pub struct GenerationSettings {
+    /// `None` fails generation jobs for good.
+    pub provider: Option<ModelProvider>,
+    pub duplicates: DuplicatePolicy,
+    /// Tag new haikus with the existing tags their text mentions.
+    pub auto_tag: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GenerateHaikuPayload {
+    pub prompt_id: Uuid,
+    pub max_tokens: i32,
+    pub temperature: f32,
+}
+
+pub async fn generate_haiku(
+    pool: PgPool,
+    job: Job,
+    settings: GenerationSettings,
+) -> Result<(), JobError> {
+    let payload: GenerateHaikuPayload = serde_json::from_value(job.payload)?;
+    generate(
+        &pool,
+        payload.prompt_id,
+        payload.max_tokens,
+        payload.temperature,
+        &settings,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Asks the model for a haiku on `prompt_id` and stores it, applying the
+/// near-duplicate policy first and auto-tagging it if enabled.
+pub async fn generate(
+    pool: &PgPool,
+    prompt_id: Uuid,
+    max_tokens: i32,
+    temperature: f32,
+    settings: &GenerationSettings,
+) -> Result<Haiku, JobError> {
+    let duplicates = settings.duplicates;
+    let prompt = match Prompt::get(pool, prompt_id).await {
+        Err(sqlx::Error::RowNotFound) => {
+            return Err(JobError::Permanent(format!(
+                "Prompt {} not found",
+                prompt_id
+            )));
+        }
+        result => result?,
+    };
+
+    let provider = settings
+        .provider
+        .as_ref()
+        .ok_or_else(|| JobError::Permanent("No generation provider is configured".to_string()))?;
+    let client = DeepseekClient::new(provider);
+    let response = client
+        .generate_haiku(&prompt, max_tokens, temperature)
+        .await
+        .map_err(|e| JobError::Failed(e.to_string()))?;
+
+    let fingerprint = Fingerprint::of(&response.haiku);
+    let closest = SimilarHaiku::list(pool, &fingerprint, None, duplicates.threshold, 1)
+        .await?
+        .into_iter()
+        .next();
+    let duplicate_of = match (closest, duplicates.action) {
+        (Some(similar), DuplicateAction::Reject) => {
+            return Err(JobError::Failed(format!(
+                "Generated haiku is a near-duplicate of {} ({:.0}% similar)",
+                similar.haiku.id,
+                similar.similarity * 100.0
+            )));
+        }
+        (Some(similar), DuplicateAction::Flag) => Some(similar.haiku.id),
+        (None, _) => None,
+    };
+
+    let haiku = InputHaiku::create(
+        pool,
+        InputHaiku {
+            content: response.haiku,
+            is_funny: response.is_funny,
+            prompt_id,
+            duplicate_of,
+            author: None,
+        },
+    )
+    .await?;
+
+    if settings.auto_tag {
+        let names: Vec<String> = Tag::suggest(pool, &haiku.content)
+            .await?
+            .into_iter()
+            .map(|tag| tag.name)
+            .collect();
+        Tag::tag_haiku(pool, haiku.id, &names).await?;
+    }
+
+    Ok(haiku)
+}
+
+/// Fingerprints haikus stored without one, either from before near-duplicate
+/// detection existed or imported.
+pub async fn fingerprint_haikus(pool: PgPool, _job: Job) -> Result<(), JobError> {
+    loop {
+        let haikus = Haiku::list_unfingerprinted(&pool, 500).await?;
+        if haikus.is_empty() {
+            return Ok(());
+        }
+
+        for haiku in haikus {
+            Haiku::set_fingerprint(&pool, haiku.id, &Fingerprint::of(&haiku.content)).await?;
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReindexHaikusPayload {
+    pub prompt_id: Uuid,
+}
+
+/// Brings the haikus of a prompt whose language changed to that language,
+/// so that search stems them like the prompt.
+pub async fn reindex_haikus(pool: PgPool, job: Job) -> Result<(), JobError> {
+    let payload: ReindexHaikusPayload = serde_json::from_value(job.payload)?;
+    loop {
+        let reindexed = Haiku::reindex(&pool, payload.prompt_id, 500).await?;
+        if reindexed < 500 {
+            return Ok(());
+        }
+    }
+}
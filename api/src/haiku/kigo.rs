@@ -0,0 +1,332 @@
+//! Season words (kigo) and the saijiki they are looked up in.
+//!
+//! The dictionary bundled below covers the classic English and Japanese
+//! season words. Admins can add entries, which are stored in the `kigo` table
+//! and take precedence over a bundled entry with the same word.
+
+use super::similarity::normalize;
+use async_graphql::{Enum, InputObject, SimpleObject};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Enum, sqlx::Type)]
+#[sqlx(type_name = "season", rename_all = "snake_case")]
+pub enum Season {
+    Spring,
+    Summer,
+    Autumn,
+    Winter,
+    NewYear,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Enum, sqlx::Type)]
+#[sqlx(type_name = "text")]
+pub enum KigoLanguage {
+    #[sqlx(rename = "en")]
+    English,
+    #[sqlx(rename = "ja")]
+    Japanese,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject, FromRow)]
+pub struct Kigo {
+    /// `null` for entries of the bundled dictionary.
+    pub id: Option<Uuid>,
+    pub word: String,
+    pub season: Season,
+    pub language: KigoLanguage,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl Kigo {
+    /// Entries added on top of the bundled dictionary.
+    pub async fn list_custom(pool: &PgPool) -> Result<Vec<Kigo>, sqlx::Error> {
+        let kigo = sqlx::query_as::<_, Kigo>(
+            r#"
+            SELECT id, word, season, language, created_at
+            FROM kigo
+            ORDER BY created_at
+            "#,
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(kigo)
+    }
+
+    pub async fn destroy(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM kigo
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+        Saijiki::invalidate();
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, InputObject)]
+pub struct KigoInput {
+    word: String,
+    season: Season,
+    language: KigoLanguage,
+}
+
+impl KigoInput {
+    pub async fn create(
+        pool: &PgPool,
+        data: KigoInput,
+        created_by: Uuid,
+    ) -> Result<Kigo, sqlx::Error> {
+        let word = match data.language {
+            KigoLanguage::English => normalize(&data.word),
+            KigoLanguage::Japanese => data.word.trim().to_string(),
+        };
+        let kigo = sqlx::query_as::<_, Kigo>(
+            r#"
+            INSERT INTO kigo (word, season, language, created_by)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (word, language) DO UPDATE SET season = EXCLUDED.season
+            RETURNING id, word, season, language, created_at
+            "#,
+        )
+        .bind(word)
+        .bind(data.season)
+        .bind(data.language)
+        .bind(created_by)
+        .fetch_one(pool)
+        .await?;
+
+        Saijiki::invalidate();
+
+        Ok(kigo)
+    }
+}
+
+/// Loaded dictionary, shared by every schema. Only this process is
+/// invalidated when entries change; other instances pick them up on restart.
+static CURRENT: RwLock<Option<Arc<Saijiki>>> = RwLock::new(None);
+
+#[derive(Debug, Default)]
+pub struct Saijiki {
+    entries: Vec<Kigo>,
+}
+
+impl Saijiki {
+    pub fn bundled() -> Self {
+        let entries = BUNDLED
+            .iter()
+            .map(|&(word, season, language)| Kigo {
+                id: None,
+                word: word.to_string(),
+                season,
+                language,
+                created_at: None,
+            })
+            .collect();
+
+        Self { entries }
+    }
+
+    /// The bundled dictionary merged with the stored entries.
+    pub async fn load(pool: &PgPool) -> Result<Self, sqlx::Error> {
+        let mut entries: HashMap<(String, KigoLanguage), Kigo> = Self::bundled()
+            .entries
+            .into_iter()
+            .map(|kigo| ((kigo.word.clone(), kigo.language), kigo))
+            .collect();
+        for kigo in Kigo::list_custom(pool).await? {
+            entries.insert((kigo.word.clone(), kigo.language), kigo);
+        }
+
+        let mut entries: Vec<Kigo> = entries.into_values().collect();
+        entries.sort_by(|a, b| (a.season as u8, &a.word).cmp(&(b.season as u8, &b.word)));
+
+        Ok(Self { entries })
+    }
+
+    /// The cached dictionary, loading it on first use.
+    pub async fn current(pool: &PgPool) -> Result<Arc<Saijiki>, sqlx::Error> {
+        if let Some(saijiki) = CURRENT.read().expect("saijiki lock poisoned").as_ref() {
+            return Ok(saijiki.clone());
+        }
+
+        let saijiki = Arc::new(Self::load(pool).await?);
+        *CURRENT.write().expect("saijiki lock poisoned") = Some(saijiki.clone());
+        Ok(saijiki)
+    }
+
+    pub fn invalidate() {
+        *CURRENT.write().expect("saijiki lock poisoned") = None;
+    }
+
+    pub fn entries(&self) -> &[Kigo] {
+        &self.entries
+    }
+
+    /// Season words found in `content`, in the order they appear. English
+    /// words match whole words only; a word that is part of a longer match,
+    /// like "moon" in "winter moon", is dropped.
+    pub fn find(&self, content: &str) -> Vec<Kigo> {
+        let words = format!(" {} ", normalize(content));
+        let mut found: Vec<(usize, &Kigo)> = self
+            .entries
+            .iter()
+            .filter_map(|kigo| {
+                let position = match kigo.language {
+                    KigoLanguage::English => words.find(&format!(" {} ", kigo.word)),
+                    KigoLanguage::Japanese => content.find(&kigo.word),
+                };
+                position.map(|position| (position, kigo))
+            })
+            .collect();
+
+        let all = found.clone();
+        found.retain(|(_, kigo)| {
+            !all.iter().any(|(_, other)| {
+                other.language == kigo.language
+                    && other.word.len() > kigo.word.len()
+                    && other.word.contains(kigo.word.as_str())
+            })
+        });
+        found.sort_by_key(|&(position, _)| position);
+        found.into_iter().map(|(_, kigo)| kigo.clone()).collect()
+    }
+}
+
+/// The season most of `kigo` point to, the earliest one winning ties.
+pub fn season_of(kigo: &[Kigo]) -> Option<Season> {
+    let mut counts: Vec<(Season, usize)> = Vec::new();
+    for kigo in kigo {
+        match counts.iter_mut().find(|(season, _)| *season == kigo.season) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((kigo.season, 1)),
+        }
+    }
+
+    counts
+        .iter()
+        .rev()
+        .max_by_key(|&&(_, count)| count)
+        .map(|&(season, _)| season)
+}
+
+use KigoLanguage::{English as En, Japanese as Ja};
+use Season::{Autumn, NewYear, Spring, Summer, Winter};
+
+const BUNDLED: &[(&str, Season, KigoLanguage)] = &[
+    ("spring", Spring, En),
+    ("cherry blossom", Spring, En),
+    ("cherry blossoms", Spring, En),
+    ("plum blossom", Spring, En),
+    ("plum blossoms", Spring, En),
+    ("spring rain", Spring, En),
+    ("haze", Spring, En),
+    ("frog", Spring, En),
+    ("frogs", Spring, En),
+    ("skylark", Spring, En),
+    ("warbler", Spring, En),
+    ("butterfly", Spring, En),
+    ("swallow", Spring, En),
+    ("swallows", Spring, En),
+    ("thaw", Spring, En),
+    ("wisteria", Spring, En),
+    ("summer", Summer, En),
+    ("cicada", Summer, En),
+    ("cicadas", Summer, En),
+    ("firefly", Summer, En),
+    ("fireflies", Summer, En),
+    ("cuckoo", Summer, En),
+    ("lotus", Summer, En),
+    ("peony", Summer, En),
+    ("summer grass", Summer, En),
+    ("summer rain", Summer, En),
+    ("heat", Summer, En),
+    ("thunder", Summer, En),
+    ("mosquito", Summer, En),
+    ("hydrangea", Summer, En),
+    ("autumn", Autumn, En),
+    ("moon", Autumn, En),
+    ("harvest moon", Autumn, En),
+    ("autumn wind", Autumn, En),
+    ("maple", Autumn, En),
+    ("red leaves", Autumn, En),
+    ("cricket", Autumn, En),
+    ("crickets", Autumn, En),
+    ("chrysanthemum", Autumn, En),
+    ("scarecrow", Autumn, En),
+    ("wild geese", Autumn, En),
+    ("dragonfly", Autumn, En),
+    ("persimmon", Autumn, En),
+    ("morning glory", Autumn, En),
+    ("mist", Autumn, En),
+    ("dew", Autumn, En),
+    ("winter", Winter, En),
+    ("snow", Winter, En),
+    ("frost", Winter, En),
+    ("winter wind", Winter, En),
+    ("winter moon", Winter, En),
+    ("withered field", Winter, En),
+    ("plover", Winter, En),
+    ("sleet", Winter, En),
+    ("icicle", Winter, En),
+    ("icicles", Winter, En),
+    ("ice", Winter, En),
+    ("new year", NewYear, En),
+    ("first dream", NewYear, En),
+    ("first sunrise", NewYear, En),
+    ("春", Spring, Ja),
+    ("桜", Spring, Ja),
+    ("梅", Spring, Ja),
+    ("春雨", Spring, Ja),
+    ("霞", Spring, Ja),
+    ("蛙", Spring, Ja),
+    ("雲雀", Spring, Ja),
+    ("鶯", Spring, Ja),
+    ("蝶", Spring, Ja),
+    ("燕", Spring, Ja),
+    ("夏", Summer, Ja),
+    ("蝉", Summer, Ja),
+    ("蛍", Summer, Ja),
+    ("時鳥", Summer, Ja),
+    ("蓮", Summer, Ja),
+    ("牡丹", Summer, Ja),
+    ("五月雨", Summer, Ja),
+    ("夕立", Summer, Ja),
+    ("紫陽花", Summer, Ja),
+    ("秋", Autumn, Ja),
+    ("月", Autumn, Ja),
+    ("名月", Autumn, Ja),
+    ("秋風", Autumn, Ja),
+    ("紅葉", Autumn, Ja),
+    ("蜻蛉", Autumn, Ja),
+    ("菊", Autumn, Ja),
+    ("柿", Autumn, Ja),
+    ("霧", Autumn, Ja),
+    ("朝顔", Autumn, Ja),
+    ("露", Autumn, Ja),
+    ("冬", Winter, Ja),
+    ("雪", Winter, Ja),
+    ("霜", Winter, Ja),
+    ("時雨", Winter, Ja),
+    ("枯野", Winter, Ja),
+    ("千鳥", Winter, Ja),
+    ("氷", Winter, Ja),
+    ("木枯らし", Winter, Ja),
+    ("冬の月", Winter, Ja),
+    ("正月", NewYear, Ja),
+    ("元日", NewYear, Ja),
+    ("初日", NewYear, Ja),
+    ("初夢", NewYear, Ja),
+    ("門松", NewYear, Ja),
+];
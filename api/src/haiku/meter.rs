@@ -0,0 +1,251 @@
+//! A 5-7-5 meter checker.
+//!
+//! English syllables are estimated from vowel groups with the usual
+//! corrections for silent endings, which is right for most words and off by
+//! one for some; accented Latin vowels count like plain ones. Japanese is
+//! counted in morae from kana; kanji readings cannot be known without a
+//! dictionary, so lines containing kanji are left uncounted rather than
+//! guessed.
+
+use async_graphql::SimpleObject;
+
+/// Syllables expected on each line.
+pub const PATTERN: [i32; 3] = [5, 7, 5];
+
+#[derive(Debug, Clone, SimpleObject)]
+pub struct Meter {
+    /// Syllables, or morae for Japanese, per line. `null` for lines with
+    /// kanji, which are not checked.
+    pub syllables: Vec<Option<i32>>,
+    pub valid: bool,
+    /// Why the haiku does not follow the pattern.
+    pub problem: Option<String>,
+}
+
+/// Checks `content` against [`PATTERN`], allowing each line to be off by
+/// up to `tolerance` syllables.
+pub fn check(content: &str, tolerance: i32) -> Meter {
+    let lines: Vec<&str> = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    let syllables: Vec<Option<i32>> = lines.iter().map(|line| count_line(line)).collect();
+
+    let problem = if lines.len() != PATTERN.len() {
+        Some(format!(
+            "Expected {} lines, found {}",
+            PATTERN.len(),
+            lines.len()
+        ))
+    } else {
+        syllables
+            .iter()
+            .zip(PATTERN)
+            .enumerate()
+            .find_map(|(index, (count, expected))| {
+                let count = (*count)?;
+                ((count - expected).abs() > tolerance).then(|| {
+                    format!(
+                        "Line {} has {} syllables, expected {}",
+                        index + 1,
+                        count,
+                        expected
+                    )
+                })
+            })
+    };
+
+    Meter {
+        syllables,
+        valid: problem.is_none(),
+        problem,
+    }
+}
+
+fn count_line(line: &str) -> Option<i32> {
+    if line.chars().any(is_kanji) {
+        return None;
+    }
+    let morae = line.chars().filter(|&c| is_mora(c)).count() as i32;
+    let syllables: i32 = line
+        .split(|c: char| (!c.is_alphanumeric() && c != '\'') || is_kana(c))
+        .map(english_syllables)
+        .sum();
+    Some(morae + syllables)
+}
+
+fn english_syllables(word: &str) -> i32 {
+    let word: Vec<char> = word
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect();
+    if word.is_empty() {
+        return 0;
+    }
+    // Numbers are read digit by digit, which is close enough for the
+    // short numbers found in poems.
+    if word.iter().all(char::is_ascii_digit) {
+        return word.len() as i32;
+    }
+    if word.len() <= 3 {
+        return 1;
+    }
+
+    let is_vowel = |c: char| {
+        matches!(
+            c,
+            'a' | 'e' | 'i' | 'o' | 'u' | 'y' | 'à'..='æ' | 'è'..='ï' | 'ò'..='ö' | 'ø'..='ü' | 'ÿ'
+        )
+    };
+    let mut count = 0;
+    let mut previous = false;
+    for &c in &word {
+        let vowel = is_vowel(c);
+        // A diaeresis splits a vowel from the one before, as in "naïve".
+        let diaeresis = matches!(c, 'ä' | 'ë' | 'ï' | 'ö' | 'ü' | 'ÿ');
+        if vowel && (!previous || diaeresis) {
+            count += 1;
+        }
+        previous = vowel;
+    }
+
+    let n = word.len();
+    let ends_with = |suffix: &str| {
+        word.iter()
+            .rev()
+            .zip(suffix.chars().rev())
+            .all(|(a, b)| *a == b)
+    };
+    let consonant_le = ends_with("le") && !is_vowel(word[n - 3]);
+    if ends_with("e") && !ends_with("ee") && !consonant_le {
+        // Silent final e, as in "stone", but not "ee" or "-le" as in "bubble".
+        count -= 1;
+    } else if ends_with("ed") && !matches!(word[n - 3], 't' | 'd') {
+        // "-ed" is silent except after t and d, as in "rested".
+        count -= 1;
+    } else if ends_with("es")
+        && !matches!(word[n - 3], 's' | 'x' | 'z' | 'c' | 'g')
+        && !(ends_with("shes") || ends_with("ches"))
+    {
+        // "-es" is silent except after sibilants, as in "roses".
+        count -= 1;
+    }
+
+    count.max(1)
+}
+
+fn is_kanji(c: char) -> bool {
+    matches!(c, '\u{3400}'..='\u{4DBF}' | '\u{4E00}'..='\u{9FFF}' | '\u{F900}'..='\u{FAFF}')
+}
+
+/// Whether `c` is a kana counting as its own mora. Small vowels and small
+/// ya, yu, yo fuse with the kana before them; the small tsu and the long
+/// vowel mark count on their own.
+fn is_mora(c: char) -> bool {
+    let fused = matches!(
+        c,
+        'ぁ' | 'ぃ'
+            | 'ぅ'
+            | 'ぇ'
+            | 'ぉ'
+            | 'ゃ'
+            | 'ゅ'
+            | 'ょ'
+            | 'ゎ'
+            | 'ァ'
+            | 'ィ'
+            | 'ゥ'
+            | 'ェ'
+            | 'ォ'
+            | 'ャ'
+            | 'ュ'
+            | 'ョ'
+            | 'ヮ'
+    );
+    is_kana(c) && !fused
+}
+
+fn is_kana(c: char) -> bool {
+    matches!(c, '\u{3041}'..='\u{3096}' | '\u{30A1}'..='\u{30FA}' | 'ー')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_english_syllables() {
+        let cases = [
+            ("stone", 1),
+            ("rested", 2),
+            ("jumped", 1),
+            ("roses", 2),
+            ("bubble", 2),
+            ("tree", 1),
+            ("blossoms", 2),
+            ("evening", 3),
+            ("the", 1),
+            ("1999", 4),
+        ];
+        for (word, expected) in cases {
+            assert_eq!(english_syllables(word), expected, "{}", word);
+        }
+    }
+
+    #[test]
+    fn counts_accented_letters() {
+        assert_eq!(english_syllables("café"), 2);
+        assert_eq!(english_syllables("naïve"), 2);
+        assert_eq!(count_line("Über the frozen façade"), Some(7));
+    }
+
+    #[test]
+    fn counts_kana_in_morae() {
+        // Furu-ike ya / kawazu tobikomu / mizu no oto
+        assert_eq!(count_line("ふるいけや"), Some(5));
+        assert_eq!(count_line("かわずとびこむ"), Some(7));
+        // Small ya fuses, the small tsu and long vowel mark do not.
+        assert_eq!(count_line("きゃっと"), Some(3));
+        assert_eq!(count_line("コーヒー"), Some(4));
+    }
+
+    #[test]
+    fn leaves_kanji_uncounted() {
+        assert_eq!(count_line("古池や"), None);
+        let meter = check("古池や\nかわずとびこむ\n水の音", 0);
+        assert_eq!(meter.syllables, [None, Some(7), None]);
+        assert!(meter.valid);
+    }
+
+    #[test]
+    fn checks_the_pattern() {
+        let meter = check(
+            "An old silent pond\nA frog jumps into the pond\nSplash! Silence again",
+            0,
+        );
+        assert_eq!(meter.syllables, [Some(5), Some(7), Some(5)]);
+        assert!(meter.valid);
+
+        let meter = check(
+            "An old pond\nA frog jumps into the pond\nSplash! Silence again",
+            0,
+        );
+        assert!(!meter.valid);
+        assert_eq!(
+            meter.problem.as_deref(),
+            Some("Line 1 has 3 syllables, expected 5")
+        );
+        assert!(
+            check(
+                "An old pond\nA frog jumps into the pond\nSplash! Silence again",
+                2
+            )
+            .valid
+        );
+
+        let meter = check("An old silent pond\n\nA frog jumps in", 0);
+        assert_eq!(meter.problem.as_deref(), Some("Expected 3 lines, found 2"));
+    }
+}
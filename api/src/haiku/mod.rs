@@ -1 +1,12 @@
-pub mod entity;
\ No newline at end of file
+pub mod corpus;
+pub mod entity;
+pub mod favorite;
+pub mod jobs;
+pub mod kigo;
+pub mod meter;
+pub mod page;
+pub mod rating;
+pub mod resolver;
+pub mod schema;
+pub mod share;
+pub mod similarity;
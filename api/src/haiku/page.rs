@@ -0,0 +1,112 @@
+use super::entity::Haiku;
+use super::share::{HaikuShare, PublicUrl};
+use axum::{
+    extract::{Extension, Path},
+    http::{StatusCode, header},
+    response::{Html, IntoResponse, Response},
+};
+use sqlx::PgPool;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum PageError {
+    #[error("This link does not exist.")]
+    NotFound,
+    #[error("This link has expired or was revoked.")]
+    Gone,
+    #[error("Something went wrong.")]
+    Database(#[from] sqlx::Error),
+}
+
+impl IntoResponse for PageError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            PageError::NotFound => StatusCode::NOT_FOUND,
+            PageError::Gone => StatusCode::GONE,
+            PageError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let body = format!(
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Haiku</title></head><body><p>{}</p></body></html>",
+            self
+        );
+        (status, Html(body)).into_response()
+    }
+}
+
+/// Public page of a shared haiku, with OpenGraph tags for link previews.
+pub async fn share_page(
+    Extension(pool): Extension<PgPool>,
+    Extension(public_url): Extension<PublicUrl>,
+    Path(slug): Path<String>,
+) -> Result<Response, PageError> {
+    let share = match HaikuShare::get_by_slug(&pool, &slug).await {
+        Err(sqlx::Error::RowNotFound) => return Err(PageError::NotFound),
+        result => result?,
+    };
+    if !share.is_active() {
+        return Err(PageError::Gone);
+    }
+    let haiku = match Haiku::get(&pool, share.haiku_id).await {
+        Err(sqlx::Error::RowNotFound) => return Err(PageError::Gone),
+        result => result?,
+    };
+    HaikuShare::record_view(&pool, share.id).await?;
+
+    let base = public_url.0.trim_end_matches('/');
+    let lines: Vec<&str> = haiku
+        .content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    let description = escape(&lines.join(" / "));
+    let title = escape(lines.first().copied().unwrap_or("A haiku"));
+    let body = lines
+        .iter()
+        .map(|line| escape(line))
+        .collect::<Vec<_>>()
+        .join("<br>\n        ");
+
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <meta name="robots" content="noindex">
+    <title>{title}</title>
+    <meta name="description" content="{description}">
+    <meta property="og:type" content="article">
+    <meta property="og:title" content="{title}">
+    <meta property="og:description" content="{description}">
+    <meta property="og:url" content="{base}/s/{slug}">
+    <meta property="og:image" content="{base}/haikus/{id}/card.png">
+    <meta property="og:image:width" content="1200">
+    <meta property="og:image:height" content="630">
+    <meta name="twitter:card" content="summary_large_image">
+    <style>
+        body {{ margin: 0; min-height: 100vh; display: flex; align-items: center; justify-content: center;
+               background: #f5efe0; color: #2b2620; font-family: Georgia, serif; }}
+        p {{ font-size: 1.75rem; line-height: 1.6; text-align: center; }}
+    </style>
+</head>
+<body>
+    <p>
+        {body}
+    </p>
+</body>
+</html>
+"#,
+        slug = escape(&share.slug),
+        id = haiku.id,
+    );
+
+    Ok(([(header::CACHE_CONTROL, "no-cache")], Html(html)).into_response())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
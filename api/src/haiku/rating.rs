@@ -0,0 +1,79 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub struct Rating;
+
+impl Rating {
+    pub async fn average(pool: &PgPool, haiku_id: Uuid) -> Result<Option<f64>, sqlx::Error> {
+        let average = sqlx::query_scalar::<_, Option<f64>>(
+            r#"
+            SELECT AVG(rating)::float8
+            FROM haiku_ratings
+            WHERE haiku_id = $1
+            "#,
+        )
+        .bind(haiku_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(average)
+    }
+
+    pub async fn get(
+        pool: &PgPool,
+        user_id: Uuid,
+        haiku_id: Uuid,
+    ) -> Result<Option<i32>, sqlx::Error> {
+        let rating = sqlx::query_scalar::<_, i16>(
+            r#"
+            SELECT rating
+            FROM haiku_ratings
+            WHERE user_id = $1 AND haiku_id = $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(haiku_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(rating.map(i32::from))
+    }
+
+    pub async fn set(
+        pool: &PgPool,
+        user_id: Uuid,
+        haiku_id: Uuid,
+        rating: i32,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO haiku_ratings (user_id, haiku_id, rating)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, haiku_id)
+            DO UPDATE SET rating = EXCLUDED.rating, updated_at = now()
+            "#,
+        )
+        .bind(user_id)
+        .bind(haiku_id)
+        .bind(rating as i16)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn clear(pool: &PgPool, user_id: Uuid, haiku_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM haiku_ratings
+            WHERE user_id = $1 AND haiku_id = $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(haiku_id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,295 @@
+use super::corpus::{self, CorpusOptions, CorpusReport};
+use super::entity::{Haiku, SimilarHaiku, UpdateHaiku};
+use super::favorite::Favorite;
+use super::jobs::{GENERATE_HAIKU, GenerateHaikuPayload};
+use super::kigo::{Kigo, KigoInput, KigoLanguage, Saijiki, Season};
+use super::meter::{self, Meter};
+use super::rating::Rating;
+use super::share::HaikuShare;
+use super::similarity::{DuplicatePolicy, Fingerprint, MIN_SIMILARITY};
+use crate::app::error::AppError;
+use crate::app::validation::Validate;
+use crate::auth::viewer::Viewer;
+use crate::jobs::entity::{Job, NewJob};
+use crate::tags::entity::normalize_names;
+use async_graphql::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub struct QueryRoot;
+
+#[async_graphql::Object]
+impl QueryRoot {
+    /// Haikus, optionally only those tagged with all of `tags`.
+    async fn list_haikus(
+        &self,
+        ctx: &Context<'_>,
+        tags: Option<Vec<String>>,
+    ) -> async_graphql::Result<Vec<Haiku>> {
+        let pool = ctx.data::<PgPool>()?;
+        let tags = tags.map(normalize_names);
+        let haikus = Haiku::list(pool, tags.as_deref()).await?;
+        Ok(haikus)
+    }
+
+    async fn get_haiku(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<Haiku> {
+        let pool = ctx.data::<PgPool>()?;
+        let haiku = Haiku::get(pool, id).await?;
+        Ok(haiku)
+    }
+
+    /// Existing haikus closest to the given one, most similar first.
+    ///
+    /// `minSimilarity` defaults to 0.6: below it, the estimate for texts as
+    /// short as haikus mostly reflects common trigrams.
+    async fn similar_haikus(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+        #[graphql(default_with = "MIN_SIMILARITY", validator(minimum = 0, maximum = 1))]
+        min_similarity: f64,
+        #[graphql(default = 10)] limit: i64,
+    ) -> async_graphql::Result<Vec<SimilarHaiku>> {
+        let pool = ctx.data::<PgPool>()?;
+        let haiku = Haiku::get(pool, id).await?;
+        let fingerprint = Fingerprint::of(&haiku.content);
+        let similar = SimilarHaiku::list(
+            pool,
+            &fingerprint,
+            Some(haiku.id),
+            min_similarity as f32,
+            limit,
+        )
+        .await?;
+        Ok(similar)
+    }
+
+    /// Syllable count per line of `content`, checked against 5-7-5.
+    async fn check_meter(
+        &self,
+        content: String,
+        #[graphql(default = 0, validator(minimum = 0))] tolerance: i32,
+    ) -> Meter {
+        meter::check(&content, tolerance)
+    }
+
+    /// The season word dictionary, bundled and admin-added entries alike.
+    async fn saijiki(
+        &self,
+        ctx: &Context<'_>,
+        season: Option<Season>,
+        language: Option<KigoLanguage>,
+    ) -> async_graphql::Result<Vec<Kigo>> {
+        let pool = ctx.data::<PgPool>()?;
+        let saijiki = Saijiki::current(pool).await?;
+        let kigo = saijiki
+            .entries()
+            .iter()
+            .filter(|kigo| season.is_none_or(|season| kigo.season == season))
+            .filter(|kigo| language.is_none_or(|language| kigo.language == language))
+            .cloned()
+            .collect();
+        Ok(kigo)
+    }
+
+    async fn favorite_haikus(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Haiku>> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require(ctx)?;
+        let haikus = Favorite::list(pool, viewer.user_id).await?;
+        Ok(haikus)
+    }
+
+    /// Share links the viewer created, with their view counts.
+    async fn haiku_shares(
+        &self,
+        ctx: &Context<'_>,
+        haiku_id: Option<Uuid>,
+    ) -> async_graphql::Result<Vec<HaikuShare>> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require(ctx)?;
+        let shares = HaikuShare::list(pool, viewer.user_id, haiku_id).await?;
+        Ok(shares)
+    }
+}
+
+pub struct MutationRoot;
+
+#[async_graphql::Object]
+impl MutationRoot {
+    /// Adds a season word to the saijiki. Admin only.
+    async fn add_kigo(&self, ctx: &Context<'_>, data: KigoInput) -> async_graphql::Result<Kigo> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require_admin(ctx).await?;
+        let kigo = KigoInput::create(pool, data, viewer.user_id).await?;
+        Ok(kigo)
+    }
+
+    /// Removes an admin-added season word. Admin only.
+    async fn remove_kigo(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<bool> {
+        let pool = ctx.data::<PgPool>()?;
+        Viewer::require_admin(ctx).await?;
+        Kigo::destroy(pool, id).await?;
+        Ok(true)
+    }
+
+    /// Imports hand-written haikus from plain text or Markdown: three-line
+    /// stanzas separated by blank lines, with optional front matter giving
+    /// their `prompt`, `tags` and `author`. `promptId` applies to stanzas
+    /// that name no prompt; `tolerance` is how many syllables each line may
+    /// be off by. Admin only.
+    async fn import_haikus(
+        &self,
+        ctx: &Context<'_>,
+        text: String,
+        prompt_id: Option<Uuid>,
+        #[graphql(default = false)] dry_run: bool,
+        #[graphql(default = 0, validator(minimum = 0))] tolerance: i32,
+    ) -> async_graphql::Result<CorpusReport> {
+        let pool = ctx.data::<PgPool>()?;
+        let duplicates = ctx.data::<DuplicatePolicy>()?;
+        Viewer::require_admin(ctx).await?;
+        let options = CorpusOptions {
+            prompt_id,
+            dry_run,
+            tolerance,
+            duplicate_threshold: duplicates.threshold,
+        };
+        let report = corpus::import(pool, &text, options).await?;
+        Ok(report)
+    }
+
+    /// Queues the generation of a haiku for a prompt and returns the job.
+    /// Read-only API keys may not.
+    async fn generate_haiku(
+        &self,
+        ctx: &Context<'_>,
+        prompt_id: Uuid,
+        #[graphql(default = 64)] max_tokens: i32,
+        #[graphql(default_with = "0.7")] temperature: f32,
+        #[graphql(default = 0)] priority: i32,
+        run_at: Option<DateTime<Utc>>,
+    ) -> async_graphql::Result<Job> {
+        let pool = ctx.data::<PgPool>()?;
+        Viewer::require(ctx)?;
+        let payload = serde_json::to_value(GenerateHaikuPayload {
+            prompt_id,
+            max_tokens,
+            temperature,
+        })?;
+
+        let mut job = NewJob::new(GENERATE_HAIKU, payload).priority(priority);
+        if let Some(run_at) = run_at {
+            job = job.run_at(run_at);
+        }
+
+        let job = job.enqueue(pool).await?;
+        Ok(job)
+    }
+
+    /// Creates a public link to the haiku, valid until `expiresAt` if given.
+    async fn share_haiku(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> async_graphql::Result<HaikuShare> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require(ctx)?;
+        if expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+            return Err(AppError::validation("expiresAt must be in the future").into());
+        }
+        let haiku = Haiku::get(pool, id).await?;
+        let share = HaikuShare::create(pool, haiku.id, viewer.user_id, expires_at).await?;
+        Ok(share)
+    }
+
+    async fn revoke_haiku_share(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+    ) -> async_graphql::Result<HaikuShare> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require(ctx)?;
+        let share = HaikuShare::revoke(pool, id, viewer.user_id).await?;
+        Ok(share)
+    }
+
+    async fn rate_haiku(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+        #[graphql(validator(minimum = 1, maximum = 5))] rating: i32,
+    ) -> async_graphql::Result<Haiku> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require(ctx)?;
+        let haiku = Haiku::get(pool, id).await?;
+        Rating::set(pool, viewer.user_id, haiku.id, rating).await?;
+        Ok(haiku)
+    }
+
+    async fn clear_rating(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<Haiku> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require(ctx)?;
+        let haiku = Haiku::get(pool, id).await?;
+        Rating::clear(pool, viewer.user_id, haiku.id).await?;
+        Ok(haiku)
+    }
+
+    async fn favorite_haiku(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<Haiku> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require(ctx)?;
+        let haiku = Haiku::get(pool, id).await?;
+        Favorite::add(pool, viewer.user_id, haiku.id).await?;
+        Ok(haiku)
+    }
+
+    async fn unfavorite_haiku(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<Haiku> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require(ctx)?;
+        let haiku = Haiku::get(pool, id).await?;
+        Favorite::remove(pool, viewer.user_id, haiku.id).await?;
+        Ok(haiku)
+    }
+
+    /// With `expectedVersion`, fails with a `CONFLICT` error holding the
+    /// current haiku if someone else updated it since.
+    async fn update_haiku(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+        mut data: UpdateHaiku,
+        expected_version: Option<i32>,
+    ) -> async_graphql::Result<Haiku> {
+        let pool = ctx.data::<PgPool>()?;
+        Viewer::require(ctx)?;
+        data.validate("data")?;
+        let haiku = UpdateHaiku::update(pool, id, data, expected_version)
+            .await
+            .map_err(AppError::from)?;
+        Ok(haiku)
+    }
+
+    async fn delete_haiku(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<Haiku> {
+        let pool = ctx.data::<PgPool>()?;
+        Viewer::require(ctx)?;
+        let haiku = Haiku::delete(pool, id).await?;
+        Ok(haiku)
+    }
+
+    /// Admin only.
+    async fn restore_haiku(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<Haiku> {
+        let pool = ctx.data::<PgPool>()?;
+        Viewer::require_admin(ctx).await?;
+        let haiku = Haiku::restore(pool, id).await?;
+        Ok(haiku)
+    }
+
+    /// Admin only.
+    async fn destroy_haiku(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<bool> {
+        let pool = ctx.data::<PgPool>()?;
+        Viewer::require_admin(ctx).await?;
+        Haiku::destroy(pool, id).await?;
+        Ok(true)
+    }
+}
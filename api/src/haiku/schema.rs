@@ -0,0 +1,23 @@
+pub(crate) use super::resolver::{MutationRoot, QueryRoot};
+use super::share::PublicUrl;
+use super::similarity::DuplicatePolicy;
+use crate::app::error::ErrorCodes;
+use crate::audit::extension::Audit;
+use async_graphql::{EmptySubscription, Schema};
+use sqlx::PgPool;
+
+pub type HaikuSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+pub fn create_schema(
+    pool: PgPool,
+    public_url: PublicUrl,
+    duplicates: DuplicatePolicy,
+) -> HaikuSchema {
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .data(pool)
+        .data(public_url)
+        .data(duplicates)
+        .extension(ErrorCodes)
+        .extension(Audit)
+        .finish()
+}
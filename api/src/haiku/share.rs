@@ -0,0 +1,155 @@
+use async_graphql::{ComplexObject, Context, SimpleObject};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use rand::distributions::Alphanumeric;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+/// 22 alphanumeric characters, about 131 bits of randomness.
+const SLUG_LEN: usize = 22;
+
+/// Base URL the API is publicly reachable at, used to build share links.
+#[derive(Debug, Clone)]
+pub struct PublicUrl(pub String);
+
+#[derive(Debug, Serialize, Deserialize, SimpleObject, FromRow)]
+#[graphql(complex)]
+pub struct HaikuShare {
+    pub id: Uuid,
+    pub slug: String,
+    pub haiku_id: Uuid,
+    /// `None` once its creator is purged from the trash.
+    pub created_by: Option<Uuid>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub view_count: i64,
+    created_at: DateTime<Utc>,
+}
+
+#[ComplexObject]
+impl HaikuShare {
+    async fn url(&self, ctx: &Context<'_>) -> async_graphql::Result<String> {
+        let public_url = ctx.data::<PublicUrl>()?;
+        Ok(format!(
+            "{}/s/{}",
+            public_url.0.trim_end_matches('/'),
+            self.slug
+        ))
+    }
+
+    async fn active(&self) -> bool {
+        self.is_active()
+    }
+}
+
+impl HaikuShare {
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none()
+            && self
+                .expires_at
+                .is_none_or(|expires_at| expires_at > Utc::now())
+    }
+
+    /// Links created by `user_id`, optionally only those of one haiku.
+    pub async fn list(
+        pool: &PgPool,
+        user_id: Uuid,
+        haiku_id: Option<Uuid>,
+    ) -> Result<Vec<HaikuShare>, sqlx::Error> {
+        let shares = sqlx::query_as::<_, HaikuShare>(
+            r#"
+            SELECT id, slug, haiku_id, created_by, expires_at, revoked_at, view_count, created_at
+            FROM haiku_shares
+            WHERE created_by = $1 AND ($2::uuid IS NULL OR haiku_id = $2)
+            ORDER BY created_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .bind(haiku_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(shares)
+    }
+
+    pub async fn get_by_slug(pool: &PgPool, slug: &str) -> Result<HaikuShare, sqlx::Error> {
+        let share = sqlx::query_as::<_, HaikuShare>(
+            r#"
+            SELECT id, slug, haiku_id, created_by, expires_at, revoked_at, view_count, created_at
+            FROM haiku_shares
+            WHERE slug = $1
+            "#,
+        )
+        .bind(slug)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(share)
+    }
+
+    pub async fn create(
+        pool: &PgPool,
+        haiku_id: Uuid,
+        created_by: Uuid,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<HaikuShare, sqlx::Error> {
+        let slug: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(SLUG_LEN)
+            .map(char::from)
+            .collect();
+        let share = sqlx::query_as::<_, HaikuShare>(
+            r#"
+            INSERT INTO haiku_shares (slug, haiku_id, created_by, expires_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, slug, haiku_id, created_by, expires_at, revoked_at, view_count, created_at
+            "#,
+        )
+        .bind(slug)
+        .bind(haiku_id)
+        .bind(created_by)
+        .bind(expires_at)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(share)
+    }
+
+    /// Revokes a link, only if `created_by` made it.
+    pub async fn revoke(
+        pool: &PgPool,
+        id: Uuid,
+        created_by: Uuid,
+    ) -> Result<HaikuShare, sqlx::Error> {
+        let share = sqlx::query_as::<_, HaikuShare>(
+            r#"
+            UPDATE haiku_shares
+            SET revoked_at = COALESCE(revoked_at, now())
+            WHERE id = $1 AND created_by = $2
+            RETURNING id, slug, haiku_id, created_by, expires_at, revoked_at, view_count, created_at
+            "#,
+        )
+        .bind(id)
+        .bind(created_by)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(share)
+    }
+
+    pub async fn record_view(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            UPDATE haiku_shares
+            SET view_count = view_count + 1
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+//! MinHash fingerprints used to spot near-duplicate haikus.
+//!
+//! The text is normalized, cut into character trigrams and reduced to a
+//! fixed-size MinHash signature. Two signatures agreeing on a fraction `s` of
+//! their slots estimate a Jaccard similarity of `s` between the trigram sets.
+//! Signatures are also split into bands so that candidates can be found with
+//! an indexed array overlap instead of comparing against every haiku.
+//!
+//! Fingerprints are stored, so the hashing below must never change.
+
+/// Number of hash slots in a signature.
+pub const SIGNATURE_SIZE: usize = 64;
+/// Slots per band, `SIGNATURE_SIZE / BAND_SIZE` bands in total.
+const BAND_SIZE: usize = 4;
+const SHINGLE_SIZE: usize = 3;
+/// Similarity below which haikus are not worth listing as similar. Short
+/// texts share many common trigrams, so lower estimates are mostly noise.
+pub const MIN_SIMILARITY: f64 = 0.6;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub signature: Vec<i32>,
+    pub bands: Vec<i64>,
+}
+
+impl Fingerprint {
+    pub fn of(content: &str) -> Self {
+        let shingles = shingles(&normalize(content));
+        let signature: Vec<i32> = (0..SIGNATURE_SIZE as u64)
+            .map(|slot| {
+                let seed = slot.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+                shingles
+                    .iter()
+                    .map(|&shingle| mix(shingle ^ seed) as u32)
+                    .min()
+                    .unwrap_or(u32::MAX) as i32
+            })
+            .collect();
+        let bands = signature
+            .chunks(BAND_SIZE)
+            .enumerate()
+            .map(|(band, slots)| {
+                let bytes: Vec<u8> = slots.iter().flat_map(|slot| slot.to_le_bytes()).collect();
+                mix(fnv1a(&bytes) ^ band as u64) as i64
+            })
+            .collect();
+
+        Self { signature, bands }
+    }
+
+    /// Estimated Jaccard similarity with `other`, between 0 and 1.
+    pub fn similarity(&self, other: &Fingerprint) -> f32 {
+        let equal = self
+            .signature
+            .iter()
+            .zip(&other.signature)
+            .filter(|(a, b)| a == b)
+            .count();
+        equal as f32 / SIGNATURE_SIZE as f32
+    }
+}
+
+/// Lowercases and keeps only letters and digits, separated by single spaces.
+pub fn normalize(content: &str) -> String {
+    content
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn shingles(text: &str) -> Vec<u64> {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.len() <= SHINGLE_SIZE {
+        return vec![fnv1a(text.as_bytes())];
+    }
+
+    chars
+        .windows(SHINGLE_SIZE)
+        .map(|window| fnv1a(window.iter().collect::<String>().as_bytes()))
+        .collect()
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// SplitMix64 finalizer.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// What happens to a freshly generated haiku that is a near-duplicate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateAction {
+    /// The generation fails and is retried.
+    Reject,
+    /// The haiku is kept with `duplicate_of` pointing at the closest match.
+    Flag,
+}
+
+impl std::str::FromStr for DuplicateAction {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "reject" => Ok(Self::Reject),
+            "flag" => Ok(Self::Flag),
+            other => Err(format!("unknown duplicate action `{}`", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DuplicatePolicy {
+    /// Similarity from which two haikus count as near-duplicates.
+    pub threshold: f32,
+    pub action: DuplicateAction,
+}
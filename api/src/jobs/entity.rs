@@ -0,0 +1,331 @@
+use async_graphql::{Enum, SimpleObject};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, PgExecutor, PgPool};
+use std::time::Duration;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum, sqlx::Type)]
+#[sqlx(type_name = "job_status", rename_all = "lowercase")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Completed,
+    Dead,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject, FromRow)]
+pub struct Job {
+    pub id: Uuid,
+    pub kind: String,
+    pub payload: Value,
+    pub status: JobStatus,
+    pub priority: i32,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub last_error: Option<String>,
+    pub run_at: DateTime<Utc>,
+    locked_at: Option<DateTime<Utc>>,
+    completed_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl Job {
+    pub async fn list(
+        pool: &PgPool,
+        status: Option<JobStatus>,
+        kind: Option<String>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Job>, sqlx::Error> {
+        let jobs = sqlx::query_as::<_, Job>(
+            r#"
+            SELECT id, kind, payload, status, priority, attempts, max_attempts, last_error,
+                   run_at, locked_at, completed_at, created_at, updated_at
+            FROM jobs
+            WHERE ($1::job_status IS NULL OR status = $1)
+              AND ($2::varchar IS NULL OR kind = $2)
+            ORDER BY created_at DESC
+            LIMIT $3 OFFSET $4
+            "#,
+        )
+        .bind(status)
+        .bind(kind)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(jobs)
+    }
+
+    pub async fn get(pool: &PgPool, id: Uuid) -> Result<Job, sqlx::Error> {
+        let job = sqlx::query_as::<_, Job>(
+            r#"
+            SELECT id, kind, payload, status, priority, attempts, max_attempts, last_error,
+                   run_at, locked_at, completed_at, created_at, updated_at
+            FROM jobs
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(job)
+    }
+
+    /// Claims the next runnable job for this worker.
+    ///
+    /// Jobs left in `running` by a worker that died are picked up again once
+    /// their lock is older than `lock_timeout`, unless that was their last
+    /// attempt; see [`Job::dead_letter_stale`].
+    pub async fn claim(
+        pool: &PgPool,
+        lock_timeout: Duration,
+        excluded_kinds: &[String],
+    ) -> Result<Option<Job>, sqlx::Error> {
+        let job = sqlx::query_as::<_, Job>(
+            r#"
+            UPDATE jobs
+            SET status = 'running', attempts = attempts + 1, locked_at = now(), updated_at = now()
+            WHERE id = (
+                SELECT id
+                FROM jobs
+                WHERE ((status = 'pending' AND run_at <= now())
+                   OR (status = 'running' AND locked_at < now() - make_interval(secs => $1)
+                       AND attempts < max_attempts))
+                  AND kind <> ALL($2)
+                ORDER BY priority DESC, run_at
+                FOR UPDATE SKIP LOCKED
+                LIMIT 1
+            )
+            RETURNING id, kind, payload, status, priority, attempts, max_attempts, last_error,
+                      run_at, locked_at, completed_at, created_at, updated_at
+            "#,
+        )
+        .bind(lock_timeout.as_secs_f64())
+        .bind(excluded_kinds)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(job)
+    }
+
+    /// Moves jobs whose worker died during their last attempt to the dead
+    /// letter state, returning them.
+    pub async fn dead_letter_stale(
+        pool: &PgPool,
+        lock_timeout: Duration,
+    ) -> Result<Vec<Job>, sqlx::Error> {
+        let jobs = sqlx::query_as::<_, Job>(
+            r#"
+            UPDATE jobs
+            SET status = 'dead', last_error = 'The worker stopped during the last attempt',
+                locked_at = NULL, updated_at = now()
+            WHERE id IN (
+                SELECT id
+                FROM jobs
+                WHERE status = 'running'
+                  AND locked_at < now() - make_interval(secs => $1)
+                  AND attempts >= max_attempts
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, kind, payload, status, priority, attempts, max_attempts, last_error,
+                      run_at, locked_at, completed_at, created_at, updated_at
+            "#,
+        )
+        .bind(lock_timeout.as_secs_f64())
+        .fetch_all(pool)
+        .await?;
+
+        Ok(jobs)
+    }
+
+    /// Marks the job as done, unless the attempt lost it to another worker
+    /// after running past the lock timeout. Returns whether it still held it.
+    pub async fn complete(pool: &PgPool, job: &Job) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE jobs
+            SET status = 'completed', last_error = NULL, locked_at = NULL,
+                completed_at = now(), updated_at = now()
+            WHERE id = $1 AND status = 'running' AND attempts = $2 AND locked_at = $3
+            "#,
+        )
+        .bind(job.id)
+        .bind(job.attempts)
+        .bind(job.locked_at)
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Records a failed attempt, either scheduling a retry with exponential
+    /// backoff or moving the job to the dead letter state once it has used up
+    /// its attempts. Returns which of the two it did, or `None` if the
+    /// attempt lost the job to another worker, as [`Job::complete`] does.
+    pub async fn fail(
+        pool: &PgPool,
+        job: &Job,
+        error: &str,
+        retry: bool,
+    ) -> Result<Option<JobStatus>, sqlx::Error> {
+        let status = if retry && job.attempts < job.max_attempts {
+            JobStatus::Pending
+        } else {
+            JobStatus::Dead
+        };
+        let run_at = Utc::now() + backoff(job.attempts);
+
+        let result = sqlx::query(
+            r#"
+            UPDATE jobs
+            SET status = $2, last_error = $3, run_at = $4, locked_at = NULL, updated_at = now()
+            WHERE id = $1 AND status = 'running' AND attempts = $5 AND locked_at = $6
+            "#,
+        )
+        .bind(job.id)
+        .bind(status)
+        .bind(error)
+        .bind(run_at)
+        .bind(job.attempts)
+        .bind(job.locked_at)
+        .execute(pool)
+        .await?;
+
+        Ok((result.rows_affected() > 0).then_some(status))
+    }
+
+    pub async fn retry(pool: &PgPool, id: Uuid) -> Result<Job, sqlx::Error> {
+        let job = sqlx::query_as::<_, Job>(
+            r#"
+            UPDATE jobs
+            SET status = 'pending', attempts = 0, run_at = now(), locked_at = NULL, updated_at = now()
+            WHERE id = $1 AND status IN ('dead', 'cancelled')
+            RETURNING id, kind, payload, status, priority, attempts, max_attempts, last_error,
+                      run_at, locked_at, completed_at, created_at, updated_at
+            "#,
+        )
+        .bind(id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(job)
+    }
+
+    pub async fn cancel(pool: &PgPool, id: Uuid) -> Result<Job, sqlx::Error> {
+        let job = sqlx::query_as::<_, Job>(
+            r#"
+            UPDATE jobs
+            SET status = 'cancelled', locked_at = NULL, updated_at = now()
+            WHERE id = $1 AND status = 'pending'
+            RETURNING id, kind, payload, status, priority, attempts, max_attempts, last_error,
+                      run_at, locked_at, completed_at, created_at, updated_at
+            "#,
+        )
+        .bind(id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(job)
+    }
+}
+
+/// Delay before the next attempt: 5s, 10s, 20s, ... capped at one hour.
+fn backoff(attempts: i32) -> chrono::Duration {
+    let exponent = attempts.clamp(1, 16) as u32 - 1;
+    let seconds = 5i64.saturating_mul(2i64.pow(exponent)).min(3600);
+    chrono::Duration::seconds(seconds)
+}
+
+pub struct NewJob {
+    pub kind: String,
+    pub payload: Value,
+    pub priority: i32,
+    pub max_attempts: i32,
+    pub run_at: Option<DateTime<Utc>>,
+}
+
+impl NewJob {
+    pub fn new(kind: &str, payload: Value) -> Self {
+        Self {
+            kind: kind.to_string(),
+            payload,
+            priority: 0,
+            max_attempts: 5,
+            run_at: None,
+        }
+    }
+
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn run_at(mut self, run_at: DateTime<Utc>) -> Self {
+        self.run_at = Some(run_at);
+        self
+    }
+
+    pub async fn enqueue<'e>(self, executor: impl PgExecutor<'e>) -> Result<Job, sqlx::Error> {
+        let job = sqlx::query_as::<_, Job>(
+            r#"
+            INSERT INTO jobs (kind, payload, priority, max_attempts, run_at)
+            VALUES ($1, $2, $3, $4, COALESCE($5, now()))
+            RETURNING id, kind, payload, status, priority, attempts, max_attempts, last_error,
+                      run_at, locked_at, completed_at, created_at, updated_at
+            "#,
+        )
+        .bind(self.kind)
+        .bind(self.payload)
+        .bind(self.priority)
+        .bind(self.max_attempts)
+        .bind(self.run_at)
+        .fetch_one(executor)
+        .await?;
+
+        Ok(job)
+    }
+
+    /// Enqueues one job of `kind` per payload in a single statement.
+    pub async fn enqueue_many<'e>(
+        executor: impl PgExecutor<'e>,
+        kind: &str,
+        payloads: Vec<Value>,
+        priority: i32,
+    ) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO jobs (kind, payload, priority)
+            SELECT $1, payload, $3
+            FROM UNNEST($2::jsonb[]) AS payload
+            "#,
+        )
+        .bind(kind)
+        .bind(payloads)
+        .bind(priority)
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_an_hour() {
+        let seconds: Vec<i64> = [0, 1, 2, 3, 10, 11, 40]
+            .into_iter()
+            .map(|attempts| backoff(attempts).num_seconds())
+            .collect();
+        assert_eq!(seconds, [5, 5, 10, 20, 2560, 3600, 3600]);
+    }
+}
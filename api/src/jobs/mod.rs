@@ -0,0 +1,4 @@
+pub mod entity;
+pub mod resolver;
+pub mod schema;
+pub mod worker;
@@ -0,0 +1,50 @@
+use super::entity::{Job, JobStatus};
+use crate::auth::viewer::Viewer;
+use async_graphql::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub struct QueryRoot;
+
+#[async_graphql::Object]
+impl QueryRoot {
+    async fn list_jobs(
+        &self,
+        ctx: &Context<'_>,
+        status: Option<JobStatus>,
+        kind: Option<String>,
+        #[graphql(default = 50)] limit: i64,
+        #[graphql(default = 0)] offset: i64,
+    ) -> async_graphql::Result<Vec<Job>> {
+        Viewer::require_admin(ctx).await?;
+        let pool = ctx.data::<PgPool>()?;
+        let jobs = Job::list(pool, status, kind, limit, offset).await?;
+        Ok(jobs)
+    }
+
+    async fn get_job(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<Job> {
+        Viewer::require_admin(ctx).await?;
+        let pool = ctx.data::<PgPool>()?;
+        let job = Job::get(pool, id).await?;
+        Ok(job)
+    }
+}
+
+pub struct MutationRoot;
+
+#[async_graphql::Object]
+impl MutationRoot {
+    async fn retry_job(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<Job> {
+        Viewer::require_admin(ctx).await?;
+        let pool = ctx.data::<PgPool>()?;
+        let job = Job::retry(pool, id).await?;
+        Ok(job)
+    }
+
+    async fn cancel_job(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<Job> {
+        Viewer::require_admin(ctx).await?;
+        let pool = ctx.data::<PgPool>()?;
+        let job = Job::cancel(pool, id).await?;
+        Ok(job)
+    }
+}
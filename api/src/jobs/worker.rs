@@ -0,0 +1,224 @@
+use super::entity::{Job, JobStatus};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::Semaphore;
+use tokio::task::{JoinError, JoinHandle};
+
+#[derive(Debug, Error)]
+pub enum JobError {
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("Invalid job payload: {0}")]
+    Payload(#[from] serde_json::Error),
+    #[error("{0}")]
+    Failed(String),
+    /// The job can never succeed, so it goes straight to the dead letter state.
+    #[error("{0}")]
+    Permanent(String),
+}
+
+type JobFuture = Pin<Box<dyn Future<Output = Result<(), JobError>> + Send>>;
+type JobHandler = Arc<dyn Fn(PgPool, Job) -> JobFuture + Send + Sync>;
+
+#[derive(Default)]
+pub struct JobRegistry {
+    handlers: HashMap<String, JobHandler>,
+    dead_letter_handlers: HashMap<String, JobHandler>,
+    limits: HashMap<String, Arc<Semaphore>>,
+}
+
+impl JobRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<F, Fut>(mut self, kind: &str, handler: F) -> Self
+    where
+        F: Fn(PgPool, Job) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), JobError>> + Send + 'static,
+    {
+        let handler: JobHandler = Arc::new(move |pool, job| Box::pin(handler(pool, job)));
+        self.handlers.insert(kind.to_string(), handler);
+        self
+    }
+
+    /// Runs `handler` once a job of `kind` moves to the dead letter state,
+    /// however it got there, to clean up after it.
+    pub fn on_dead_letter<F, Fut>(mut self, kind: &str, handler: F) -> Self
+    where
+        F: Fn(PgPool, Job) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), JobError>> + Send + 'static,
+    {
+        let handler: JobHandler = Arc::new(move |pool, job| Box::pin(handler(pool, job)));
+        self.dead_letter_handlers.insert(kind.to_string(), handler);
+        self
+    }
+
+    /// Caps how many jobs of `kind` this process runs at the same time.
+    pub fn limit(mut self, kind: &str, concurrency: usize) -> Self {
+        self.limits
+            .insert(kind.to_string(), Arc::new(Semaphore::new(concurrency)));
+        self
+    }
+
+    /// Kinds that are already running at their concurrency limit and must not
+    /// be claimed right now.
+    fn saturated_kinds(&self) -> Vec<String> {
+        self.limits
+            .iter()
+            .filter(|(_, permits)| permits.available_permits() == 0)
+            .map(|(kind, _)| kind.clone())
+            .collect()
+    }
+}
+
+pub struct WorkerPool {
+    pool: PgPool,
+    registry: Arc<JobRegistry>,
+    workers: usize,
+    poll_interval: Duration,
+    lock_timeout: Duration,
+}
+
+impl WorkerPool {
+    pub fn new(
+        pool: PgPool,
+        registry: JobRegistry,
+        workers: usize,
+        poll_interval: Duration,
+    ) -> Self {
+        Self {
+            pool,
+            registry: Arc::new(registry),
+            workers,
+            poll_interval,
+            lock_timeout: Duration::from_secs(15 * 60),
+        }
+    }
+
+    pub fn start(self) -> Vec<JoinHandle<()>> {
+        (0..self.workers)
+            .map(|_| {
+                let pool = self.pool.clone();
+                let registry = self.registry.clone();
+                let poll_interval = self.poll_interval;
+                let lock_timeout = self.lock_timeout;
+
+                tokio::spawn(async move {
+                    loop {
+                        match Job::dead_letter_stale(&pool, lock_timeout).await {
+                            Ok(jobs) => {
+                                for job in jobs {
+                                    dead_lettered(&pool, &registry, job).await;
+                                }
+                            }
+                            Err(err) => {
+                                tracing::error!(error = %err, "Failed to dead-letter stale jobs")
+                            }
+                        }
+                        let saturated = registry.saturated_kinds();
+                        match Job::claim(&pool, lock_timeout, &saturated).await {
+                            Ok(Some(job)) => run(&pool, &registry, job).await,
+                            Ok(None) => tokio::time::sleep(poll_interval).await,
+                            Err(err) => {
+                                tracing::error!(error = %err, "Failed to claim job");
+                                tokio::time::sleep(poll_interval).await;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect()
+    }
+}
+
+async fn run(pool: &PgPool, registry: &JobRegistry, mut job: Job) {
+    // Another worker may have taken the last permit between the claim and
+    // now, in which case this job simply waits for its turn.
+    let _permit = match registry.limits.get(&job.kind) {
+        Some(permits) => permits.clone().acquire_owned().await.ok(),
+        None => None,
+    };
+
+    // The handler runs in its own task so that a panic fails the attempt
+    // rather than leaving the job `running` and taking the worker down.
+    let result = match registry.handlers.get(&job.kind) {
+        Some(handler) => tokio::spawn(handler(pool.clone(), job.clone()))
+            .await
+            .unwrap_or_else(|err| Err(JobError::Failed(panic_message(err)))),
+        None => Err(JobError::Permanent(format!(
+            "No handler registered for job kind `{}`",
+            job.kind
+        ))),
+    };
+
+    let outcome = match result {
+        Ok(()) => Job::complete(pool, &job)
+            .await
+            .map(|held| held.then_some(JobStatus::Completed)),
+        Err(err) => {
+            let retry = !matches!(err, JobError::Permanent(_));
+            let error = err.to_string();
+            let status = Job::fail(pool, &job, &error, retry).await;
+            job.last_error = Some(error);
+            status
+        }
+    };
+
+    match outcome {
+        Ok(Some(JobStatus::Dead)) => dead_lettered(pool, registry, job).await,
+        Ok(Some(_)) => {}
+        // The attempt ran past the lock timeout and the job was claimed
+        // again, the result of the current attempt is the one that counts.
+        Ok(None) => tracing::warn!(job = %job.id, "Job was reclaimed while running"),
+        Err(err) => tracing::error!(job = %job.id, error = %err, "Failed to record job result"),
+    }
+}
+
+async fn dead_lettered(pool: &PgPool, registry: &JobRegistry, job: Job) {
+    let Some(handler) = registry.dead_letter_handlers.get(&job.kind) else {
+        return;
+    };
+    let id = job.id;
+    let result = tokio::spawn(handler(pool.clone(), job))
+        .await
+        .unwrap_or_else(|err| Err(JobError::Failed(panic_message(err))));
+    if let Err(err) = result {
+        tracing::error!(job = %id, error = %err, "Failed to clean up after dead job");
+    }
+}
+
+fn panic_message(err: JoinError) -> String {
+    let Ok(panic) = err.try_into_panic() else {
+        return "The job was cancelled".to_string();
+    };
+    let message = panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown cause".to_string());
+    format!("The job panicked: {}", message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn panics_are_reported_with_their_message() {
+        let err = tokio::spawn(async { panic!("out of {}", "ink") })
+            .await
+            .unwrap_err();
+        assert_eq!(panic_message(err), "The job panicked: out of ink");
+
+        let err = tokio::spawn(async { std::panic::panic_any(42) })
+            .await
+            .unwrap_err();
+        assert_eq!(panic_message(err), "The job panicked: unknown cause");
+    }
+}
@@ -1,13 +1,68 @@
+use crate::app::cli::Command;
+use crate::app::config::Config;
 use crate::app::server::Server;
+use std::io::IsTerminal;
+use tracing_subscriber::EnvFilter;
 
+mod anthology;
+mod api_keys;
 mod app;
+mod audit;
+mod auth;
+mod batches;
+mod cards;
+mod collections;
+mod exports;
+mod feeds;
+mod haiku;
+mod jobs;
+mod mail;
 mod prompts;
+mod search;
+mod tags;
+mod transfer;
+mod trash;
 mod users;
-mod haiku;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let server = Server::new().await?;
-    server.run().await?;
+    // Logs go to stderr, leaving stdout to commands such as `export`.
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .init();
+    let command = match Command::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(2);
+        }
+    };
+    if let Command::PrintConfig = command {
+        if let Err(error) = Config::print() {
+            eprintln!("\n{}", error);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let server = match Server::new().await {
+        Ok(server) => server,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+    match command {
+        Command::Serve => server.run().await?,
+        command => {
+            if let Err(error) = command.run(server.pool(), server.config()).await {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        }
+    }
     Ok(())
 }
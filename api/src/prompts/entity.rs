@@ -1,28 +1,73 @@
-use async_graphql::SimpleObject;
-use chrono::{DateTime, NaiveDateTime, Utc};
+use crate::app::error::UpdateError;
+use crate::app::validation::{FieldValue, Rule, Validate, Validator};
+use crate::haiku::jobs::{REINDEX_HAIKUS, ReindexHaikusPayload};
+use crate::jobs::entity::NewJob;
+use crate::tags::entity::Tag;
+use async_graphql::{ComplexObject, Context, InputObject, SimpleObject};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool, Pool};
+use sqlx::{FromRow, PgExecutor, PgPool};
 use uuid::Uuid;
 
 #[derive(Debug, Serialize, Deserialize, SimpleObject, FromRow)]
+#[graphql(complex)]
 pub struct Prompt {
-    id: Uuid,
-    title: String,
+    pub id: Uuid,
+    pub title: String,
     pub(crate) content: String,
+    /// Text search configuration used to index the prompt, e.g. `english`.
+    pub language: String,
+    /// Incremented on every update; pass it back as `expectedVersion` to
+    /// detect concurrent edits.
+    pub version: i32,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
     deleted_at: Option<DateTime<Utc>>,
 }
 
+#[ComplexObject]
 impl Prompt {
-    pub async fn list(pool: &PgPool) -> Result<Vec<Prompt>, sqlx::Error> {
+    async fn tags(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Tag>> {
+        let pool = ctx.data::<PgPool>()?;
+        let tags = Tag::list_for_prompt(pool, self.id).await?;
+        Ok(tags)
+    }
+}
+
+impl Prompt {
+    /// Live prompts, restricted to those carrying every tag in `tags` if given.
+    pub async fn list(pool: &PgPool, tags: Option<&[String]>) -> Result<Vec<Prompt>, sqlx::Error> {
         let prompts = sqlx::query_as::<_, Prompt>(
             r#"
-            SELECT id, title, content, created_at, updated_at, deleted_at
+            SELECT id, title, content, language::text AS language, version, created_at, updated_at, deleted_at
             FROM prompts
             WHERE deleted_at IS NULL
+              AND ($1::text[] IS NULL OR id IN (
+                  SELECT pt.prompt_id
+                  FROM prompt_tags pt
+                  JOIN tags t ON t.id = pt.tag_id
+                  WHERE t.name = ANY($1)
+                  GROUP BY pt.prompt_id
+                  HAVING count(*) = cardinality($1)
+              ))
+            "#,
+        )
+        .bind(tags)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(prompts)
+    }
+
+    pub async fn list_by_ids(pool: &PgPool, ids: &[Uuid]) -> Result<Vec<Prompt>, sqlx::Error> {
+        let prompts = sqlx::query_as::<_, Prompt>(
+            r#"
+            SELECT id, title, content, language::text AS language, version, created_at, updated_at, deleted_at
+            FROM prompts
+            WHERE id = ANY($1) AND deleted_at IS NULL
             "#,
         )
+        .bind(ids)
         .fetch_all(pool)
         .await?;
 
@@ -32,7 +77,7 @@ impl Prompt {
     pub async fn get(pool: &PgPool, id: Uuid) -> Result<Prompt, sqlx::Error> {
         let prompt = sqlx::query_as::<_, Prompt>(
             r#"
-            SELECT id, title, content, created_at, updated_at, deleted_at
+            SELECT id, title, content, language::text AS language, version, created_at, updated_at, deleted_at
             FROM prompts
             WHERE id = $1 AND deleted_at IS NULL
             "#,
@@ -44,24 +89,56 @@ impl Prompt {
         Ok(prompt)
     }
 
+    /// The live prompt titled `title`, ignoring case.
+    pub async fn find_by_title<'e>(
+        executor: impl PgExecutor<'e>,
+        title: &str,
+    ) -> Result<Option<Prompt>, sqlx::Error> {
+        let prompt = sqlx::query_as::<_, Prompt>(
+            r#"
+            SELECT id, title, content, language::text AS language, version, created_at, updated_at, deleted_at
+            FROM prompts
+            WHERE lower(title) = lower($1) AND deleted_at IS NULL
+            ORDER BY created_at
+            LIMIT 1
+            "#,
+        )
+        .bind(title)
+        .fetch_optional(executor)
+        .await?;
+
+        Ok(prompt)
+    }
+
+    /// Moves the prompt to the trash along with its live haikus, which
+    /// share its deletion time so that restoring it brings them back.
     pub async fn delete(pool: &PgPool, id: Uuid) -> Result<Prompt, sqlx::Error> {
+        let mut tx = pool.begin().await?;
         let prompt = sqlx::query_as::<_, Prompt>(
             r#"
             UPDATE prompts
             SET deleted_at = now()
             WHERE id = $1 AND deleted_at IS NULL
-            RETURNING id, title, content, created_at, updated_at, deleted_at
+            RETURNING id, title, content, language::text AS language, version, created_at, updated_at, deleted_at
             "#,
         )
         .bind(id)
-        .fetch_one(pool)
+        .fetch_one(&mut *tx)
         .await?;
+        sqlx::query(
+            "UPDATE haikus SET deleted_at = $2 WHERE prompt_id = $1 AND deleted_at IS NULL",
+        )
+        .bind(id)
+        .bind(prompt.deleted_at)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
 
         Ok(prompt)
     }
 
-    pub async fn destroy(pool: &PgPool, id: Uuid) -> Result<Prompt, sqlx::Error> {
-        let prompt = sqlx::query_as::<_, Prompt>(
+    pub async fn destroy(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query(
             r#"
             DELETE FROM prompts
             WHERE id = $1
@@ -71,74 +148,155 @@ impl Prompt {
         .execute(pool)
         .await?;
 
-        Ok(prompt)
+        Ok(())
     }
 
+    /// Restores the prompt with the haikus trashed along with it. Those
+    /// trashed on their own beforehand stay in the trash.
     pub async fn restore(pool: &PgPool, id: Uuid) -> Result<Prompt, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let deleted_at = sqlx::query_scalar::<_, DateTime<Utc>>(
+            "SELECT deleted_at FROM prompts WHERE id = $1 AND deleted_at IS NOT NULL FOR UPDATE",
+        )
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
         let prompt = sqlx::query_as::<_, Prompt>(
             r#"
             UPDATE prompts
             SET deleted_at = NULL
-            WHERE id = $1 AND deleted_at IS NOT NULL
-            RETURNING id, title, content, created_at, updated_at, deleted_at
+            WHERE id = $1
+            RETURNING id, title, content, language::text AS language, version, created_at, updated_at, deleted_at
             "#,
         )
         .bind(id)
-        .fetch_one(pool)
+        .fetch_one(&mut *tx)
         .await?;
+        sqlx::query("UPDATE haikus SET deleted_at = NULL WHERE prompt_id = $1 AND deleted_at = $2")
+            .bind(id)
+            .bind(deleted_at)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
 
         Ok(prompt)
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, InputObject)]
 pub struct PromptInput {
-    title: String,
-    content: String,
+    pub title: String,
+    pub content: String,
+    pub language: Option<String>,
+}
+
+const TITLE: &[Rule] = &[Rule::NotBlank, Rule::MaxLength(255)];
+/// Prompts are a theme for a few haikus, not an essay.
+const CONTENT: &[Rule] = &[Rule::NotBlank, Rule::MaxLength(2000)];
+
+impl Validate for PromptInput {
+    fn normalize(&mut self) {
+        self.title.trim_in_place();
+        self.content.trim_in_place();
+    }
+
+    fn rules(&self, validator: &mut Validator) {
+        validator.field("title", &self.title, TITLE);
+        validator.field("content", &self.content, CONTENT);
+    }
 }
 
 impl PromptInput {
-    pub async fn create(pool: &PgPool, input: PromptInput) -> Result<Prompt, sqlx::Error> {
+    pub async fn create<'e>(
+        executor: impl PgExecutor<'e>,
+        input: PromptInput,
+    ) -> Result<Prompt, sqlx::Error> {
         let prompt = sqlx::query_as::<_, Prompt>(
             r#"
-            INSERT INTO prompts (title, content)
-            VALUES ($1, $2)
-            RETURNING id, title, content, created_at, updated_at, deleted_at
+            INSERT INTO prompts (title, content, language)
+            VALUES ($1, $2, COALESCE($3::regconfig, 'english'))
+            RETURNING id, title, content, language::text AS language, version, created_at, updated_at, deleted_at
             "#,
         )
         .bind(input.title)
         .bind(input.content)
-        .fetch_one(pool)
+        .bind(input.language)
+        .fetch_one(executor)
         .await?;
 
         Ok(prompt)
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, InputObject)]
 pub struct UpdatePrompt {
     title: Option<String>,
     content: Option<String>,
+    language: Option<String>,
+}
+
+impl Validate for UpdatePrompt {
+    fn normalize(&mut self) {
+        self.title.trim_in_place();
+        self.content.trim_in_place();
+    }
+
+    fn rules(&self, validator: &mut Validator) {
+        validator.field("title", &self.title, TITLE);
+        validator.field("content", &self.content, CONTENT);
+    }
 }
 
 impl UpdatePrompt {
+    /// Fails with a conflict when `expected_version` is given and the prompt
+    /// is no longer at that version.
+    ///
+    /// Setting the language enqueues a reindex of the prompt's haikus, which
+    /// take their language from it.
     pub async fn update(
         pool: &PgPool,
         id: Uuid,
         input: UpdatePrompt,
-    ) -> Result<Prompt, sqlx::Error> {
+        expected_version: Option<i32>,
+    ) -> Result<Prompt, UpdateError<Prompt>> {
+        let mut tx = pool.begin().await?;
+        let reindex = input.language.is_some();
         let prompt = sqlx::query_as::<_, Prompt>(
             r#"
             UPDATE prompts
-            SET title = COALESCE($1, title), content = COALESCE($2, content), updated_at = now()
-            WHERE id = $3 AND deleted_at IS NULL
-            RETURNING id, title, content, created_at, updated_at, deleted_at
+            SET title = COALESCE($1, title), content = COALESCE($2, content),
+                language = COALESCE($4::regconfig, language), version = version + 1,
+                updated_at = now()
+            WHERE id = $3 AND deleted_at IS NULL AND ($5::integer IS NULL OR version = $5)
+            RETURNING id, title, content, language::text AS language, version, created_at, updated_at, deleted_at
             "#,
         )
         .bind(input.title)
         .bind(input.content)
         .bind(id)
-        .fetch_one(pool)
+        .bind(input.language)
+        .bind(expected_version)
+        .fetch_optional(&mut *tx)
         .await?;
 
-        Ok(prompt)
+        match prompt {
+            Some(prompt) => {
+                if reindex {
+                    let payload = serde_json::to_value(ReindexHaikusPayload {
+                        prompt_id: prompt.id,
+                    })
+                    .expect("payload serializes");
+                    NewJob::new(REINDEX_HAIKUS, payload)
+                        .enqueue(&mut *tx)
+                        .await?;
+                }
+                tx.commit().await?;
+                Ok(prompt)
+            }
+            None => {
+                let current = Prompt::get(pool, id).await.ok();
+                Err(UpdateError::unmatched(expected_version, current))
+            }
+        }
     }
 }
@@ -1,4 +1,8 @@
 use super::entity::{Prompt, PromptInput, UpdatePrompt};
+use crate::app::error::AppError;
+use crate::app::validation::Validate;
+use crate::auth::viewer::Viewer;
+use crate::tags::entity::normalize_names;
 use async_graphql::Context;
 use sqlx::PgPool;
 use uuid::Uuid;
@@ -7,9 +11,15 @@ pub struct QueryRoot;
 
 #[async_graphql::Object]
 impl QueryRoot {
-    async fn list_prompts(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Prompt>> {
+    /// Prompts, optionally only those tagged with all of `tags`.
+    async fn list_prompts(
+        &self,
+        ctx: &Context<'_>,
+        tags: Option<Vec<String>>,
+    ) -> async_graphql::Result<Vec<Prompt>> {
         let pool = ctx.data::<PgPool>()?;
-        let prompts = Prompt::list(pool).await?;
+        let tags = tags.map(normalize_names);
+        let prompts = Prompt::list(pool, tags.as_deref()).await?;
         Ok(prompts)
     }
 
@@ -27,38 +37,52 @@ impl MutationRoot {
     async fn create_prompt(
         &self,
         ctx: &Context<'_>,
-        data: PromptInput,
+        mut data: PromptInput,
     ) -> async_graphql::Result<Prompt> {
         let pool = ctx.data::<PgPool>()?;
+        Viewer::require(ctx)?;
+        data.validate("data")?;
         let prompt = PromptInput::create(pool, data).await?;
         Ok(prompt)
     }
 
+    /// With `expectedVersion`, fails with a `CONFLICT` error holding the
+    /// current prompt if someone else updated it since.
     async fn update_prompt(
         &self,
         ctx: &Context<'_>,
         id: Uuid,
-        data: UpdatePrompt,
+        mut data: UpdatePrompt,
+        expected_version: Option<i32>,
     ) -> async_graphql::Result<Prompt> {
         let pool = ctx.data::<PgPool>()?;
-        let prompt = UpdatePrompt::update(pool, id, data).await?;
+        Viewer::require(ctx)?;
+        data.validate("data")?;
+        let prompt = UpdatePrompt::update(pool, id, data, expected_version)
+            .await
+            .map_err(AppError::from)?;
         Ok(prompt)
     }
 
     async fn delete_prompt(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<Prompt> {
         let pool = ctx.data::<PgPool>()?;
+        Viewer::require(ctx)?;
         let prompt = Prompt::delete(pool, id).await?;
         Ok(prompt)
     }
 
+    /// Admin only.
     async fn restore_prompt(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<Prompt> {
         let pool = ctx.data::<PgPool>()?;
+        Viewer::require_admin(ctx).await?;
         let prompt = Prompt::restore(pool, id).await?;
         Ok(prompt)
     }
 
+    /// Admin only.
     async fn destroy_prompt(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<bool> {
         let pool = ctx.data::<PgPool>()?;
+        Viewer::require_admin(ctx).await?;
         Prompt::destroy(pool, id).await?;
         Ok(true)
     }
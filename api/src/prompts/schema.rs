@@ -1,4 +1,6 @@
 pub(crate) use super::resolver::{MutationRoot, QueryRoot};
+use crate::app::error::ErrorCodes;
+use crate::audit::extension::Audit;
 use async_graphql::{EmptySubscription, Schema};
 use sqlx::PgPool;
 
@@ -7,5 +9,7 @@ pub type PromptSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
 pub fn create_schema(pool: PgPool) -> PromptSchema {
     Schema::build(QueryRoot, MutationRoot, EmptySubscription)
         .data(pool)
+        .extension(ErrorCodes)
+        .extension(Audit)
         .finish()
 }
@@ -0,0 +1,88 @@
+use crate::haiku::entity::Haiku;
+use crate::prompts::entity::Prompt;
+use async_graphql::{Enum, SimpleObject, Union};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum, sqlx::Type)]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum SearchType {
+    Haiku,
+    Prompt,
+}
+
+#[derive(Union)]
+pub enum SearchNode {
+    Haiku(Haiku),
+    Prompt(Prompt),
+}
+
+#[derive(SimpleObject)]
+pub struct SearchResult {
+    pub rank: f32,
+    /// Matching text with the search terms wrapped in `<mark>` tags.
+    pub headline: String,
+    pub node: SearchNode,
+}
+
+#[derive(Debug, FromRow)]
+pub struct SearchHit {
+    pub kind: SearchType,
+    pub id: Uuid,
+    pub rank: f32,
+    pub headline: String,
+}
+
+pub struct SearchQuery {
+    pub query: String,
+    pub types: Vec<SearchType>,
+    pub language: String,
+}
+
+impl SearchQuery {
+    /// Ranked matches across haikus and prompts indexed with `language`.
+    pub async fn hits(
+        &self,
+        pool: &PgPool,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<SearchHit>, sqlx::Error> {
+        let hits = sqlx::query_as::<_, SearchHit>(
+            r#"
+            WITH q AS (SELECT websearch_to_tsquery($2::regconfig, $1) AS query)
+            SELECT kind, id, rank, headline
+            FROM (
+                SELECT 'haiku' AS kind, h.id, ts_rank(h.search_vector, q.query) AS rank,
+                       ts_headline($2::regconfig, h.content, q.query,
+                                   'StartSel=<mark>, StopSel=</mark>, HighlightAll=true') AS headline
+                FROM haikus h, q
+                WHERE 'haiku' = ANY($3)
+                  AND h.deleted_at IS NULL
+                  AND h.language = $2::regconfig
+                  AND h.search_vector @@ q.query
+                UNION ALL
+                SELECT 'prompt' AS kind, p.id, ts_rank(p.search_vector, q.query) AS rank,
+                       ts_headline($2::regconfig, p.title || E'\n' || p.content, q.query,
+                                   'StartSel=<mark>, StopSel=</mark>') AS headline
+                FROM prompts p, q
+                WHERE 'prompt' = ANY($3)
+                  AND p.deleted_at IS NULL
+                  AND p.language = $2::regconfig
+                  AND p.search_vector @@ q.query
+            ) results
+            ORDER BY rank DESC, id
+            LIMIT $4 OFFSET $5
+            "#,
+        )
+        .bind(&self.query)
+        .bind(&self.language)
+        .bind(&self.types)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(hits)
+    }
+}
@@ -0,0 +1,3 @@
+pub mod entity;
+pub mod resolver;
+pub mod schema;
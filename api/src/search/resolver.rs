@@ -0,0 +1,92 @@
+use super::entity::{SearchNode, SearchQuery, SearchResult, SearchType};
+use crate::haiku::entity::Haiku;
+use crate::prompts::entity::Prompt;
+use async_graphql::Context;
+use async_graphql::connection::{self, Connection, Edge};
+use sqlx::PgPool;
+use std::collections::HashMap;
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 100;
+
+/// Text search configuration used when `search` is called without a language.
+pub struct DefaultSearchLanguage(pub String);
+
+pub struct QueryRoot;
+
+#[async_graphql::Object]
+impl QueryRoot {
+    /// Full-text search over haikus and prompts, best matches first.
+    async fn search(
+        &self,
+        ctx: &Context<'_>,
+        query: String,
+        types: Option<Vec<SearchType>>,
+        language: Option<String>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> async_graphql::Result<Connection<usize, SearchResult>> {
+        let pool = ctx.data::<PgPool>()?;
+        let search = SearchQuery {
+            query,
+            types: types.unwrap_or_else(|| vec![SearchType::Haiku, SearchType::Prompt]),
+            language: match language {
+                Some(language) => language,
+                None => ctx.data::<DefaultSearchLanguage>()?.0.clone(),
+            },
+        };
+
+        connection::query(
+            after,
+            None,
+            first,
+            None,
+            |after: Option<usize>, _: Option<usize>, first, _| async move {
+                let offset = after.map(|after| after + 1).unwrap_or(0);
+                let limit = first.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+
+                // One extra row tells whether there is a next page.
+                let mut hits = search.hits(pool, limit as i64 + 1, offset as i64).await?;
+                let has_next_page = hits.len() > limit;
+                hits.truncate(limit);
+
+                let (haiku_ids, prompt_ids): (Vec<_>, Vec<_>) =
+                    hits.iter().partition(|hit| hit.kind == SearchType::Haiku);
+                let haiku_ids: Vec<_> = haiku_ids.iter().map(|hit| hit.id).collect();
+                let prompt_ids: Vec<_> = prompt_ids.iter().map(|hit| hit.id).collect();
+
+                let mut haikus: HashMap<_, _> = Haiku::list_by_ids(pool, &haiku_ids)
+                    .await?
+                    .into_iter()
+                    .map(|haiku| (haiku.id, haiku))
+                    .collect();
+                let mut prompts: HashMap<_, _> = Prompt::list_by_ids(pool, &prompt_ids)
+                    .await?
+                    .into_iter()
+                    .map(|prompt| (prompt.id, prompt))
+                    .collect();
+
+                let mut connection = Connection::new(offset > 0, has_next_page);
+                for (index, hit) in hits.into_iter().enumerate() {
+                    let node = match hit.kind {
+                        SearchType::Haiku => haikus.remove(&hit.id).map(SearchNode::Haiku),
+                        SearchType::Prompt => prompts.remove(&hit.id).map(SearchNode::Prompt),
+                    };
+                    if let Some(node) = node {
+                        connection.edges.push(Edge::new(
+                            offset + index,
+                            SearchResult {
+                                rank: hit.rank,
+                                headline: hit.headline,
+                                node,
+                            },
+                        ));
+                    }
+                }
+
+                Ok::<_, async_graphql::Error>(connection)
+            },
+        )
+        .await
+    }
+}
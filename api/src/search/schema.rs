@@ -0,0 +1,15 @@
+use super::resolver::DefaultSearchLanguage;
+pub(crate) use super::resolver::QueryRoot;
+use crate::app::error::ErrorCodes;
+use async_graphql::{EmptyMutation, EmptySubscription, Schema};
+use sqlx::PgPool;
+
+pub type SearchSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+pub fn create_schema(pool: PgPool, default_language: String) -> SearchSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(pool)
+        .data(DefaultSearchLanguage(default_language))
+        .extension(ErrorCodes)
+        .finish()
+}
@@ -0,0 +1,374 @@
+use crate::haiku::similarity::normalize;
+use async_graphql::{Enum, InputObject, SimpleObject};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Acquire, FromRow, PgConnection, PgPool, Postgres};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum, sqlx::Type)]
+#[sqlx(type_name = "tag_kind", rename_all = "lowercase")]
+pub enum TagKind {
+    /// Seasonal reference, e.g. a kigo.
+    Season,
+    Mood,
+    Subject,
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject, FromRow)]
+pub struct Tag {
+    pub id: Uuid,
+    pub name: String,
+    pub kind: TagKind,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, SimpleObject, FromRow)]
+pub struct TagCount {
+    #[sqlx(flatten)]
+    pub tag: Tag,
+    pub haiku_count: i64,
+    pub prompt_count: i64,
+}
+
+impl Tag {
+    pub async fn list(pool: &PgPool, kind: Option<TagKind>) -> Result<Vec<Tag>, sqlx::Error> {
+        let tags = sqlx::query_as::<_, Tag>(
+            r#"
+            SELECT id, name, kind, created_at, updated_at
+            FROM tags
+            WHERE $1::tag_kind IS NULL OR kind = $1
+            ORDER BY name
+            "#,
+        )
+        .bind(kind)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(tags)
+    }
+
+    pub async fn get(pool: &PgPool, id: Uuid) -> Result<Tag, sqlx::Error> {
+        let tag = sqlx::query_as::<_, Tag>(
+            r#"
+            SELECT id, name, kind, created_at, updated_at
+            FROM tags
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(tag)
+    }
+
+    pub async fn destroy(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM tags
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Tags by number of live haikus and prompts using them, busiest first.
+    pub async fn cloud(
+        pool: &PgPool,
+        kind: Option<TagKind>,
+        limit: i64,
+    ) -> Result<Vec<TagCount>, sqlx::Error> {
+        let counts = sqlx::query_as::<_, TagCount>(
+            r#"
+            SELECT *
+            FROM (
+                SELECT t.id, t.name, t.kind, t.created_at, t.updated_at,
+                       (SELECT count(*) FROM haiku_tags ht JOIN haikus h ON h.id = ht.haiku_id
+                        WHERE ht.tag_id = t.id AND h.deleted_at IS NULL) AS haiku_count,
+                       (SELECT count(*) FROM prompt_tags pt JOIN prompts p ON p.id = pt.prompt_id
+                        WHERE pt.tag_id = t.id AND p.deleted_at IS NULL) AS prompt_count
+                FROM tags t
+                WHERE $1::tag_kind IS NULL OR t.kind = $1
+            ) counts
+            ORDER BY haiku_count + prompt_count DESC, name
+            LIMIT $2
+            "#,
+        )
+        .bind(kind)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(counts)
+    }
+
+    /// Existing tags whose name appears as whole words in `content`.
+    pub async fn suggest(pool: &PgPool, content: &str) -> Result<Vec<Tag>, sqlx::Error> {
+        let tags = sqlx::query_as::<_, Tag>(
+            r#"
+            SELECT id, name, kind, created_at, updated_at
+            FROM tags
+            WHERE strpos(' ' || $1 || ' ', ' ' || name || ' ') > 0
+            ORDER BY name
+            "#,
+        )
+        .bind(normalize(content))
+        .fetch_all(pool)
+        .await?;
+
+        Ok(tags)
+    }
+
+    pub async fn list_for_haiku(pool: &PgPool, haiku_id: Uuid) -> Result<Vec<Tag>, sqlx::Error> {
+        let tags = sqlx::query_as::<_, Tag>(
+            r#"
+            SELECT t.id, t.name, t.kind, t.created_at, t.updated_at
+            FROM haiku_tags ht
+            JOIN tags t ON t.id = ht.tag_id
+            WHERE ht.haiku_id = $1
+            ORDER BY t.name
+            "#,
+        )
+        .bind(haiku_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(tags)
+    }
+
+    /// `(haiku_id, tag name)` pairs of the given haikus, by name.
+    pub async fn names_for_haikus(
+        pool: &PgPool,
+        haiku_ids: &[Uuid],
+    ) -> Result<Vec<(Uuid, String)>, sqlx::Error> {
+        let names = sqlx::query_as::<_, (Uuid, String)>(
+            r#"
+            SELECT ht.haiku_id, t.name
+            FROM haiku_tags ht
+            JOIN tags t ON t.id = ht.tag_id
+            WHERE ht.haiku_id = ANY($1)
+            ORDER BY t.name
+            "#,
+        )
+        .bind(haiku_ids)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(names)
+    }
+
+    pub async fn list_for_prompt(pool: &PgPool, prompt_id: Uuid) -> Result<Vec<Tag>, sqlx::Error> {
+        let tags = sqlx::query_as::<_, Tag>(
+            r#"
+            SELECT t.id, t.name, t.kind, t.created_at, t.updated_at
+            FROM prompt_tags pt
+            JOIN tags t ON t.id = pt.tag_id
+            WHERE pt.prompt_id = $1
+            ORDER BY t.name
+            "#,
+        )
+        .bind(prompt_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(tags)
+    }
+
+    /// Links the haiku to the tags named `names`, creating missing tags.
+    /// Tags gaining or losing a haiku count as updated, for their feeds.
+    pub async fn tag_haiku<'c>(
+        conn: impl Acquire<'c, Database = Postgres>,
+        haiku_id: Uuid,
+        names: &[String],
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = conn.begin().await?;
+        let tag_ids = Tag::ensure(&mut tx, names).await?;
+
+        sqlx::query(
+            r#"
+            WITH linked AS (
+                INSERT INTO haiku_tags (haiku_id, tag_id)
+                SELECT $1, UNNEST($2::uuid[])
+                ON CONFLICT DO NOTHING
+                RETURNING tag_id
+            )
+            UPDATE tags SET updated_at = now()
+            WHERE id IN (SELECT tag_id FROM linked)
+            "#,
+        )
+        .bind(haiku_id)
+        .bind(&tag_ids)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn untag_haiku(
+        pool: &PgPool,
+        haiku_id: Uuid,
+        names: &[String],
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            WITH unlinked AS (
+                DELETE FROM haiku_tags
+                WHERE haiku_id = $1
+                  AND tag_id IN (SELECT id FROM tags WHERE name = ANY($2))
+                RETURNING tag_id
+            )
+            UPDATE tags SET updated_at = now()
+            WHERE id IN (SELECT tag_id FROM unlinked)
+            "#,
+        )
+        .bind(haiku_id)
+        .bind(names)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Links the prompt to the tags named `names`, creating missing tags.
+    pub async fn tag_prompt(
+        pool: &PgPool,
+        prompt_id: Uuid,
+        names: &[String],
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let tag_ids = Tag::ensure(&mut tx, names).await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO prompt_tags (prompt_id, tag_id)
+            SELECT $1, UNNEST($2::uuid[])
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(prompt_id)
+        .bind(&tag_ids)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn untag_prompt(
+        pool: &PgPool,
+        prompt_id: Uuid,
+        names: &[String],
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM prompt_tags
+            WHERE prompt_id = $1
+              AND tag_id IN (SELECT id FROM tags WHERE name = ANY($2))
+            "#,
+        )
+        .bind(prompt_id)
+        .bind(names)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Ids of the tags named `names`, creating the missing ones as `other`.
+    async fn ensure(conn: &mut PgConnection, names: &[String]) -> Result<Vec<Uuid>, sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO tags (name)
+            SELECT UNNEST($1::varchar[])
+            ON CONFLICT (name) DO NOTHING
+            "#,
+        )
+        .bind(names)
+        .execute(&mut *conn)
+        .await?;
+
+        let tag_ids = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            SELECT id
+            FROM tags
+            WHERE name = ANY($1)
+            "#,
+        )
+        .bind(names)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        Ok(tag_ids)
+    }
+}
+
+/// Trims and lowercases tag names, dropping blanks and repeats.
+pub fn normalize_names(names: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = names
+        .into_iter()
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    normalized.sort();
+    normalized.dedup();
+    normalized
+}
+
+#[derive(Debug, Serialize, Deserialize, InputObject)]
+pub struct TagInput {
+    name: String,
+    kind: Option<TagKind>,
+}
+
+impl TagInput {
+    pub async fn create(pool: &PgPool, data: TagInput) -> Result<Tag, sqlx::Error> {
+        let tag = sqlx::query_as::<_, Tag>(
+            r#"
+            INSERT INTO tags (name, kind)
+            VALUES ($1, COALESCE($2, 'other'::tag_kind))
+            RETURNING id, name, kind, created_at, updated_at
+            "#,
+        )
+        .bind(data.name.trim().to_lowercase())
+        .bind(data.kind)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(tag)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, InputObject)]
+pub struct UpdateTag {
+    name: Option<String>,
+    kind: Option<TagKind>,
+}
+
+impl UpdateTag {
+    pub async fn update(pool: &PgPool, id: Uuid, data: UpdateTag) -> Result<Tag, sqlx::Error> {
+        let tag = sqlx::query_as::<_, Tag>(
+            r#"
+            UPDATE tags
+            SET name = COALESCE($2, name), kind = COALESCE($3, kind), updated_at = now()
+            WHERE id = $1
+            RETURNING id, name, kind, created_at, updated_at
+            "#,
+        )
+        .bind(id)
+        .bind(data.name.map(|name| name.trim().to_lowercase()))
+        .bind(data.kind)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(tag)
+    }
+}
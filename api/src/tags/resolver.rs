@@ -0,0 +1,126 @@
+use super::entity::{Tag, TagCount, TagInput, TagKind, UpdateTag, normalize_names};
+use async_graphql::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub struct QueryRoot;
+
+#[async_graphql::Object]
+impl QueryRoot {
+    async fn list_tags(
+        &self,
+        ctx: &Context<'_>,
+        kind: Option<TagKind>,
+    ) -> async_graphql::Result<Vec<Tag>> {
+        let pool = ctx.data::<PgPool>()?;
+        let tags = Tag::list(pool, kind).await?;
+        Ok(tags)
+    }
+
+    async fn get_tag(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<Tag> {
+        let pool = ctx.data::<PgPool>()?;
+        let tag = Tag::get(pool, id).await?;
+        Ok(tag)
+    }
+
+    /// Tags with the number of haikus and prompts using them.
+    async fn tag_cloud(
+        &self,
+        ctx: &Context<'_>,
+        kind: Option<TagKind>,
+        #[graphql(default = 50)] limit: i64,
+    ) -> async_graphql::Result<Vec<TagCount>> {
+        let pool = ctx.data::<PgPool>()?;
+        let counts = Tag::cloud(pool, kind, limit).await?;
+        Ok(counts)
+    }
+
+    /// Existing tags mentioned in `text`.
+    async fn suggest_tags(
+        &self,
+        ctx: &Context<'_>,
+        text: String,
+    ) -> async_graphql::Result<Vec<Tag>> {
+        let pool = ctx.data::<PgPool>()?;
+        let tags = Tag::suggest(pool, &text).await?;
+        Ok(tags)
+    }
+}
+
+pub struct MutationRoot;
+
+#[async_graphql::Object]
+impl MutationRoot {
+    async fn create_tag(&self, ctx: &Context<'_>, data: TagInput) -> async_graphql::Result<Tag> {
+        let pool = ctx.data::<PgPool>()?;
+        let tag = TagInput::create(pool, data).await?;
+        Ok(tag)
+    }
+
+    async fn update_tag(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+        data: UpdateTag,
+    ) -> async_graphql::Result<Tag> {
+        let pool = ctx.data::<PgPool>()?;
+        let tag = UpdateTag::update(pool, id, data).await?;
+        Ok(tag)
+    }
+
+    async fn destroy_tag(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<bool> {
+        let pool = ctx.data::<PgPool>()?;
+        Tag::destroy(pool, id).await?;
+        Ok(true)
+    }
+
+    /// Adds tags to a haiku, creating unknown names, and returns all its tags.
+    async fn tag_haiku(
+        &self,
+        ctx: &Context<'_>,
+        haiku_id: Uuid,
+        tags: Vec<String>,
+    ) -> async_graphql::Result<Vec<Tag>> {
+        let pool = ctx.data::<PgPool>()?;
+        Tag::tag_haiku(pool, haiku_id, &normalize_names(tags)).await?;
+        let tags = Tag::list_for_haiku(pool, haiku_id).await?;
+        Ok(tags)
+    }
+
+    async fn untag_haiku(
+        &self,
+        ctx: &Context<'_>,
+        haiku_id: Uuid,
+        tags: Vec<String>,
+    ) -> async_graphql::Result<Vec<Tag>> {
+        let pool = ctx.data::<PgPool>()?;
+        Tag::untag_haiku(pool, haiku_id, &normalize_names(tags)).await?;
+        let tags = Tag::list_for_haiku(pool, haiku_id).await?;
+        Ok(tags)
+    }
+
+    /// Adds tags to a prompt, creating unknown names, and returns all its tags.
+    async fn tag_prompt(
+        &self,
+        ctx: &Context<'_>,
+        prompt_id: Uuid,
+        tags: Vec<String>,
+    ) -> async_graphql::Result<Vec<Tag>> {
+        let pool = ctx.data::<PgPool>()?;
+        Tag::tag_prompt(pool, prompt_id, &normalize_names(tags)).await?;
+        let tags = Tag::list_for_prompt(pool, prompt_id).await?;
+        Ok(tags)
+    }
+
+    async fn untag_prompt(
+        &self,
+        ctx: &Context<'_>,
+        prompt_id: Uuid,
+        tags: Vec<String>,
+    ) -> async_graphql::Result<Vec<Tag>> {
+        let pool = ctx.data::<PgPool>()?;
+        Tag::untag_prompt(pool, prompt_id, &normalize_names(tags)).await?;
+        let tags = Tag::list_for_prompt(pool, prompt_id).await?;
+        Ok(tags)
+    }
+}
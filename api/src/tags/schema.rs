@@ -0,0 +1,15 @@
+pub(crate) use super::resolver::{MutationRoot, QueryRoot};
+use crate::app::error::ErrorCodes;
+use crate::audit::extension::Audit;
+use async_graphql::{EmptySubscription, Schema};
+use sqlx::PgPool;
+
+pub type TagSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+pub fn create_schema(pool: PgPool) -> TagSchema {
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .data(pool)
+        .extension(ErrorCodes)
+        .extension(Audit)
+        .finish()
+}
@@ -0,0 +1,327 @@
+use super::record::{
+    CsvRow, HaikuRecord, PromptRecord, Record, TransferError, TransferFormat, UserHaikuRecord,
+    UserRecord,
+};
+use async_graphql::SimpleObject;
+use async_graphql::futures_util::TryStreamExt;
+use sqlx::{FromRow, PgPool};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// Number of rows of each entity, soft-deleted ones included.
+#[derive(Debug, Default, SimpleObject, FromRow)]
+pub struct ExportSummary {
+    pub users: i64,
+    pub prompts: i64,
+    pub haikus: i64,
+    pub user_haikus: i64,
+}
+
+impl ExportSummary {
+    pub async fn count(pool: &PgPool) -> Result<ExportSummary, sqlx::Error> {
+        let summary = sqlx::query_as::<_, ExportSummary>(
+            r#"
+            SELECT (SELECT count(*) FROM users) AS users,
+                   (SELECT count(*) FROM prompts) AS prompts,
+                   (SELECT count(*) FROM haikus) AS haikus,
+                   (SELECT count(*) FROM user_haikus) AS user_haikus
+            "#,
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(summary)
+    }
+}
+
+/// Streams every user, prompt, haiku and `user_haikus` link to `out`,
+/// soft-deleted rows included, in the order they can be imported back.
+///
+/// Everything is read from one snapshot, so rows written meanwhile cannot
+/// leave a haiku pointing to a prompt missing from the export.
+pub async fn export<W: AsyncWrite + Unpin>(
+    pool: &PgPool,
+    format: TransferFormat,
+    out: &mut W,
+) -> Result<ExportSummary, TransferError> {
+    let mut writer = RecordWriter::new(out, format);
+    let mut summary = ExportSummary::default();
+    let mut tx = pool.begin().await?;
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY")
+        .execute(&mut *tx)
+        .await?;
+    writer.begin().await?;
+
+    writer.section("users").await?;
+    let mut users = sqlx::query_as::<_, UserRecord>(
+        r#"
+        SELECT id, first_name, last_name, email, password, role, created_at, updated_at, deleted_at
+        FROM users
+        ORDER BY created_at, id
+        "#,
+    )
+    .fetch(&mut *tx);
+    while let Some(user) = users.try_next().await? {
+        writer.write(Record::User(user)).await?;
+        summary.users += 1;
+    }
+    drop(users);
+
+    writer.section("prompts").await?;
+    let mut prompts = sqlx::query_as::<_, PromptRecord>(
+        r#"
+        SELECT id, title, content, language::text AS language, created_at, updated_at, deleted_at
+        FROM prompts
+        ORDER BY created_at, id
+        "#,
+    )
+    .fetch(&mut *tx);
+    while let Some(prompt) = prompts.try_next().await? {
+        writer.write(Record::Prompt(prompt)).await?;
+        summary.prompts += 1;
+    }
+    drop(prompts);
+
+    writer.section("haikus").await?;
+    let mut haikus = sqlx::query_as::<_, HaikuRecord>(
+        r#"
+        SELECT id, content, is_funny, prompt_id, language::text AS language, duplicate_of, author, created_at, updated_at, deleted_at
+        FROM haikus
+        ORDER BY created_at, id
+        "#,
+    )
+    .fetch(&mut *tx);
+    while let Some(haiku) = haikus.try_next().await? {
+        writer.write(Record::Haiku(haiku)).await?;
+        summary.haikus += 1;
+    }
+    drop(haikus);
+
+    writer.section("user_haikus").await?;
+    let mut links = sqlx::query_as::<_, UserHaikuRecord>(
+        r#"
+        SELECT user_id, haiku_id, is_read, created_at, updated_at
+        FROM user_haikus
+        ORDER BY created_at, user_id, haiku_id
+        "#,
+    )
+    .fetch(&mut *tx);
+    while let Some(link) = links.try_next().await? {
+        writer.write(Record::UserHaiku(link)).await?;
+        summary.user_haikus += 1;
+    }
+    drop(links);
+    tx.commit().await?;
+
+    writer.finish().await?;
+    Ok(summary)
+}
+
+/// Encodes records one at a time, so exports never sit in memory whole.
+struct RecordWriter<'a, W> {
+    out: &'a mut W,
+    format: TransferFormat,
+    sections: usize,
+    items: usize,
+    rows: usize,
+}
+
+impl<'a, W: AsyncWrite + Unpin> RecordWriter<'a, W> {
+    fn new(out: &'a mut W, format: TransferFormat) -> Self {
+        Self {
+            out,
+            format,
+            sections: 0,
+            items: 0,
+            rows: 0,
+        }
+    }
+
+    async fn begin(&mut self) -> Result<(), TransferError> {
+        if self.format == TransferFormat::Json {
+            self.out.write_all(b"{").await?;
+        }
+        Ok(())
+    }
+
+    async fn section(&mut self, name: &str) -> Result<(), TransferError> {
+        if self.format == TransferFormat::Json {
+            if self.sections > 0 {
+                self.out.write_all(b"],").await?;
+            }
+            self.out
+                .write_all(format!("\"{}\":[", name).as_bytes())
+                .await?;
+            self.sections += 1;
+            self.items = 0;
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, record: Record) -> Result<(), TransferError> {
+        let bytes = match self.format {
+            TransferFormat::Ndjson => {
+                let mut line = serde_json::to_vec(&record)?;
+                line.push(b'\n');
+                line
+            }
+            TransferFormat::Json => {
+                let mut item = record.to_json()?;
+                if self.items > 0 {
+                    item.insert(0, b',');
+                }
+                self.items += 1;
+                item
+            }
+            TransferFormat::Csv => {
+                let mut csv = csv::WriterBuilder::new()
+                    .has_headers(self.rows == 0)
+                    .from_writer(Vec::new());
+                csv.serialize(CsvRow::from(record))?;
+                csv.into_inner().map_err(|e| e.into_error())?
+            }
+        };
+        self.rows += 1;
+        self.out.write_all(&bytes).await?;
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<(), TransferError> {
+        if self.format == TransferFormat::Json {
+            if self.sections > 0 {
+                self.out.write_all(b"]").await?;
+            }
+            self.out.write_all(b"}\n").await?;
+        }
+        self.out.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::import::parse;
+    use crate::users::entity::UserRole;
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    fn sample() -> Vec<Record> {
+        let at = Utc.with_ymd_and_hms(2025, 3, 1, 12, 0, 0).unwrap();
+        let user = Uuid::new_v4();
+        let prompt = Uuid::new_v4();
+        let haiku = Uuid::new_v4();
+        vec![
+            Record::User(UserRecord {
+                id: user,
+                first_name: "Matsuo".to_string(),
+                last_name: "Bashō".to_string(),
+                email: "basho@example.com".to_string(),
+                password: "$argon2id$v=19$hash".to_string(),
+                role: UserRole::Admin,
+                created_at: at,
+                updated_at: at,
+                deleted_at: None,
+            }),
+            Record::Prompt(PromptRecord {
+                id: prompt,
+                title: "Autumn, \"moon\"".to_string(),
+                content: "A pond,\nat night".to_string(),
+                language: "english".to_string(),
+                created_at: at,
+                updated_at: at,
+                deleted_at: Some(at),
+            }),
+            Record::Haiku(HaikuRecord {
+                id: haiku,
+                content: "An old silent pond\nA frog jumps into the pond\nSplash! Silence again"
+                    .to_string(),
+                is_funny: false,
+                prompt_id: prompt,
+                language: "english".to_string(),
+                duplicate_of: None,
+                author: Some("Bashō".to_string()),
+                created_at: at,
+                updated_at: at,
+                deleted_at: None,
+            }),
+            Record::UserHaiku(UserHaikuRecord {
+                user_id: user,
+                haiku_id: haiku,
+                is_read: true,
+                created_at: at,
+                updated_at: at,
+            }),
+        ]
+    }
+
+    async fn encode(format: TransferFormat, records: &[Record]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut writer = RecordWriter::new(&mut out, format);
+        writer.begin().await.unwrap();
+        let sections = ["users", "prompts", "haikus", "user_haikus"];
+        for (rank, name) in sections.iter().enumerate() {
+            writer.section(name).await.unwrap();
+            for record in records.iter().filter(|r| r.rank() as usize == rank) {
+                writer.write(record.clone()).await.unwrap();
+            }
+        }
+        writer.finish().await.unwrap();
+        out
+    }
+
+    #[tokio::test]
+    async fn every_format_reads_back_what_it_wrote() {
+        let records = sample();
+        let expected = serde_json::to_value(&records).unwrap();
+        for format in [
+            TransferFormat::Ndjson,
+            TransferFormat::Json,
+            TransferFormat::Csv,
+        ] {
+            let data = encode(format, &records).await;
+            let parsed = parse(format, &data).unwrap();
+            assert_eq!(
+                serde_json::to_value(&parsed).unwrap(),
+                expected,
+                "{format:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn empty_exports_read_back_empty() {
+        for format in [
+            TransferFormat::Ndjson,
+            TransferFormat::Json,
+            TransferFormat::Csv,
+        ] {
+            let data = encode(format, &[]).await;
+            assert!(parse(format, &data).unwrap().is_empty(), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn parse_sorts_records_for_import() {
+        let mut records = sample();
+        records.reverse();
+        let data: Vec<u8> = records
+            .iter()
+            .flat_map(|r| {
+                let mut line = serde_json::to_vec(r).unwrap();
+                line.push(b'\n');
+                line
+            })
+            .collect();
+        let parsed = parse(TransferFormat::Ndjson, &data).unwrap();
+        let ranks: Vec<u8> = parsed.iter().map(Record::rank).collect();
+        assert_eq!(ranks, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn csv_rows_need_their_columns() {
+        let data =
+            b"type,id,created_at,updated_at\nprompt,,2025-03-01T12:00:00Z,2025-03-01T12:00:00Z\n";
+        let err = parse(TransferFormat::Csv, data).unwrap_err();
+        assert!(err.to_string().contains("missing `id`"), "{err}");
+    }
+}
@@ -0,0 +1,349 @@
+use super::record::{CsvRow, Dump, Record, TransferError, TransferFormat};
+use crate::haiku::jobs::FINGERPRINT_HAIKUS;
+use crate::jobs::entity::NewJob;
+use crate::users::entity::normalize_email;
+use async_graphql::SimpleObject;
+use serde::Serialize;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use std::fmt;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImportOptions {
+    /// Overwrite rows that already exist instead of skipping them.
+    pub upsert: bool,
+    /// Run the whole import, then roll it back.
+    pub dry_run: bool,
+    /// Give imported rows fresh IDs, rewriting the references to them.
+    pub remap_ids: bool,
+}
+
+#[derive(Debug, Default, Serialize, SimpleObject)]
+pub struct EntityReport {
+    pub inserted: i64,
+    pub updated: i64,
+    pub skipped: i64,
+    /// Inserted under a fresh ID, as a different row already had theirs.
+    pub remapped: i64,
+}
+
+impl EntityReport {
+    fn record(&mut self, outcome: Option<bool>) {
+        match outcome {
+            Some(true) => self.inserted += 1,
+            Some(false) => self.updated += 1,
+            None => self.skipped += 1,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, SimpleObject)]
+pub struct ImportReport {
+    /// Whether the changes were rolled back.
+    pub dry_run: bool,
+    pub users: EntityReport,
+    pub prompts: EntityReport,
+    pub haikus: EntityReport,
+    pub user_haikus: EntityReport,
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entities = [
+            ("users", &self.users),
+            ("prompts", &self.prompts),
+            ("haikus", &self.haikus),
+            ("user_haikus", &self.user_haikus),
+        ];
+        for (name, report) in entities {
+            writeln!(
+                f,
+                "{:<12} {} inserted, {} updated, {} skipped, {} remapped",
+                name, report.inserted, report.updated, report.skipped, report.remapped
+            )?;
+        }
+        if self.dry_run {
+            write!(f, "Dry run, nothing was written.")?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes an export, in any order, into records sorted for import.
+pub fn parse(format: TransferFormat, data: &[u8]) -> Result<Vec<Record>, TransferError> {
+    let mut records = match format {
+        TransferFormat::Ndjson => {
+            let text = std::str::from_utf8(data)
+                .map_err(|_| TransferError::Invalid("Input is not valid UTF-8".to_string()))?;
+            let mut records = Vec::new();
+            for (number, line) in text.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let record = serde_json::from_str::<Record>(line)
+                    .map_err(|e| TransferError::Invalid(format!("Line {}: {}", number + 1, e)))?;
+                records.push(record);
+            }
+            records
+        }
+        TransferFormat::Json => serde_json::from_slice::<Dump>(data)?.into_records(),
+        TransferFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(data);
+            let mut records = Vec::new();
+            for (number, row) in reader.deserialize::<CsvRow>().enumerate() {
+                let record = Record::try_from(row?)
+                    .map_err(|e| TransferError::Invalid(format!("Row {}: {}", number + 1, e)))?;
+                records.push(record);
+            }
+            records
+        }
+    };
+    records.sort_by_key(Record::rank);
+    Ok(records)
+}
+
+/// Writes `records` in a single transaction, so a failing row leaves the
+/// database untouched.
+///
+/// Users are matched by email first, whatever its case, so an account that
+/// already exists in the target environment keeps its ID and everything
+/// imported for it is attached there, and users whose ID belongs to another
+/// account get a fresh one rather than that account's credentials. Without
+/// `upsert`, existing rows are left as they are, and prompts and haikus
+/// whose ID is taken by a different one get a fresh ID instead of being
+/// skipped, so that nothing is linked to the wrong row.
+pub async fn import(
+    pool: &PgPool,
+    records: Vec<Record>,
+    options: ImportOptions,
+) -> Result<ImportReport, TransferError> {
+    let mut tx = pool.begin().await?;
+    let mut report = ImportReport {
+        dry_run: options.dry_run,
+        ..Default::default()
+    };
+    // Imported ID to the ID the row is stored under.
+    let mut ids: HashMap<Uuid, Uuid> = HashMap::new();
+    let mut duplicates: Vec<(Uuid, Uuid)> = Vec::new();
+
+    for record in records {
+        match record {
+            Record::User(user) => {
+                let email = normalize_email(&user.email);
+                let existing =
+                    sqlx::query_scalar::<_, Uuid>("SELECT id FROM users WHERE lower(email) = $1")
+                        .bind(&email)
+                        .fetch_optional(&mut *tx)
+                        .await?;
+                let id = match existing {
+                    Some(id) => id,
+                    None => {
+                        let id = new_id(user.id, options);
+                        // No user has this email, so whoever has the ID is
+                        // someone else.
+                        let taken = sqlx::query_scalar::<_, bool>(
+                            "SELECT EXISTS (SELECT 1 FROM users WHERE id = $1)",
+                        )
+                        .bind(id)
+                        .fetch_one(&mut *tx)
+                        .await?;
+                        if taken {
+                            report.users.remapped += 1;
+                            Uuid::new_v4()
+                        } else {
+                            id
+                        }
+                    }
+                };
+                ids.insert(user.id, id);
+                let outcome = sqlx::query_scalar::<_, bool>(
+                    r#"
+                    INSERT INTO users (id, first_name, last_name, email, password, role, created_at, updated_at, deleted_at)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                    ON CONFLICT (id) DO UPDATE
+                    SET first_name = EXCLUDED.first_name, last_name = EXCLUDED.last_name,
+                        email = EXCLUDED.email, password = EXCLUDED.password, role = EXCLUDED.role,
+                        created_at = EXCLUDED.created_at, updated_at = EXCLUDED.updated_at,
+                        deleted_at = EXCLUDED.deleted_at, version = users.version + 1
+                    WHERE $10 AND lower(users.email) = EXCLUDED.email
+                    RETURNING xmax = 0
+                    "#,
+                )
+                .bind(id)
+                .bind(&user.first_name)
+                .bind(&user.last_name)
+                .bind(&email)
+                .bind(&user.password)
+                .bind(user.role)
+                .bind(user.created_at)
+                .bind(user.updated_at)
+                .bind(user.deleted_at)
+                .bind(options.upsert)
+                .fetch_optional(&mut *tx)
+                .await?;
+                report.users.record(outcome);
+            }
+            Record::Prompt(prompt) => {
+                let mut id = new_id(prompt.id, options);
+                if !options.upsert {
+                    let same = sqlx::query_scalar::<_, bool>(
+                        "SELECT title = $2 AND content = $3 FROM prompts WHERE id = $1",
+                    )
+                    .bind(id)
+                    .bind(&prompt.title)
+                    .bind(&prompt.content)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+                    if same == Some(false) {
+                        id = Uuid::new_v4();
+                        report.prompts.remapped += 1;
+                    }
+                }
+                ids.insert(prompt.id, id);
+                let outcome = sqlx::query_scalar::<_, bool>(
+                    r#"
+                    INSERT INTO prompts (id, title, content, language, created_at, updated_at, deleted_at)
+                    VALUES ($1, $2, $3, $4::regconfig, $5, $6, $7)
+                    ON CONFLICT (id) DO UPDATE
+                    SET title = EXCLUDED.title, content = EXCLUDED.content,
+                        language = EXCLUDED.language, created_at = EXCLUDED.created_at,
+                        updated_at = EXCLUDED.updated_at, deleted_at = EXCLUDED.deleted_at,
+                        version = prompts.version + 1
+                    WHERE $8
+                    RETURNING xmax = 0
+                    "#,
+                )
+                .bind(id)
+                .bind(&prompt.title)
+                .bind(&prompt.content)
+                .bind(&prompt.language)
+                .bind(prompt.created_at)
+                .bind(prompt.updated_at)
+                .bind(prompt.deleted_at)
+                .bind(options.upsert)
+                .fetch_optional(&mut *tx)
+                .await?;
+                report.prompts.record(outcome);
+            }
+            Record::Haiku(haiku) => {
+                let mut id = new_id(haiku.id, options);
+                let prompt_id = resolve(&ids, haiku.prompt_id);
+                if !options.upsert {
+                    let same = sqlx::query_scalar::<_, bool>(
+                        "SELECT content = $2 AND prompt_id = $3 FROM haikus WHERE id = $1",
+                    )
+                    .bind(id)
+                    .bind(&haiku.content)
+                    .bind(prompt_id)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+                    if same == Some(false) {
+                        id = Uuid::new_v4();
+                        report.haikus.remapped += 1;
+                    }
+                }
+                ids.insert(haiku.id, id);
+                // Inserted without a fingerprint, which a background job
+                // fills in once the import is committed.
+                let outcome = sqlx::query_scalar::<_, bool>(
+                    r#"
+                    INSERT INTO haikus (id, content, is_funny, prompt_id, language, author, created_at, updated_at, deleted_at)
+                    VALUES ($1, $2, $3, $4, $5::regconfig, $6, $7, $8, $9)
+                    ON CONFLICT (id) DO UPDATE
+                    SET content = EXCLUDED.content, is_funny = EXCLUDED.is_funny,
+                        prompt_id = EXCLUDED.prompt_id, language = EXCLUDED.language,
+                        author = EXCLUDED.author,
+                        created_at = EXCLUDED.created_at, updated_at = EXCLUDED.updated_at,
+                        deleted_at = EXCLUDED.deleted_at,
+                        fingerprint = NULL, fingerprint_bands = NULL,
+                        version = haikus.version + 1
+                    WHERE $10
+                    RETURNING xmax = 0
+                    "#,
+                )
+                .bind(id)
+                .bind(&haiku.content)
+                .bind(haiku.is_funny)
+                .bind(prompt_id)
+                .bind(&haiku.language)
+                .bind(&haiku.author)
+                .bind(haiku.created_at)
+                .bind(haiku.updated_at)
+                .bind(haiku.deleted_at)
+                .bind(options.upsert)
+                .fetch_optional(&mut *tx)
+                .await?;
+                // The original may come later in the export.
+                if let (Some(_), Some(duplicate_of)) = (outcome, haiku.duplicate_of) {
+                    duplicates.push((id, duplicate_of));
+                }
+                report.haikus.record(outcome);
+            }
+            Record::UserHaiku(link) => {
+                let outcome = sqlx::query_scalar::<_, bool>(
+                    r#"
+                    INSERT INTO user_haikus (user_id, haiku_id, is_read, created_at, updated_at)
+                    VALUES ($1, $2, $3, $4, $5)
+                    ON CONFLICT (user_id, haiku_id) DO UPDATE
+                    SET is_read = EXCLUDED.is_read, created_at = EXCLUDED.created_at,
+                        updated_at = EXCLUDED.updated_at
+                    WHERE $6
+                    RETURNING xmax = 0
+                    "#,
+                )
+                .bind(resolve(&ids, link.user_id))
+                .bind(resolve(&ids, link.haiku_id))
+                .bind(link.is_read)
+                .bind(link.created_at)
+                .bind(link.updated_at)
+                .bind(options.upsert)
+                .fetch_optional(&mut *tx)
+                .await?;
+                report.user_haikus.record(outcome);
+            }
+        }
+    }
+
+    link_duplicates(&mut tx, &ids, duplicates).await?;
+    if report.haikus.inserted + report.haikus.updated > 0 {
+        NewJob::new(FINGERPRINT_HAIKUS, serde_json::json!({}))
+            .enqueue(&mut *tx)
+            .await?;
+    }
+
+    if options.dry_run {
+        tx.rollback().await?;
+    } else {
+        tx.commit().await?;
+    }
+    Ok(report)
+}
+
+async fn link_duplicates(
+    tx: &mut Transaction<'_, Postgres>,
+    ids: &HashMap<Uuid, Uuid>,
+    duplicates: Vec<(Uuid, Uuid)>,
+) -> Result<(), sqlx::Error> {
+    for (id, duplicate_of) in duplicates {
+        sqlx::query("UPDATE haikus SET duplicate_of = $2 WHERE id = $1")
+            .bind(id)
+            .bind(resolve(ids, duplicate_of))
+            .execute(&mut **tx)
+            .await?;
+    }
+    Ok(())
+}
+
+fn new_id(id: Uuid, options: ImportOptions) -> Uuid {
+    match options.remap_ids {
+        true => Uuid::new_v4(),
+        false => id,
+    }
+}
+
+/// References to rows outside the import are kept as they are, and must
+/// already exist in the target database.
+fn resolve(ids: &HashMap<Uuid, Uuid>, id: Uuid) -> Uuid {
+    ids.get(&id).copied().unwrap_or(id)
+}
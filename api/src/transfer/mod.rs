@@ -0,0 +1,5 @@
+pub mod export;
+pub mod import;
+pub mod record;
+pub mod resolver;
+pub mod schema;
@@ -0,0 +1,295 @@
+use crate::app::error::AppError;
+use crate::users::entity::UserRole;
+use async_graphql::Enum;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::str::FromStr;
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Debug, Error)]
+pub enum TransferError {
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid CSV: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("{0}")]
+    Invalid(String),
+}
+
+impl From<TransferError> for AppError {
+    /// Anything wrong with the data itself is the caller's to fix.
+    fn from(err: TransferError) -> Self {
+        match err {
+            TransferError::Database(err) => AppError::from(err),
+            TransferError::Io(err) => AppError::Internal(err.to_string()),
+            err => AppError::Validation(err.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+pub enum TransferFormat {
+    /// One `{"type": ..., ...}` object per line.
+    Ndjson,
+    /// A single object with one array per entity.
+    Json,
+    /// One row per record, with a `type` column and the union of all columns.
+    Csv,
+}
+
+impl FromStr for TransferFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ndjson" | "jsonl" => Ok(TransferFormat::Ndjson),
+            "json" => Ok(TransferFormat::Json),
+            "csv" => Ok(TransferFormat::Csv),
+            _ => Err(format!("Unknown format `{}`", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct UserRecord {
+    pub id: Uuid,
+    pub first_name: String,
+    pub last_name: String,
+    pub email: String,
+    /// Argon2 hash, so accounts keep working in the target environment.
+    pub password: String,
+    pub role: UserRole,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct PromptRecord {
+    pub id: Uuid,
+    pub title: String,
+    pub content: String,
+    /// Text search configuration, e.g. `english`.
+    pub language: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct HaikuRecord {
+    pub id: Uuid,
+    pub content: String,
+    pub is_funny: bool,
+    pub prompt_id: Uuid,
+    pub language: String,
+    pub duplicate_of: Option<Uuid>,
+    #[serde(default)]
+    pub author: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct UserHaikuRecord {
+    pub user_id: Uuid,
+    pub haiku_id: Uuid,
+    pub is_read: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// One exported row. Variants are listed in the order they must be
+/// imported for references to resolve.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record {
+    User(UserRecord),
+    Prompt(PromptRecord),
+    Haiku(HaikuRecord),
+    UserHaiku(UserHaikuRecord),
+}
+
+impl Record {
+    pub fn rank(&self) -> u8 {
+        match self {
+            Record::User(_) => 0,
+            Record::Prompt(_) => 1,
+            Record::Haiku(_) => 2,
+            Record::UserHaiku(_) => 3,
+        }
+    }
+
+    /// The record without its `type` tag.
+    pub fn to_json(&self) -> Result<Vec<u8>, serde_json::Error> {
+        match self {
+            Record::User(user) => serde_json::to_vec(user),
+            Record::Prompt(prompt) => serde_json::to_vec(prompt),
+            Record::Haiku(haiku) => serde_json::to_vec(haiku),
+            Record::UserHaiku(link) => serde_json::to_vec(link),
+        }
+    }
+}
+
+/// Layout of the JSON format.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Dump {
+    pub users: Vec<UserRecord>,
+    pub prompts: Vec<PromptRecord>,
+    pub haikus: Vec<HaikuRecord>,
+    pub user_haikus: Vec<UserHaikuRecord>,
+}
+
+impl Dump {
+    pub fn into_records(self) -> Vec<Record> {
+        let users = self.users.into_iter().map(Record::User);
+        let prompts = self.prompts.into_iter().map(Record::Prompt);
+        let haikus = self.haikus.into_iter().map(Record::Haiku);
+        let links = self.user_haikus.into_iter().map(Record::UserHaiku);
+        users.chain(prompts).chain(haikus).chain(links).collect()
+    }
+}
+
+/// Row of the CSV format. Columns a record type does not have are empty.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CsvRow {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub id: Option<Uuid>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub email: Option<String>,
+    pub password: Option<String>,
+    pub role: Option<UserRole>,
+    pub title: Option<String>,
+    pub content: Option<String>,
+    pub language: Option<String>,
+    pub is_funny: Option<bool>,
+    pub prompt_id: Option<Uuid>,
+    pub duplicate_of: Option<Uuid>,
+    pub author: Option<String>,
+    pub user_id: Option<Uuid>,
+    pub haiku_id: Option<Uuid>,
+    pub is_read: Option<bool>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl From<Record> for CsvRow {
+    fn from(record: Record) -> Self {
+        match record {
+            Record::User(user) => CsvRow {
+                kind: "user".to_string(),
+                id: Some(user.id),
+                first_name: Some(user.first_name),
+                last_name: Some(user.last_name),
+                email: Some(user.email),
+                password: Some(user.password),
+                role: Some(user.role),
+                created_at: Some(user.created_at),
+                updated_at: Some(user.updated_at),
+                deleted_at: user.deleted_at,
+                ..Default::default()
+            },
+            Record::Prompt(prompt) => CsvRow {
+                kind: "prompt".to_string(),
+                id: Some(prompt.id),
+                title: Some(prompt.title),
+                content: Some(prompt.content),
+                language: Some(prompt.language),
+                created_at: Some(prompt.created_at),
+                updated_at: Some(prompt.updated_at),
+                deleted_at: prompt.deleted_at,
+                ..Default::default()
+            },
+            Record::Haiku(haiku) => CsvRow {
+                kind: "haiku".to_string(),
+                id: Some(haiku.id),
+                content: Some(haiku.content),
+                is_funny: Some(haiku.is_funny),
+                prompt_id: Some(haiku.prompt_id),
+                language: Some(haiku.language),
+                duplicate_of: haiku.duplicate_of,
+                author: haiku.author,
+                created_at: Some(haiku.created_at),
+                updated_at: Some(haiku.updated_at),
+                deleted_at: haiku.deleted_at,
+                ..Default::default()
+            },
+            Record::UserHaiku(link) => CsvRow {
+                kind: "user_haiku".to_string(),
+                user_id: Some(link.user_id),
+                haiku_id: Some(link.haiku_id),
+                is_read: Some(link.is_read),
+                created_at: Some(link.created_at),
+                updated_at: Some(link.updated_at),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl TryFrom<CsvRow> for Record {
+    type Error = String;
+
+    fn try_from(row: CsvRow) -> Result<Self, Self::Error> {
+        fn required<T>(value: Option<T>, column: &str) -> Result<T, String> {
+            value.ok_or_else(|| format!("missing `{}`", column))
+        }
+
+        let created_at = required(row.created_at, "created_at")?;
+        let updated_at = required(row.updated_at, "updated_at")?;
+        let record = match row.kind.as_str() {
+            "user" => Record::User(UserRecord {
+                id: required(row.id, "id")?,
+                first_name: required(row.first_name, "first_name")?,
+                last_name: required(row.last_name, "last_name")?,
+                email: required(row.email, "email")?,
+                password: required(row.password, "password")?,
+                role: required(row.role, "role")?,
+                created_at,
+                updated_at,
+                deleted_at: row.deleted_at,
+            }),
+            "prompt" => Record::Prompt(PromptRecord {
+                id: required(row.id, "id")?,
+                title: required(row.title, "title")?,
+                content: required(row.content, "content")?,
+                language: required(row.language, "language")?,
+                created_at,
+                updated_at,
+                deleted_at: row.deleted_at,
+            }),
+            "haiku" => Record::Haiku(HaikuRecord {
+                id: required(row.id, "id")?,
+                content: required(row.content, "content")?,
+                is_funny: required(row.is_funny, "is_funny")?,
+                prompt_id: required(row.prompt_id, "prompt_id")?,
+                language: required(row.language, "language")?,
+                duplicate_of: row.duplicate_of,
+                author: row.author,
+                created_at,
+                updated_at,
+                deleted_at: row.deleted_at,
+            }),
+            "user_haiku" => Record::UserHaiku(UserHaikuRecord {
+                user_id: required(row.user_id, "user_id")?,
+                haiku_id: required(row.haiku_id, "haiku_id")?,
+                is_read: required(row.is_read, "is_read")?,
+                created_at,
+                updated_at,
+            }),
+            kind => return Err(format!("unknown type `{}`", kind)),
+        };
+        Ok(record)
+    }
+}
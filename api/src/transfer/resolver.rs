@@ -0,0 +1,65 @@
+use super::export::{ExportSummary, export};
+use super::import::{ImportOptions, ImportReport, import, parse};
+use super::record::TransferFormat;
+use crate::app::error::AppError;
+use crate::auth::viewer::Viewer;
+use async_graphql::Context;
+use sqlx::PgPool;
+
+pub struct QueryRoot;
+
+#[async_graphql::Object]
+impl QueryRoot {
+    /// Rows an export would currently contain.
+    async fn export_summary(&self, ctx: &Context<'_>) -> async_graphql::Result<ExportSummary> {
+        Viewer::require_admin(ctx).await?;
+        let pool = ctx.data::<PgPool>()?;
+        let summary = ExportSummary::count(pool).await?;
+        Ok(summary)
+    }
+}
+
+pub struct MutationRoot;
+
+#[async_graphql::Object]
+impl MutationRoot {
+    /// Every user, prompt, haiku and `user_haikus` link, soft-deleted ones
+    /// included. Use the `export` CLI command for large databases.
+    async fn export_data(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default_with = "TransferFormat::Ndjson")] format: TransferFormat,
+    ) -> async_graphql::Result<String> {
+        Viewer::require_admin(ctx).await?;
+        let pool = ctx.data::<PgPool>()?;
+        let mut out = Vec::new();
+        export(pool, format, &mut out)
+            .await
+            .map_err(AppError::from)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    /// Loads the output of `exportData` or of the `export` CLI command.
+    async fn import_data(
+        &self,
+        ctx: &Context<'_>,
+        data: String,
+        #[graphql(default_with = "TransferFormat::Ndjson")] format: TransferFormat,
+        #[graphql(default = false)] upsert: bool,
+        #[graphql(default = false)] dry_run: bool,
+        #[graphql(default = false)] remap_ids: bool,
+    ) -> async_graphql::Result<ImportReport> {
+        Viewer::require_admin(ctx).await?;
+        let pool = ctx.data::<PgPool>()?;
+        let records = parse(format, data.as_bytes()).map_err(AppError::from)?;
+        let options = ImportOptions {
+            upsert,
+            dry_run,
+            remap_ids,
+        };
+        let report = import(pool, records, options)
+            .await
+            .map_err(AppError::from)?;
+        Ok(report)
+    }
+}
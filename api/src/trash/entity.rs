@@ -0,0 +1,163 @@
+use async_graphql::{ComplexObject, Context, Enum, SimpleObject};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::time::Duration;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum, sqlx::Type)]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+pub enum TrashKind {
+    User,
+    Prompt,
+    Haiku,
+}
+
+impl TrashKind {
+    fn table(self) -> &'static str {
+        match self {
+            TrashKind::User => "users",
+            TrashKind::Prompt => "prompts",
+            TrashKind::Haiku => "haikus",
+        }
+    }
+}
+
+/// How long soft-deleted rows stay restorable before the purge removes them
+/// for good. A `None` retention keeps them forever.
+#[derive(Debug, Clone, Copy)]
+pub struct RetentionPolicy {
+    pub users: Option<chrono::Duration>,
+    pub prompts: Option<chrono::Duration>,
+    pub haikus: Option<chrono::Duration>,
+    /// Rows hard-deleted per statement.
+    pub batch_size: i64,
+    /// Delay between two purges.
+    pub interval: Duration,
+}
+
+impl RetentionPolicy {
+    pub fn retention(&self, kind: TrashKind) -> Option<chrono::Duration> {
+        match kind {
+            TrashKind::User => self.users,
+            TrashKind::Prompt => self.prompts,
+            TrashKind::Haiku => self.haikus,
+        }
+    }
+}
+
+/// A soft-deleted user, prompt or haiku.
+#[derive(Debug, Clone, SimpleObject, FromRow)]
+#[graphql(complex)]
+pub struct TrashItem {
+    pub kind: TrashKind,
+    pub id: Uuid,
+    /// Name, title or first line, whichever fits the kind.
+    pub label: String,
+    pub deleted_at: DateTime<Utc>,
+}
+
+#[ComplexObject]
+impl TrashItem {
+    /// When the purge will remove it, `null` if it is kept forever.
+    async fn purge_at(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<DateTime<Utc>>> {
+        let policy = ctx.data::<RetentionPolicy>()?;
+        Ok(policy
+            .retention(self.kind)
+            .map(|retention| self.deleted_at + retention))
+    }
+}
+
+impl TrashItem {
+    /// Soft-deleted rows of `kind`, or of every kind, most recently deleted
+    /// first.
+    pub async fn list(
+        pool: &PgPool,
+        kind: Option<TrashKind>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<TrashItem>, sqlx::Error> {
+        let items = sqlx::query_as::<_, TrashItem>(
+            r#"
+            SELECT kind, id, label, deleted_at
+            FROM (
+                SELECT 'user'::varchar AS kind, id,
+                       trim(first_name || ' ' || last_name) || ' <' || email || '>' AS label,
+                       deleted_at
+                FROM users
+                WHERE deleted_at IS NOT NULL
+                UNION ALL
+                SELECT 'prompt', id, title, deleted_at
+                FROM prompts
+                WHERE deleted_at IS NOT NULL
+                UNION ALL
+                SELECT 'haiku', id, split_part(content, E'\n', 1), deleted_at
+                FROM haikus
+                WHERE deleted_at IS NOT NULL
+            ) trash
+            WHERE $1::varchar IS NULL OR kind = $1
+            ORDER BY deleted_at DESC, id
+            LIMIT $2 OFFSET $3
+            "#,
+        )
+        .bind(kind)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(items)
+    }
+
+    /// Hard-deletes up to `limit` rows of `kind` soft-deleted more than
+    /// `retention` ago, oldest first. Prompts still having haikus, trashed
+    /// or not, and users owning collections shared with live users are kept
+    /// until those are gone. What else references them goes with them
+    /// through `ON DELETE CASCADE` keys, such as a prompt's tags or a user's
+    /// ratings, favorites and collections; batch items and share links lose
+    /// their reference instead.
+    pub async fn purge_expired(
+        pool: &PgPool,
+        kind: TrashKind,
+        retention: chrono::Duration,
+        limit: i64,
+    ) -> Result<u64, sqlx::Error> {
+        let query = format!(
+            r#"
+            DELETE FROM {table}
+            WHERE id IN (
+                SELECT id
+                FROM {table}
+                WHERE deleted_at < now() - make_interval(secs => $1)
+                  {guard}
+                ORDER BY deleted_at
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            "#,
+            table = kind.table(),
+            guard = match kind {
+                TrashKind::Haiku => "",
+                TrashKind::Prompt => {
+                    "AND NOT EXISTS (SELECT 1 FROM haikus h WHERE h.prompt_id = prompts.id)"
+                }
+                TrashKind::User => {
+                    r#"AND NOT EXISTS (
+                        SELECT 1
+                        FROM collections c
+                        JOIN collection_shares cs ON cs.collection_id = c.id
+                        JOIN users u ON u.id = cs.user_id
+                        WHERE c.owner_id = users.id AND u.deleted_at IS NULL
+                    )"#
+                }
+            },
+        );
+        let result = sqlx::query(&query)
+            .bind(retention.num_seconds() as f64)
+            .bind(limit)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,73 @@
+use super::entity::{RetentionPolicy, TrashItem, TrashKind};
+use crate::jobs::entity::Job;
+use crate::jobs::worker::JobError;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub const PURGE_TRASH: &str = "purge_trash";
+
+/// Hard-deletes the rows whose retention has run out, then schedules the
+/// next purge.
+pub async fn purge_trash(pool: PgPool, job: Job, policy: RetentionPolicy) -> Result<(), JobError> {
+    let purged = purge(&pool, &policy).await;
+
+    // The next run is scheduled even when this one failed, or a single
+    // error would stop the purge for good.
+    let next_run = Utc::now() + policy.interval;
+    schedule_purge(&pool, next_run, Some(job.id)).await?;
+    purged
+}
+
+/// Keeps purging after a run that never got to schedule the next one, as
+/// when it panicked.
+pub async fn purge_dead(pool: PgPool, job: Job, policy: RetentionPolicy) -> Result<(), JobError> {
+    let next_run = Utc::now() + policy.interval;
+    schedule_purge(&pool, next_run, Some(job.id)).await?;
+    Ok(())
+}
+
+async fn purge(pool: &PgPool, policy: &RetentionPolicy) -> Result<(), JobError> {
+    // Haikus go first so that their prompt can follow in the same run.
+    for kind in [TrashKind::Haiku, TrashKind::Prompt, TrashKind::User] {
+        let Some(retention) = policy.retention(kind) else {
+            continue;
+        };
+        loop {
+            let purged = TrashItem::purge_expired(pool, kind, retention, policy.batch_size).await?;
+            if purged < policy.batch_size as u64 {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Enqueues a purge at `run_at` unless one other than `current` is already
+/// pending or running, so restarts and retries never start a second chain.
+pub async fn schedule_purge(
+    pool: &PgPool,
+    run_at: DateTime<Utc>,
+    current: Option<Uuid>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO jobs (kind, payload, run_at)
+        SELECT $1, '{}'::jsonb, $2
+        WHERE NOT EXISTS (
+            SELECT 1
+            FROM jobs
+            WHERE kind = $1
+              AND status IN ('pending', 'running')
+              AND id IS DISTINCT FROM $3
+        )
+        "#,
+    )
+    .bind(PURGE_TRASH)
+    .bind(run_at)
+    .bind(current)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
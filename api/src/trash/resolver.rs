@@ -0,0 +1,56 @@
+use super::entity::{TrashItem, TrashKind};
+use crate::auth::viewer::Viewer;
+use crate::haiku::entity::Haiku;
+use crate::prompts::entity::Prompt;
+use crate::users::entity::User;
+use async_graphql::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub struct QueryRoot;
+
+#[async_graphql::Object]
+impl QueryRoot {
+    /// Soft-deleted users, prompts and haikus that are still restorable,
+    /// most recently deleted first. Admin only.
+    async fn trash(
+        &self,
+        ctx: &Context<'_>,
+        kind: Option<TrashKind>,
+        #[graphql(default = 50, validator(minimum = 1, maximum = 500))] limit: i64,
+        #[graphql(default = 0, validator(minimum = 0))] offset: i64,
+    ) -> async_graphql::Result<Vec<TrashItem>> {
+        Viewer::require_admin(ctx).await?;
+        let pool = ctx.data::<PgPool>()?;
+        let items = TrashItem::list(pool, kind, limit, offset).await?;
+        Ok(items)
+    }
+}
+
+pub struct MutationRoot;
+
+#[async_graphql::Object]
+impl MutationRoot {
+    /// Brings a trashed item back before it is purged. Admin only.
+    async fn restore_from_trash(
+        &self,
+        ctx: &Context<'_>,
+        kind: TrashKind,
+        id: Uuid,
+    ) -> async_graphql::Result<bool> {
+        Viewer::require_admin(ctx).await?;
+        let pool = ctx.data::<PgPool>()?;
+        match kind {
+            TrashKind::User => {
+                User::restore(pool, id).await?;
+            }
+            TrashKind::Prompt => {
+                Prompt::restore(pool, id).await?;
+            }
+            TrashKind::Haiku => {
+                Haiku::restore(pool, id).await?;
+            }
+        }
+        Ok(true)
+    }
+}
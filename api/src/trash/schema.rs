@@ -0,0 +1,17 @@
+use super::entity::RetentionPolicy;
+pub(crate) use super::resolver::{MutationRoot, QueryRoot};
+use crate::app::error::ErrorCodes;
+use crate::audit::extension::Audit;
+use async_graphql::{EmptySubscription, Schema};
+use sqlx::PgPool;
+
+pub type TrashSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+pub fn create_schema(pool: PgPool, retention: RetentionPolicy) -> TrashSchema {
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .data(pool)
+        .data(retention)
+        .extension(ErrorCodes)
+        .extension(Audit)
+        .finish()
+}
@@ -0,0 +1,233 @@
+use super::entity::User;
+use crate::mail::mailer::{Email, Mailer};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// What a token emailed to a user lets them do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum TokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+    /// Answered with a second factor to finish logging in.
+    TwoFactorLogin,
+}
+
+/// An email with a link carrying a token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountEmail {
+    Verification,
+    PasswordReset,
+}
+
+impl AccountEmail {
+    fn purpose(self) -> TokenPurpose {
+        match self {
+            AccountEmail::Verification => TokenPurpose::VerifyEmail,
+            AccountEmail::PasswordReset => TokenPurpose::ResetPassword,
+        }
+    }
+}
+
+/// Where the links in account emails point to and how long they work.
+#[derive(Debug, Clone)]
+pub struct AccountSettings {
+    /// Base URL of the app that handles the links, which calls
+    /// `verifyEmail` or `resetPassword` with their `token`.
+    pub app_url: String,
+    pub verification_ttl: chrono::Duration,
+    pub reset_ttl: chrono::Duration,
+}
+
+impl AccountSettings {
+    fn ttl(&self, email: AccountEmail) -> chrono::Duration {
+        match email {
+            AccountEmail::Verification => self.verification_ttl,
+            AccountEmail::PasswordReset => self.reset_ttl,
+        }
+    }
+
+    fn link(&self, email: AccountEmail, token: &str) -> String {
+        let path = match email {
+            AccountEmail::Verification => "verify-email",
+            AccountEmail::PasswordReset => "reset-password",
+        };
+        format!(
+            "{}/{}?token={}",
+            self.app_url.trim_end_matches('/'),
+            path,
+            token
+        )
+    }
+}
+
+fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Creates a token for `user` sent to their current address, replacing the
+/// unused ones with the same purpose. Only its hash is stored: the token
+/// itself is returned once, for the email.
+pub async fn issue(
+    pool: &PgPool,
+    user: &User,
+    purpose: TokenPurpose,
+    ttl: chrono::Duration,
+) -> Result<String, sqlx::Error> {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = hex::encode(bytes);
+
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        r#"
+        DELETE FROM account_tokens
+        WHERE user_id = $1 AND purpose = $2 AND used_at IS NULL
+        "#,
+    )
+    .bind(user.id)
+    .bind(purpose)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        r#"
+        INSERT INTO account_tokens (user_id, purpose, token_hash, email, expires_at)
+        VALUES ($1, $2, $3, $4, now() + make_interval(secs => $5))
+        "#,
+    )
+    .bind(user.id)
+    .bind(purpose)
+    .bind(hash(&token))
+    .bind(&user.email)
+    .bind(ttl.num_seconds() as f64)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(token)
+}
+
+/// The user a valid `token` was issued for, without using it up.
+pub async fn peek(
+    pool: &PgPool,
+    token: &str,
+    purpose: TokenPurpose,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let user_id = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        SELECT user_id
+        FROM account_tokens
+        WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > now()
+        "#,
+    )
+    .bind(hash(token))
+    .bind(purpose)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(user_id)
+}
+
+/// Counts a wrong answer to `token`, using it up at the `max`th one.
+pub async fn fail(
+    pool: &PgPool,
+    token: &str,
+    purpose: TokenPurpose,
+    max: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE account_tokens
+        SET failures = failures + 1,
+            used_at = CASE WHEN failures + 1 >= $3 THEN now() END
+        WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL
+        "#,
+    )
+    .bind(hash(token))
+    .bind(purpose)
+    .bind(max)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Uses up `token`, returning the user and address it was issued for, or
+/// `None` if it is unknown, already used or expired.
+pub async fn redeem(
+    pool: &PgPool,
+    token: &str,
+    purpose: TokenPurpose,
+) -> Result<Option<(Uuid, String)>, sqlx::Error> {
+    let redeemed = sqlx::query_as::<_, (Uuid, String)>(
+        r#"
+        UPDATE account_tokens
+        SET used_at = now()
+        WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > now()
+        RETURNING user_id, email
+        "#,
+    )
+    .bind(hash(token))
+    .bind(purpose)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(redeemed)
+}
+
+/// Issues a token for `user` and emails them the link using it. The email is
+/// sent in the background: failures are only logged, so that the response
+/// says nothing about them.
+pub async fn send_link(
+    pool: &PgPool,
+    mailer: &Arc<dyn Mailer>,
+    settings: &AccountSettings,
+    user: &User,
+    kind: AccountEmail,
+) -> Result<(), sqlx::Error> {
+    let ttl = settings.ttl(kind);
+    let token = issue(pool, user, kind.purpose(), ttl).await?;
+    let link = settings.link(kind, &token);
+    let expiry = match ttl.num_minutes() {
+        1 => "1 minute".to_string(),
+        60 => "1 hour".to_string(),
+        minutes if minutes % 60 == 0 => format!("{} hours", minutes / 60),
+        minutes => format!("{} minutes", minutes),
+    };
+    let (subject, body) = match kind {
+        AccountEmail::Verification => (
+            "Verify your email address",
+            format!(
+                "Open this link to confirm that this address is yours:\n\n{}\n\n\
+                 It expires in {}.",
+                link, expiry
+            ),
+        ),
+        AccountEmail::PasswordReset => (
+            "Reset your password",
+            format!(
+                "Open this link to choose a new password:\n\n{}\n\n\
+                 It expires in {} and works once. If you did not ask \
+                 for it, ignore this email.",
+                link, expiry
+            ),
+        ),
+    };
+    let email = Email {
+        to: user.email.clone(),
+        subject: subject.to_string(),
+        body,
+    };
+
+    let mailer = Arc::clone(mailer);
+    tokio::spawn(async move {
+        if let Err(err) = mailer.send(&email).await {
+            tracing::error!(to = %email.to, error = %err, "Failed to send email");
+        }
+    });
+
+    Ok(())
+}
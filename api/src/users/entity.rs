@@ -1,26 +1,96 @@
-use async_graphql::{InputObject, SimpleObject};
+use crate::app::error::UpdateError;
+use crate::app::validation::{FieldValue, Rule, Validate, Validator};
+use async_graphql::{Enum, InputObject, SimpleObject, Union};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{FromRow, PgPool};
 use uuid::Uuid;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum, sqlx::Type)]
+#[sqlx(type_name = "user_role", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum UserRole {
+    User,
+    Admin,
+}
+
+impl std::str::FromStr for UserRole {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "user" => Ok(Self::User),
+            "admin" => Ok(Self::Admin),
+            other => Err(format!("unknown role `{}`", other)),
+        }
+    }
+}
+
+/// An email as accounts are looked up, throttled and stored by: trimmed and
+/// lowercased.
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
 #[derive(Debug, Serialize, Deserialize, SimpleObject, FromRow)]
 pub struct User {
     pub id: Uuid,
     first_name: String,
     last_name: String,
-    email: String,
+    pub(crate) email: String,
+    #[graphql(skip)]
+    #[serde(skip_serializing)]
     password: String,
+    pub role: UserRole,
+    /// When the user confirmed they own `email`; changing it clears this.
+    pub(crate) email_verified_at: Option<DateTime<Utc>>,
+    /// Whether logging in takes a code from an authenticator app.
+    pub two_factor_enabled: bool,
+    /// What session tokens must have been issued at to be accepted.
+    #[graphql(skip)]
+    #[serde(skip_serializing)]
+    pub(crate) token_generation: i32,
+    /// Incremented on every update; pass it back as `expectedVersion` to
+    /// detect concurrent edits.
+    pub version: i32,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
     deleted_at: Option<DateTime<Utc>>,
 }
 
+/// What anyone may see of a user.
+#[derive(Debug, SimpleObject)]
+pub struct UserProfile {
+    pub id: Uuid,
+    first_name: String,
+    last_name: String,
+    created_at: DateTime<Utc>,
+}
+
+impl From<User> for UserProfile {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id,
+            first_name: user.first_name,
+            last_name: user.last_name,
+            created_at: user.created_at,
+        }
+    }
+}
+
+/// The whole user for themself and admins, their profile for anyone else.
+#[derive(Debug, Union)]
+pub enum UserView {
+    User(User),
+    Profile(UserProfile),
+}
+
 impl User {
     pub async fn list(pool: &PgPool) -> Result<Vec<User>, sqlx::Error> {
         let users = sqlx::query_as::<_, User>(
             r#"
-            SELECT id, first_name, last_name, email, password, created_at, updated_at, deleted_at
+            SELECT id, first_name, last_name, email, password, role, email_verified_at,
+                   totp_enabled_at IS NOT NULL AS two_factor_enabled, token_generation, version, created_at, updated_at, deleted_at
             FROM users
             WHERE deleted_at IS NULL
             "#,
@@ -34,7 +104,8 @@ impl User {
     pub async fn get(pool: &PgPool, id: Uuid) -> Result<User, sqlx::Error> {
         let user = sqlx::query_as::<_, User>(
             r#"
-            SELECT id, first_name, last_name, email, password, created_at, updated_at, deleted_at
+            SELECT id, first_name, last_name, email, password, role, email_verified_at,
+                   totp_enabled_at IS NOT NULL AS two_factor_enabled, token_generation, version, created_at, updated_at, deleted_at
             FROM users
             WHERE id = $1 AND deleted_at IS NULL
             "#,
@@ -52,7 +123,8 @@ impl User {
             UPDATE users
             SET deleted_at = now()
             WHERE id = $1 AND deleted_at IS NULL
-            RETURNING id, first_name, last_name, email, password, created_at, updated_at, deleted_at
+            RETURNING id, first_name, last_name, email, password, role, email_verified_at,
+                      totp_enabled_at IS NOT NULL AS two_factor_enabled, token_generation, version, created_at, updated_at, deleted_at
             "#,
         )
         .bind(id)
@@ -76,13 +148,135 @@ impl User {
         Ok(())
     }
 
+    /// Returns the user matching `email` and `password`, if any.
+    pub async fn authenticate(
+        pool: &PgPool,
+        email: &str,
+        password: &str,
+    ) -> Result<Option<User>, sqlx::Error> {
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            SELECT id, first_name, last_name, email, password, role, email_verified_at,
+                   totp_enabled_at IS NOT NULL AS two_factor_enabled, token_generation, version, created_at, updated_at, deleted_at
+            FROM users
+            WHERE lower(email) = $1 AND deleted_at IS NULL
+            "#,
+        )
+        .bind(normalize_email(email))
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(user.filter(|user| crate::auth::password::verify(password, &user.password)))
+    }
+
+    pub async fn find_by_email(pool: &PgPool, email: &str) -> Result<Option<User>, sqlx::Error> {
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            SELECT id, first_name, last_name, email, password, role, email_verified_at,
+                   totp_enabled_at IS NOT NULL AS two_factor_enabled, token_generation, version, created_at, updated_at, deleted_at
+            FROM users
+            WHERE lower(email) = $1 AND deleted_at IS NULL
+            "#,
+        )
+        .bind(normalize_email(email))
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    /// Marks `email` as verified, unless the user changed address since.
+    pub async fn verify_email(
+        pool: &PgPool,
+        id: Uuid,
+        email: &str,
+    ) -> Result<Option<User>, sqlx::Error> {
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users
+            SET email_verified_at = now(), version = version + 1, updated_at = now()
+            WHERE id = $1 AND email = $2 AND deleted_at IS NULL
+            RETURNING id, first_name, last_name, email, password, role, email_verified_at,
+                      totp_enabled_at IS NOT NULL AS two_factor_enabled, token_generation, version, created_at, updated_at, deleted_at
+            "#,
+        )
+        .bind(id)
+        .bind(email)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    /// Replaces the password hash, unless the user changed address since the
+    /// reset was requested. Receiving the reset also proves they own the
+    /// address, so it counts as verified. Signs the user out everywhere and
+    /// revokes their API keys, in case the old password leaked.
+    pub async fn reset_password(
+        pool: &PgPool,
+        id: Uuid,
+        email: &str,
+        password: &str,
+    ) -> Result<Option<User>, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users
+            SET
+                password = $3,
+                email_verified_at = COALESCE(email_verified_at, now()),
+                token_generation = token_generation + 1,
+                version = version + 1,
+                updated_at = now()
+            WHERE id = $1 AND email = $2 AND deleted_at IS NULL
+            RETURNING id, first_name, last_name, email, password, role, email_verified_at,
+                      totp_enabled_at IS NOT NULL AS two_factor_enabled, token_generation, version, created_at, updated_at, deleted_at
+            "#,
+        )
+        .bind(id)
+        .bind(email)
+        .bind(password)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if user.is_some() {
+            sqlx::query(
+                "UPDATE api_keys SET revoked_at = now() WHERE user_id = $1 AND revoked_at IS NULL",
+            )
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(user)
+    }
+
+    pub async fn set_role(pool: &PgPool, id: Uuid, role: UserRole) -> Result<User, sqlx::Error> {
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users
+            SET role = $2, version = version + 1, updated_at = now()
+            WHERE id = $1 AND deleted_at IS NULL
+            RETURNING id, first_name, last_name, email, password, role, email_verified_at,
+                      totp_enabled_at IS NOT NULL AS two_factor_enabled, token_generation, version, created_at, updated_at, deleted_at
+            "#,
+        )
+        .bind(id)
+        .bind(role)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(user)
+    }
+
     pub async fn restore(pool: &PgPool, id: Uuid) -> Result<User, sqlx::Error> {
         let user = sqlx::query_as::<_, User>(
             r#"
             UPDATE users
             SET deleted_at = NULL
             WHERE id = $1 AND deleted_at IS NOT NULL
-            RETURNING id, first_name, last_name, email, password, created_at, updated_at, deleted_at
+            RETURNING id, first_name, last_name, email, password, role, email_verified_at,
+                      totp_enabled_at IS NOT NULL AS two_factor_enabled, token_generation, version, created_at, updated_at, deleted_at
             "#,
         )
         .bind(id)
@@ -98,7 +292,28 @@ pub struct UserInput {
     first_name: String,
     last_name: String,
     email: String,
-    password: String,
+    pub password: String,
+}
+
+/// Names are capped at the width of their columns.
+const NAME: &[Rule] = &[Rule::NotBlank, Rule::MaxLength(255)];
+const EMAIL: &[Rule] = &[Rule::Email, Rule::MaxLength(255)];
+/// argon2 takes longer inputs, but nobody types passwords this long.
+pub(crate) const PASSWORD: &[Rule] = &[Rule::Password, Rule::MaxLength(128)];
+
+impl Validate for UserInput {
+    fn normalize(&mut self) {
+        self.first_name.trim_in_place();
+        self.last_name.trim_in_place();
+        self.email = normalize_email(&self.email);
+    }
+
+    fn rules(&self, validator: &mut Validator) {
+        validator.field("firstName", &self.first_name, NAME);
+        validator.field("lastName", &self.last_name, NAME);
+        validator.field("email", &self.email, EMAIL);
+        validator.field("password", &self.password, PASSWORD);
+    }
 }
 
 impl UserInput {
@@ -107,7 +322,8 @@ impl UserInput {
             r#"
             INSERT INTO users (first_name, last_name, email, password, created_at, updated_at)
             VALUES ($1, $2, $3, $4, now(), now())
-            RETURNING id, first_name, last_name, email, password, created_at, updated_at, deleted_at
+            RETURNING id, first_name, last_name, email, password, role, email_verified_at,
+                      totp_enabled_at IS NOT NULL AS two_factor_enabled, token_generation, version, created_at, updated_at, deleted_at
             "#,
         )
         .bind(data.first_name)
@@ -117,8 +333,6 @@ impl UserInput {
         .fetch_one(pool)
         .await?;
 
-        println!("UserInput::create: {:?}", user);
-
         Ok(user)
     }
 }
@@ -131,8 +345,30 @@ pub struct UpdateUser {
     pub password: Option<String>,
 }
 
+impl Validate for UpdateUser {
+    fn normalize(&mut self) {
+        self.first_name.trim_in_place();
+        self.last_name.trim_in_place();
+        self.email = self.email.as_deref().map(normalize_email);
+    }
+
+    fn rules(&self, validator: &mut Validator) {
+        validator.field("firstName", &self.first_name, NAME);
+        validator.field("lastName", &self.last_name, NAME);
+        validator.field("email", &self.email, EMAIL);
+        validator.field("password", &self.password, PASSWORD);
+    }
+}
+
 impl UpdateUser {
-    pub async fn update(pool: &PgPool, id: Uuid, data: UpdateUser) -> Result<User, sqlx::Error> {
+    /// Fails with a conflict when `expected_version` is given and the user
+    /// is no longer at that version.
+    pub async fn update(
+        pool: &PgPool,
+        id: Uuid,
+        data: UpdateUser,
+        expected_version: Option<i32>,
+    ) -> Result<User, UpdateError<User>> {
         let user = sqlx::query_as::<_, User>(
             r#"
             UPDATE users
@@ -140,10 +376,15 @@ impl UpdateUser {
                 first_name = COALESCE($2, first_name),
                 last_name = COALESCE($3, last_name),
                 email = COALESCE($4, email),
+                email_verified_at = CASE
+                    WHEN $4::varchar IS NULL OR $4 = lower(email) THEN email_verified_at
+                END,
                 password = COALESCE($5, password),
+                version = version + 1,
                 updated_at = now()
-            WHERE id = $1 AND deleted_at IS NULL
-            RETURNING id, first_name, last_name, email, password, created_at, updated_at, deleted_at
+            WHERE id = $1 AND deleted_at IS NULL AND ($6::integer IS NULL OR version = $6)
+            RETURNING id, first_name, last_name, email, password, role, email_verified_at,
+                      totp_enabled_at IS NOT NULL AS two_factor_enabled, token_generation, version, created_at, updated_at, deleted_at
             "#,
         )
         .bind(id)
@@ -151,9 +392,26 @@ impl UpdateUser {
         .bind(data.last_name)
         .bind(data.email)
         .bind(data.password)
-        .fetch_one(pool)
+        .bind(expected_version)
+        .fetch_optional(pool)
         .await?;
 
-        Ok(user)
+        match user {
+            Some(user) => Ok(user),
+            None => {
+                let current = User::get(pool, id).await.ok();
+                Err(UpdateError::unmatched(expected_version, current))
+            }
+        }
     }
 }
+
+#[derive(Debug, SimpleObject)]
+pub struct AuthPayload {
+    /// `null` while `challenge` is pending.
+    pub token: Option<String>,
+    /// Set when the user has two-factor authentication on: pass it to
+    /// `verifyTwoFactor` with a code to get the token.
+    pub challenge: Option<String>,
+    pub user: User,
+}
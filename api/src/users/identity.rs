@@ -0,0 +1,161 @@
+use super::entity::{User, UserRole, normalize_email};
+use crate::app::error::AppError;
+use crate::auth::oidc::IdClaims;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// The user signing in with `claims` from the provider `issuer`, linked by
+/// subject if they signed in this way before. Otherwise the identity is
+/// linked to the active user with the same address, which the provider
+/// must have verified, or to a new user without a password.
+///
+/// Nothing proves that whoever signed up with an address we never verified
+/// owns it, so such an account is taken over: its password is cleared and
+/// its sessions and API keys are revoked.
+///
+/// `role`, when the provider manages roles, replaces the user's role.
+pub async fn sign_in(
+    pool: &PgPool,
+    issuer: &str,
+    claims: &IdClaims,
+    role: Option<UserRole>,
+) -> Result<User, AppError> {
+    let mut tx = pool.begin().await?;
+    let linked = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        UPDATE user_identities i
+        SET email = $3, last_login_at = now()
+        FROM users u
+        WHERE u.id = i.user_id AND i.issuer = $1 AND i.subject = $2 AND u.deleted_at IS NULL
+        RETURNING i.user_id
+        "#,
+    )
+    .bind(issuer)
+    .bind(&claims.sub)
+    .bind(claims.email.as_deref().map(normalize_email))
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let user_id = match linked {
+        Some(user_id) => user_id,
+        None => {
+            let email = claims
+                .email
+                .as_deref()
+                .filter(|_| claims.email_verified)
+                .ok_or_else(|| {
+                    AppError::validation("The identity provider has no verified email for you")
+                })?;
+            let email = &normalize_email(email);
+            let existing = sqlx::query_as::<_, (Uuid, bool)>(
+                r#"
+                SELECT id, email_verified_at IS NOT NULL
+                FROM users
+                WHERE lower(email) = $1 AND deleted_at IS NULL
+                FOR UPDATE
+                "#,
+            )
+            .bind(email)
+            .fetch_optional(&mut *tx)
+            .await?;
+            let user_id = match existing {
+                Some((user_id, true)) => user_id,
+                Some((user_id, false)) => {
+                    sqlx::query(
+                        r#"
+                        UPDATE users
+                        SET password = '', email_verified_at = now(),
+                            token_generation = token_generation + 1,
+                            version = version + 1, updated_at = now()
+                        WHERE id = $1
+                        "#,
+                    )
+                    .bind(user_id)
+                    .execute(&mut *tx)
+                    .await?;
+                    sqlx::query(
+                        "UPDATE api_keys SET revoked_at = now() WHERE user_id = $1 AND revoked_at IS NULL",
+                    )
+                    .bind(user_id)
+                    .execute(&mut *tx)
+                    .await?;
+                    user_id
+                }
+                None => {
+                    let (first_name, last_name) = names(claims, email);
+                    // An empty hash never verifies, so only the provider
+                    // signs the user in until they reset their password.
+                    sqlx::query_scalar::<_, Uuid>(
+                        r#"
+                        INSERT INTO users (first_name, last_name, email, password,
+                                           email_verified_at, created_at, updated_at)
+                        VALUES ($1, $2, $3, '', now(), now(), now())
+                        RETURNING id
+                        "#,
+                    )
+                    .bind(first_name)
+                    .bind(last_name)
+                    .bind(email)
+                    .fetch_one(&mut *tx)
+                    .await?
+                }
+            };
+
+            let inserted = sqlx::query(
+                r#"
+                INSERT INTO user_identities (user_id, issuer, subject, email)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (user_id, issuer) DO NOTHING
+                "#,
+            )
+            .bind(user_id)
+            .bind(issuer)
+            .bind(&claims.sub)
+            .bind(email)
+            .execute(&mut *tx)
+            .await?;
+            if inserted.rows_affected() == 0 {
+                return Err(AppError::Conflict(
+                    "This account is linked to another identity at the provider".to_string(),
+                ));
+            }
+            user_id
+        }
+    };
+
+    if let Some(role) = role {
+        sqlx::query(
+            r#"
+            UPDATE users
+            SET role = $2, version = version + 1, updated_at = now()
+            WHERE id = $1 AND role <> $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(role)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(User::get(pool, user_id).await?)
+}
+
+/// First and last name from the claims, falling back to the full name and
+/// then to the address.
+fn names(claims: &IdClaims, email: &str) -> (String, String) {
+    let given = claims.given_name.as_deref().map(str::trim).unwrap_or("");
+    let family = claims.family_name.as_deref().map(str::trim).unwrap_or("");
+    if !given.is_empty() {
+        return (given.to_string(), family.to_string());
+    }
+    let name = claims.name.as_deref().map(str::trim).unwrap_or("");
+    match name.split_once(' ') {
+        Some((first, last)) => (first.to_string(), last.trim().to_string()),
+        None if !name.is_empty() => (name.to_string(), String::new()),
+        None => (
+            email.split('@').next().unwrap_or(email).to_string(),
+            String::new(),
+        ),
+    }
+}
@@ -1,3 +1,5 @@
+pub mod account;
 pub mod entity;
+pub mod identity;
 pub mod resolver;
 pub mod schema;
@@ -1,21 +1,49 @@
-use super::entity::{UpdateUser, User, UserInput};
+use super::account::{self, AccountEmail, AccountSettings, TokenPurpose};
+use super::entity::{AuthPayload, PASSWORD, UpdateUser, User, UserInput, UserRole, UserView};
+use super::identity;
+use crate::app::error::AppError;
+use crate::app::middlewares::RequestMeta;
+use crate::app::validation::{Validate, validate_argument};
+use crate::auth::oidc::OidcClient;
+use crate::auth::password;
+use crate::auth::throttle::{self, LoginAttempt, LoginPolicy, ResetRequest};
+use crate::auth::token::TokenKeys;
+use crate::auth::two_factor::{self, TwoFactorEnrollment, TwoFactorSettings};
+use crate::auth::viewer::Viewer;
+use crate::mail::mailer::Mailer;
 use async_graphql::Context;
 use sqlx::PgPool;
+use std::sync::Arc;
 use uuid::Uuid;
 
 pub struct QueryRoot;
 
 #[async_graphql::Object]
 impl QueryRoot {
+    /// Admin only.
     async fn list_users(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<User>> {
         let pool = ctx.data::<PgPool>()?;
+        Viewer::require_admin(ctx).await?;
         let users = User::list(pool).await?;
         Ok(users)
     }
 
-    async fn get_user(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<User> {
+    /// The whole user for themself and admins, only their public profile
+    /// for anyone else.
+    async fn get_user(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<UserView> {
         let pool = ctx.data::<PgPool>()?;
         let user = User::get(pool, id).await?;
+        let is_self = Viewer::current(ctx).is_some_and(|viewer| viewer.user_id == id);
+        if is_self || Viewer::require_admin(ctx).await.is_ok() {
+            return Ok(UserView::User(user));
+        }
+        Ok(UserView::Profile(user.into()))
+    }
+
+    async fn me(&self, ctx: &Context<'_>) -> async_graphql::Result<User> {
+        let pool = ctx.data::<PgPool>()?;
+        let viewer = Viewer::require(ctx)?;
+        let user = User::get(pool, viewer.user_id).await?;
         Ok(user)
     }
 }
@@ -24,38 +52,333 @@ pub struct MutationRoot;
 
 #[async_graphql::Object]
 impl MutationRoot {
-    async fn create_user(&self, ctx: &Context<'_>, data: UserInput) -> async_graphql::Result<User> {
+    /// Returns a token, or a `challenge` to answer with `verifyTwoFactor`
+    /// when the user has two-factor authentication on.
+    async fn login(
+        &self,
+        ctx: &Context<'_>,
+        email: String,
+        password: String,
+    ) -> async_graphql::Result<AuthPayload> {
+        let pool = ctx.data::<PgPool>()?;
+        let keys = ctx.data::<TokenKeys>()?;
+        let policy = ctx.data::<LoginPolicy>()?;
+        let attempt = LoginAttempt::new(&email, ctx.data_opt::<RequestMeta>());
+        attempt.reserve(pool, policy).await?;
+        let Some(user) = User::authenticate(pool, &email, &password).await? else {
+            attempt.failed(pool, policy).await?;
+            return Err(AppError::InvalidCredentials.into());
+        };
+        attempt.succeeded(pool).await?;
+        signed_in(pool, keys, user).await
+    }
+
+    /// Starts signing in with the identity provider: send the user to the
+    /// returned URL, after which the provider redirects them to the app
+    /// with a `code` and `state` for `oidcLogin`.
+    async fn oidc_login_url(&self, ctx: &Context<'_>) -> async_graphql::Result<String> {
+        let pool = ctx.data::<PgPool>()?;
+        let url = oidc_client(ctx)?.start(pool).await?;
+        Ok(url)
+    }
+
+    /// Finishes signing in with the identity provider, like `login`.
+    async fn oidc_login(
+        &self,
+        ctx: &Context<'_>,
+        code: String,
+        state: String,
+    ) -> async_graphql::Result<AuthPayload> {
+        let pool = ctx.data::<PgPool>()?;
+        let keys = ctx.data::<TokenKeys>()?;
+        let client = oidc_client(ctx)?;
+        let claims = client
+            .finish(pool, &code, &state)
+            .await?
+            .ok_or_else(invalid_token)?;
+        let issuer = &client.settings().issuer;
+        let user = identity::sign_in(pool, issuer, &claims, client.role(&claims)).await?;
+        signed_in(pool, keys, user).await
+    }
+
+    /// Finishes a `login` with a code from the authenticator app or a
+    /// recovery code. The challenge is used up after a few wrong codes.
+    async fn verify_two_factor(
+        &self,
+        ctx: &Context<'_>,
+        challenge: String,
+        code: String,
+    ) -> async_graphql::Result<AuthPayload> {
+        let pool = ctx.data::<PgPool>()?;
+        let keys = ctx.data::<TokenKeys>()?;
+        let settings = ctx.data::<TwoFactorSettings>()?;
+        let policy = ctx.data::<LoginPolicy>()?;
+        let user_id = account::peek(pool, &challenge, TokenPurpose::TwoFactorLogin)
+            .await?
+            .ok_or_else(invalid_token)?;
+        let user = User::get(pool, user_id).await?;
+        // Wrong codes count as failed logins, on top of using up the
+        // challenge after a few.
+        let attempt = LoginAttempt::new(&user.email, ctx.data_opt::<RequestMeta>());
+        attempt.reserve(pool, policy).await?;
+        if !two_factor::check(pool, settings, user_id, &code).await? {
+            let max = two_factor::CHALLENGE_MAX_FAILURES;
+            account::fail(pool, &challenge, TokenPurpose::TwoFactorLogin, max).await?;
+            attempt.failed(pool, policy).await?;
+            return Err(AppError::InvalidCode.into());
+        }
+        account::redeem(pool, &challenge, TokenPurpose::TwoFactorLogin)
+            .await?
+            .ok_or_else(invalid_token)?;
+        attempt.succeeded(pool).await?;
+        let token = keys.issue(user.id, user.token_generation, true)?;
+        Ok(AuthPayload {
+            token: Some(token),
+            challenge: None,
+            user,
+        })
+    }
+
+    /// Starts turning on two-factor authentication for the viewer. Confirm
+    /// with `confirmTwoFactor` once the secret is in an authenticator app.
+    async fn enroll_two_factor(
+        &self,
+        ctx: &Context<'_>,
+    ) -> async_graphql::Result<TwoFactorEnrollment> {
+        let pool = ctx.data::<PgPool>()?;
+        let settings = ctx.data::<TwoFactorSettings>()?;
+        let viewer = Viewer::require_session(ctx)?;
+        let user = User::get(pool, viewer.user_id).await?;
+        let enrollment = two_factor::enroll(pool, settings, &user).await?;
+        Ok(enrollment)
+    }
+
+    /// Turns two-factor authentication on with a first code from the app.
+    /// Returns single-use recovery codes, which are never shown again.
+    async fn confirm_two_factor(
+        &self,
+        ctx: &Context<'_>,
+        code: String,
+    ) -> async_graphql::Result<Vec<String>> {
+        let pool = ctx.data::<PgPool>()?;
+        let settings = ctx.data::<TwoFactorSettings>()?;
+        let viewer = Viewer::require_session(ctx)?;
+        let codes = two_factor::confirm(pool, settings, viewer.user_id, &code).await?;
+        Ok(codes)
+    }
+
+    /// Turns two-factor authentication off, given a current or recovery code.
+    async fn disable_two_factor(
+        &self,
+        ctx: &Context<'_>,
+        code: String,
+    ) -> async_graphql::Result<bool> {
+        let pool = ctx.data::<PgPool>()?;
+        let settings = ctx.data::<TwoFactorSettings>()?;
+        let viewer = Viewer::require_session(ctx)?;
+        if !two_factor::check(pool, settings, viewer.user_id, &code).await? {
+            return Err(AppError::InvalidCode.into());
+        }
+        two_factor::disable(pool, viewer.user_id).await?;
+        Ok(true)
+    }
+
+    async fn create_user(
+        &self,
+        ctx: &Context<'_>,
+        mut data: UserInput,
+    ) -> async_graphql::Result<User> {
         let pool = ctx.data::<PgPool>()?;
+        data.validate("data")?;
+        data.password = password::hash(&data.password)?;
         let user = UserInput::create(pool, data).await?;
         Ok(user)
     }
 
+    /// Updates the viewer, or anyone for admins. Changing the email or
+    /// password takes a session rather than an API key.
+    ///
+    /// With `expectedVersion`, fails with a `CONFLICT` error holding the
+    /// current user if someone else updated it since.
     async fn update_user(
         &self,
         ctx: &Context<'_>,
         id: Uuid,
-        data: UpdateUser,
+        mut data: UpdateUser,
+        expected_version: Option<i32>,
     ) -> async_graphql::Result<User> {
         let pool = ctx.data::<PgPool>()?;
-        let user = UpdateUser::update(pool, id, data).await?;
+        Viewer::require_self_or_admin(ctx, id).await?;
+        if data.email.is_some() || data.password.is_some() {
+            Viewer::require_session(ctx)?;
+        }
+        data.validate("data")?;
+        if let Some(new_password) = &data.password {
+            data.password = Some(password::hash(new_password)?);
+        }
+        let user = UpdateUser::update(pool, id, data, expected_version)
+            .await
+            .map_err(AppError::from)?;
         Ok(user)
     }
 
+    /// Grants or revokes admin rights. Admin only.
+    async fn set_user_role(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+        role: UserRole,
+    ) -> async_graphql::Result<User> {
+        let pool = ctx.data::<PgPool>()?;
+        Viewer::require_admin(ctx).await?;
+        let user = User::set_role(pool, id, role).await?;
+        Ok(user)
+    }
+
+    /// Moves the user to the trash. Admin only.
     async fn delete_user(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<User> {
         let pool = ctx.data::<PgPool>()?;
+        Viewer::require_admin(ctx).await?;
         let user = User::delete(pool, id).await?;
         Ok(user)
     }
 
+    /// Admin only.
     async fn restore_user(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<User> {
         let pool = ctx.data::<PgPool>()?;
+        Viewer::require_admin(ctx).await?;
         let user = User::restore(pool, id).await?;
         Ok(user)
     }
 
+    /// Deletes the user for good. Admin only.
     async fn destroy_user(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<bool> {
         let pool = ctx.data::<PgPool>()?;
+        Viewer::require_admin(ctx).await?;
         User::destroy(pool, id).await?;
         Ok(true)
     }
+
+    /// Lifts a lockout after too many failed logins. Admin only.
+    async fn unlock_user(&self, ctx: &Context<'_>, id: Uuid) -> async_graphql::Result<User> {
+        let pool = ctx.data::<PgPool>()?;
+        Viewer::require_admin(ctx).await?;
+        let user = User::get(pool, id).await?;
+        throttle::unlock(pool, &user.email).await?;
+        Ok(user)
+    }
+
+    /// Emails the viewer a link to confirm their address.
+    async fn request_email_verification(&self, ctx: &Context<'_>) -> async_graphql::Result<bool> {
+        let pool = ctx.data::<PgPool>()?;
+        let mailer = ctx.data::<Arc<dyn Mailer>>()?;
+        let settings = ctx.data::<AccountSettings>()?;
+        let viewer = Viewer::require(ctx)?;
+        let user = User::get(pool, viewer.user_id).await?;
+        if user.email_verified_at.is_some() {
+            return Err(AppError::Conflict("Email is already verified".to_string()).into());
+        }
+        account::send_link(pool, mailer, settings, &user, AccountEmail::Verification).await?;
+        Ok(true)
+    }
+
+    /// Confirms the address a `requestEmailVerification` link was sent to.
+    async fn verify_email(&self, ctx: &Context<'_>, token: String) -> async_graphql::Result<User> {
+        let pool = ctx.data::<PgPool>()?;
+        let (user_id, email) = account::redeem(pool, &token, TokenPurpose::VerifyEmail)
+            .await?
+            .ok_or_else(invalid_token)?;
+        let user = User::verify_email(pool, user_id, &email)
+            .await?
+            .ok_or_else(invalid_token)?;
+        Ok(user)
+    }
+
+    /// Emails a password reset link to `email`. Succeeds whether or not an
+    /// account uses that address, so as not to reveal which ones exist, but
+    /// only a few times an hour for an address or from a client.
+    async fn request_password_reset(
+        &self,
+        ctx: &Context<'_>,
+        email: String,
+    ) -> async_graphql::Result<bool> {
+        let pool = ctx.data::<PgPool>()?;
+        let mailer = ctx.data::<Arc<dyn Mailer>>()?;
+        let settings = ctx.data::<AccountSettings>()?;
+        ResetRequest::new(&email, ctx.data_opt::<RequestMeta>())
+            .reserve(pool)
+            .await?;
+        // Looking the account up and issuing the token happen after the
+        // response, so that its timing does not tell either.
+        let (pool, mailer, settings) = (pool.clone(), Arc::clone(mailer), settings.clone());
+        tokio::spawn(async move {
+            let sent = match User::find_by_email(&pool, &email).await {
+                Ok(Some(user)) => {
+                    let kind = AccountEmail::PasswordReset;
+                    account::send_link(&pool, &mailer, &settings, &user, kind).await
+                }
+                Ok(None) => Ok(()),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = sent {
+                tracing::error!(%email, error = %err, "Failed to send password reset");
+            }
+        });
+        Ok(true)
+    }
+
+    /// Sets a new password with the token of a `requestPasswordReset` link.
+    async fn reset_password(
+        &self,
+        ctx: &Context<'_>,
+        token: String,
+        new_password: String,
+    ) -> async_graphql::Result<bool> {
+        let pool = ctx.data::<PgPool>()?;
+        validate_argument("newPassword", &new_password, PASSWORD)?;
+        let (user_id, email) = account::redeem(pool, &token, TokenPurpose::ResetPassword)
+            .await?
+            .ok_or_else(invalid_token)?;
+        let hash = password::hash(&new_password)?;
+        User::reset_password(pool, user_id, &email, &hash)
+            .await?
+            .ok_or_else(invalid_token)?;
+        // Owning the address is enough to be let back in.
+        throttle::unlock(pool, &email).await?;
+        Ok(true)
+    }
+}
+
+/// A token for `user`, or a challenge when they have two-factor
+/// authentication on.
+async fn signed_in(
+    pool: &PgPool,
+    keys: &TokenKeys,
+    user: User,
+) -> async_graphql::Result<AuthPayload> {
+    if user.two_factor_enabled {
+        let ttl = chrono::Duration::minutes(two_factor::CHALLENGE_TTL_MINS);
+        let challenge = account::issue(pool, &user, TokenPurpose::TwoFactorLogin, ttl).await?;
+        return Ok(AuthPayload {
+            token: None,
+            challenge: Some(challenge),
+            user,
+        });
+    }
+    let token = keys.issue(user.id, user.token_generation, false)?;
+    Ok(AuthPayload {
+        token: Some(token),
+        challenge: None,
+        user,
+    })
+}
+
+fn oidc_client<'a>(ctx: &Context<'a>) -> Result<&'a OidcClient, AppError> {
+    ctx.data_opt::<OidcClient>()
+        .ok_or_else(|| AppError::validation("Sign-in with an identity provider is not set up"))
+}
+
+/// Unknown, used and expired tokens all look the same to the caller.
+fn invalid_token() -> AppError {
+    AppError::validation("Invalid or expired token")
 }
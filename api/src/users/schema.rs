@@ -1,11 +1,36 @@
+use super::account::AccountSettings;
 pub(crate) use super::resolver::{MutationRoot, QueryRoot};
+use crate::app::error::ErrorCodes;
+use crate::audit::extension::Audit;
+use crate::auth::oidc::OidcClient;
+use crate::auth::throttle::LoginPolicy;
+use crate::auth::token::TokenKeys;
+use crate::auth::two_factor::TwoFactorSettings;
+use crate::mail::mailer::Mailer;
 use async_graphql::{EmptySubscription, Schema};
 use sqlx::PgPool;
+use std::sync::Arc;
 
 pub type UserSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
 
-pub fn create_schema(pool: PgPool) -> UserSchema {
-    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+pub fn create_schema(
+    pool: PgPool,
+    keys: TokenKeys,
+    mailer: Arc<dyn Mailer>,
+    account: AccountSettings,
+    two_factor: TwoFactorSettings,
+    oidc: Option<OidcClient>,
+    login: LoginPolicy,
+) -> UserSchema {
+    let mut builder = Schema::build(QueryRoot, MutationRoot, EmptySubscription)
         .data(pool)
-        .finish()
+        .data(keys)
+        .data(mailer)
+        .data(account)
+        .data(two_factor)
+        .data(login);
+    if let Some(oidc) = oidc {
+        builder = builder.data(oidc);
+    }
+    builder.extension(ErrorCodes).extension(Audit).finish()
 }
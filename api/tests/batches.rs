@@ -0,0 +1,61 @@
+mod common;
+
+use common::TestApp;
+use serde_json::json;
+use uuid::Uuid;
+
+const START: &str = "mutation($promptIds: [UUID!]!) {
+    startBatchGeneration(promptIds: $promptIds, perPrompt: 2) { id createdBy }
+}";
+
+const BATCH: &str = "query($id: UUID!) { batch(id: $id) { id total } }";
+
+#[tokio::test]
+#[ignore = "needs PostgreSQL"]
+async fn only_their_creator_and_admins_see_batches() {
+    let app = TestApp::start(&[("TWO_FACTOR_ROLES", "")]).await;
+    let prompt: Uuid = sqlx::query_scalar(
+        "INSERT INTO prompts (title, content) VALUES ('Autumn', 'content') RETURNING id",
+    )
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    let variables = json!({ "promptIds": [prompt] });
+
+    let anonymous = app.graphql("/batches").send(START, variables.clone()).await;
+    assert_eq!(anonymous.error_code(), Some("UNAUTHENTICATED"));
+    let started: i64 = sqlx::query_scalar("SELECT count(*) FROM batches")
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+    assert_eq!(started, 0);
+
+    let owner_id = app.sign_up("poet@example.com").await;
+    let owner = app.token("poet@example.com").await;
+    let start = app
+        .graphql("/batches")
+        .token(&owner)
+        .send(START, variables)
+        .await;
+    let batch = start.data("startBatchGeneration");
+    assert_eq!(batch["createdBy"], owner_id.to_string().as_str());
+    let id = json!({ "id": batch["id"] });
+
+    let read = |token: Option<String>| {
+        let request = app.graphql("/batches");
+        let request = match &token {
+            Some(token) => request.token(token),
+            None => request,
+        };
+        request.send(BATCH, id.clone())
+    };
+    assert_eq!(read(Some(owner)).await.data("batch")["total"], 2);
+    assert_eq!(read(None).await.error_code(), Some("UNAUTHENTICATED"));
+    app.sign_up("reader@example.com").await;
+    let other = app.token("reader@example.com").await;
+    assert_eq!(read(Some(other)).await.error_code(), Some("FORBIDDEN"));
+    let admin = app.admin_token("admin@example.com").await;
+    assert_eq!(read(Some(admin)).await.data("batch")["total"], 2);
+
+    app.stop().await;
+}
@@ -0,0 +1,282 @@
+//! Runs the server binary against a database of its own, to test whole
+//! flows over HTTP.
+//!
+//! The database is created next to the one the `DB_*` variables name, as
+//! for the server, and dropped by [`TestApp::stop`]. The tests need
+//! PostgreSQL, so they are ignored by default: run them with
+//! `cargo test -- --ignored`.
+
+#![allow(dead_code)]
+
+use reqwest::{Client, RequestBuilder};
+use serde_json::{Value, json};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{Executor, PgPool};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Child, Command};
+use std::time::Duration;
+use uuid::Uuid;
+
+pub const PASSWORD: &str = "Secret123!";
+
+const TOTP_ENCRYPTION_KEY: &str =
+    "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+pub struct TestApp {
+    pub url: String,
+    /// Connected to the server's database.
+    pub pool: PgPool,
+    client: Client,
+    server: Child,
+    admin: PgPool,
+    database: String,
+    dir: PathBuf,
+}
+
+impl TestApp {
+    /// Starts the server on a fresh, migrated database, with `env` set on
+    /// top of what every server needs.
+    pub async fn start(env: &[(&str, &str)]) -> Self {
+        dotenvy::dotenv().ok();
+        let var = |name: &str| {
+            std::env::var(name).unwrap_or_else(|_| panic!("{name} must be set to run this test"))
+        };
+        let (host, port, user, password) = (
+            var("DB_HOST"),
+            var("DB_PORT"),
+            var("DB_USER"),
+            var("DB_PASSWORD"),
+        );
+        let options = PgConnectOptions::new()
+            .host(&host)
+            .port(port.parse().expect("DB_PORT is a port"))
+            .username(&user)
+            .password(&password);
+        let admin = PgPoolOptions::new()
+            .max_connections(1)
+            .connect_with(options.clone().database(&var("DB_NAME")))
+            .await
+            .expect("connect to DB_NAME");
+
+        let database = format!("haiku_test_{}", Uuid::new_v4().simple());
+        admin
+            .execute(format!("CREATE DATABASE {database}").as_str())
+            .await
+            .expect("create the test database");
+        let pool = PgPoolOptions::new()
+            .max_connections(4)
+            .connect_with(options.database(&database))
+            .await
+            .expect("connect to the test database");
+        migrate(&pool).await;
+
+        // Away from any `.env` or `haiku.toml` the server would pick up.
+        let dir = std::env::temp_dir().join(&database);
+        std::fs::create_dir_all(&dir).expect("create the server directory");
+        let server_port = free_port();
+        let server = Command::new(env!("CARGO_BIN_EXE_api"))
+            .current_dir(&dir)
+            .env_clear()
+            .envs([
+                ("DB_HOST", host.as_str()),
+                ("DB_PORT", port.as_str()),
+                ("DB_USER", user.as_str()),
+                ("DB_PASSWORD", password.as_str()),
+                ("DB_NAME", database.as_str()),
+                ("SRV_HOST", "127.0.0.1"),
+                ("SRV_PORT", &server_port.to_string()),
+                ("JWT_SECRET", "integration tests"),
+                ("TOTP_ENCRYPTION_KEY", TOTP_ENCRYPTION_KEY),
+                ("MAIL_TRANSPORT", "log"),
+                ("JOBS_POLL_INTERVAL_MS", "100"),
+                ("RUST_LOG", "warn"),
+            ])
+            .envs(env.iter().copied())
+            .spawn()
+            .expect("start the server");
+
+        let mut app = Self {
+            url: format!("http://127.0.0.1:{server_port}"),
+            pool,
+            client: Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .expect("build the HTTP client"),
+            server,
+            admin,
+            database,
+            dir,
+        };
+        app.wait_until_up().await;
+        app
+    }
+
+    async fn wait_until_up(&mut self) {
+        for _ in 0..100 {
+            if let Some(status) = self.server.try_wait().expect("poll the server") {
+                panic!("the server exited with {status}");
+            }
+            if self.client.get(&self.url).send().await.is_ok() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("the server did not start listening in time");
+    }
+
+    /// Stops the server and drops its database.
+    pub async fn stop(mut self) {
+        let _ = self.server.kill();
+        let _ = self.server.wait();
+        self.pool.close().await;
+        self.admin
+            .execute(format!("DROP DATABASE {} WITH (FORCE)", self.database).as_str())
+            .await
+            .expect("drop the test database");
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// A GraphQL request to the schema at `route`, e.g. `/users`.
+    pub fn graphql(&self, route: &str) -> GraphQl {
+        GraphQl(self.client.post(format!("{}{}", self.url, route)))
+    }
+
+    /// Signs a user up, returning their id.
+    pub async fn sign_up(&self, email: &str) -> Uuid {
+        let response = self
+            .graphql("/users")
+            .send(
+                "mutation($data: UserInput!) { createUser(data: $data) { id } }",
+                json!({
+                    "data": {
+                        "firstName": "Test",
+                        "lastName": "User",
+                        "email": email,
+                        "password": PASSWORD,
+                    }
+                }),
+            )
+            .await;
+        response.data("createUser")["id"]
+            .as_str()
+            .and_then(|id| id.parse().ok())
+            .expect("createUser returns the id")
+    }
+
+    pub async fn log_in(&self, email: &str, password: &str) -> GraphQlResponse {
+        self.graphql("/users")
+            .send(
+                "mutation($email: String!, $password: String!) {
+                    login(email: $email, password: $password) { token challenge }
+                }",
+                json!({ "email": email, "password": password }),
+            )
+            .await
+    }
+
+    /// A session token for a user without two-factor authentication.
+    pub async fn token(&self, email: &str) -> String {
+        self.log_in(email, PASSWORD).await.data("login")["token"]
+            .as_str()
+            .expect("login returns a token")
+            .to_string()
+    }
+
+    /// Signs up an admin and returns their session token.
+    pub async fn admin_token(&self, email: &str) -> String {
+        let id = self.sign_up(email).await;
+        sqlx::query("UPDATE users SET role = 'admin' WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .expect("make the user an admin");
+        self.token(email).await
+    }
+}
+
+impl Drop for TestApp {
+    /// Leaves the database behind when a test fails before [`Self::stop`],
+    /// but never the server.
+    fn drop(&mut self) {
+        let _ = self.server.kill();
+        let _ = self.server.wait();
+    }
+}
+
+/// Applies the migrations in order, as `sqlx migrate run` does.
+async fn migrate(pool: &PgPool) {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../migrations");
+    let mut files: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .expect("read the migrations")
+        .map(|entry| entry.expect("read a migration").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sql"))
+        .collect();
+    files.sort();
+    for file in files {
+        let sql = std::fs::read_to_string(&file).expect("read a migration");
+        sqlx::raw_sql(&sql)
+            .execute(pool)
+            .await
+            .unwrap_or_else(|err| panic!("apply {}: {err}", file.display()));
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("find a free port")
+        .port()
+}
+
+pub struct GraphQl(RequestBuilder);
+
+impl GraphQl {
+    pub fn token(self, token: &str) -> Self {
+        Self(self.0.bearer_auth(token))
+    }
+
+    pub fn header(self, name: &str, value: &str) -> Self {
+        Self(self.0.header(name, value))
+    }
+
+    pub async fn send(self, query: &str, variables: Value) -> GraphQlResponse {
+        let body = self
+            .0
+            .json(&json!({ "query": query, "variables": variables }))
+            .send()
+            .await
+            .expect("send the request")
+            .json()
+            .await
+            .expect("a GraphQL response");
+        GraphQlResponse(body)
+    }
+}
+
+#[derive(Debug)]
+pub struct GraphQlResponse(pub Value);
+
+impl GraphQlResponse {
+    /// The result of `field`, failing the test with the errors if there are
+    /// any.
+    pub fn data(&self, field: &str) -> &Value {
+        if let Some(errors) = self.0.get("errors") {
+            panic!("`{field}` failed: {errors}");
+        }
+        &self.0["data"][field]
+    }
+
+    /// The `code` of the first error, if any.
+    pub fn error_code(&self) -> Option<&str> {
+        self.0["errors"][0]["extensions"]["code"].as_str()
+    }
+
+    pub fn error_message(&self) -> Option<&str> {
+        self.0["errors"][0]["message"].as_str()
+    }
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use common::{PASSWORD, TestApp};
+use serde_json::json;
+use tokio::task::JoinSet;
+
+const LOGIN: &str = "mutation($email: String!, $password: String!) {
+    login(email: $email, password: $password) { token }
+}";
+
+#[tokio::test]
+#[ignore = "needs PostgreSQL"]
+async fn concurrent_guesses_wait_for_each_other() {
+    let app = TestApp::start(&[("LOGIN_DELAY_BASE_MS", "60000")]).await;
+    app.sign_up("poet@example.com").await;
+
+    let mut guesses = JoinSet::new();
+    for guess in 0..10 {
+        let variables =
+            json!({ "email": "poet@example.com", "password": format!("wrong {guess}") });
+        guesses.spawn(app.graphql("/users").send(LOGIN, variables));
+    }
+    let mut codes = Vec::new();
+    while let Some(response) = guesses.join_next().await {
+        codes.push(response.unwrap().error_code().unwrap().to_string());
+    }
+    codes.sort();
+    let mut expected = vec!["RATE_LIMITED"; 9];
+    expected.insert(0, "UNAUTHENTICATED");
+    expected.sort();
+    assert_eq!(codes, expected);
+
+    app.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs PostgreSQL"]
+async fn failures_lock_the_account_whatever_the_case_of_the_email() {
+    let app = TestApp::start(&[("LOGIN_DELAY_BASE_MS", "0"), ("LOGIN_MAX_FAILURES", "3")]).await;
+    app.sign_up("Poet@Example.com").await;
+
+    for email in ["poet@example.com", "POET@example.com", " poet@EXAMPLE.com "] {
+        let login = app.log_in(email, "wrong password").await;
+        assert_eq!(login.error_code(), Some("UNAUTHENTICATED"), "{login:?}");
+    }
+    let login = app.log_in("poet@example.com", PASSWORD).await;
+    assert_eq!(login.error_code(), Some("RATE_LIMITED"));
+
+    app.stop().await;
+}
+
+/// Fails a login for a new email from `forwarded_for`, returning the error
+/// code.
+async fn fail_from(app: &TestApp, attempt: usize, forwarded_for: &str) -> Option<String> {
+    app.graphql("/users")
+        .header("x-forwarded-for", forwarded_for)
+        .send(
+            LOGIN,
+            json!({ "email": format!("nobody{attempt}@example.com"), "password": "wrong" }),
+        )
+        .await
+        .error_code()
+        .map(str::to_string)
+}
+
+#[tokio::test]
+#[ignore = "needs PostgreSQL"]
+async fn untrusted_clients_cannot_dodge_the_address_limit() {
+    let app = TestApp::start(&[
+        ("LOGIN_DELAY_BASE_MS", "0"),
+        ("LOGIN_MAX_FAILURES_PER_IP", "3"),
+    ])
+    .await;
+
+    for attempt in 0..3 {
+        let forwarded_for = format!("203.0.113.{attempt}");
+        assert_eq!(
+            fail_from(&app, attempt, &forwarded_for).await.as_deref(),
+            Some("UNAUTHENTICATED")
+        );
+    }
+    assert_eq!(
+        fail_from(&app, 3, "203.0.113.3").await.as_deref(),
+        Some("RATE_LIMITED")
+    );
+
+    app.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs PostgreSQL"]
+async fn trusted_proxies_tell_clients_apart() {
+    let app = TestApp::start(&[
+        ("LOGIN_DELAY_BASE_MS", "0"),
+        ("LOGIN_MAX_FAILURES_PER_IP", "3"),
+        ("TRUSTED_PROXIES", "127.0.0.1"),
+    ])
+    .await;
+
+    for attempt in 0..3 {
+        assert_eq!(
+            fail_from(&app, attempt, "203.0.113.1").await.as_deref(),
+            Some("UNAUTHENTICATED")
+        );
+    }
+    assert_eq!(
+        fail_from(&app, 3, "203.0.113.1").await.as_deref(),
+        Some("RATE_LIMITED")
+    );
+    assert_eq!(
+        fail_from(&app, 4, "203.0.113.2").await.as_deref(),
+        Some("UNAUTHENTICATED")
+    );
+
+    app.stop().await;
+}
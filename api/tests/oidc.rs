@@ -0,0 +1,156 @@
+mod common;
+
+use common::{GraphQlResponse, PASSWORD, TestApp};
+use reqwest::Url;
+use reqwest::header::LOCATION;
+use serde_json::{Value, json};
+
+const OIDC_LOGIN: &str = "mutation($code: String!, $state: String!) {
+    oidcLogin(code: $code, state: $state) { token user { id email firstName lastName } }
+}";
+
+/// Starts the app against the mock provider of `docker compose --profile
+/// oidc up`, or the one `OIDC_ISSUER` names.
+async fn start() -> TestApp {
+    let issuer = std::env::var("OIDC_ISSUER")
+        .ok()
+        .filter(|issuer| !issuer.is_empty())
+        .unwrap_or_else(|| "http://localhost:8180/default".to_string());
+    TestApp::start(&[
+        ("OIDC_ISSUER", &issuer),
+        ("OIDC_CLIENT_ID", "haiku"),
+        ("OIDC_CLIENT_SECRET", "secret"),
+        ("OIDC_REDIRECT_URL", "http://localhost:3000/oidc/callback"),
+    ])
+    .await
+}
+
+/// Signs in at the provider as `subject` with `claims`, then in the app
+/// with the code the provider redirects back with.
+async fn sign_in(app: &TestApp, subject: &str, claims: Value) -> GraphQlResponse {
+    let start = app
+        .graphql("/users")
+        .send("mutation { oidcLoginUrl }", json!({}))
+        .await;
+    let url = start.data("oidcLoginUrl").as_str().unwrap();
+    let redirect = app
+        .client()
+        .post(url)
+        .form(&[("username", subject), ("claims", &claims.to_string())])
+        .send()
+        .await
+        .unwrap();
+    let location = redirect.headers()[LOCATION].to_str().unwrap();
+    let callback = Url::parse(location).unwrap();
+    let param = |name: &str| {
+        callback
+            .query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+            .unwrap_or_else(|| panic!("no `{name}` in {location}"))
+    };
+    app.graphql("/users")
+        .send(
+            OIDC_LOGIN,
+            json!({ "code": param("code"), "state": param("state") }),
+        )
+        .await
+}
+
+async fn me(app: &TestApp, token: &str) -> GraphQlResponse {
+    app.graphql("/users")
+        .token(token)
+        .send("{ me { id } }", json!({}))
+        .await
+}
+
+#[tokio::test]
+#[ignore = "needs PostgreSQL and the mock OIDC provider"]
+async fn first_sign_in_creates_a_user_that_later_ones_find() {
+    let app = start().await;
+    let claims = json!({
+        "email": "Alice@IdP.example",
+        "email_verified": true,
+        "given_name": "Alice",
+        "family_name": "Liddell",
+    });
+
+    let first = sign_in(&app, "alice", claims.clone()).await;
+    let user = &first.data("oidcLogin")["user"];
+    assert_eq!(user["email"], "alice@idp.example");
+    assert_eq!(user["firstName"], "Alice");
+    assert_eq!(user["lastName"], "Liddell");
+    let token = first.data("oidcLogin")["token"].as_str().unwrap();
+    assert_eq!(me(&app, token).await.data("me")["id"], user["id"]);
+
+    let again = sign_in(&app, "alice", claims).await;
+    assert_eq!(again.data("oidcLogin")["user"]["id"], user["id"]);
+    // Only the provider signs the user in.
+    let login = app.log_in("alice@idp.example", "").await;
+    assert_eq!(login.error_code(), Some("UNAUTHENTICATED"));
+
+    app.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs PostgreSQL and the mock OIDC provider"]
+async fn verified_local_accounts_are_linked() {
+    let app = start().await;
+    let id = app.sign_up("poet@corp.example").await;
+    sqlx::query("UPDATE users SET email_verified_at = now() WHERE id = $1")
+        .bind(id)
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+    let claims = json!({ "email": "poet@corp.example", "email_verified": true });
+    let signed_in = sign_in(&app, "poet", claims).await;
+    assert_eq!(
+        signed_in.data("oidcLogin")["user"]["id"],
+        id.to_string().as_str()
+    );
+    let login = app.log_in("poet@corp.example", PASSWORD).await;
+    assert!(login.data("login")["token"].is_string());
+
+    app.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs PostgreSQL and the mock OIDC provider"]
+async fn unverified_local_accounts_are_taken_over() {
+    let app = start().await;
+    // Someone registers the victim's address ahead of their first sign-in.
+    let id = app.sign_up("victim@corp.example").await;
+    let squatter = app.token("victim@corp.example").await;
+
+    let claims = json!({ "email": "Victim@corp.example", "email_verified": true });
+    let signed_in = sign_in(&app, "victim", claims).await;
+    let payload = signed_in.data("oidcLogin");
+    assert_eq!(payload["user"]["id"], id.to_string().as_str());
+    let token = payload["token"].as_str().unwrap();
+    assert!(me(&app, token).await.data("me")["id"].is_string());
+
+    assert_eq!(
+        me(&app, &squatter).await.error_code(),
+        Some("UNAUTHENTICATED")
+    );
+    let login = app.log_in("victim@corp.example", PASSWORD).await;
+    assert_eq!(login.error_code(), Some("UNAUTHENTICATED"));
+
+    app.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs PostgreSQL and the mock OIDC provider"]
+async fn unverified_provider_emails_are_refused() {
+    let app = start().await;
+    app.sign_up("victim@corp.example").await;
+
+    let claims = json!({ "email": "victim@corp.example", "email_verified": false });
+    let signed_in = sign_in(&app, "mallory", claims).await;
+    assert_eq!(signed_in.error_code(), Some("VALIDATION"));
+    let login = app.log_in("victim@corp.example", PASSWORD).await;
+    assert!(login.data("login")["token"].is_string());
+
+    app.stop().await;
+}
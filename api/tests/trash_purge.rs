@@ -0,0 +1,218 @@
+mod common;
+
+use common::TestApp;
+use serde_json::json;
+use sqlx::PgPool;
+use std::time::Duration;
+use uuid::Uuid;
+
+async fn prompt(pool: &PgPool, title: &str) -> Uuid {
+    sqlx::query_scalar("INSERT INTO prompts (title, content) VALUES ($1, 'content') RETURNING id")
+        .bind(title)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+async fn haiku(pool: &PgPool, prompt_id: Uuid, content: &str) -> Uuid {
+    sqlx::query_scalar(
+        "INSERT INTO haikus (content, is_funny, prompt_id) VALUES ($1, false, $2) RETURNING id",
+    )
+    .bind(content)
+    .bind(prompt_id)
+    .fetch_one(pool)
+    .await
+    .unwrap()
+}
+
+async fn exists(pool: &PgPool, table: &str, id: Uuid) -> bool {
+    sqlx::query_scalar(&format!(
+        "SELECT EXISTS (SELECT 1 FROM {table} WHERE id = $1)"
+    ))
+    .bind(id)
+    .fetch_one(pool)
+    .await
+    .unwrap()
+}
+
+async fn deleted(pool: &PgPool, table: &str, id: Uuid) -> bool {
+    sqlx::query_scalar(&format!(
+        "SELECT deleted_at IS NOT NULL FROM {table} WHERE id = $1"
+    ))
+    .bind(id)
+    .fetch_one(pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+#[ignore = "needs PostgreSQL"]
+async fn trashed_prompts_take_their_haikus_along_and_are_purged_with_them() {
+    let app = TestApp::start(&[("TWO_FACTOR_ROLES", ""), ("TRASH_PURGE_INTERVAL_SECS", "1")]).await;
+    let token = app.admin_token("admin@example.com").await;
+    let pool = &app.pool;
+
+    let trashed = prompt(pool, "Autumn").await;
+    let alone = haiku(pool, trashed, "trashed on its own").await;
+    let along = haiku(pool, trashed, "trashed along").await;
+    let kept = prompt(pool, "Winter").await;
+    let live = haiku(pool, kept, "never trashed").await;
+    // Trashed before prompts took their haikus along: the purge must wait
+    // for the haiku rather than cascade to it.
+    let legacy = prompt(pool, "Spring").await;
+    let orphan = haiku(pool, legacy, "under a trashed prompt").await;
+    sqlx::query("UPDATE prompts SET deleted_at = now() - interval '60 days' WHERE id = $1")
+        .bind(legacy)
+        .execute(pool)
+        .await
+        .unwrap();
+
+    let delete_haiku = "mutation($id: UUID!) { deleteHaiku(id: $id) { id } }";
+    let delete_prompt = "mutation($id: UUID!) { deletePrompt(id: $id) { id } }";
+    let restore_prompt = "mutation($id: UUID!) { restorePrompt(id: $id) { id } }";
+    let graphql = |route| app.graphql(route).token(&token);
+    graphql("/haikus")
+        .send(delete_haiku, json!({ "id": alone }))
+        .await
+        .data("deleteHaiku");
+    graphql("/prompts")
+        .send(delete_prompt, json!({ "id": trashed }))
+        .await
+        .data("deletePrompt");
+    assert!(deleted(pool, "haikus", along).await);
+
+    let trash = graphql("/trash")
+        .send("{ trash(kind: HAIKU) { id } }", json!({}))
+        .await;
+    let mut ids: Vec<&str> = trash
+        .data("trash")
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["id"].as_str().unwrap())
+        .collect();
+    ids.sort();
+    let mut expected = [alone.to_string(), along.to_string()];
+    expected.sort();
+    assert_eq!(ids, expected);
+
+    // Restoring brings back what was trashed along, not what was trashed
+    // before.
+    graphql("/prompts")
+        .send(restore_prompt, json!({ "id": trashed }))
+        .await
+        .data("restorePrompt");
+    assert!(!deleted(pool, "haikus", along).await);
+    assert!(deleted(pool, "haikus", alone).await);
+
+    graphql("/prompts")
+        .send(delete_prompt, json!({ "id": trashed }))
+        .await
+        .data("deletePrompt");
+    for table in ["prompts", "haikus"] {
+        sqlx::query(&format!(
+            "UPDATE {table} SET deleted_at = deleted_at - interval '60 days' WHERE deleted_at IS NOT NULL AND id <> $1"
+        ))
+        .bind(legacy)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    let mut purged = false;
+    for _ in 0..50 {
+        if !exists(pool, "prompts", trashed).await {
+            purged = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    assert!(purged, "the purge never removed the trashed prompt");
+    assert!(!exists(pool, "haikus", alone).await);
+    assert!(!exists(pool, "haikus", along).await);
+    assert!(exists(pool, "prompts", kept).await);
+    assert!(exists(pool, "haikus", live).await);
+    assert!(exists(pool, "prompts", legacy).await);
+    assert!(exists(pool, "haikus", orphan).await);
+
+    let scheduled: i64 = sqlx::query_scalar(
+        "SELECT count(*) FROM jobs WHERE kind = 'purge_trash' AND status IN ('pending', 'running')",
+    )
+    .fetch_one(pool)
+    .await
+    .unwrap();
+    assert_eq!(scheduled, 1, "exactly one purge stays scheduled");
+
+    app.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs PostgreSQL"]
+async fn a_dead_lettered_purge_schedules_the_next_one() {
+    let app = TestApp::start(&[("TRASH_PURGE_INTERVAL_SECS", "3600")]).await;
+    let pool = &app.pool;
+
+    let job: Uuid = sqlx::query_scalar(
+        "SELECT id FROM jobs WHERE kind = 'purge_trash' ORDER BY created_at LIMIT 1",
+    )
+    .fetch_one(pool)
+    .await
+    .unwrap();
+    let mut ran = false;
+    for _ in 0..50 {
+        let status: String = sqlx::query_scalar("SELECT status::text FROM jobs WHERE id = $1")
+            .bind(job)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        if status == "completed" {
+            ran = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(ran, "the first purge never ran");
+    // Make it a run that died without scheduling the next one, out of
+    // attempts.
+    sqlx::query("DELETE FROM jobs WHERE kind = 'purge_trash' AND id <> $1")
+        .bind(job)
+        .execute(pool)
+        .await
+        .unwrap();
+    sqlx::query(
+        r#"
+        UPDATE jobs
+        SET status = 'running', attempts = max_attempts,
+            locked_at = now() - interval '1 day', completed_at = NULL
+        WHERE id = $1
+        "#,
+    )
+    .bind(job)
+    .execute(pool)
+    .await
+    .unwrap();
+
+    let mut scheduled = false;
+    for _ in 0..50 {
+        let pending: i64 = sqlx::query_scalar(
+            "SELECT count(*) FROM jobs WHERE kind = 'purge_trash' AND status = 'pending'",
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        if pending == 1 {
+            scheduled = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    assert!(scheduled, "no purge was scheduled after the dead one");
+    let status: String = sqlx::query_scalar("SELECT status::text FROM jobs WHERE id = $1")
+        .bind(job)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "dead");
+
+    app.stop().await;
+}
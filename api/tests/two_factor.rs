@@ -0,0 +1,151 @@
+mod common;
+
+use common::{PASSWORD, TestApp};
+use hmac::{Hmac, Mac};
+use serde_json::{Value, json};
+use sha1::Sha1;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const EMAIL: &str = "admin@example.com";
+
+const VERIFY: &str = "mutation($challenge: String!, $code: String!) {
+    verifyTwoFactor(challenge: $challenge, code: $code) { token }
+}";
+
+fn current_step() -> i64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    now.as_secs() as i64 / 30
+}
+
+/// The code an authenticator app shows for `secret` at `step`.
+fn code_at(secret: &str, step: i64) -> String {
+    let secret = base32::decode(base32::Alphabet::Rfc4648 { padding: false }, secret).unwrap();
+    let mut mac = Hmac::<Sha1>::new_from_slice(&secret).unwrap();
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[19] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    format!("{:06}", binary % 1_000_000)
+}
+
+async fn challenge(app: &TestApp) -> String {
+    let login = app.log_in(EMAIL, PASSWORD).await;
+    let payload = login.data("login");
+    assert_eq!(payload["token"], Value::Null, "no token before the code");
+    payload["challenge"].as_str().unwrap().to_string()
+}
+
+async fn trash(app: &TestApp, token: &str) -> Option<String> {
+    app.graphql("/trash")
+        .token(token)
+        .send("{ trash { id } }", json!({}))
+        .await
+        .error_code()
+        .map(str::to_string)
+}
+
+#[tokio::test]
+#[ignore = "needs PostgreSQL"]
+async fn admins_sign_in_with_a_second_factor() {
+    let app = TestApp::start(&[
+        ("TWO_FACTOR_ROLES", "admin"),
+        ("LOGIN_DELAY_BASE_MS", "0"),
+        ("LOGIN_MAX_FAILURES", "20"),
+    ])
+    .await;
+    let password_only = app.admin_token(EMAIL).await;
+    assert_eq!(
+        trash(&app, &password_only).await.as_deref(),
+        Some("FORBIDDEN")
+    );
+
+    let enrollment = app
+        .graphql("/users")
+        .token(&password_only)
+        .send("mutation { enrollTwoFactor { secret } }", json!({}))
+        .await;
+    let secret = enrollment.data("enrollTwoFactor")["secret"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let step = current_step();
+    let confirmation = app
+        .graphql("/users")
+        .token(&password_only)
+        .send(
+            "mutation($code: String!) { confirmTwoFactor(code: $code) }",
+            json!({ "code": code_at(&secret, step) }),
+        )
+        .await;
+    let recovery_codes: Vec<String> =
+        serde_json::from_value(confirmation.data("confirmTwoFactor").clone()).unwrap();
+    // The step confirmation used is spent, the next one is still valid.
+    let next_code = code_at(&secret, step + 1);
+    let wrong_code = ["000000", "111111"]
+        .into_iter()
+        .find(|code| (step - 1..=step + 2).all(|step| code_at(&secret, step) != *code))
+        .unwrap();
+
+    // Wrong codes use the challenge up, and count as failed logins.
+    let used_up = challenge(&app).await;
+    for _ in 0..3 {
+        let verify = app
+            .graphql("/users")
+            .send(VERIFY, json!({ "challenge": used_up, "code": wrong_code }))
+            .await;
+        assert_eq!(verify.error_code(), Some("UNAUTHENTICATED"), "{verify:?}");
+    }
+    let verify = app
+        .graphql("/users")
+        .send(VERIFY, json!({ "challenge": used_up, "code": next_code }))
+        .await;
+    assert_eq!(verify.error_message(), Some("Invalid or expired token"));
+    let failures: i32 = sqlx::query_scalar(
+        "SELECT failures FROM login_throttles WHERE kind = 'account' AND subject = $1",
+    )
+    .bind(EMAIL)
+    .fetch_one(&app.pool)
+    .await
+    .unwrap();
+    assert_eq!(failures, 3);
+
+    let login = challenge(&app).await;
+    let verify = app
+        .graphql("/users")
+        .send(VERIFY, json!({ "challenge": login, "code": next_code }))
+        .await;
+    let token = verify.data("verifyTwoFactor")["token"].as_str().unwrap();
+    assert_eq!(trash(&app, token).await, None);
+
+    // Neither codes nor recovery codes work twice.
+    let login = challenge(&app).await;
+    let replay = app
+        .graphql("/users")
+        .send(VERIFY, json!({ "challenge": login, "code": next_code }))
+        .await;
+    assert_eq!(replay.error_code(), Some("UNAUTHENTICATED"));
+    let recovery = app
+        .graphql("/users")
+        .send(
+            VERIFY,
+            json!({ "challenge": login, "code": recovery_codes[0] }),
+        )
+        .await;
+    assert!(recovery.data("verifyTwoFactor")["token"].is_string());
+    let login = challenge(&app).await;
+    let replay = app
+        .graphql("/users")
+        .send(
+            VERIFY,
+            json!({ "challenge": login, "code": recovery_codes[0] }),
+        )
+        .await;
+    assert_eq!(replay.error_code(), Some("UNAUTHENTICATED"));
+
+    app.stop().await;
+}
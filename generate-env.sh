@@ -22,7 +22,7 @@ fi
 
 source "$ENV_FILE"
 
-required_vars=("DB_HOST" "DB_PORT" "DB_USER" "DB_PASSWORD" "DB_NAME" "SRV_HOST" "SRV_PORT")
+required_vars=("DB_HOST" "DB_PORT" "DB_USER" "DB_PASSWORD" "DB_NAME" "SRV_HOST" "SRV_PORT" "JWT_SECRET" "TOTP_ENCRYPTION_KEY")
 for var in "${required_vars[@]}"; do
   if [ -z "${!var}" ]; then
     echo "Error: Variable $var is not set in $ENV_FILE!"
@@ -40,6 +40,8 @@ DB_USER=${DB_USER}
 DB_PASSWORD=${DB_PASSWORD}
 DB_NAME=${DB_NAME}
 CONTAINER_DB_NAME=${CONTAINER_DB_NAME}
+DB_MAX_CONNECTIONS=${DB_MAX_CONNECTIONS:-10}
+DB_ACQUIRE_TIMEOUT_SECS=${DB_ACQUIRE_TIMEOUT_SECS:-10}
 
 # Sqlx
 DATABASE_URL=${DATABASE_URL}
@@ -47,6 +49,77 @@ DATABASE_URL=${DATABASE_URL}
 # Api
 SRV_HOST=${SRV_HOST}
 SRV_PORT=${SRV_PORT}
+PUBLIC_URL=${PUBLIC_URL:-http://${SRV_HOST}:${SRV_PORT}}
+CORS_ALLOWED_ORIGINS=${CORS_ALLOWED_ORIGINS:-}
+
+# Haiku generation
+DEEPSEEK_API_URL=${DEEPSEEK_API_URL:-}
+DEEPSEEK_API_KEY=${DEEPSEEK_API_KEY:-}
+
+# Auth
+JWT_SECRET=${JWT_SECRET}
+JWT_TTL_SECS=${JWT_TTL_SECS:-86400}
+TOTP_ENCRYPTION_KEY=${TOTP_ENCRYPTION_KEY}
+TOTP_ISSUER=${TOTP_ISSUER:-Haiku}
+TWO_FACTOR_ROLES=${TWO_FACTOR_ROLES:-admin}
+LOGIN_MAX_FAILURES=${LOGIN_MAX_FAILURES:-5}
+LOGIN_MAX_FAILURES_PER_IP=${LOGIN_MAX_FAILURES_PER_IP:-20}
+LOGIN_FAILURE_WINDOW_MINS=${LOGIN_FAILURE_WINDOW_MINS:-15}
+LOGIN_LOCKOUT_MINS=${LOGIN_LOCKOUT_MINS:-15}
+LOGIN_DELAY_BASE_MS=${LOGIN_DELAY_BASE_MS:-1000}
+LOGIN_DELAY_MAX_MS=${LOGIN_DELAY_MAX_MS:-30000}
+
+# Jobs
+JOBS_WORKERS=${JOBS_WORKERS:-4}
+JOBS_POLL_INTERVAL_MS=${JOBS_POLL_INTERVAL_MS:-1000}
+BATCH_CONCURRENCY=${BATCH_CONCURRENCY:-2}
+
+# Search
+SEARCH_LANGUAGE=${SEARCH_LANGUAGE:-english}
+
+# Near-duplicates (DUPLICATE_ACTION is reject or flag)
+DUPLICATE_THRESHOLD=${DUPLICATE_THRESHOLD:-0.8}
+DUPLICATE_ACTION=${DUPLICATE_ACTION:-flag}
+
+# Tags
+AUTO_TAG=${AUTO_TAG:-false}
+
+# Cards (CARD_FONTS_DIR adds fonts to the system ones)
+CARD_ATTRIBUTION=${CARD_ATTRIBUTION:-anonymous}
+CARD_FONTS_DIR=${CARD_FONTS_DIR:-}
+
+# Trash (retentions in days, 0 keeps soft-deleted rows forever;
+# the per-entity ones default to TRASH_RETENTION_DAYS)
+TRASH_RETENTION_DAYS=${TRASH_RETENTION_DAYS:-30}
+TRASH_RETENTION_USERS_DAYS=${TRASH_RETENTION_USERS_DAYS:-${TRASH_RETENTION_DAYS:-30}}
+TRASH_RETENTION_PROMPTS_DAYS=${TRASH_RETENTION_PROMPTS_DAYS:-${TRASH_RETENTION_DAYS:-30}}
+TRASH_RETENTION_HAIKUS_DAYS=${TRASH_RETENTION_HAIKUS_DAYS:-${TRASH_RETENTION_DAYS:-30}}
+TRASH_PURGE_INTERVAL_SECS=${TRASH_PURGE_INTERVAL_SECS:-3600}
+TRASH_PURGE_BATCH_SIZE=${TRASH_PURGE_BATCH_SIZE:-500}
+
+# Mail (MAIL_TRANSPORT is log, file or smtp; SMTP_TLS is tls, starttls or none)
+MAIL_TRANSPORT=${MAIL_TRANSPORT:-log}
+MAIL_FROM="${MAIL_FROM:-Haiku <no-reply@localhost>}"
+MAIL_DIR=${MAIL_DIR:-mail}
+SMTP_HOST=${SMTP_HOST:-}
+SMTP_PORT=${SMTP_PORT:-587}
+SMTP_TLS=${SMTP_TLS:-starttls}
+SMTP_USERNAME=${SMTP_USERNAME:-}
+SMTP_PASSWORD=${SMTP_PASSWORD:-}
+
+# Accounts (APP_URL is where verification and reset links point to)
+APP_URL=${APP_URL:-${PUBLIC_URL:-http://${SRV_HOST}:${SRV_PORT}}}
+EMAIL_VERIFICATION_TTL_MINS=${EMAIL_VERIFICATION_TTL_MINS:-1440}
+PASSWORD_RESET_TTL_MINS=${PASSWORD_RESET_TTL_MINS:-60}
+
+# OpenID Connect (off while OIDC_ISSUER is empty)
+OIDC_ISSUER=${OIDC_ISSUER:-}
+OIDC_CLIENT_ID=${OIDC_CLIENT_ID:-}
+OIDC_CLIENT_SECRET=${OIDC_CLIENT_SECRET:-}
+OIDC_REDIRECT_URL=${OIDC_REDIRECT_URL:-${APP_URL:-${PUBLIC_URL:-http://${SRV_HOST}:${SRV_PORT}}}/oidc/callback}
+OIDC_SCOPES="${OIDC_SCOPES:-openid email profile}"
+OIDC_ROLE_CLAIM=${OIDC_ROLE_CLAIM:-}
+OIDC_ROLE_MAP=${OIDC_ROLE_MAP:-}
 EOF
 
 echo ".env file generated successfully for environment: $ENVIRONMENT"
\ No newline at end of file
@@ -0,0 +1,9 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS haiku_favorites (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    haiku_id UUID NOT NULL REFERENCES haikus(id) ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, haiku_id)
+);
+
+CREATE INDEX idx_haiku_favorites_haiku_id ON haiku_favorites(haiku_id);
@@ -0,0 +1,32 @@
+-- Add migration script here
+CREATE EXTENSION IF NOT EXISTS "uuid-ossp";
+
+CREATE TABLE IF NOT EXISTS collections (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    owner_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    description TEXT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_collections_owner_id ON collections(owner_id);
+
+CREATE TABLE IF NOT EXISTS collection_haikus (
+    collection_id UUID NOT NULL REFERENCES collections(id) ON DELETE CASCADE,
+    haiku_id UUID NOT NULL REFERENCES haikus(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (collection_id, haiku_id)
+);
+
+CREATE INDEX idx_collection_haikus_haiku_id ON collection_haikus(haiku_id);
+
+CREATE TABLE IF NOT EXISTS collection_shares (
+    collection_id UUID NOT NULL REFERENCES collections(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (collection_id, user_id)
+);
+
+CREATE INDEX idx_collection_shares_user_id ON collection_shares(user_id);
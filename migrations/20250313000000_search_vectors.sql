@@ -0,0 +1,16 @@
+-- Add migration script here
+ALTER TABLE prompts ADD COLUMN language REGCONFIG NOT NULL DEFAULT 'english';
+ALTER TABLE prompts ADD COLUMN search_vector TSVECTOR GENERATED ALWAYS AS (
+    setweight(to_tsvector(language, title), 'A') || setweight(to_tsvector(language, content), 'B')
+) STORED;
+
+CREATE INDEX idx_prompts_search_vector ON prompts USING GIN(search_vector);
+CREATE INDEX idx_prompts_language ON prompts(language);
+
+ALTER TABLE haikus ADD COLUMN language REGCONFIG NOT NULL DEFAULT 'english';
+ALTER TABLE haikus ADD COLUMN search_vector TSVECTOR GENERATED ALWAYS AS (
+    to_tsvector(language, content)
+) STORED;
+
+CREATE INDEX idx_haikus_search_vector ON haikus USING GIN(search_vector);
+CREATE INDEX idx_haikus_language ON haikus(language);
@@ -0,0 +1,10 @@
+-- Add migration script here
+ALTER TABLE haikus ADD COLUMN fingerprint INTEGER[];
+ALTER TABLE haikus ADD COLUMN fingerprint_bands BIGINT[];
+ALTER TABLE haikus ADD COLUMN duplicate_of UUID REFERENCES haikus(id) ON DELETE SET NULL;
+
+CREATE INDEX idx_haikus_fingerprint_bands ON haikus USING GIN(fingerprint_bands);
+CREATE INDEX idx_haikus_duplicate_of ON haikus(duplicate_of);
+
+-- Existing haikus are fingerprinted by the worker.
+INSERT INTO jobs (kind) VALUES ('fingerprint_haikus');
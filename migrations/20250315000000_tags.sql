@@ -0,0 +1,32 @@
+-- Add migration script here
+CREATE EXTENSION IF NOT EXISTS "uuid-ossp";
+
+CREATE TYPE tag_kind AS ENUM ('season', 'mood', 'subject', 'other');
+
+CREATE TABLE IF NOT EXISTS tags (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    name VARCHAR(64) NOT NULL UNIQUE,
+    kind tag_kind NOT NULL DEFAULT 'other',
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_tags_kind ON tags(kind);
+
+CREATE TABLE IF NOT EXISTS haiku_tags (
+    haiku_id UUID NOT NULL REFERENCES haikus(id) ON DELETE CASCADE,
+    tag_id UUID NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (haiku_id, tag_id)
+);
+
+CREATE INDEX idx_haiku_tags_tag_id ON haiku_tags(tag_id);
+
+CREATE TABLE IF NOT EXISTS prompt_tags (
+    prompt_id UUID NOT NULL REFERENCES prompts(id) ON DELETE CASCADE,
+    tag_id UUID NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (prompt_id, tag_id)
+);
+
+CREATE INDEX idx_prompt_tags_tag_id ON prompt_tags(tag_id);
@@ -0,0 +1,4 @@
+-- What a user may do beyond their own content, checked on each admin call.
+CREATE TYPE user_role AS ENUM ('user', 'admin');
+
+ALTER TABLE users ADD COLUMN role user_role NOT NULL DEFAULT 'user';
@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE EXTENSION IF NOT EXISTS "uuid-ossp";
+
+CREATE TYPE season AS ENUM ('spring', 'summer', 'autumn', 'winter', 'new_year');
+
+-- Saijiki entries added on top of the dictionary bundled with the API.
+CREATE TABLE IF NOT EXISTS kigo (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    word VARCHAR(64) NOT NULL,
+    season season NOT NULL,
+    language TEXT NOT NULL,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (word, language)
+);
@@ -0,0 +1,2 @@
+-- Who wrote a haiku, for those imported from a corpus rather than generated.
+ALTER TABLE haikus ADD COLUMN author VARCHAR(255);
@@ -0,0 +1,4 @@
+-- Add migration script here
+CREATE INDEX IF NOT EXISTS idx_users_deleted_at ON users(deleted_at) WHERE deleted_at IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_prompts_deleted_at ON prompts(deleted_at) WHERE deleted_at IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_haikus_deleted_at ON haikus(deleted_at) WHERE deleted_at IS NOT NULL;
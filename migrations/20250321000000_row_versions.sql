@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE users ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE prompts ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE haikus ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,14 @@
+-- Add migration script here
+
+-- Recent failed logins per account (kind 'account', subject the lowercased
+-- email, known or not) and per client address (kind 'ip'). failures counts
+-- those since the window started at window_started_at.
+CREATE TABLE IF NOT EXISTS login_throttles (
+    kind VARCHAR(16) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    failures INTEGER NOT NULL DEFAULT 0,
+    window_started_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_failure_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    locked_until TIMESTAMP WITH TIME ZONE,
+    PRIMARY KEY (kind, subject)
+);
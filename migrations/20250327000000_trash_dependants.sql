@@ -0,0 +1,12 @@
+-- Rows that outlive the prompt or user they came from once it is purged.
+-- A batch keeps the record of its items, and share links keep working
+-- after their creator is gone.
+ALTER TABLE batch_items ALTER COLUMN prompt_id DROP NOT NULL;
+ALTER TABLE batch_items DROP CONSTRAINT batch_items_prompt_id_fkey;
+ALTER TABLE batch_items ADD CONSTRAINT batch_items_prompt_id_fkey
+    FOREIGN KEY (prompt_id) REFERENCES prompts(id) ON DELETE SET NULL;
+
+ALTER TABLE haiku_shares ALTER COLUMN created_by DROP NOT NULL;
+ALTER TABLE haiku_shares DROP CONSTRAINT haiku_shares_created_by_fkey;
+ALTER TABLE haiku_shares ADD CONSTRAINT haiku_shares_created_by_fkey
+    FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL;
@@ -0,0 +1,3 @@
+-- Wrong answers to a token, such as codes given for a two-factor challenge,
+-- which is used up after a few of them.
+ALTER TABLE account_tokens ADD COLUMN failures INT NOT NULL DEFAULT 0;
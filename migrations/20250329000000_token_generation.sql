@@ -0,0 +1,3 @@
+-- Session tokens carry the generation they were issued at, so bumping it
+-- signs the user out everywhere, as resetting their password does.
+ALTER TABLE users ADD COLUMN token_generation INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,20 @@
+-- Accounts are looked up by lowercased email, so it must identify one.
+-- Where several accounts differ only by the case of their email, the one
+-- with a verified email keeps it, then a live one, then the oldest; the
+-- others get an undeliverable placeholder, keeping their data, for an admin
+-- to sort out.
+WITH ranked AS (
+    SELECT id, row_number() OVER (
+        PARTITION BY lower(btrim(email))
+        ORDER BY email_verified_at IS NULL, deleted_at IS NOT NULL, created_at, id
+    ) AS rank
+    FROM users
+)
+UPDATE users
+SET email = users.id || '@duplicate.invalid'
+FROM ranked
+WHERE users.id = ranked.id AND ranked.rank > 1;
+
+UPDATE users SET email = lower(btrim(email)) WHERE email <> lower(btrim(email));
+
+CREATE UNIQUE INDEX idx_users_email_lower ON users (lower(email));
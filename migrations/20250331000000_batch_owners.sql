@@ -0,0 +1,3 @@
+-- Who started each batch, as only they and admins may follow it. Batches
+-- started before, or whose creator is purged, are left to admins.
+ALTER TABLE batches ADD COLUMN created_by UUID REFERENCES users(id) ON DELETE SET NULL;